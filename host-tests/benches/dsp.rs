@@ -0,0 +1,42 @@
+// Compares both implementations per call. The host has an FPU, so the f32 path wins here; on the
+// ESP32-C6 every f32 operation is a soft-float call and only the `benchmark` command tells.
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use mosquitto_bzzz_host_tests::{
+    dsp::{self, Decibel, RawAdc},
+    fixed_point,
+};
+
+const ITERATIONS: u32 = 100_000;
+
+fn time<T>(name: &str, mut f: impl FnMut() -> T) -> Duration {
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+    let per_call = started.elapsed() / ITERATIONS;
+    println!("{:<16} {:>8?}/call", name, per_call);
+    per_call
+}
+
+fn main() {
+    let mut state = 0x2545_f491u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    let samples: Vec<RawAdc> = (0..5).map(|_| RawAdc((next() % 4096) as u16)).collect();
+    let levels: Vec<Decibel> = (0..1000)
+        .map(|_| Decibel(30.0 + (next() % 6000) as f32 / 100.0))
+        .collect();
+
+    time("rms f32", || dsp::rms_to_db_f32(black_box(&samples)));
+    time("rms fixed", || fixed_point::rms_to_db(black_box(&samples)));
+    time("leq(1000) f32", || dsp::leq_f32(black_box(&levels)));
+    time("leq(1000) fixed", || fixed_point::leq(black_box(&levels)));
+}
@@ -0,0 +1,177 @@
+// Writes a unit's identity and WiFi credentials into its NVS partition, with the firmware's own
+// FactoryData so a blob that passes here also passes at boot. Needs the NVS partition generator
+// and esptool from pip (`esp-idf-nvs-partition-gen`, `esptool`).
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context, Result};
+use mosquitto_bzzz_host_tests::factory::{self, FactoryData};
+
+// The nvs entry of the two-OTA partition table in sdkconfig.defaults
+const NVS_OFFSET: &str = "0x9000";
+const NVS_SIZE: &str = "0x4000";
+
+const USAGE: &str = "Usage: bzzz-provision [--device-id ID] [--mqtt-host HOST] [--ca-cert PEM_FILE]
+    [--client-cert PEM_FILE --client-key PEM_FILE] [--tenant-id ID] [--site-id ID] [--floor FLOOR]
+    [--wifi-ssid SSID [--wifi-password PASSWORD]] [--out DIR] [--port SERIAL_PORT]
+
+Generates <out>/nvs.bin (the current directory by default) and, with --port, flashes it at
+0x9000. This replaces the whole NVS partition, settings changed at runtime included.";
+
+#[derive(Default)]
+struct Options {
+    data: FactoryData,
+    wifi: Option<(String, String)>,
+    out: PathBuf,
+    port: Option<String>,
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{:#}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let options = parse_args(env::args().skip(1))?;
+    let blob = serde_json::to_vec(&options.data).context("Unable to serialize factory data")?;
+    FactoryData::parse(&blob).context("The firmware would ignore this factory data")?;
+
+    fs::create_dir_all(&options.out)
+        .with_context(|| format!("Unable to create {}", options.out.display()))?;
+    let blob_path = options.out.join("factory.json");
+    fs::write(&blob_path, &blob)
+        .with_context(|| format!("Unable to write {}", blob_path.display()))?;
+    let csv_path = options.out.join("nvs.csv");
+    fs::write(&csv_path, nvs_csv(&blob_path, options.wifi.as_ref()))
+        .with_context(|| format!("Unable to write {}", csv_path.display()))?;
+    let bin_path = options.out.join("nvs.bin");
+    run_python(
+        "esp_idf_nvs_partition_gen",
+        &[
+            "generate",
+            &csv_path.to_string_lossy(),
+            &bin_path.to_string_lossy(),
+            NVS_SIZE,
+        ],
+    )?;
+    println!("Generated {}", bin_path.display());
+
+    if let Some(port) = options.port.as_deref() {
+        run_python(
+            "esptool",
+            &[
+                "--chip",
+                "esp32c6",
+                "--port",
+                port,
+                "write_flash",
+                NVS_OFFSET,
+                &bin_path.to_string_lossy(),
+            ],
+        )?;
+        println!("Flashed {} to {}", bin_path.display(), port);
+    }
+    Ok(())
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    let mut options = Options {
+        data: FactoryData::new(),
+        out: PathBuf::from("."),
+        ..Default::default()
+    };
+    let mut wifi_ssid = None;
+    let mut wifi_password = None;
+    while let Some(name) = args.next() {
+        if name == "--help" || name == "-h" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        let Some(value) = args.next() else {
+            bail!("{} needs a value\n\n{}", name, USAGE);
+        };
+        let data = &mut options.data;
+        match name.as_str() {
+            "--device-id" => data.device_id = Some(value),
+            "--mqtt-host" => data.mqtt_host = Some(value),
+            "--ca-cert" => data.ca_cert = Some(read_pem(&value)?),
+            "--client-cert" => data.client_cert = Some(read_pem(&value)?),
+            "--client-key" => data.client_key = Some(read_pem(&value)?),
+            "--tenant-id" => data.tenant_id = Some(value),
+            "--site-id" => data.site_id = Some(value),
+            "--floor" => data.floor = Some(value),
+            "--wifi-ssid" => wifi_ssid = Some(value),
+            "--wifi-password" => wifi_password = Some(value),
+            "--out" => options.out = PathBuf::from(value),
+            "--port" => options.port = Some(value),
+            _ => bail!("Unknown option {}\n\n{}", name, USAGE),
+        }
+    }
+    // Same limits as the captive portal
+    options.wifi = match (wifi_ssid, wifi_password) {
+        (Some(ssid), _) if ssid.is_empty() || ssid.len() > 32 => {
+            bail!("WiFi SSIDs are 1 to 32 bytes")
+        }
+        (Some(_), Some(password))
+            if !password.is_empty() && !(8..=64).contains(&password.len()) =>
+        {
+            bail!("WiFi passwords are 8 to 64 characters")
+        }
+        (Some(ssid), password) => Some((ssid, password.unwrap_or_default())),
+        (None, Some(_)) => bail!("A WiFi password needs --wifi-ssid"),
+        (None, None) => None,
+    };
+    Ok(options)
+}
+
+fn read_pem(path: &str) -> Result<String> {
+    fs::read_to_string(path).with_context(|| format!("Unable to read {}", path))
+}
+
+// The partition generator's CSV: a namespace line, then the entries in it
+fn nvs_csv(blob_path: &Path, wifi: Option<&(String, String)>) -> String {
+    let mut csv = String::from("key,type,encoding,value\n");
+    csv.push_str(&format!("{},namespace,,\n", factory::NVS_NAMESPACE));
+    csv.push_str(&format!(
+        "{},file,binary,{}\n",
+        factory::NVS_KEY,
+        quoted(&blob_path.to_string_lossy())
+    ));
+    if let Some((ssid, password)) = wifi {
+        csv.push_str(&format!("{},namespace,,\n", factory::NVS_DEVICE_NAMESPACE));
+        csv.push_str(&format!(
+            "{},data,string,{}\n",
+            factory::NVS_WIFI_SSID_KEY,
+            quoted(ssid)
+        ));
+        csv.push_str(&format!(
+            "{},data,string,{}\n",
+            factory::NVS_WIFI_PASSWORD_KEY,
+            quoted(password)
+        ));
+    }
+    csv
+}
+
+fn quoted(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+fn run_python(module: &str, args: &[&str]) -> Result<()> {
+    let status = Command::new("python3")
+        .arg("-m")
+        .arg(module)
+        .args(args)
+        .status()
+        .with_context(|| format!("Unable to run python3 -m {}", module))?;
+    if !status.success() {
+        bail!("python3 -m {} failed with {}", module, status);
+    }
+    Ok(())
+}
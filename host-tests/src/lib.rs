@@ -0,0 +1,76 @@
+#[path = "../../src/alerting/burst.rs"]
+pub mod alert_burst;
+#[path = "../../src/alerting/rule.rs"]
+pub mod alert_rule;
+#[path = "../../src/alerting/thresholds.rs"]
+pub mod alert_thresholds;
+// Where the firmware has the alert rule, for the modules using it from there
+pub mod alerting {
+    pub use crate::alert_rule::AlertRule;
+}
+#[path = "../../src/autotune.rs"]
+pub mod autotune;
+#[path = "../../src/backoff.rs"]
+pub mod backoff;
+#[path = "../../src/bands.rs"]
+pub mod bands;
+#[path = "../../src/calibration.rs"]
+pub mod calibration;
+#[path = "../../src/claim.rs"]
+pub mod claim;
+#[path = "../../src/classification.rs"]
+pub mod classification;
+#[path = "../../src/clock/broker.rs"]
+pub mod clock_broker;
+#[path = "../../src/config/settings.rs"]
+pub mod config;
+#[path = "../../src/diag_bundle.rs"]
+pub mod diag_bundle;
+#[path = "../../src/direction.rs"]
+pub mod direction;
+#[path = "../../src/display.rs"]
+pub mod display;
+#[path = "../../src/dsp.rs"]
+pub mod dsp;
+#[path = "../../src/encoding.rs"]
+pub mod encoding;
+#[path = "../../src/factory.rs"]
+pub mod factory;
+#[path = "../../src/fft.rs"]
+pub mod fft;
+#[path = "../../src/fixed_point.rs"]
+pub mod fixed_point;
+#[path = "../../src/frame.rs"]
+pub mod frame;
+#[path = "../../src/fusion.rs"]
+pub mod fusion;
+#[path = "../../src/home_assistant.rs"]
+pub mod home_assistant;
+#[path = "../../src/homie.rs"]
+pub mod homie;
+#[path = "../../src/led.rs"]
+pub mod led;
+#[path = "../../src/loopback.rs"]
+pub mod loopback;
+#[path = "../../src/offline.rs"]
+pub mod offline;
+#[path = "../../src/reporting.rs"]
+pub mod reporting;
+#[path = "../../src/rules.rs"]
+pub mod rules;
+#[path = "../../src/schedule.rs"]
+pub mod schedule;
+#[path = "../../src/spectrum.rs"]
+pub mod spectrum;
+#[path = "../../src/support/stream.rs"]
+pub mod support_stream;
+#[path = "../../src/tone.rs"]
+pub mod tone;
+#[path = "../../src/topics.rs"]
+pub mod topics;
+#[path = "../../src/vibration.rs"]
+pub mod vibration;
+#[path = "../../src/wear/estimate.rs"]
+pub mod wear_estimate;
+#[path = "../../src/weighting.rs"]
+pub mod weighting;
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+use mosquitto_bzzz_host_tests::{alert_burst::AlertBurst, dsp::Decibel};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+// Levels every 50 ms, as the measurement loop takes them
+fn feed(burst: &mut AlertBurst, start: Instant, from_ms: u64, to_ms: u64) -> Vec<String> {
+    (from_ms..to_ms)
+        .step_by(50)
+        .filter_map(|ms| {
+            burst.add(
+                Decibel(ms as f32 / 100.0),
+                start + Duration::from_millis(ms),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn series_covers_the_window_before_and_after_the_trigger() {
+    let mut burst = AlertBurst::new(WINDOW, 4);
+    let start = Instant::now();
+    assert!(feed(&mut burst, start, 0, 3000).is_empty());
+    burst.trigger(
+        7,
+        "very_loud",
+        Some(1_700_000_000),
+        start + Duration::from_millis(3000),
+    );
+    // Not finished before the window after the trigger passed
+    assert!(feed(&mut burst, start, 3000, 4000).is_empty());
+    assert_eq!(
+        feed(&mut burst, start, 4000, 4050),
+        vec![String::from(
+            r#"{"seq":7,"alert":"very_loud","ts":1700000000,"rate_hz":4,"offset_ms":[-1000,-750,-500,-250,0,250,500,750,1000],"db":[20.0,22.5,25.0,27.5,30.0,32.5,35.0,37.5,40.0]}"#
+        )]
+    );
+    assert!(feed(&mut burst, start, 4050, 6000).is_empty());
+}
+
+#[test]
+fn alerts_during_a_capture_join_it() {
+    let mut burst = AlertBurst::new(WINDOW, 4);
+    let start = Instant::now();
+    burst.trigger(1, "very_loud", None, start);
+    burst.trigger(2, "fire_alarm", None, start + Duration::from_millis(500));
+    let series = feed(&mut burst, start, 0, 1050);
+    assert_eq!(series.len(), 1);
+    assert!(series[0].starts_with(r#"{"seq":1,"alert":"very_loud","ts":null,"#));
+    assert!(series[0].contains(r#""offset_ms":[0,250,500,750,1000]"#));
+}
+
+#[test]
+fn zero_window_captures_nothing() {
+    let mut burst = AlertBurst::new(WINDOW, 4);
+    let start = Instant::now();
+    feed(&mut burst, start, 0, 500);
+    burst.configure(Duration::ZERO, 4);
+    burst.trigger(1, "very_loud", None, start + Duration::from_millis(500));
+    assert!(feed(&mut burst, start, 500, 5000).is_empty());
+}
+
+#[test]
+fn alerts_carry_the_last_levels_of_the_history() {
+    let mut burst = AlertBurst::new(WINDOW, 4);
+    burst.configure_context(Duration::from_millis(750));
+    let start = Instant::now();
+    feed(&mut burst, start, 0, 3000);
+    assert_eq!(
+        burst.context_fields(start + Duration::from_millis(2950)),
+        r#""context_step_ms":250,"context_db":[22.5,25.0,27.5]"#
+    );
+    burst.configure_context(Duration::ZERO);
+    assert_eq!(
+        burst.context_fields(start + Duration::from_millis(2950)),
+        ""
+    );
+}
+
+#[test]
+fn context_outlasts_the_capture_window() {
+    let mut burst = AlertBurst::new(Duration::ZERO, 4);
+    burst.configure_context(Duration::from_millis(500));
+    let start = Instant::now();
+    feed(&mut burst, start, 0, 1000);
+    assert_eq!(
+        burst.context_fields(start + Duration::from_millis(950)),
+        r#""context_step_ms":250,"context_db":[5.0,7.5]"#
+    );
+    // Without a capture window, nothing gets captured
+    burst.trigger(1, "very_loud", None, start + Duration::from_millis(1000));
+    assert!(feed(&mut burst, start, 1000, 3000).is_empty());
+}
+
+#[test]
+fn series_leaves_out_the_longer_context() {
+    let mut burst = AlertBurst::new(Duration::from_millis(500), 4);
+    burst.configure_context(Duration::from_secs(2));
+    let start = Instant::now();
+    feed(&mut burst, start, 0, 3000);
+    burst.trigger(7, "very_loud", None, start + Duration::from_millis(3000));
+    let series = feed(&mut burst, start, 3000, 3550);
+    assert_eq!(series.len(), 1);
+    assert!(series[0].contains(r#""offset_ms":[-500,-250,0,250,500]"#));
+}
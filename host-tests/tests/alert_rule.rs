@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+use mosquitto_bzzz_host_tests::{alert_rule::AlertRule, dsp::Decibel};
+
+fn rule(trigger_s: u64, clear_s: u64) -> AlertRule {
+    AlertRule::new(
+        Decibel(80.0),
+        Decibel(77.0),
+        Duration::from_secs(trigger_s),
+        Duration::from_secs(clear_s),
+    )
+}
+
+// Levels one second apart, returns the seconds at which the alert was raised
+fn raised(rule: &mut AlertRule, levels: &[f32]) -> Vec<usize> {
+    let start = Instant::now();
+    levels
+        .iter()
+        .enumerate()
+        .filter(|(second, level)| {
+            rule.update(
+                Decibel(**level),
+                start + Duration::from_secs(*second as u64),
+            )
+        })
+        .map(|(second, _)| second)
+        .collect()
+}
+
+#[test]
+fn without_dwell_times_the_dead_band_alone_stops_flapping() {
+    let mut rule = rule(0, 0);
+    assert_eq!(
+        raised(&mut rule, &[79.0, 80.0, 79.0, 80.5, 78.0, 81.0, 76.9, 80.0]),
+        vec![1, 7]
+    );
+}
+
+#[test]
+fn the_trigger_level_has_to_hold() {
+    let mut rule = rule(2, 0);
+    assert_eq!(
+        raised(&mut rule, &[80.0, 81.0, 79.0, 80.0, 80.0, 80.0]),
+        vec![5]
+    );
+}
+
+#[test]
+fn rearms_only_after_staying_below_the_clear_level() {
+    let mut rule = rule(0, 3);
+    assert_eq!(
+        raised(
+            &mut rule,
+            &[85.0, 70.0, 70.0, 85.0, 70.0, 70.0, 70.0, 70.0, 85.0]
+        ),
+        vec![0, 8]
+    );
+}
+
+#[test]
+fn reconfiguring_keeps_an_active_alert() {
+    let mut rule = rule(0, 0);
+    let now = Instant::now();
+    assert!(rule.update(Decibel(85.0), now));
+    rule.configure(Decibel(70.0), Decibel(67.0), Duration::ZERO, Duration::ZERO);
+    assert!(!rule.update(Decibel(85.0), now));
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use mosquitto_bzzz_host_tests::{
+    alert_thresholds::{ThresholdWatch, Thresholds},
+    config::{Config, ConfigStore},
+    dsp::Decibel,
+};
+
+fn store() -> ConfigStore {
+    ConfigStore::new(Config::defaults(), None)
+}
+
+#[test]
+fn starts_from_the_current_settings() {
+    let store = store();
+    store.update(b"loud_from_db=30").unwrap();
+    let watch = ThresholdWatch::new(&store);
+    assert_eq!(watch.current().level.loud_from, Decibel(30.0));
+}
+
+#[test]
+fn config_update_reaches_the_next_evaluation() {
+    let store = store();
+    let mut watch = ThresholdWatch::new(&store);
+    assert_eq!(watch.changed(), None);
+    store
+        .update(b"very_loud_from_db=85 alert_trigger_s=5")
+        .unwrap();
+    let thresholds = watch.changed().unwrap();
+    assert_eq!(thresholds.level.very_loud_from, Decibel(85.0));
+    assert_eq!(thresholds.level.alert_trigger_dwell, Duration::from_secs(5));
+    assert_eq!(watch.current(), thresholds);
+    assert_eq!(watch.changed(), None);
+}
+
+#[test]
+fn update_from_the_mqtt_thread_is_seen() {
+    let store = store();
+    let mut watch = ThresholdWatch::new(&store);
+    let writer = store.clone();
+    std::thread::spawn(move || writer.update(b"vibration_loud_from_db=40").unwrap())
+        .join()
+        .unwrap();
+    assert_eq!(
+        watch
+            .changed()
+            .map(|thresholds| thresholds.vibration.loud_from),
+        Some(Decibel(40.0))
+    );
+}
+
+#[test]
+fn other_settings_are_not_reported() {
+    let store = store();
+    let mut watch = ThresholdWatch::new(&store);
+    store.update(b"batch_size=10 thermal_limit_c=70").unwrap();
+    assert_eq!(watch.changed(), None);
+}
+
+#[test]
+fn rejected_update_keeps_the_thresholds() {
+    let store = store();
+    let mut watch = ThresholdWatch::new(&store);
+    assert!(store.update(b"loud_from_db=90").is_err());
+    assert_eq!(watch.changed(), None);
+    assert_eq!(watch.current(), Thresholds::of(&Config::defaults()));
+}
+
+#[test]
+fn night_offset_moves_levels_but_not_dwell_times() {
+    let limits = Thresholds::of(&Config::defaults()).level;
+    let night = limits.offset(-10.0);
+    assert_eq!(night.loud_from, limits.loud_from + -10.0);
+    assert_eq!(night.alert_clear, limits.alert_clear + -10.0);
+    assert_eq!(night.alert_clear_dwell, limits.alert_clear_dwell);
+}
@@ -0,0 +1,126 @@
+use std::time::{Duration, Instant};
+
+use mosquitto_bzzz_host_tests::{autotune::AutoTune, dsp::Decibel};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+// A room at 35 dB most of the time, 50 dB for a fifth and 70 dB for a fiftieth of it
+fn observe(autotune: &mut AutoTune) {
+    for i in 0..10_000 {
+        let level = match i % 100 {
+            0 | 1 => 70.2,
+            2..=21 => 50.2,
+            _ => 35.2,
+        };
+        autotune.add(Decibel(level));
+    }
+}
+
+#[test]
+fn proposes_thresholds_from_the_percentiles() {
+    let start = Instant::now();
+    let mut autotune = AutoTune::new(24, start);
+    observe(&mut autotune);
+    assert!(autotune.proposal(start + 23 * HOUR).is_none());
+    let proposal = autotune.proposal(start + 24 * HOUR).unwrap();
+    assert_eq!(proposal.l90, Decibel(35.5));
+    assert_eq!(proposal.l50, Decibel(35.5));
+    assert_eq!(proposal.l10, Decibel(50.5));
+    assert_eq!(proposal.l1, Decibel(70.5));
+    assert_eq!(proposal.normal_from_db, 38.5);
+    assert_eq!(proposal.loud_from_db, 53.5);
+    assert_eq!(proposal.very_loud_from_db, 73.5);
+    assert_eq!(proposal.alert_trigger_db, 73.5);
+    assert_eq!(proposal.alert_clear_db, 70.5);
+    assert_eq!(
+        proposal.to_settings(),
+        "normal_from_db=38.5 loud_from_db=53.5 very_loud_from_db=73.5 alert_trigger_db=73.5 alert_clear_db=70.5"
+    );
+}
+
+#[test]
+fn thresholds_stay_apart_in_a_steady_room() {
+    let start = Instant::now();
+    let mut autotune = AutoTune::new(24, start);
+    for _ in 0..5000 {
+        autotune.add(Decibel(42.0));
+    }
+    let proposal = autotune.proposal(start + 24 * HOUR).unwrap();
+    assert!(proposal.normal_from_db < proposal.loud_from_db);
+    assert!(proposal.loud_from_db < proposal.very_loud_from_db);
+    assert!(proposal.alert_clear_db < proposal.alert_trigger_db);
+}
+
+#[test]
+fn needs_enough_levels() {
+    let start = Instant::now();
+    let mut autotune = AutoTune::new(24, start);
+    autotune.add(Decibel(40.0));
+    autotune.add(Decibel(f32::NAN));
+    let end = start + 24 * HOUR;
+    assert!(autotune.proposal(end).is_none());
+    assert!(autotune
+        .to_json(end)
+        .starts_with("{\"state\":\"insufficient\""));
+}
+
+#[test]
+fn observation_is_a_day_or_two() {
+    let start = Instant::now();
+    assert!(!AutoTune::new(1, start).is_complete(start + 23 * HOUR));
+    assert!(AutoTune::new(100, start).is_complete(start + 48 * HOUR));
+}
+
+#[test]
+fn reports_progress_then_the_proposal() {
+    let start = Instant::now();
+    let mut autotune = AutoTune::new(48, start);
+    observe(&mut autotune);
+    assert_eq!(
+        autotune.to_json(start + 12 * HOUR),
+        "{\"state\":\"observing\",\"observed_h\":12.0,\"duration_h\":48,\"levels\":10000}"
+    );
+    assert_eq!(
+        autotune.to_json(start + 50 * HOUR),
+        "{\"state\":\"proposed\",\"observed_h\":48.0,\"duration_h\":48,\"levels\":10000,\"percentiles\":{\"l90\":35.5,\"l50\":35.5,\"l10\":50.5,\"l1\":70.5},\"thresholds\":{\"normal_from_db\":38.5,\"loud_from_db\":53.5,\"very_loud_from_db\":73.5,\"alert_trigger_db\":73.5,\"alert_clear_db\":70.5}}"
+    );
+}
+
+#[test]
+fn resumes_where_it_was_stored() {
+    let start = Instant::now();
+    let mut autotune = AutoTune::new(24, start);
+    observe(&mut autotune);
+    let stored = autotune.to_stored(start + 20 * HOUR);
+    let reboot = start + 30 * HOUR;
+    let resumed = AutoTune::from_stored(&stored, reboot).unwrap();
+    assert_eq!(resumed.observed(reboot), 20 * HOUR);
+    assert!(resumed.proposal(reboot + 3 * HOUR).is_none());
+    assert_eq!(
+        resumed.proposal(reboot + 4 * HOUR),
+        autotune.proposal(start + 24 * HOUR)
+    );
+    assert!(AutoTune::from_stored(&stored[1..], reboot).is_none());
+}
+
+#[test]
+fn learns_rooms_quieter_than_one_count() {
+    let start = Instant::now();
+    let mut autotune = AutoTune::new(24, start);
+    for _ in 0..5000 {
+        autotune.add(Decibel(-4.8));
+    }
+    let proposal = autotune.proposal(start + 24 * HOUR).unwrap();
+    assert_eq!(proposal.l90, Decibel(-4.5));
+    assert_eq!(proposal.normal_from_db, -1.5);
+}
+
+#[test]
+fn drops_histograms_of_levels_with_the_dc_bias() {
+    let start = Instant::now();
+    let mut autotune = AutoTune::new(24, start);
+    observe(&mut autotune);
+    let mut stored = autotune.to_stored(start);
+    stored[0] = 1;
+    assert!(AutoTune::from_stored(&stored, start).is_none());
+}
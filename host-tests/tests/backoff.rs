@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use mosquitto_bzzz_host_tests::backoff::{Backoff, BackoffPolicy};
+
+const POLICY: BackoffPolicy = BackoffPolicy {
+    initial: Duration::from_secs(2),
+    max: Duration::from_secs(30),
+    multiplier: 2,
+    jitter: 0.0,
+};
+
+#[test]
+fn grows_up_to_the_maximum() {
+    let mut backoff = Backoff::new(POLICY, 42);
+    let delays: Vec<u64> = (0..6).map(|_| backoff.next_delay().as_secs()).collect();
+    assert_eq!(delays, [2, 4, 8, 16, 30, 30]);
+}
+
+#[test]
+fn reset_starts_over() {
+    let mut backoff = Backoff::new(POLICY, 42);
+    backoff.next_delay();
+    backoff.next_delay();
+    backoff.reset();
+    assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+}
+
+#[test]
+fn jitter_stays_within_bounds_and_the_maximum() {
+    let policy = BackoffPolicy {
+        jitter: 0.2,
+        ..POLICY
+    };
+    for seed in 0..100 {
+        let mut backoff = Backoff::new(policy, seed);
+        let first = backoff.next_delay();
+        assert!(first >= Duration::from_millis(1600) && first <= Duration::from_millis(2400));
+        for _ in 0..10 {
+            assert!(backoff.next_delay() <= policy.max);
+        }
+    }
+}
+
+#[test]
+fn different_seeds_spread_the_retries() {
+    let policy = BackoffPolicy {
+        jitter: 0.2,
+        ..POLICY
+    };
+    let first = Backoff::new(policy, 1).next_delay();
+    assert!((2..50).any(|seed| Backoff::new(policy, seed).next_delay() != first));
+}
@@ -0,0 +1,87 @@
+use std::f64::consts::PI;
+
+use mosquitto_bzzz_host_tests::{
+    bands::{OctaveAnalyzer, OctaveBands},
+    dsp::{Decibel, RawAdc},
+};
+
+const SAMPLE_RATE_HZ: f32 = 16000.0;
+
+fn tone(len: usize, frequency_hz: f64, amplitude: f64) -> Vec<RawAdc> {
+    (0..len)
+        .map(|n| {
+            let phase = 2.0 * PI * frequency_hz * n as f64 / f64::from(SAMPLE_RATE_HZ);
+            RawAdc((2048.0 + amplitude * phase.sin()).round() as u16)
+        })
+        .collect()
+}
+
+fn level(bands: &OctaveBands, center_hz: f32) -> Decibel {
+    bands
+        .0
+        .iter()
+        .find(|(center, _)| *center == center_hz)
+        .map(|(_, level)| *level)
+        .unwrap()
+}
+
+#[test]
+fn bands_below_nyquist_with_bins() {
+    let centers = |fft_len| {
+        OctaveAnalyzer::new(fft_len, SAMPLE_RATE_HZ)
+            .unwrap()
+            .analyze(&tone(1600, 1000.0, 100.0))
+            .unwrap()
+            .0
+            .iter()
+            .map(|(center, _)| *center)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        centers(512),
+        [31.5, 63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0]
+    );
+    // 62.5 Hz bins leave nothing for the lowest band
+    assert_eq!(centers(256)[0], 63.0);
+    assert!(OctaveAnalyzer::new(0, SAMPLE_RATE_HZ).is_none());
+}
+
+#[test]
+fn tone_shows_in_its_band_at_its_level() {
+    let analyzer = OctaveAnalyzer::new(512, SAMPLE_RATE_HZ).unwrap();
+    let bands = analyzer.analyze(&tone(1600, 1000.0, 1000.0)).unwrap();
+    // A sine of amplitude 1000 has an RMS of 707 counts, 57 dB
+    let expected = 20.0 * (1000.0f32 / 2f32.sqrt()).log10();
+    assert!((level(&bands, 1000.0).0 - expected).abs() < 0.5);
+    assert!(level(&bands, 1000.0).0 - level(&bands, 125.0).0 > 40.0);
+    assert!(level(&bands, 1000.0).0 - level(&bands, 4000.0).0 > 40.0);
+}
+
+#[test]
+fn rumble_and_alarms_are_told_apart() {
+    let analyzer = OctaveAnalyzer::new(512, SAMPLE_RATE_HZ).unwrap();
+    let rumble = analyzer.analyze(&tone(1600, 63.0, 500.0)).unwrap();
+    let alarm = analyzer.analyze(&tone(1600, 3100.0, 500.0)).unwrap();
+    assert!(level(&rumble, 63.0).0 > level(&rumble, 4000.0).0 + 20.0);
+    assert!(level(&alarm, 4000.0).0 > level(&alarm, 63.0).0 + 20.0);
+}
+
+#[test]
+fn needs_a_whole_block() {
+    let analyzer = OctaveAnalyzer::new(512, SAMPLE_RATE_HZ).unwrap();
+    assert!(analyzer.analyze(&tone(511, 1000.0, 100.0)).is_none());
+}
+
+#[test]
+fn serializes_as_an_object_by_center() {
+    let bands = OctaveBands(vec![
+        (31.5, Decibel(40.04)),
+        (63.0, Decibel(f32::NEG_INFINITY)),
+        (125.0, Decibel(44.0)),
+    ])
+    .with_offset(10.0);
+    assert_eq!(
+        serde_json::to_string(&bands).unwrap(),
+        "{\"31.5\":50.0,\"63\":null,\"125\":54.0}"
+    );
+}
@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+
+use mosquitto_bzzz_host_tests::{
+    calibration::{self, Calibration, Session},
+    dsp::Decibel,
+};
+
+#[test]
+fn offset_turns_levels_into_spl() {
+    let calibration = Calibration::against(Decibel(94.0), Decibel(41.5)).unwrap();
+    assert_eq!(calibration.offset_db, 52.5);
+    assert_eq!(calibration.reference_db, Some(94.0));
+    assert_eq!(calibration.apply(Decibel(30.0)), Decibel(82.5));
+}
+
+#[test]
+fn uncalibrated_levels_stay_as_they_are() {
+    let calibration = Calibration::default();
+    assert!(!calibration.is_set());
+    assert_eq!(calibration.apply(Decibel(41.5)), Decibel(41.5));
+}
+
+#[test]
+fn rejects_implausible_offsets() {
+    assert!(Calibration::with_offset(f32::NAN).is_none());
+    assert!(Calibration::with_offset(-200.0).is_none());
+    assert!(Calibration::against(Decibel(94.0), Decibel(f32::NEG_INFINITY)).is_none());
+}
+
+#[test]
+fn survives_storage() {
+    for calibration in [
+        Calibration::with_offset(-3.25).unwrap(),
+        Calibration::against(Decibel(94.0), Decibel(41.5)).unwrap(),
+    ] {
+        assert_eq!(
+            Calibration::from_stored(&calibration.to_stored()),
+            Some(calibration)
+        );
+    }
+    assert!(Calibration::from_stored("loud").is_none());
+    assert!(Calibration::from_stored("12,").is_none());
+}
+
+#[test]
+fn json_says_where_the_offset_came_from() {
+    assert_eq!(
+        Calibration::against(Decibel(94.0), Decibel(41.5))
+            .unwrap()
+            .to_json(),
+        "{\"offset_db\":52.50,\"reference_db\":94.0}"
+    );
+    assert_eq!(
+        Calibration::default().to_json(),
+        "{\"offset_db\":0.00,\"reference_db\":null}"
+    );
+}
+
+#[test]
+fn session_averages_over_its_duration() {
+    let start = Instant::now();
+    let mut session = Session::new(Decibel(94.0), start);
+    for _ in 0..10 {
+        session.add(Decibel(44.0));
+    }
+    assert!(!session.is_over(start + Duration::from_secs(1)));
+    assert!(session.is_over(start + calibration::SESSION));
+    let calibration = session.finish().unwrap();
+    assert!((calibration.offset_db - 50.0).abs() < 0.1);
+}
+
+#[test]
+fn session_without_levels_calibrates_nothing() {
+    assert!(Session::new(Decibel(94.0), Instant::now())
+        .finish()
+        .is_none());
+}
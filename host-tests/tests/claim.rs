@@ -0,0 +1,59 @@
+use mosquitto_bzzz_host_tests::claim::{self, Activation, ClaimTopics};
+
+#[test]
+fn claiming_is_off_without_a_topic() {
+    assert!(ClaimTopics::new("", "bzzz-0042").is_none());
+    let topics = ClaimTopics::new("fleet/claim/", "bzzz-0042").unwrap();
+    assert_eq!(topics.request, "fleet/claim/request");
+    assert_eq!(topics.response, "fleet/claim/bzzz-0042");
+}
+
+#[test]
+fn uuids_are_version_4() {
+    let uuid = claim::uuid_from_random([0xff; 16]);
+    assert_eq!(uuid, "ffffffff-ffff-4fff-bfff-ffffffffffff");
+    assert_eq!(
+        claim::uuid_from_random([0; 16]),
+        "00000000-0000-4000-8000-000000000000"
+    );
+}
+
+#[test]
+fn activation_settings_become_config_pairs() {
+    let activation = Activation::parse(
+        br#"{"status":"activated","settings":{"alert_trigger_db":72.5,"heartbeat_interval_s":"600","dashboard":false}}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        activation,
+        Activation::Activated(String::from(
+            "alert_trigger_db=72.5,dashboard=false,heartbeat_interval_s=600"
+        ))
+    );
+    assert_eq!(
+        Activation::parse(br#"{"status":"activated"}"#).unwrap(),
+        Activation::Activated(String::new())
+    );
+}
+
+#[test]
+fn rejections_carry_the_reason() {
+    assert_eq!(
+        Activation::parse(br#"{"status":"rejected","reason":"unknown device"}"#).unwrap(),
+        Activation::Rejected(String::from("unknown device"))
+    );
+}
+
+#[test]
+fn rejects_malformed_responses() {
+    for invalid in [
+        &br#"{"status":"pending"}"#[..],
+        br#"{"status":"activated","settings":{"rules":[]}}"#,
+        br#"{"status":"activated","settings":{"site":"two words"}}"#,
+        br#"{"status":"activated","settings":{"site":"a,b"}}"#,
+        br#"{"status":"activated","extra":1}"#,
+        b"activated",
+    ] {
+        assert!(Activation::parse(invalid).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::{Duration, Instant};
+
+use mosquitto_bzzz_host_tests::clock_broker::{self, BrokerClock};
+
+const MINUTE: Duration = Duration::from_secs(60);
+const UNIX_MS: u64 = 1_700_000_000_000;
+
+#[test]
+fn parses_unix_seconds() {
+    assert_eq!(clock_broker::parse(b"1700000000"), Some(UNIX_MS));
+    assert_eq!(clock_broker::parse(b"1700000000.5\n"), Some(UNIX_MS + 500));
+    assert_eq!(
+        clock_broker::parse(b"1700000000.123456789"),
+        Some(UNIX_MS + 123)
+    );
+    for payload in [&b""[..], b"now", b".5", b"1700000000.x", b"-1", b"\xff"] {
+        assert_eq!(clock_broker::parse(payload), None, "{:?}", payload);
+    }
+}
+
+#[test]
+fn keeps_time_from_the_message_on() {
+    let mut clock = BrokerClock::new(MINUTE);
+    let now = Instant::now();
+    assert_eq!(clock.unix_time(now), None);
+    clock.subscribed();
+    clock.received(UNIX_MS, now);
+    assert_eq!(
+        clock.unix_time(now + Duration::from_secs(90)),
+        Some(UNIX_MS / 1000 + 90)
+    );
+}
+
+#[test]
+fn retained_time_is_as_old_as_the_interval() {
+    let mut clock = BrokerClock::new(MINUTE);
+    let now = Instant::now();
+    clock.subscribed();
+    clock.received(UNIX_MS, now);
+    assert_eq!(clock.accuracy(now), Some(Duration::from_secs(62)));
+    // The next one was published live
+    clock.received(UNIX_MS + 10_000, now + Duration::from_secs(10));
+    assert_eq!(
+        clock.accuracy(now + Duration::from_secs(10)),
+        Some(Duration::from_secs(2))
+    );
+}
+
+#[test]
+fn a_fresh_estimate_beats_the_retained_one() {
+    let mut clock = BrokerClock::new(MINUTE);
+    let now = Instant::now();
+    clock.subscribed();
+    clock.received(UNIX_MS, now);
+    clock.received(UNIX_MS + 1000, now + Duration::from_secs(1));
+    // After a reconnection the retained time is worse than what the clock keeps
+    clock.subscribed();
+    clock.received(UNIX_MS - 30_000, now + Duration::from_secs(2));
+    assert_eq!(
+        clock.unix_time(now + Duration::from_secs(2)),
+        Some(UNIX_MS / 1000 + 2)
+    );
+}
+
+#[test]
+fn estimates_age_with_the_crystal() {
+    let mut clock = BrokerClock::new(MINUTE);
+    let now = Instant::now();
+    clock.subscribed();
+    clock.received(UNIX_MS, now);
+    clock.received(UNIX_MS, now);
+    // 100 ppm of a day
+    let accuracy = clock.accuracy(now + Duration::from_secs(86_400)).unwrap();
+    assert!(
+        (accuracy.as_secs_f32() - 10.64).abs() < 0.01,
+        "{:?}",
+        accuracy
+    );
+}
@@ -0,0 +1,271 @@
+use std::sync::{Arc, Mutex};
+
+use mosquitto_bzzz_host_tests::{
+    config::{Config, ConfigStore, OverrideStorage},
+    weighting::Weighting,
+};
+
+fn store() -> ConfigStore {
+    ConfigStore::new(Config::defaults(), None)
+}
+
+// Keeps every blob that would have gone to NVS
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl OverrideStorage for Recorder {
+    fn store(&mut self, blob: &str) {
+        self.0.lock().unwrap().push(blob.to_string());
+    }
+}
+
+impl Recorder {
+    fn blobs(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[test]
+fn partial_update_keeps_other_settings() {
+    let store = store();
+    let updated = store.update(b"very_loud_from_db=75").unwrap();
+    assert_eq!(
+        updated,
+        Config {
+            very_loud_from_db: 75.0,
+            ..Config::defaults()
+        }
+    );
+    assert_eq!(store.get(), updated);
+}
+
+#[test]
+fn accepts_several_separators() {
+    let store = store();
+    let updated = store
+        .update(b"normal_from_db=35, loud_from_db=60\nvery_loud_from_db=70\n")
+        .unwrap();
+    assert_eq!(
+        (
+            updated.normal_from_db,
+            updated.loud_from_db,
+            updated.very_loud_from_db
+        ),
+        (35.0, 60.0, 70.0)
+    );
+}
+
+#[test]
+fn switches_operating_mode() {
+    let store = store();
+    let updated = store
+        .update(b"operating_mode=logger logger_interval_s=300")
+        .unwrap();
+    assert_eq!(
+        (updated.operating_mode, updated.logger_interval_s),
+        ("logger", 300)
+    );
+}
+
+#[test]
+fn selects_weighting() {
+    let store = store();
+    assert_eq!(
+        store.update(b"weighting=A").unwrap().weighting,
+        Weighting::A
+    );
+}
+
+#[test]
+fn invalid_update_changes_nothing() {
+    let store = store();
+    let mut watch = store.subscribe();
+    for payload in [
+        &b"loud_from_db=90"[..],
+        b"quiet_from_db=10",
+        b"mqtt_host=elsewhere",
+        b"loud_from_db",
+        b"loud_from_db=NaN",
+        b"loud_from_db=loud",
+        b"outlier_window=0",
+        b"day_led_brightness=256",
+        b"latitude=91",
+        b"maintenance_day=8",
+        b"maintenance_hour=24",
+        b"vibration_loud_from_db=60",
+        b"report_delta_db=-1",
+        b"sample_interval_ms=0",
+        b"sample_interval_ms=101",
+        b"sample_window_ms=49",
+        b"sample_window_ms=501",
+        b"batch_size=101",
+        b"weighting=B",
+        b"operating_mode=recorder",
+        b"logger_interval_s=0",
+        b"logger_interval_s=3601",
+        b"offline_queue_len=501",
+        b"flash_endurance_cycles=0",
+        b"flash_wear_throttle_pct=0",
+        b"flash_wear_throttle_pct=101",
+        b"band_fft_len=128",
+        b"support_session_s=59",
+        b"support_session_s=14401",
+        b"alert_burst_s=61",
+        b"alert_burst_hz=0",
+        b"alert_context_s=6",
+        b"alert_clear_db=81",
+        b"\xff",
+    ] {
+        assert!(store.update(payload).is_err());
+    }
+    assert_eq!(store.get(), Config::defaults());
+    assert_eq!(watch.changed(), None);
+}
+
+#[test]
+fn dump_redacts_secrets_and_marks_runtime_changes() {
+    let store = ConfigStore::new(
+        Config {
+            wifi_password: "hunter22",
+            web_token: "",
+            ..Config::defaults()
+        },
+        None,
+    );
+    store.update(b"loud_from_db=40").unwrap();
+    let dump = store.dump();
+    let lines: Vec<&str> = dump.lines().collect();
+    assert!(lines.contains(&"wifi_password = <redacted>"));
+    assert!(lines.contains(&"web_token = \"\""));
+    assert!(lines.contains(&"loud_from_db = 40.0 (runtime)"));
+    assert!(lines.contains(&"very_loud_from_db = 50.0"));
+    assert!(!dump.contains("hunter22"));
+}
+
+#[test]
+fn dump_lists_every_setting_once_and_redacts_every_secret() {
+    let secret = "hunter22";
+    let store = ConfigStore::new(
+        Config {
+            wifi_password: secret,
+            mqtt_password: secret,
+            jwt_key: secret,
+            est_password: secret,
+            web_token: secret,
+            diagnostics_ap_password: secret,
+            ..Config::defaults()
+        },
+        None,
+    );
+    let dump = store.dump();
+    let keys: Vec<&str> = dump
+        .lines()
+        .filter_map(|line| line.split_once(" = "))
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(keys.len(), dump.lines().count());
+    let mut unique = keys.clone();
+    unique.sort_unstable();
+    unique.dedup();
+    assert_eq!(unique.len(), keys.len());
+    assert_eq!(keys.first(), Some(&"wifi_ssid"));
+    assert_eq!(keys.last(), Some(&"rules"));
+    assert!(dump.lines().any(|line| line == "weighting = \"Z\""));
+    assert!(!dump.contains(secret));
+}
+
+#[test]
+fn update_is_all_or_nothing() {
+    let store = store();
+    assert!(store.update(b"thermal_limit_c=70 loud_from_db=90").is_err());
+    assert_eq!(
+        store.get().thermal_limit_c,
+        Config::defaults().thermal_limit_c
+    );
+}
+
+#[test]
+fn later_pairs_win() {
+    let recorder = Recorder::default();
+    let store = ConfigStore::new(Config::defaults(), Some(Box::new(recorder.clone())));
+    store
+        .update(b"thermal_limit_c=70 thermal_limit_c=72")
+        .unwrap();
+    assert_eq!(store.get().thermal_limit_c, 72.0);
+    assert_eq!(recorder.blobs(), vec![String::from("thermal_limit_c=72\n")]);
+}
+
+#[test]
+fn watch_sees_each_change_once() {
+    let store = store();
+    let mut watch = store.subscribe();
+    assert_eq!(watch.changed(), None);
+    store.update(b"very_loud_from_db=85").unwrap();
+    assert_eq!(watch.changed().map(|c| c.very_loud_from_db), Some(85.0));
+    assert_eq!(watch.changed(), None);
+}
+
+#[test]
+fn watch_sees_latest_of_several_changes() {
+    let store = store();
+    let mut watch = store.subscribe();
+    store.update(b"very_loud_from_db=85").unwrap();
+    store.update(b"very_loud_from_db=90").unwrap();
+    assert_eq!(watch.changed().map(|c| c.very_loud_from_db), Some(90.0));
+    assert_eq!(watch.changed(), None);
+}
+
+#[test]
+fn every_subscriber_is_notified() {
+    let store = store();
+    let mut first = store.subscribe();
+    let mut second = store.subscribe();
+    store.update(b"night_led_brightness=10").unwrap();
+    assert!(first.changed().is_some());
+    assert!(second.changed().is_some());
+}
+
+#[test]
+fn update_from_another_thread_is_visible() {
+    let store = store();
+    let mut watch = store.subscribe();
+    let writer = store.clone();
+    std::thread::spawn(move || writer.update(b"loud_from_db=40").unwrap())
+        .join()
+        .unwrap();
+    assert_eq!(watch.changed().map(|c| c.loud_from_db), Some(40.0));
+}
+
+#[test]
+fn watch_ignores_changes_made_before_it() {
+    let store = store();
+    store.update(b"loud_from_db=40").unwrap();
+    assert_eq!(store.subscribe().changed(), None);
+}
+
+#[test]
+fn restoring_does_not_store_again() {
+    let recorder = Recorder::default();
+    let store = ConfigStore::new(Config::defaults(), Some(Box::new(recorder.clone())));
+    assert_eq!(
+        store.restore("loud_from_db=40\n").unwrap().loud_from_db,
+        40.0
+    );
+    assert!(recorder.blobs().is_empty());
+}
+
+#[test]
+fn unchanged_overrides_are_not_stored_again() {
+    let recorder = Recorder::default();
+    let store = ConfigStore::new(Config::defaults(), Some(Box::new(recorder.clone())));
+    store
+        .restore("loud_from_db=40\nthermal_limit_c=72\n")
+        .unwrap();
+    store.update(b"thermal_limit_c=72 loud_from_db=40").unwrap();
+    assert!(recorder.blobs().is_empty());
+    store.update(b"loud_from_db=38").unwrap();
+    assert_eq!(
+        recorder.blobs(),
+        vec![String::from("loud_from_db=38\nthermal_limit_c=72\n")]
+    );
+}
@@ -0,0 +1,48 @@
+use miniz_oxide::inflate::decompress_to_vec_zlib;
+use mosquitto_bzzz_host_tests::diag_bundle::{
+    assemble, chunk_count, chunk_topic, chunks, manifest_json, CHUNK_LEN,
+};
+
+#[test]
+fn sections_unpack_to_plain_text() {
+    let bundle = assemble(&[
+        ("log", String::from("I (12) bzzz: Hello, world!\n")),
+        ("config", String::from("mqtt_password = <set>")),
+    ]);
+    let text = String::from_utf8(decompress_to_vec_zlib(&bundle).unwrap()).unwrap();
+    assert_eq!(
+        text,
+        "=== log ===\nI (12) bzzz: Hello, world!\n=== config ===\nmqtt_password = <set>\n"
+    );
+}
+
+#[test]
+fn chunks_cover_the_bundle_in_order() {
+    let bundle: Vec<u8> = (0..2 * CHUNK_LEN + 10).map(|n| n as u8).collect();
+    assert_eq!(chunk_count(&bundle), 3);
+    let joined: Vec<u8> = chunks(&bundle)
+        .enumerate()
+        .flat_map(|(expected, (index, chunk))| {
+            assert_eq!(index, expected);
+            chunk.to_vec()
+        })
+        .collect();
+    assert_eq!(joined, bundle);
+    assert_eq!(chunk_count(&[]), 0);
+}
+
+#[test]
+fn manifest_describes_the_chunks() {
+    assert_eq!(
+        manifest_json(7, &[0u8; 1500]),
+        r#"{"id":7,"encoding":"zlib","len":1500,"chunks":2,"chunk_len":1024}"#
+    );
+}
+
+#[test]
+fn chunks_go_below_the_bundle_id() {
+    assert_eq!(
+        chunk_topic("bzzz/a0b1/diagnostics/bundle", 3141592653, 2),
+        "bzzz/a0b1/diagnostics/bundle/3141592653/2"
+    );
+}
@@ -0,0 +1,123 @@
+use mosquitto_bzzz_host_tests::{
+    direction::{self, Direction, DirectionHint, StereoBurst},
+    dsp::{self, RawAdc},
+};
+
+// Deterministic broadband noise around mid-scale
+fn noise(len: usize) -> Vec<f32> {
+    let mut state = 0x2545_f491u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 22) as f32 - 512.0
+        })
+        .collect()
+}
+
+// The same sound on both mics, the right one `delay` samples later (earlier when negative) and
+// scaled by `right_gain`
+fn stereo(delay: isize, right_gain: f32) -> StereoBurst {
+    let len = direction::BURST_LEN;
+    let source = noise(len + 32);
+    let at = |index: isize| RawAdc((2048.0 + source[(index + 16) as usize]).round() as u16);
+    let left = (0..len as isize).map(at).collect();
+    let right = (0..len as isize)
+        .map(|index| RawAdc((2048.0 + right_gain * (at(index - delay).0 as f32 - 2048.0)) as u16))
+        .collect();
+    StereoBurst {
+        left,
+        right,
+        sample_rate_hz: 12_000.0,
+    }
+}
+
+fn raw(values: &[f32]) -> Vec<RawAdc> {
+    values
+        .iter()
+        .map(|value| RawAdc((2048.0 + value).round() as u16))
+        .collect()
+}
+
+#[test]
+fn cross_correlation_finds_the_delay() {
+    let source = noise(200);
+    let a = raw(&source[8..]);
+    let b = raw(&source[5..197]);
+    let (lag, correlation) = dsp::cross_correlation(&a, &b, 8).unwrap();
+    assert_eq!(lag, 3);
+    assert!(correlation > 0.9, "{}", correlation);
+    let (lag, _) = dsp::cross_correlation(&b, &a, 8).unwrap();
+    assert_eq!(lag, -3);
+}
+
+#[test]
+fn cross_correlation_needs_signal() {
+    let silence = vec![RawAdc(2048); 64];
+    assert_eq!(dsp::cross_correlation(&silence, &silence, 8), None);
+    assert_eq!(
+        dsp::cross_correlation(&silence[..8], &silence[..8], 8),
+        None
+    );
+}
+
+#[test]
+fn the_earlier_and_louder_mic_is_the_side() {
+    let hint = DirectionHint::estimate(&stereo(3, 0.5));
+    assert_eq!(hint.direction, Direction::Left);
+    assert_eq!(hint.lag_samples, 3);
+    assert!((hint.level_difference_db - 6.0).abs() < 0.5);
+    assert_eq!(
+        DirectionHint::estimate(&stereo(-3, 2.0)).direction,
+        Direction::Right
+    );
+}
+
+#[test]
+fn timing_alone_or_level_alone_is_enough() {
+    assert_eq!(
+        DirectionHint::estimate(&stereo(-2, 1.0)).direction,
+        Direction::Right
+    );
+    assert_eq!(
+        DirectionHint::estimate(&stereo(0, 0.5)).direction,
+        Direction::Left
+    );
+}
+
+#[test]
+fn conflicting_or_missing_cues_are_ambiguous() {
+    assert_eq!(
+        DirectionHint::estimate(&stereo(0, 1.0)).direction,
+        Direction::Ambiguous
+    );
+    // Earlier on the left but louder on the right, e.g. a reflection
+    assert_eq!(
+        DirectionHint::estimate(&stereo(3, 2.0)).direction,
+        Direction::Ambiguous
+    );
+    let silent = StereoBurst {
+        left: vec![RawAdc(2048); direction::BURST_LEN],
+        right: vec![RawAdc(2048); direction::BURST_LEN],
+        sample_rate_hz: 12_000.0,
+    };
+    let hint = DirectionHint::estimate(&silent);
+    assert_eq!(hint.direction, Direction::Ambiguous);
+    assert!(!hint.to_json().contains("NaN"), "{}", hint.to_json());
+}
+
+#[test]
+fn hint_json() {
+    let hint = DirectionHint {
+        direction: Direction::Left,
+        level_difference_db: 4.04,
+        lag_samples: 3,
+        correlation: 0.876,
+        sample_rate_hz: 12_000.0,
+    };
+    assert_eq!(
+        hint.to_json(),
+        "{\"direction\":\"left\",\"level_diff_db\":4.0,\"lag_samples\":3,\"lag_us\":250,\"correlation\":0.88}"
+    );
+}
@@ -0,0 +1,34 @@
+use mosquitto_bzzz_host_tests::{
+    classification::NoiseClass, display::DisplaySettings, dsp::Decibel,
+};
+
+#[test]
+fn defaults_keep_the_usual_formatting() {
+    let display = DisplaySettings::new("dB", false, "24h", "").unwrap();
+    assert_eq!(display, DisplaySettings::default());
+    assert_eq!(display.level(Some(Decibel(52.34))), "52.3 dB");
+    assert_eq!(display.level(None), "-- dB");
+    assert_eq!(display.class(Some(NoiseClass::VeryLoud)), "very loud");
+    assert_eq!(display.class(None), "--");
+    assert_eq!(display.clock(Some(14 * 60 + 5)), "14:05");
+    assert_eq!(display.clock(None), "--:--");
+}
+
+#[test]
+fn regional_settings_change_the_text() {
+    let display =
+        DisplaySettings::new("dB(A)", true, "12h", "Calme, Normal, Fort, Très fort").unwrap();
+    assert_eq!(display.level(Some(Decibel(52.34))), "52,3 dB(A)");
+    assert_eq!(display.class(Some(NoiseClass::Loud)), "Fort");
+    assert_eq!(display.class(Some(NoiseClass::VeryLoud)), "Très fort");
+    assert_eq!(display.clock(Some(0)), "12:00 AM");
+    assert_eq!(display.clock(Some(12 * 60 + 30)), "12:30 PM");
+    assert_eq!(display.clock(Some(23 * 60 + 59)), "11:59 PM");
+}
+
+#[test]
+fn invalid_settings_are_refused() {
+    assert!(DisplaySettings::new("dB", false, "am/pm", "").is_err());
+    assert!(DisplaySettings::new("dB", false, "24h", "a,b,c").is_err());
+    assert!(DisplaySettings::new("dB", false, "24h", "a,,c,d").is_err());
+}
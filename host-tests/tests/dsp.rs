@@ -0,0 +1,210 @@
+use mosquitto_bzzz_host_tests::dsp::{self, Decibel, LevelFilter, Plausibility, RawAdc};
+use proptest::prelude::*;
+
+// Levels the sensor can realistically report
+fn level() -> impl Strategy<Value = f32> {
+    0.0f32..130.0
+}
+
+fn levels() -> impl Strategy<Value = Vec<Decibel>> {
+    prop::collection::vec(level().prop_map(Decibel), 1..200)
+}
+
+// What the 12-bit ADC can return, without silence
+fn samples() -> impl Strategy<Value = Vec<RawAdc>> {
+    prop::collection::vec((1u16..=4095).prop_map(RawAdc), 1..50)
+}
+
+// A tone around mid-scale, as a burst from the ADC would hold it
+fn tone(frequency_hz: f32, sample_rate_hz: f32, len: usize) -> Vec<RawAdc> {
+    (0..len)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * frequency_hz * i as f32 / sample_rate_hz;
+            RawAdc((2048.0 + 1000.0 * phase.sin()).round() as u16)
+        })
+        .collect()
+}
+
+// Alternating between bias - amplitude and bias + amplitude, an RMS of exactly `amplitude`
+fn square_wave(bias: u16, amplitude: u16, periods: usize) -> Vec<RawAdc> {
+    [RawAdc(bias - amplitude), RawAdc(bias + amplitude)].repeat(periods)
+}
+
+const BURST_RATE_HZ: f32 = 20_000.0;
+const OCTAVE_BINS_HZ: [f32; 7] = [125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0];
+
+fn close(a: Decibel, b: Decibel) -> bool {
+    (a.0 - b.0).abs() < 1e-3
+}
+
+proptest! {
+    #[test]
+    fn db_of_square_wave_is_its_amplitude(amplitude in 1u16..=2047, periods in 1usize..25) {
+        let db = dsp::rms_to_db(&square_wave(2048, amplitude, periods));
+        prop_assert!(close(db, Decibel(20.0 * (amplitude as f32).log10())));
+    }
+
+    #[test]
+    fn dc_bias_does_not_count(amplitude in 1u16..=1000, bias in 1000u16..=3000, periods in 1usize..25) {
+        let db = dsp::rms_to_db(&square_wave(bias, amplitude, periods));
+        prop_assert!(close(db, dsp::rms_to_db(&square_wave(2048, amplitude, periods))));
+    }
+
+    #[test]
+    fn db_grows_with_amplitude(deviations in prop::collection::vec(-200i16..=200, 2..50), gain in 1i16..10) {
+        let around_bias = |gain: i16| -> Vec<RawAdc> {
+            deviations
+                .iter()
+                .map(|deviation| RawAdc((2048 + deviation * gain) as u16))
+                .collect()
+        };
+        prop_assert!(dsp::rms_to_db(&around_bias(gain)) >= dsp::rms_to_db(&around_bias(1)));
+    }
+
+    #[test]
+    fn db_stays_within_adc_range(samples in samples()) {
+        // Swinging from 0 to full scale is the most sound the ADC can take
+        let db = dsp::rms_to_db(&samples);
+        prop_assert!(db <= Decibel(20.0 * 2047.5f32.log10() + 1e-3));
+    }
+
+    #[test]
+    fn millivolts_stay_within_full_scale(samples in samples()) {
+        let rms = dsp::rms_millivolts(&samples);
+        prop_assert!(rms.0 >= 0.0 && rms.0 <= 1650.0 + 1e-2);
+    }
+
+    #[test]
+    fn leq_of_constant_level_is_the_level(level in level(), len in 1usize..200) {
+        let leq = dsp::leq(&vec![Decibel(level); len]).unwrap();
+        prop_assert!(close(leq, Decibel(level)));
+    }
+
+    #[test]
+    fn leq_lies_between_arithmetic_mean_and_max(levels in levels()) {
+        let leq = dsp::leq(&levels).unwrap();
+        let mean = levels.iter().map(|level| level.0).sum::<f32>() / levels.len() as f32;
+        let max = levels.iter().map(|level| level.0).fold(f32::MIN, f32::max);
+        // Energy averaging weighs loud readings more, so it never falls below the plain mean
+        prop_assert!(leq.0 >= mean - 1e-3);
+        prop_assert!(leq.0 <= max + 1e-3);
+    }
+
+    #[test]
+    fn weighted_leq_is_the_leq_of_the_repeated_levels(levels in levels(), readings in 1u32..20) {
+        let repeated: Vec<Decibel> = levels
+            .iter()
+            .flat_map(|level| std::iter::repeat(*level).take(readings as usize))
+            .collect();
+        let weighted = dsp::leq_weighted(levels.iter().map(|level| (*level, readings))).unwrap();
+        prop_assert!(close(weighted, dsp::leq(&repeated).unwrap()));
+    }
+
+    #[test]
+    fn leq_never_drops_when_a_level_rises(levels in levels(), index in any::<prop::sample::Index>(), step in 0.1f32..20.0) {
+        let mut louder = levels.clone();
+        louder[index.index(levels.len())].0 += step;
+        prop_assert!(dsp::leq(&louder).unwrap() >= dsp::leq(&levels).unwrap());
+    }
+
+    #[test]
+    fn percentiles_are_ordered(levels in levels(), a in 0.0f32..=100.0, b in 0.0f32..=100.0) {
+        let (low, high) = if a <= b { (a, b) } else { (b, a) };
+        prop_assert!(
+            dsp::percentile(&levels, low).unwrap() <= dsp::percentile(&levels, high).unwrap()
+        );
+    }
+
+    #[test]
+    fn percentiles_are_readings_within_bounds(levels in levels(), percent in 0.0f32..=100.0) {
+        let value = dsp::percentile(&levels, percent).unwrap();
+        prop_assert!(levels.contains(&value));
+        prop_assert_eq!(dsp::percentile(&levels, 0.0), levels.iter().copied().reduce(|a, b| if b < a { b } else { a }));
+        prop_assert_eq!(dsp::percentile(&levels, 100.0), levels.iter().copied().reduce(|a, b| if b > a { b } else { a }));
+    }
+
+    #[test]
+    fn zero_crossing_rate_of_tone_is_twice_its_frequency(frequency in 100.0f32..4000.0) {
+        let rate = dsp::zero_crossing_rate(&tone(frequency, BURST_RATE_HZ, 1024), BURST_RATE_HZ);
+        // Each period crosses twice, give or take one crossing at the ends of the burst
+        let tolerance = 2.0 * BURST_RATE_HZ / 1023.0 + 0.01 * frequency;
+        prop_assert!((rate - 2.0 * frequency).abs() <= tolerance, "{} Hz gave {}", frequency, rate);
+    }
+
+    #[test]
+    fn goertzel_power_peaks_at_the_tone(bin in 0usize..7) {
+        let frequency = OCTAVE_BINS_HZ[bin];
+        let samples = tone(frequency, BURST_RATE_HZ, 512);
+        let power = dsp::goertzel_power(&samples, BURST_RATE_HZ, frequency);
+        for other in OCTAVE_BINS_HZ.iter().filter(|other| **other != frequency) {
+            prop_assert!(power > dsp::goertzel_power(&samples, BURST_RATE_HZ, *other));
+        }
+    }
+
+    #[test]
+    fn centroid_stays_within_the_bins(samples in prop::collection::vec((0u16..=4095).prop_map(RawAdc), 16..256)) {
+        if let Some(centroid) = dsp::spectral_centroid(&samples, BURST_RATE_HZ, &OCTAVE_BINS_HZ) {
+            prop_assert!((125.0 - 1e-2..=8000.0 + 1e-2).contains(&centroid));
+        }
+    }
+
+    #[test]
+    fn filter_output_stays_within_floor_and_ceiling(readings in prop::collection::vec(-50.0f32..200.0, 1..100)) {
+        let mut filter = LevelFilter::new(Decibel(0.0), Decibel(130.0), 5, 30.0);
+        for reading in readings {
+            match filter.check(Decibel(reading)) {
+                Plausibility::Accepted(level) | Plausibility::Clamped(level) => {
+                    prop_assert!(level >= Decibel(0.0) && level <= Decibel(130.0));
+                }
+                Plausibility::Rejected => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn silence_has_no_level() {
+    assert_eq!(
+        dsp::rms_to_db(&[RawAdc(2048); 16]),
+        Decibel(f32::NEG_INFINITY)
+    );
+    assert_eq!(dsp::rms_counts(&[RawAdc(0), RawAdc(4095)]), 2047.5);
+}
+
+#[test]
+fn empty_interval_has_no_statistics() {
+    assert_eq!(dsp::leq(&[]), None);
+    assert_eq!(dsp::percentile(&[], 50.0), None);
+}
+
+#[test]
+fn centroid_follows_the_tone() {
+    let centroid = |frequency| {
+        dsp::spectral_centroid(
+            &tone(frequency, BURST_RATE_HZ, 512),
+            BURST_RATE_HZ,
+            &OCTAVE_BINS_HZ,
+        )
+        .unwrap()
+    };
+    assert!(centroid(250.0) < centroid(1000.0));
+    assert!(centroid(1000.0) < centroid(4000.0));
+    // Silence has no spectrum, and bins from Nyquist up are ignored
+    assert_eq!(
+        dsp::spectral_centroid(&[RawAdc(2048); 64], BURST_RATE_HZ, &OCTAVE_BINS_HZ),
+        None
+    );
+    assert_eq!(
+        dsp::spectral_centroid(&tone(1000.0, 8000.0, 256), 8000.0, &[4000.0, 8000.0]),
+        None
+    );
+}
+
+#[test]
+fn nan_is_rejected() {
+    let mut filter = LevelFilter::new(Decibel(0.0), Decibel(130.0), 5, 30.0);
+    assert!(matches!(
+        filter.check(Decibel(f32::NAN)),
+        Plausibility::Rejected
+    ));
+}
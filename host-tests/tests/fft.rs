@@ -0,0 +1,50 @@
+use std::f64::consts::PI;
+
+use mosquitto_bzzz_host_tests::{dsp::RawAdc, fft::Fft};
+
+// Around the ADC's mid-scale bias
+fn tone(len: usize, cycles: f64, amplitude: f64) -> Vec<RawAdc> {
+    (0..len)
+        .map(|n| {
+            let phase = 2.0 * PI * cycles * n as f64 / len as f64;
+            RawAdc((2048.0 + amplitude * phase.sin()).round() as u16)
+        })
+        .collect()
+}
+
+#[test]
+fn only_powers_of_two() {
+    for len in [0, 8, 100, 2048] {
+        assert!(Fft::new(len).is_none());
+    }
+    assert_eq!(Fft::new(512).unwrap().block_len(), 512);
+}
+
+#[test]
+fn tone_lands_in_its_bin() {
+    let fft = Fft::new(256).unwrap();
+    let spectrum = fft.power_spectrum(&tone(256, 16.0, 1000.0));
+    assert_eq!(spectrum.len(), 129);
+    let peak = (0..spectrum.len()).max_by_key(|k| spectrum[*k]).unwrap();
+    assert_eq!(peak, 16);
+    // The Hann window spreads it over the neighbours and nowhere else
+    let near: u64 = spectrum[15..=17].iter().sum();
+    let total: u64 = spectrum.iter().sum();
+    assert!(near as f64 > 0.999 * total as f64);
+}
+
+#[test]
+fn bins_add_up_to_the_windowed_mean_square() {
+    let fft = Fft::new(512).unwrap();
+    let total: u64 = fft.power_spectrum(&tone(512, 40.0, 1000.0)).iter().sum();
+    // A sine's mean square, times the 3/8 the Hann window keeps, in Q16 counts
+    let expected = 1000.0f64.powi(2) / 2.0 * 0.375 * 2f64.powi(32);
+    assert!((total as f64 / expected - 1.0).abs() < 0.01);
+}
+
+#[test]
+fn bias_is_left_out() {
+    let fft = Fft::new(256).unwrap();
+    let spectrum = fft.power_spectrum(&[RawAdc(2048); 256]);
+    assert!(spectrum.iter().all(|bin| *bin == 0));
+}
@@ -0,0 +1,71 @@
+use mosquitto_bzzz_host_tests::{
+    dsp::{self, Decibel, RawAdc},
+    fixed_point,
+};
+use proptest::prelude::*;
+
+// Well below what the sensor can resolve
+const TOLERANCE_DB: f32 = 0.01;
+
+fn levels() -> impl Strategy<Value = Vec<Decibel>> {
+    prop::collection::vec((-20.0f32..130.0).prop_map(Decibel), 1..2000)
+}
+
+fn samples() -> impl Strategy<Value = Vec<RawAdc>> {
+    prop::collection::vec((0u16..=4095).prop_map(RawAdc), 1..50)
+}
+
+proptest! {
+    #[test]
+    fn log2_matches_float(value in 1u64..) {
+        let log2 = fixed_point::log2_q16(value) as f64 / 65536.0;
+        prop_assert!((log2 - (value as f64).log2()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn exp2_matches_float(exponent in -(40i64 << 16)..=0) {
+        let exp2 = fixed_point::exp2_q31(exponent) as f64 / (1u64 << 31) as f64;
+        prop_assert!((exp2 - (exponent as f64 / 65536.0).exp2()).abs() < 1e-8);
+    }
+
+    #[test]
+    fn rms_matches_float(samples in samples().prop_filter("silence", |samples| {
+        samples.iter().any(|sample| *sample != samples[0])
+    })) {
+        let fixed = fixed_point::rms_to_db(&samples);
+        let float = dsp::rms_to_db_f32(&samples);
+        prop_assert!((fixed.0 - float.0).abs() < TOLERANCE_DB, "{} vs {}", fixed, float);
+    }
+
+    #[test]
+    fn leq_matches_float(levels in levels()) {
+        let fixed = fixed_point::leq(&levels).unwrap();
+        let float = dsp::leq_f32(&levels).unwrap();
+        prop_assert!((fixed.0 - float.0).abs() < TOLERANCE_DB, "{} vs {}", fixed, float);
+    }
+
+    #[test]
+    fn weighted_leq_matches_float(levels in levels(), readings in 1u32..100_000) {
+        let weighted = || levels.iter().map(move |level| (*level, readings));
+        let fixed = fixed_point::leq_weighted(weighted()).unwrap();
+        let float = dsp::leq_weighted_f32(weighted()).unwrap();
+        prop_assert!((fixed.0 - float.0).abs() < TOLERANCE_DB, "{} vs {}", fixed, float);
+    }
+}
+
+#[test]
+fn log2_of_powers_of_two_is_exact() {
+    for power in 0..64 {
+        assert_eq!(fixed_point::log2_q16(1 << power), power << 16);
+    }
+}
+
+#[test]
+fn edge_cases_match_float() {
+    assert!(fixed_point::rms_to_db(&[]).0.is_nan());
+    assert_eq!(
+        fixed_point::rms_to_db(&[RawAdc(0); 5]),
+        dsp::rms_to_db_f32(&[RawAdc(0); 5])
+    );
+    assert_eq!(fixed_point::leq(&[]), None);
+}
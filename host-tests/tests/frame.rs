@@ -0,0 +1,44 @@
+use mosquitto_bzzz_host_tests::{
+    dsp::RawAdc,
+    frame::{Frame, Input},
+};
+
+#[test]
+fn sorts_inputs_and_stops_at_a_full_window() {
+    let mut frame = Frame::new(2, 16_000.0);
+    for sample in 0..3u16 {
+        frame.push(Input::Mic, RawAdc(sample));
+        frame.push(Input::Vibration, RawAdc(100 + sample));
+    }
+    assert!(frame.is_full());
+    assert_eq!(frame.mic, vec![RawAdc(0), RawAdc(1)]);
+    assert_eq!(frame.vibration, vec![RawAdc(100), RawAdc(101)]);
+    assert!(frame.second_mic.is_empty());
+    frame.clear();
+    assert!(!frame.is_full());
+    assert!(frame.mic.is_empty());
+}
+
+#[test]
+fn bursts_stay_within_the_window() {
+    let mut frame = Frame::new(10, 16_000.0);
+    for sample in 0..10u16 {
+        frame.push(Input::Mic, RawAdc(sample));
+    }
+    let offsets: Vec<usize> = frame.mic_bursts(4, 3).map(|(offset, _)| offset).collect();
+    assert_eq!(offsets, vec![0, 3, 6]);
+    let (_, last) = frame.mic_bursts(4, 3).last().unwrap();
+    assert_eq!(last, &[RawAdc(6), RawAdc(7), RawAdc(8), RawAdc(9)]);
+    assert_eq!(frame.mic_bursts(11, 3).count(), 0);
+    assert_eq!(frame.mic_bursts(0, 3).count(), 0);
+}
+
+#[test]
+fn vibration_is_averaged_over_whole_blocks() {
+    let mut frame = Frame::new(5, 16_000.0);
+    for sample in [10, 20, 30, 50, 70] {
+        frame.push(Input::Vibration, RawAdc(sample));
+    }
+    let decimated: Vec<RawAdc> = frame.vibration_decimated(2).collect();
+    assert_eq!(decimated, vec![RawAdc(15), RawAdc(40)]);
+}
@@ -0,0 +1,64 @@
+use mosquitto_bzzz_host_tests::{classification::NoiseClass, dsp::Decibel, fusion::FusedInterval};
+
+#[test]
+fn nothing_measured_is_no_document() {
+    assert_eq!(
+        FusedInterval::default().take_json("\"device_id\":\"abc\"", None, None, None),
+        None
+    );
+}
+
+#[test]
+fn noise_only() {
+    let mut fused = FusedInterval::default();
+    fused.add_level(Decibel(50.0), Some(NoiseClass::Normal));
+    fused.add_level(Decibel(50.0), Some(NoiseClass::Normal));
+    assert_eq!(
+        fused.take_json("\"device_id\":\"abc\"", Some(1_700_000_000), None, None).unwrap(),
+        "{\"device_id\":\"abc\",\"timestamp\":1700000000,\"interval_s\":0,\"noise\":{\"leq_db\":50.0,\"max_db\":50.0,\"class\":\"normal\"}}"
+    );
+}
+
+#[test]
+fn combines_every_channel() {
+    let mut fused = FusedInterval::default();
+    fused.add_level(Decibel(40.0), None);
+    fused.add_level(Decibel(70.0), None);
+    fused.add_vibration(Decibel(30.0), Decibel(36.0), Some(NoiseClass::Normal));
+    fused.add_vibration(Decibel(30.0), Decibel(33.0), Some(NoiseClass::Normal));
+    assert_eq!(
+        fused.take_json("\"device_id\":\"abc\"", None, None, Some(41.25)).unwrap(),
+        "{\"device_id\":\"abc\",\"timestamp\":null,\"interval_s\":0,\"noise\":{\"leq_db\":67.0,\"max_db\":70.0,\"class\":null},\"vibration\":{\"level_db\":30.0,\"peak_db\":36.0,\"class\":\"normal\"},\"temperature\":{\"chip_c\":41.2}}"
+    );
+}
+
+#[test]
+fn each_interval_starts_over() {
+    let mut fused = FusedInterval::default();
+    fused.add_level(Decibel(40.0), None);
+    fused.add_vibration(Decibel(30.0), Decibel(36.0), None);
+    assert!(fused
+        .take_json("\"device_id\":\"abc\"", None, None, None)
+        .is_some());
+    assert_eq!(
+        fused.take_json("\"device_id\":\"abc\"", None, None, None),
+        None
+    );
+    fused.add_level(Decibel(40.0), None);
+    assert!(!fused
+        .take_json("\"device_id\":\"abc\"", None, None, None)
+        .unwrap()
+        .contains("vibration"));
+}
+
+#[test]
+fn broker_time_is_flagged() {
+    let mut fused = FusedInterval::default();
+    fused.add_level(Decibel(50.0), None);
+    assert!(fused
+        .take_json("\"device_id\":\"abc\"", Some(1_700_000_000), Some(3), None)
+        .unwrap()
+        .starts_with(
+            "{\"device_id\":\"abc\",\"timestamp\":1700000000,\"ts_source\":\"broker\",\"ts_accuracy_s\":3,\"interval_s\":0,"
+        ));
+}
@@ -0,0 +1,73 @@
+use mosquitto_bzzz_host_tests::{
+    home_assistant::{self, LevelFormat},
+    topics::{Namespace, Topics},
+};
+use serde_json::Value;
+
+fn documents(level_format: LevelFormat) -> Vec<(String, Value)> {
+    let namespace = Namespace {
+        tenant_id: "",
+        site_id: "",
+        floor: "",
+    };
+    let topics = Topics::new(
+        "bzzz/noise/{device_id}",
+        "bzzz.0042",
+        "a0b1",
+        &namespace,
+        false,
+    )
+    .unwrap();
+    home_assistant::documents(
+        "homeassistant",
+        "bzzz.0042",
+        "a0:b1:c2:d3:e4:f5",
+        &topics,
+        level_format,
+    )
+    .into_iter()
+    .map(|(topic, document)| (topic, serde_json::from_str(&document).unwrap()))
+    .collect()
+}
+
+#[test]
+fn level_sensor_describes_the_device() {
+    let documents = documents(LevelFormat::Number);
+    let topics: Vec<&str> = documents.iter().map(|(topic, _)| topic.as_str()).collect();
+    assert_eq!(
+        topics,
+        [
+            "homeassistant/sensor/bzzz_0042/level/config",
+            "homeassistant/sensor/bzzz_0042/noise_class/config",
+            "homeassistant/sensor/bzzz_0042/telemetry/config",
+        ]
+    );
+    let level = &documents[0].1;
+    assert_eq!(level["state_topic"], "bzzz/noise/bzzz.0042");
+    assert_eq!(level["unit_of_measurement"], "dB");
+    assert_eq!(level["device_class"], "sound_pressure");
+    assert_eq!(level["unique_id"], "bzzz_0042_level");
+    assert_eq!(level["availability_topic"], "bzzz/noise/bzzz.0042/status");
+    assert!(level.get("value_template").is_none());
+    assert_eq!(level["device"]["identifiers"][0], "bzzz_bzzz_0042");
+    assert_eq!(level["device"]["connections"][0][1], "a0:b1:c2:d3:e4:f5");
+    // Every entity belongs to the same device
+    assert!(documents
+        .iter()
+        .all(|(_, document)| document["device"] == level["device"]));
+    assert_eq!(documents[1].1["options"][3], "very_loud");
+}
+
+#[test]
+fn template_follows_the_level_format() {
+    let template = |level_format| documents(level_format)[0].1["value_template"].clone();
+    assert_eq!(template(LevelFormat::Document), "{{ value_json.db }}");
+    assert_eq!(
+        template(LevelFormat::Batch { documents: false }),
+        "{{ value_json[-1] }}"
+    );
+    assert_eq!(
+        template(LevelFormat::Batch { documents: true }),
+        "{{ value_json[-1].db }}"
+    );
+}
@@ -0,0 +1,65 @@
+use mosquitto_bzzz_host_tests::{
+    classification::NoiseClass,
+    dsp::Decibel,
+    homie::{self, Homie},
+};
+
+#[test]
+fn device_id_is_made_valid() {
+    let homie = Homie::new("_Bzzz.0042_");
+    assert_eq!(homie.state_topic(), "homie/bzzz-0042/$state");
+}
+
+#[test]
+fn description_is_framed_by_state() {
+    let description = Homie::new("a0b1c2d3e4f5").description("Bzzz a0b1c2d3e4f5");
+    let state = String::from("homie/a0b1c2d3e4f5/$state");
+    assert_eq!(
+        description.first(),
+        Some(&(state.clone(), String::from("init")))
+    );
+    assert_eq!(description.last(), Some(&(state, String::from("ready"))));
+    let attribute = |path: &str| {
+        description
+            .iter()
+            .find(|(topic, _)| topic == &format!("homie/a0b1c2d3e4f5/{}", path))
+            .map(|(_, value)| value.as_str())
+    };
+    assert_eq!(attribute("$homie"), Some("4.0"));
+    assert_eq!(attribute("$nodes"), Some("noise"));
+    assert_eq!(attribute("noise/$properties"), Some("level,class"));
+    assert_eq!(attribute("noise/level/$datatype"), Some("float"));
+    assert_eq!(attribute("noise/level/$retained"), Some("false"));
+    assert_eq!(
+        attribute("noise/class/$format"),
+        Some("quiet,normal,loud,very_loud")
+    );
+}
+
+#[test]
+fn values_are_plain_text() {
+    let homie = Homie::new("a0b1c2d3e4f5");
+    assert_eq!(
+        homie.level(Decibel(52.34)),
+        (
+            String::from("homie/a0b1c2d3e4f5/noise/level"),
+            String::from("52.3")
+        )
+    );
+    assert_eq!(
+        homie.class(NoiseClass::VeryLoud),
+        (
+            String::from("homie/a0b1c2d3e4f5/noise/class"),
+            String::from("very_loud")
+        )
+    );
+}
+
+#[test]
+fn only_attributes_are_retained() {
+    let homie = Homie::new("a0b1c2d3e4f5");
+    assert!(homie::is_attribute(&homie.state_topic()));
+    assert!(homie::is_attribute("homie/a0b1c2d3e4f5/noise/level/$unit"));
+    assert!(!homie::is_attribute(&homie.level(Decibel(52.3)).0));
+    assert!(!homie::is_attribute(&homie.class(NoiseClass::Loud).0));
+}
@@ -0,0 +1,94 @@
+use mosquitto_bzzz_host_tests::{
+    classification::NoiseClass,
+    led::{self, ColorStep, DeviceStatus},
+};
+use serde_json::Value;
+
+fn codes() -> Value {
+    serde_json::from_str(&led::codes_json()).unwrap()
+}
+
+fn pattern(code: &str) -> Value {
+    codes()["patterns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|pattern| pattern["code"] == code)
+        .unwrap()
+        .clone()
+}
+
+#[test]
+fn codes_come_from_the_sequences() {
+    let pattern = pattern("mqtt_bad_credentials");
+    assert_eq!(pattern["status"], DeviceStatus::MqttBadCredentials as u8);
+    let steps = pattern["steps"].as_array().unwrap();
+    let sequence = DeviceStatus::MqttBadCredentials.light_sequence();
+    assert_eq!(steps.len(), sequence.len());
+    assert_eq!(steps[0]["color"], "#ff00ff");
+    assert_eq!(steps[0]["ms"], 100);
+    assert_eq!(steps[3]["color"], "#000000");
+    assert_eq!(steps[3]["ms"], 700);
+}
+
+#[test]
+fn every_status_is_listed_once() {
+    let codes = codes();
+    let statuses: Vec<u64> = codes["patterns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|pattern| pattern["status"].as_u64())
+        .collect();
+    let mut sorted = statuses.clone();
+    sorted.sort_unstable();
+    assert_eq!(sorted, [0, 1, 2, 3, 4, 5]);
+    for status in statuses {
+        assert!(DeviceStatus::try_from(status as u8).is_ok());
+    }
+}
+
+#[test]
+fn codes_follow_the_priority() {
+    let codes = codes();
+    let order: Vec<&str> = codes["patterns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|pattern| pattern["code"].as_str().unwrap())
+        .collect();
+    assert_eq!(order[..3], ["identify", "maintenance", "rule"]);
+    assert_eq!(order.last(), Some(&"ok"));
+    let logger = order.iter().position(|code| *code == "logger").unwrap();
+    let wifi = order.iter().position(|code| *code == "wifi_error").unwrap();
+    assert!(wifi < logger);
+    assert_eq!(pattern("rule")["steps"], Value::Null);
+}
+
+#[test]
+fn every_pattern_has_every_language() {
+    let codes = codes();
+    let languages = codes["languages"].as_array().unwrap();
+    for pattern in codes["patterns"].as_array().unwrap() {
+        for language in languages {
+            let meaning = &pattern["meaning"][language.as_str().unwrap()];
+            assert!(!meaning.as_str().unwrap().is_empty(), "{}", pattern["code"]);
+        }
+    }
+}
+
+#[test]
+fn class_colors_match_the_led() {
+    assert_eq!(
+        led::steady_sequence(led::class_color(NoiseClass::VeryLoud)),
+        [ColorStep::new(255, 0, 0, 500)]
+    );
+    assert_eq!(pattern("class_loud")["steps"][0]["color"], "#ff9000");
+}
+
+#[test]
+fn retries_shorten_dark_steps() {
+    assert_eq!(led::retry_pause_scale(1.0), 1.0);
+    assert!((led::retry_pause_scale(0.0) - 0.2).abs() < 1e-6);
+    assert_eq!(codes()["retry_pause_min_pct"], 20);
+}
@@ -0,0 +1,43 @@
+use std::time::{Duration, Instant};
+
+use mosquitto_bzzz_host_tests::loopback::Loopback;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[test]
+fn echoed_marker_reports_both_latencies() {
+    let mut loopback = Loopback::new(TIMEOUT);
+    let now = Instant::now();
+    let marker = Loopback::marker(42);
+    assert_eq!(marker, r#"{"loopback":42}"#);
+    loopback.start(marker.clone().into_bytes(), 1, Some(7), now);
+    assert!(loopback.is_running());
+    loopback.acknowledge(8, now + Duration::from_millis(5));
+    loopback.acknowledge(7, now + Duration::from_millis(20));
+    // Readings published meanwhile are no echo
+    assert_eq!(
+        loopback.received(b"52.5", now + Duration::from_millis(30)),
+        None
+    );
+    assert_eq!(
+        loopback
+            .received(marker.as_bytes(), now + Duration::from_millis(45))
+            .as_deref(),
+        Some(r#"{"ok":true,"qos":1,"puback_ms":20,"round_trip_ms":45}"#)
+    );
+    assert!(!loopback.is_running());
+    assert_eq!(loopback.take_timed_out(now + TIMEOUT), None);
+}
+
+#[test]
+fn missing_echo_times_out() {
+    let mut loopback = Loopback::new(TIMEOUT);
+    let now = Instant::now();
+    loopback.start(Loopback::marker(1).into_bytes(), 0, Some(0), now);
+    assert_eq!(loopback.take_timed_out(now + Duration::from_secs(9)), None);
+    assert_eq!(
+        loopback.take_timed_out(now + TIMEOUT).as_deref(),
+        Some(r#"{"ok":false,"qos":0,"puback_ms":null,"round_trip_ms":null}"#)
+    );
+    assert!(!loopback.is_running());
+}
@@ -0,0 +1,94 @@
+use mosquitto_bzzz_host_tests::offline::OfflineQueue;
+
+#[test]
+fn replays_oldest_first_with_the_queue_time() {
+    let mut queue = OfflineQueue::new(10);
+    assert!(queue.push(String::from("50.5"), Some(1_700_000_000), None));
+    assert!(queue.push(String::from(r#"{"db":51.0}"#), None, None));
+    assert_eq!(
+        queue.peek_replay().as_deref(),
+        Some(r#"{"ts":1700000000,"queued":true,"reading":50.5}"#)
+    );
+    // Not published yet
+    assert_eq!(queue.len(), 2);
+    queue.pop();
+    assert_eq!(
+        queue.peek_replay().as_deref(),
+        Some(r#"{"ts":null,"queued":true,"reading":{"db":51.0}}"#)
+    );
+    queue.pop();
+    assert!(queue.is_empty());
+    assert_eq!(queue.peek_replay(), None);
+}
+
+#[test]
+fn full_queue_drops_the_oldest() {
+    let mut queue = OfflineQueue::new(2);
+    assert!(queue.push(String::from("50"), Some(1), None));
+    assert!(queue.push(String::from("51"), Some(2), None));
+    assert!(!queue.push(String::from("52"), Some(3), None));
+    assert_eq!(queue.len(), 2);
+    assert_eq!(
+        queue.peek_replay().as_deref(),
+        Some(r#"{"ts":2,"queued":true,"reading":51}"#)
+    );
+    queue.configure(1);
+    assert_eq!(
+        queue.peek_replay().as_deref(),
+        Some(r#"{"ts":3,"queued":true,"reading":52}"#)
+    );
+    queue.configure(0);
+    assert!(queue.is_empty());
+    assert!(!queue.push(String::from("53"), Some(4), None));
+}
+
+#[test]
+fn blob_keeps_the_newest_and_restores_before_new_readings() {
+    let mut queue = OfflineQueue::new(10);
+    for (ts, reading) in [(1, "50"), (2, "51"), (3, r#"{"a":1,"b":2}"#)] {
+        queue.push(String::from(reading), Some(ts), None);
+    }
+    queue.push(String::from("53"), None, None);
+    let blob = queue.to_blob(3);
+    assert_eq!(blob, "2,51\n3,{\"a\":1,\"b\":2}\n,53\n");
+
+    let mut restored = OfflineQueue::new(3);
+    restored.push(String::from("60"), Some(9), None);
+    restored.restore(&blob);
+    assert_eq!(restored.len(), 3);
+    assert_eq!(
+        restored.peek_replay().as_deref(),
+        Some(r#"{"ts":3,"queued":true,"reading":{"a":1,"b":2}}"#)
+    );
+    restored.pop();
+    restored.pop();
+    assert_eq!(
+        restored.peek_replay().as_deref(),
+        Some(r#"{"ts":9,"queued":true,"reading":60}"#)
+    );
+}
+
+#[test]
+fn broker_time_is_flagged_and_survives_a_reboot() {
+    let mut queue = OfflineQueue::new(10);
+    queue.push(String::from("50"), Some(1_700_000_000), Some(3));
+    // Nothing to be off without a time
+    queue.push(String::from("51"), None, Some(3));
+    assert_eq!(
+        queue.peek_replay().as_deref(),
+        Some(
+            r#"{"ts":1700000000,"ts_source":"broker","ts_accuracy_s":3,"queued":true,"reading":50}"#
+        )
+    );
+    let blob = queue.to_blob(10);
+    assert_eq!(blob, "1700000000:3,50\n,51\n");
+
+    let mut restored = OfflineQueue::new(10);
+    restored.restore(&blob);
+    assert_eq!(restored.peek_replay(), queue.peek_replay());
+    restored.pop();
+    assert_eq!(
+        restored.peek_replay().as_deref(),
+        Some(r#"{"ts":null,"queued":true,"reading":51}"#)
+    );
+}
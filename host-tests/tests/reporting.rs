@@ -0,0 +1,359 @@
+use std::time::{Duration, Instant};
+
+use mosquitto_bzzz_host_tests::{
+    bands::OctaveBands,
+    dsp::{Decibel, RawAdc},
+    reporting::{IntervalLeq, LevelBatch, LevelReading, Reporter},
+    schedule::Schedule,
+    weighting::Weighting,
+};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+#[test]
+fn without_delta_every_reading_goes_out() {
+    let mut reporter = Reporter::new(0.0, MINUTE);
+    let now = Instant::now();
+    assert!(reporter.should_publish(Decibel(50.0), now));
+    assert!(reporter.should_publish(Decibel(50.0), now));
+}
+
+#[test]
+fn holds_back_small_changes_up_to_max_silence() {
+    let mut reporter = Reporter::new(1.0, MINUTE);
+    let now = Instant::now();
+    assert!(reporter.should_publish(Decibel(50.0), now));
+    assert!(!reporter.should_publish(Decibel(50.9), now + Duration::from_secs(1)));
+    // Compared with the last published reading, so slow drifts are published eventually
+    assert!(!reporter.should_publish(Decibel(50.5), now + Duration::from_secs(2)));
+    assert!(reporter.should_publish(Decibel(51.0), now + Duration::from_secs(3)));
+    assert!(reporter.should_publish(Decibel(51.0), now + Duration::from_secs(63)));
+}
+
+#[test]
+fn interval_leq_averages_energy_once_the_interval_is_over() {
+    let mut interval = IntervalLeq::new(MINUTE, None);
+    let now = Instant::now();
+    assert_eq!(interval.add(Decibel(60.0), now, None), None);
+    assert_eq!(
+        interval.add(Decibel(60.0), now + Duration::from_secs(30), None),
+        None
+    );
+    // Two readings at 60 dB and one at 70 dB: the loud one dominates
+    let leq = interval.add(Decibel(70.0), now + MINUTE, None).unwrap();
+    assert!((leq.0 - 66.0).abs() < 0.1, "{}", leq.0);
+    // The next interval starts with the next reading
+    assert_eq!(interval.add(Decibel(50.0), now + 2 * MINUTE, None), None);
+    let leq = interval.add(Decibel(50.0), now + 3 * MINUTE, None).unwrap();
+    assert!((leq.0 - 50.0).abs() < 0.01);
+}
+
+#[test]
+fn interval_leq_covers_readings_of_many_blocks() {
+    let mut interval = IntervalLeq::new(MINUTE, None);
+    let now = Instant::now();
+    // Half of them at 60 dB, half at 70 dB, 10 * log10((10^6 + 10^7) / 2) = 67.4 dB
+    for reading in 0..1000 {
+        let level = if reading < 500 { 60.0 } else { 70.0 };
+        let at = now + Duration::from_millis(reading * 50);
+        assert_eq!(interval.add(Decibel(level), at, None), None);
+    }
+    let leq = interval.add(Decibel(70.0), now + MINUTE, None).unwrap();
+    assert!((leq.0 - 67.4).abs() < 0.01, "{}", leq.0);
+}
+
+#[test]
+fn scheduled_intervals_close_on_the_boundaries() {
+    let schedule = Schedule::parse("*/5").unwrap();
+    let mut interval = IntervalLeq::new(MINUTE, Some(schedule));
+    let now = Instant::now();
+    // 00:03:30 UTC, the first interval after boot runs to 00:05
+    let unix_time = 1_700_006_400 + 3 * 60 + 30;
+    assert_eq!(interval.add(Decibel(60.0), now, Some(unix_time)), None);
+    assert_eq!(
+        interval.add(Decibel(60.0), now + MINUTE, Some(unix_time + 60)),
+        None
+    );
+    let leq = interval.add(Decibel(60.0), now + 2 * MINUTE, Some(unix_time + 90));
+    assert!((leq.unwrap().0 - 60.0).abs() < 0.01);
+    assert_eq!(interval.closed_at(), Some(1_700_006_400 + 5 * 60));
+    // A full five minutes from then on
+    assert_eq!(
+        interval.add(Decibel(50.0), now + 3 * MINUTE, Some(unix_time + 100)),
+        None
+    );
+    assert_eq!(
+        interval.add(Decibel(50.0), now + 7 * MINUTE, Some(unix_time + 389)),
+        None
+    );
+    assert!(interval
+        .add(Decibel(50.0), now + 8 * MINUTE, Some(unix_time + 390))
+        .is_some());
+    assert_eq!(interval.closed_at(), Some(1_700_006_400 + 10 * 60));
+}
+
+#[test]
+fn scheduled_intervals_follow_clock_jumps() {
+    let schedule = Schedule::parse("*/5").unwrap();
+    let mut interval = IntervalLeq::new(MINUTE, Some(schedule));
+    let now = Instant::now();
+    // 00:03:30 UTC, due at 00:05
+    let unix_time = 1_700_006_400 + 3 * 60 + 30;
+    assert_eq!(interval.add(Decibel(60.0), now, Some(unix_time)), None);
+    // An hour back, the interval now ends at 23:05 of the day before
+    let back = unix_time - 3600;
+    assert_eq!(interval.add(Decibel(60.0), now + MINUTE, Some(back)), None);
+    assert_eq!(
+        interval.add(Decibel(60.0), now + 2 * MINUTE, Some(back + 89)),
+        None
+    );
+    assert!(interval
+        .add(Decibel(60.0), now + 3 * MINUTE, Some(back + 90))
+        .is_some());
+    assert_eq!(interval.closed_at(), Some(1_700_006_400 - 3600 + 5 * 60));
+    // Due at 23:10, then an hour ahead to 00:05: closed at the next boundary from there, 00:10
+    let ahead = back + 90 + 3600;
+    assert_eq!(
+        interval.add(Decibel(60.0), now + 4 * MINUTE, Some(back + 100)),
+        None
+    );
+    assert_eq!(
+        interval.add(Decibel(60.0), now + 5 * MINUTE, Some(ahead)),
+        None
+    );
+    assert!(interval
+        .add(Decibel(60.0), now + 9 * MINUTE, Some(ahead + 300))
+        .is_some());
+    assert_eq!(interval.closed_at(), Some(1_700_006_400 + 10 * 60));
+}
+
+#[test]
+fn scheduled_intervals_run_free_without_the_clock() {
+    let mut interval = IntervalLeq::new(MINUTE, Some(Schedule::parse("0").unwrap()));
+    let now = Instant::now();
+    assert_eq!(interval.add(Decibel(60.0), now, None), None);
+    assert!(interval.add(Decibel(60.0), now + MINUTE, None).is_some());
+    assert_eq!(interval.closed_at(), None);
+}
+
+#[test]
+fn publish_next_skips_the_delta_once() {
+    let mut reporter = Reporter::new(1.0, MINUTE);
+    let now = Instant::now();
+    assert!(reporter.should_publish(Decibel(50.0), now));
+    reporter.publish_next();
+    assert!(reporter.should_publish(Decibel(50.0), now + Duration::from_secs(1)));
+    assert!(!reporter.should_publish(Decibel(50.0), now + Duration::from_secs(2)));
+}
+
+#[test]
+fn heartbeat_once_nothing_went_out_for_the_interval() {
+    let mut reporter = Reporter::new(1.0, 10 * MINUTE);
+    let start = Instant::now();
+    assert!(reporter.should_publish(Decibel(50.0), start));
+    for second in 1..=120 {
+        reporter.should_publish(Decibel(50.0), start + Duration::from_secs(second));
+    }
+    assert_eq!(
+        reporter.heartbeat(MINUTE, start + Duration::from_secs(30), false),
+        None
+    );
+    assert_eq!(
+        reporter.heartbeat(MINUTE, start + Duration::from_secs(121), false),
+        Some(String::from(
+            "{\"seq\":1,\"status\":\"running\",\"suppressed\":120}"
+        ))
+    );
+    assert_eq!(
+        reporter.heartbeat(MINUTE, start + Duration::from_secs(150), true),
+        None
+    );
+    assert_eq!(
+        reporter.heartbeat(MINUTE, start + Duration::from_secs(181), true),
+        Some(String::from(
+            "{\"seq\":2,\"status\":\"paused\",\"suppressed\":0}"
+        ))
+    );
+}
+
+#[test]
+fn published_levels_postpone_the_heartbeat() {
+    let mut reporter = Reporter::new(0.0, MINUTE);
+    let start = Instant::now();
+    reporter.should_publish(Decibel(50.0), start + Duration::from_secs(50));
+    assert_eq!(
+        reporter.heartbeat(MINUTE, start + Duration::from_secs(70), false),
+        None
+    );
+    assert_eq!(
+        reporter.heartbeat(Duration::ZERO, start + MINUTE * 5, false),
+        None
+    );
+}
+
+#[test]
+fn level_reading_is_self_describing() {
+    let samples = [RawAdc(0), RawAdc(4095)];
+    assert_eq!(
+        LevelReading::new(
+            "bzzz-0042",
+            Some(1_700_000_000),
+            Decibel(72.5),
+            Some(&samples),
+            Some(-61),
+            false
+        )
+        .to_json(),
+        r#"{"device_id":"bzzz-0042","ts":1700000000,"db":72.5,"samples":2,"rssi":-61}"#
+    );
+}
+
+#[test]
+fn level_reading_names_its_weighting() {
+    let reading = |weighting| {
+        LevelReading::new("bzzz-0042", None, Decibel(40.0), None, None, false)
+            .with_weighting(weighting)
+            .to_json()
+    };
+    assert_eq!(
+        reading(Weighting::A),
+        r#"{"device_id":"bzzz-0042","ts":null,"db":40.0,"weighting":"A","samples":0,"rssi":null}"#
+    );
+    assert!(!reading(Weighting::Z).contains("weighting"));
+}
+
+#[test]
+fn level_reading_ends_with_its_bands() {
+    let reading = LevelReading::new("bzzz-0042", None, Decibel(40.0), None, None, false)
+        .with_bands(Some(OctaveBands(vec![(63.0, Decibel(41.2))])));
+    assert_eq!(
+        reading.to_json(),
+        r#"{"device_id":"bzzz-0042","ts":null,"db":40.0,"samples":0,"rssi":null,"bands_db":{"63":41.2}}"#
+    );
+}
+
+#[test]
+fn level_reading_carries_its_sequence_number() {
+    assert_eq!(
+        LevelReading::new("bzzz-0042", None, Decibel(40.0), None, None, false)
+            .with_seq(258)
+            .to_json(),
+        r#"{"device_id":"bzzz-0042","seq":258,"ts":null,"db":40.0,"samples":0,"rssi":null}"#
+    );
+}
+
+#[test]
+fn level_reading_is_marked_in_maintenance_mode() {
+    let reading = |in_mode| {
+        LevelReading::new("bzzz-0042", None, Decibel(40.0), None, None, false)
+            .with_bands(Some(OctaveBands(vec![(63.0, Decibel(41.2))])))
+            .with_maintenance(in_mode)
+            .to_json()
+    };
+    assert_eq!(
+        reading(true),
+        r#"{"device_id":"bzzz-0042","ts":null,"db":40.0,"samples":0,"rssi":null,"bands_db":{"63":41.2},"maintenance":true}"#
+    );
+    assert!(!reading(false).contains("maintenance"));
+}
+
+#[test]
+fn heartbeats_continue_the_previous_numbering() {
+    let mut reporter = Reporter::new(0.0, MINUTE);
+    reporter.resume_heartbeats(41);
+    let start = Instant::now();
+    assert_eq!(
+        reporter
+            .heartbeat(MINUTE, start + MINUTE * 2, false)
+            .as_deref(),
+        Some("{\"seq\":42,\"status\":\"running\",\"suppressed\":0}")
+    );
+    assert_eq!(reporter.heartbeat_seq(), 42);
+}
+
+#[test]
+fn level_reading_flags_broker_time() {
+    let reading = LevelReading::new(
+        "bzzz-0042",
+        Some(1_700_000_000),
+        Decibel(50.0),
+        None,
+        None,
+        false,
+    );
+    assert_eq!(
+        reading
+            .with_broker_time(Some(Duration::from_millis(2001)))
+            .to_json(),
+        r#"{"device_id":"bzzz-0042","ts":1700000000,"ts_source":"broker","ts_accuracy_s":3,"db":50.0,"samples":0,"rssi":null}"#
+    );
+    let reading = LevelReading::new(
+        "bzzz-0042",
+        Some(1_700_000_000),
+        Decibel(50.0),
+        None,
+        None,
+        false,
+    );
+    assert_eq!(
+        reading.with_broker_time(None).to_json(),
+        r#"{"device_id":"bzzz-0042","ts":1700000000,"db":50.0,"samples":0,"rssi":null}"#
+    );
+}
+
+#[test]
+fn level_reading_carries_the_raw_rms() {
+    let samples = [RawAdc(0), RawAdc(4095)];
+    assert_eq!(
+        LevelReading::new("bzzz-0042", None, Decibel(72.5), Some(&samples), None, true).to_json(),
+        r#"{"device_id":"bzzz-0042","ts":null,"db":72.5,"samples":2,"rssi":null,"rms_counts":2047.5,"rms_mv":1650.0}"#
+    );
+    assert_eq!(
+        LevelReading::new("bzzz-0042", None, Decibel(40.0), None, None, true).to_json(),
+        r#"{"device_id":"bzzz-0042","ts":null,"db":40.0,"samples":0,"rssi":null,"rms_counts":null,"rms_mv":null}"#
+    );
+}
+
+#[test]
+fn without_batching_readings_pass_through() {
+    let mut batch = LevelBatch::new(1, MINUTE);
+    let now = Instant::now();
+    assert_eq!(batch.add(String::from("50"), now).as_deref(), Some("50"));
+    assert_eq!(batch.take_due(now + MINUTE), None);
+}
+
+#[test]
+fn full_batches_go_out_as_an_array() {
+    let mut batch = LevelBatch::new(3, MINUTE);
+    let now = Instant::now();
+    assert_eq!(batch.add(String::from("50"), now), None);
+    assert_eq!(batch.add(String::from("51.5"), now), None);
+    assert_eq!(
+        batch.add(String::from("52"), now).as_deref(),
+        Some("[50,51.5,52]")
+    );
+    assert_eq!(batch.take_due(now + MINUTE), None);
+}
+
+#[test]
+fn partial_batches_go_out_after_the_interval() {
+    let mut batch = LevelBatch::new(10, MINUTE);
+    let now = Instant::now();
+    assert_eq!(batch.take_due(now + MINUTE), None);
+    assert_eq!(batch.add(String::from("50"), now), None);
+    assert_eq!(batch.take_due(now + Duration::from_secs(59)), None);
+    assert_eq!(batch.take_due(now + MINUTE).as_deref(), Some("[50]"));
+}
+
+#[test]
+fn readings_collected_before_disabling_go_out_together() {
+    let mut batch = LevelBatch::new(10, Duration::ZERO);
+    let now = Instant::now();
+    assert_eq!(batch.add(String::from("50"), now), None);
+    assert_eq!(batch.take_due(now + MINUTE), None);
+    batch.configure(0, Duration::ZERO);
+    assert_eq!(
+        batch.add(String::from("51"), now).as_deref(),
+        Some("[50,51]")
+    );
+}
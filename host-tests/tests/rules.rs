@@ -0,0 +1,112 @@
+use std::time::{Duration, Instant};
+
+use mosquitto_bzzz_host_tests::{
+    dsp::Decibel,
+    rules::{Action, RuleEngine},
+};
+
+const NIGHT: &str = r#"[{
+    "name": "night",
+    "above_db": 70,
+    "for_s": 10,
+    "between": "22:00-07:00",
+    "then": [{"gpio": {"pin": 4, "high": true}}, {"led": [255, 0, 0]}, {"publish": "too loud"}],
+    "clear": [{"gpio": {"pin": 4, "high": false}}, {"led": null}]
+}]"#;
+
+fn actions(due: Vec<(&str, Action)>) -> Vec<Action> {
+    due.into_iter().map(|(_, action)| action).collect()
+}
+
+#[test]
+fn no_rules() {
+    assert!(RuleEngine::parse("").unwrap().is_empty());
+    assert!(RuleEngine::parse("[]").unwrap().is_empty());
+}
+
+#[test]
+fn fires_once_the_condition_held_and_clears_after() {
+    let mut engine = RuleEngine::parse(NIGHT).unwrap();
+    let start = Instant::now();
+    let at = |s| start + Duration::from_secs(s);
+    let midnight = Some(0);
+    assert!(engine.update(Decibel(75.0), midnight, at(0)).is_empty());
+    assert!(engine.update(Decibel(75.0), midnight, at(9)).is_empty());
+    let due = engine.update(Decibel(75.0), midnight, at(10));
+    assert!(due.iter().all(|(rule, _)| *rule == "night"));
+    assert_eq!(
+        actions(due),
+        vec![
+            Action::Gpio { pin: 4, high: true },
+            Action::Led(Some([255, 0, 0])),
+            Action::Publish(String::from("too loud")),
+        ]
+    );
+    assert!(engine.update(Decibel(75.0), midnight, at(11)).is_empty());
+    assert_eq!(
+        actions(engine.update(Decibel(60.0), midnight, at(12))),
+        vec![
+            Action::Gpio {
+                pin: 4,
+                high: false
+            },
+            Action::Led(None)
+        ]
+    );
+    assert!(engine.update(Decibel(60.0), midnight, at(13)).is_empty());
+}
+
+#[test]
+fn a_dip_restarts_the_duration() {
+    let mut engine = RuleEngine::parse(NIGHT).unwrap();
+    let start = Instant::now();
+    let at = |s| start + Duration::from_secs(s);
+    engine.update(Decibel(75.0), Some(0), at(0));
+    // Never fired, so nothing to clear either
+    assert!(engine.update(Decibel(60.0), Some(0), at(5)).is_empty());
+    assert!(engine.update(Decibel(75.0), Some(0), at(10)).is_empty());
+    assert!(!engine.update(Decibel(75.0), Some(0), at(20)).is_empty());
+}
+
+#[test]
+fn time_windows() {
+    let mut engine = RuleEngine::parse(NIGHT).unwrap();
+    let start = Instant::now();
+    let noon = Some(12 * 60);
+    engine.update(Decibel(75.0), noon, start);
+    assert!(engine
+        .update(Decibel(75.0), noon, start + Duration::from_secs(60))
+        .is_empty());
+    // Without a clock a window never matches
+    engine.update(Decibel(75.0), None, start);
+    assert!(engine
+        .update(Decibel(75.0), None, start + Duration::from_secs(60))
+        .is_empty());
+    let daytime = r#"[{"name":"day","below_db":30,"between":"07:00-22:00","then":[{"buzzer":{"pin":5,"ms":200}}]}]"#;
+    let mut engine = RuleEngine::parse(daytime).unwrap();
+    assert!(engine
+        .update(Decibel(20.0), Some(6 * 60 + 59), start)
+        .is_empty());
+    assert_eq!(
+        actions(engine.update(Decibel(20.0), Some(7 * 60), start)),
+        vec![Action::Buzzer { pin: 5, ms: 200 }]
+    );
+}
+
+#[test]
+fn rejects_invalid_rules() {
+    for rules in [
+        "{",
+        r#"[{"name":"x","then":[]}]"#,
+        r#"[{"name":"Loud!","then":[{"led":null}]}]"#,
+        r#"[{"name":"x","then":[{"gpio":{"pin":8,"high":true}}]}]"#,
+        r#"[{"name":"x","then":[{"gpio":{"pin":16,"high":true}}]}]"#,
+        r#"[{"name":"x","then":[{"buzzer":{"pin":30,"ms":1}}]}]"#,
+        r#"[{"name":"x","between":"22:00","then":[{"led":null}]}]"#,
+        r#"[{"name":"x","between":"25:00-07:00","then":[{"led":null}]}]"#,
+        r#"[{"name":"x","above":70,"then":[{"led":null}]}]"#,
+        r#"[{"name":"x","then":[{"siren":true}]}]"#,
+    ] {
+        assert!(RuleEngine::parse(rules).is_err(), "{}", rules);
+    }
+}
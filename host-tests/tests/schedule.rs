@@ -0,0 +1,62 @@
+use mosquitto_bzzz_host_tests::schedule::Schedule;
+
+// 2023-11-15T00:00:00Z
+const MIDNIGHT: u64 = 1_700_006_400;
+const MINUTE: u64 = 60;
+const HOUR: u64 = 3600;
+
+fn next(spec: &str, unix_time: u64) -> u64 {
+    Schedule::parse(spec).unwrap().next_after(unix_time)
+}
+
+#[test]
+fn steps_through_the_hour() {
+    assert_eq!(next("*/5", MIDNIGHT), MIDNIGHT + 5 * MINUTE);
+    assert_eq!(
+        next("*/5", MIDNIGHT + 4 * MINUTE + 59),
+        MIDNIGHT + 5 * MINUTE
+    );
+    // Strictly after, a boundary itself moves on to the next one
+    assert_eq!(next("*/5", MIDNIGHT + 5 * MINUTE), MIDNIGHT + 10 * MINUTE);
+    assert_eq!(next("*", MIDNIGHT + 30), MIDNIGHT + MINUTE);
+}
+
+#[test]
+fn hourly_at_the_full_hour() {
+    assert_eq!(next("0", MIDNIGHT + 10 * MINUTE), MIDNIGHT + HOUR);
+    assert_eq!(next("0 *", MIDNIGHT + 23 * HOUR + 1), MIDNIGHT + 24 * HOUR);
+    assert_eq!(
+        next("15", MIDNIGHT + 20 * MINUTE),
+        MIDNIGHT + HOUR + 15 * MINUTE
+    );
+}
+
+#[test]
+fn hours_narrow_it_down() {
+    assert_eq!(next("0 */6", MIDNIGHT + HOUR), MIDNIGHT + 6 * HOUR);
+    let daytime = Schedule::parse("0,30 8-18").unwrap();
+    assert_eq!(daytime.next_after(MIDNIGHT), MIDNIGHT + 8 * HOUR);
+    assert_eq!(
+        daytime.next_after(MIDNIGHT + 8 * HOUR),
+        MIDNIGHT + 8 * HOUR + 30 * MINUTE
+    );
+    // After the last one of the day, the first one of the next
+    assert_eq!(
+        daytime.next_after(MIDNIGHT + 18 * HOUR + 30 * MINUTE),
+        MIDNIGHT + 32 * HOUR
+    );
+    assert_eq!(
+        next("10-20/5 3", MIDNIGHT),
+        MIDNIGHT + 3 * HOUR + 10 * MINUTE
+    );
+    assert_eq!(next("50/5", MIDNIGHT), MIDNIGHT + 50 * MINUTE);
+}
+
+#[test]
+fn rejects_what_cron_would() {
+    for spec in [
+        "", "60", "*/0", "0 24", "20-10", "a", "0,", "* * *", "-1", "5/x",
+    ] {
+        assert!(Schedule::parse(spec).is_err(), "{:?}", spec);
+    }
+}
@@ -0,0 +1,21 @@
+use mosquitto_bzzz_host_tests::{dsp::RawAdc, encoding, support_stream};
+
+#[test]
+fn samples_are_little_endian_base64() {
+    let json = support_stream::adc_json(&[RawAdc(0x0102), RawAdc(4095)], 16000.0);
+    assert_eq!(json, "{\"sample_rate_hz\":16000,\"samples\":\"AgH/Dw==\"}");
+    assert_eq!(
+        encoding::decode_base64("AgH/Dw=="),
+        Some(vec![2, 1, 0xff, 0x0f])
+    );
+}
+
+#[test]
+fn signatures_decode_from_base64url() {
+    let hmac: Vec<u8> = (0..32).map(|byte| byte * 7).collect();
+    assert_eq!(
+        encoding::decode_base64url(&encoding::base64url(&hmac)),
+        Some(hmac)
+    );
+    assert_eq!(encoding::decode_base64url("AgH/Dw"), None);
+}
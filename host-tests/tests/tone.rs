@@ -0,0 +1,199 @@
+use std::time::{Duration, Instant};
+
+use mosquitto_bzzz_host_tests::{
+    dsp::{Decibel, RawAdc},
+    spectrum::Burst,
+    tone::{self, Cadence, CadenceDetector, ToneEvent},
+};
+
+const RATE_HZ: f32 = 20_000.0;
+
+fn burst(frequency_hz: f32, amplitude: f32) -> Burst {
+    let samples = (0..tone::BURST_LEN)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * frequency_hz * i as f32 / RATE_HZ;
+            RawAdc((2048.0 + amplitude * phase.sin()).round() as u16)
+        })
+        .collect();
+    Burst {
+        samples,
+        sample_rate_hz: RATE_HZ,
+    }
+}
+
+// Deterministic broadband noise, loud but without any dominant tone
+fn noise() -> Burst {
+    let mut state = 0x2545_f491u32;
+    let samples = (0..tone::BURST_LEN)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            RawAdc(1048 + (state >> 21) as u16)
+        })
+        .collect();
+    Burst {
+        samples,
+        sample_rate_hz: RATE_HZ,
+    }
+}
+
+#[test]
+fn parses_detectors() {
+    let detectors =
+        tone::parse_detectors(" smoke_alarm_suspected:3100:50, beeper:1000:40 ").unwrap();
+    assert_eq!(
+        detectors.iter().map(|d| d.event()).collect::<Vec<_>>(),
+        ["smoke_alarm_suspected", "beeper"]
+    );
+    assert!(tone::parse_detectors("").unwrap().is_empty());
+}
+
+#[test]
+fn rejects_invalid_detectors() {
+    for spec in [
+        "smoke:3100",
+        "smoke:3100:50:1",
+        "Smoke:3100:50",
+        "smoke,alarm:3100:50",
+        "smoke:-1:50",
+        "smoke:3100:NaN",
+        ":3100:50",
+    ] {
+        assert!(tone::parse_detectors(spec).is_err(), "{}", spec);
+    }
+}
+
+#[test]
+fn fires_on_a_loud_tone_once_per_sound() {
+    let mut detectors = tone::parse_detectors("smoke_alarm_suspected:3100:50").unwrap();
+    let detector = &mut detectors[0];
+    let now = Instant::now();
+    // A 1000 count amplitude is 57 dB on the scale of the levels
+    let Some(ToneEvent::Tone(level)) = detector.update(&burst(3100.0, 1000.0), now) else {
+        panic!("No tone event");
+    };
+    assert!((level.0 - 57.0).abs() < 0.5, "{}", level);
+    let later = now + Duration::from_millis(20);
+    assert_eq!(detector.update(&burst(3100.0, 1000.0), later), None);
+    // Still the same sound after a minute, as long as it goes on
+    let later = now + Duration::from_secs(90);
+    assert_eq!(detector.update(&burst(3100.0, 1000.0), later), None);
+}
+
+#[test]
+fn fires_again_once_the_tone_was_gone_for_a_minute() {
+    let mut detectors = tone::parse_detectors("smoke_alarm_suspected:3100:50").unwrap();
+    let detector = &mut detectors[0];
+    let now = Instant::now();
+    assert!(detector.update(&burst(3100.0, 1000.0), now).is_some());
+    let gone = now + Duration::from_secs(1);
+    assert_eq!(detector.update(&noise(), gone), None);
+    // Back before the minute is up, e.g. in the gaps of a cadence
+    let back = gone + Duration::from_secs(30);
+    assert_eq!(detector.update(&burst(3100.0, 1000.0), back), None);
+    assert_eq!(
+        detector.update(&noise(), back + Duration::from_secs(1)),
+        None
+    );
+    let quiet = back + Duration::from_secs(62);
+    assert_eq!(detector.update(&noise(), quiet), None);
+    assert!(matches!(
+        detector.update(&burst(3100.0, 1000.0), quiet + Duration::from_secs(1)),
+        Some(ToneEvent::Tone(_))
+    ));
+}
+
+#[test]
+fn ignores_quiet_tones_noise_and_other_frequencies() {
+    let mut detectors = tone::parse_detectors("smoke_alarm_suspected:3100:50").unwrap();
+    let detector = &mut detectors[0];
+    let now = Instant::now();
+    assert_eq!(detector.update(&burst(3100.0, 100.0), now), None);
+    assert_eq!(detector.update(&burst(1000.0, 1000.0), now), None);
+    assert_eq!(detector.update(&noise(), now), None);
+}
+
+#[test]
+fn ignores_frequencies_above_nyquist() {
+    let mut detectors = tone::parse_detectors("smoke_alarm_suspected:3100:50").unwrap();
+    let mut slow = burst(3100.0, 1000.0);
+    slow.sample_rate_hz = 6000.0;
+    assert_eq!(detectors[0].update(&slow, Instant::now()), None);
+}
+
+// Feeds tone on/off segments in milliseconds, as seen by checks every 13 ms
+fn run_cadence(segments: &[(bool, u64)], repeat: usize) -> Vec<tone::CadenceMatch> {
+    let mut detector = CadenceDetector::default();
+    let start = Instant::now();
+    let mut at = 0;
+    let mut found = vec![];
+    for (present, length) in segments.iter().cycle().take(segments.len() * repeat) {
+        let end = at + length;
+        while at < end {
+            let level = present.then_some(Decibel(70.0));
+            found.extend(detector.update(start + Duration::from_millis(at), level));
+            at += 13;
+        }
+    }
+    found
+}
+
+const T3: [(bool, u64); 6] = [
+    (true, 500),
+    (false, 500),
+    (true, 500),
+    (false, 500),
+    (true, 500),
+    (false, 1500),
+];
+const T4: [(bool, u64); 8] = [
+    (true, 100),
+    (false, 100),
+    (true, 100),
+    (false, 100),
+    (true, 100),
+    (false, 100),
+    (true, 100),
+    (false, 5000),
+];
+
+#[test]
+fn recognizes_t3_once_per_alarm() {
+    let found = run_cadence(&T3, 5);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].cadence, Cadence::T3);
+    assert!(found[0].confidence > 0.8, "{}", found[0].confidence);
+    // Two groups, up to the end of the last pulse of the second
+    assert!((found[0].duration.as_secs_f32() - 6.5).abs() < 0.1);
+    assert_eq!(found[0].level, Decibel(70.0));
+}
+
+#[test]
+fn recognizes_t4() {
+    let found = run_cadence(&T4, 3);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].cadence, Cadence::T4);
+    assert!(found[0].confidence > 0.6, "{}", found[0].confidence);
+}
+
+#[test]
+fn a_single_group_is_not_an_alarm() {
+    assert!(run_cadence(&T3, 1).is_empty());
+}
+
+#[test]
+fn other_patterns_are_no_cadence() {
+    // Continuous tone, two pulses and slow beeps
+    assert!(run_cadence(&[(true, 5000), (false, 2000)], 3).is_empty());
+    assert!(run_cadence(&[(true, 500), (false, 500), (true, 500), (false, 1500)], 4).is_empty());
+    assert!(run_cadence(&[(true, 1500), (false, 1500)], 6).is_empty());
+}
+
+#[test]
+fn reports_again_after_the_alarm_stopped() {
+    let mut segments = T3.to_vec();
+    segments.extend(T3);
+    segments.push((false, 15_000));
+    assert_eq!(run_cadence(&segments, 2).len(), 2);
+}
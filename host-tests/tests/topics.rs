@@ -0,0 +1,90 @@
+use mosquitto_bzzz_host_tests::topics::{Namespace, Topics};
+
+const NO_NAMESPACE: Namespace = Namespace {
+    tenant_id: "",
+    site_id: "",
+    floor: "",
+};
+
+const ACME: Namespace = Namespace {
+    tenant_id: "acme",
+    site_id: "madrid",
+    floor: "2",
+};
+
+#[test]
+fn without_tenant_topics_stay_flat() {
+    let topics = Topics::new("bzzz/noise/{device_id}", "abc", "abc", &NO_NAMESPACE, true).unwrap();
+    assert_eq!(topics.level, "bzzz/noise/abc");
+    assert_eq!(topics.cmd, "bzzz/noise/abc/cmd");
+    assert_eq!(topics.replay, "bzzz/noise/abc/replay");
+    assert!(topics.legacy.is_none());
+    assert_eq!(topics.metadata, "\"device_id\":\"abc\"");
+}
+
+#[test]
+fn tenant_and_site_prefix_every_topic() {
+    let topics = Topics::new("bzzz/noise/{device_id}", "abc", "abc", &ACME, false).unwrap();
+    assert_eq!(topics.level, "acme/madrid/bzzz/noise/abc");
+    assert_eq!(topics.alerts, "acme/madrid/bzzz/noise/abc/alerts");
+    assert_eq!(
+        topics.outage,
+        "acme/madrid/bzzz/noise/abc/diagnostics/outage"
+    );
+    assert!(topics.legacy.is_none());
+    assert_eq!(
+        topics.metadata,
+        "\"device_id\":\"abc\",\"tenant_id\":\"acme\",\"site_id\":\"madrid\""
+    );
+}
+
+#[test]
+fn template_can_place_the_namespace() {
+    let topics = Topics::new("bzzz/{tenant_id}/{device_id}", "abc", "abc", &ACME, false).unwrap();
+    assert_eq!(topics.level, "bzzz/acme/abc");
+    let topics = Topics::new("{site}/{floor}/{device_id}", "abc", "abc", &ACME, false).unwrap();
+    assert_eq!(topics.level, "acme/madrid/2/abc");
+}
+
+#[test]
+fn migration_keeps_the_flat_topics() {
+    let topics = Topics::new("bzzz/noise/{device_id}", "abc", "abc", &ACME, true).unwrap();
+    let legacy = topics.legacy.unwrap();
+    assert_eq!(legacy.level, "bzzz/noise/abc");
+    assert_eq!(legacy.cmd, "bzzz/noise/abc/cmd");
+    assert_eq!(legacy.config, "bzzz/noise/abc/config");
+    assert_eq!(legacy.moved, "bzzz/noise/abc/moved");
+}
+
+#[test]
+fn rejects_ids_that_break_topics() {
+    for tenant_id in ["a/b", "a+", "#", "a\"b"] {
+        let namespace = Namespace {
+            tenant_id,
+            ..NO_NAMESPACE
+        };
+        assert!(Topics::new("bzzz/{device_id}", "abc", "abc", &namespace, false).is_err());
+    }
+    assert!(Topics::new(
+        "bzzz/{tenant_id}/{device_id}",
+        "abc",
+        "abc",
+        &NO_NAMESPACE,
+        false
+    )
+    .is_err());
+}
+
+#[test]
+fn mac_identifies_the_device() {
+    let topics = Topics::new(
+        "noise/{mac}",
+        "bzzz-0042",
+        "a0b1c2d3e4f5",
+        &NO_NAMESPACE,
+        false,
+    )
+    .unwrap();
+    assert_eq!(topics.level, "noise/a0b1c2d3e4f5");
+    assert!(Topics::new("noise/{floor}", "bzzz-0042", "a0b1c2d3e4f5", &ACME, false).is_err());
+}
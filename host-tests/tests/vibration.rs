@@ -0,0 +1,44 @@
+use mosquitto_bzzz_host_tests::{
+    dsp::{Decibel, RawAdc},
+    vibration::VibrationMeter,
+};
+
+fn feed(meter: &mut VibrationMeter, frequency_hz: f32, amplitude: f32, offset: f32, len: usize) {
+    for i in 0..len {
+        let phase = 2.0 * std::f32::consts::PI * frequency_hz * i as f32 / 100.0;
+        meter.add(RawAdc((offset + amplitude * phase.sin()).round() as u16));
+    }
+}
+
+#[test]
+fn nothing_to_report_without_samples() {
+    assert_eq!(VibrationMeter::default().take(), None);
+}
+
+#[test]
+fn a_steady_offset_is_no_vibration() {
+    let mut meter = VibrationMeter::default();
+    feed(&mut meter, 0.0, 0.0, 1800.0, 200);
+    assert_eq!(meter.take(), Some((Decibel(0.0), Decibel(0.0))));
+}
+
+#[test]
+fn measures_low_frequency_vibration() {
+    let mut meter = VibrationMeter::default();
+    // Let the high-pass settle
+    feed(&mut meter, 25.0, 1000.0, 2048.0, 200);
+    meter.take();
+    feed(&mut meter, 25.0, 1000.0, 2048.0, 100);
+    let (level, peak) = meter.take().unwrap();
+    // 1000 counts peak is 57 dB RMS and 60 dB peak
+    assert!((level.0 - 57.0).abs() < 0.5, "{}", level);
+    assert!((peak.0 - 60.0).abs() < 0.5, "{}", peak);
+}
+
+#[test]
+fn slow_drift_is_filtered_out() {
+    let mut meter = VibrationMeter::default();
+    feed(&mut meter, 0.05, 1000.0, 2048.0, 400);
+    let (level, _) = meter.take().unwrap();
+    assert!(level.0 < 40.0, "{}", level);
+}
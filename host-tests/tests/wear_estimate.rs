@@ -0,0 +1,29 @@
+use mosquitto_bzzz_host_tests::wear_estimate::{nvs_entries, WearEstimate};
+
+#[test]
+fn nvs_values_take_a_header_and_their_data() {
+    assert_eq!(nvs_entries(0), 1);
+    assert_eq!(nvs_entries(1), 2);
+    assert_eq!(nvs_entries(32), 2);
+    assert_eq!(nvs_entries(33), 3);
+}
+
+#[test]
+fn cycles_count_how_often_the_partition_was_filled() {
+    let estimate = WearEstimate {
+        written: 1512,
+        capacity: 756,
+    };
+    assert_eq!(estimate.cycles(), 2.0);
+    assert_eq!(estimate.used_pct(100), 2.0);
+    assert_eq!(estimate.used_pct(0), 200.0);
+}
+
+#[test]
+fn unknown_capacity_reads_as_unworn() {
+    let estimate = WearEstimate {
+        written: 10,
+        capacity: 0,
+    };
+    assert_eq!(estimate.cycles(), 0.0);
+}
@@ -0,0 +1,66 @@
+use std::f32::consts::PI;
+
+use mosquitto_bzzz_host_tests::{
+    dsp::{self, RawAdc},
+    weighting::{Weighting, WeightingFilter},
+};
+
+const SAMPLE_RATE_HZ: f32 = 16_000.0;
+
+// A 100 ms window around mid-scale, 1000 counts of amplitude are 57.0 dB
+fn sine(frequency_hz: f32) -> Vec<RawAdc> {
+    (0..1600)
+        .map(|n| {
+            let phase = 2.0 * PI * frequency_hz * n as f32 / SAMPLE_RATE_HZ;
+            RawAdc((2048.0 + 1000.0 * phase.sin()).round() as u16)
+        })
+        .collect()
+}
+
+fn weighted(weighting: Weighting, frequency_hz: f32) -> f32 {
+    WeightingFilter::new(weighting, SAMPLE_RATE_HZ)
+        .level(&sine(frequency_hz))
+        .0
+        - 57.0
+}
+
+#[test]
+fn curves_are_zero_at_one_kilohertz() {
+    assert!(weighted(Weighting::A, 1000.0).abs() < 0.3);
+    assert!(weighted(Weighting::C, 1000.0).abs() < 0.3);
+}
+
+#[test]
+fn a_weighting_follows_the_standard_curve() {
+    for (frequency_hz, expected_db) in [(100.0, -19.1), (250.0, -8.6), (2000.0, 1.2)] {
+        let level = weighted(Weighting::A, frequency_hz);
+        assert!(
+            (level - expected_db).abs() < 1.0,
+            "{} Hz: {} dB",
+            frequency_hz,
+            level
+        );
+    }
+}
+
+#[test]
+fn c_weighting_only_drops_the_extremes() {
+    assert!((weighted(Weighting::C, 100.0) + 0.3).abs() < 0.5);
+    assert!((weighted(Weighting::C, 31.5) + 3.0).abs() < 1.0);
+}
+
+#[test]
+fn z_is_the_raw_level() {
+    let samples = sine(440.0);
+    assert_eq!(
+        WeightingFilter::new(Weighting::Z, SAMPLE_RATE_HZ).level(&samples),
+        dsp::rms_to_db(&samples)
+    );
+}
+
+#[test]
+fn parses_the_config_values() {
+    assert_eq!(Weighting::parse("A"), Some(Weighting::A));
+    assert_eq!(Weighting::parse("a"), None);
+    assert_eq!(Weighting::C.as_str(), "C");
+}
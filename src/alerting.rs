@@ -0,0 +1,264 @@
+mod burst;
+mod rule;
+mod thresholds;
+
+use std::{collections::HashMap, time::Instant};
+
+use esp_idf_svc::{
+    mqtt::client::{EspMqttClient, MessageId, QoS},
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::EspError,
+};
+
+use crate::{
+    bus::{self, BusEvent},
+    clock,
+    dsp::Decibel,
+    maintenance,
+    mqtt5::Publish,
+    payload_log::{self, Module},
+    reporting, sealing, signing, wear,
+};
+
+pub use burst::AlertBurst;
+pub use rule::AlertRule;
+pub use thresholds::{Limits, ThresholdWatch, Thresholds};
+
+const NVS_ALERTS_NAMESPACE: &str = "bzzz_alerts";
+const NVS_JOURNAL_KEY: &str = "journal";
+const NVS_NEXT_SEQ_KEY: &str = "next_seq";
+// Keeps the blob well below the NVS page size
+const MAX_JOURNAL_ENTRIES: usize = 16;
+
+struct Alert {
+    seq: u32,
+    kind: String,
+    level: f32,
+    timestamp: Option<u64>,
+    replayed: bool,
+    // Extra JSON fields, e.g. `"priority":"high"`. Last in the journal line, it may hold commas.
+    details: String,
+}
+
+impl Alert {
+    fn to_json(&self) -> String {
+        let timestamp = self
+            .timestamp
+            .map_or_else(|| String::from("null"), |ts| ts.to_string());
+        let details = if self.details.is_empty() {
+            String::new()
+        } else {
+            format!(",{}", self.details)
+        };
+        format!(
+            "{{\"seq\":{},\"alert\":\"{}\",\"level\":{},\"timestamp\":{},\"replayed\":{}{}}}",
+            self.seq, self.kind, self.level, timestamp, self.replayed, details
+        )
+    }
+
+    fn to_journal_line(&self) -> String {
+        let timestamp = self.timestamp.map_or_else(String::new, |ts| ts.to_string());
+        format!(
+            "{},{},{},{},{}\n",
+            self.seq, timestamp, self.kind, self.level, self.details
+        )
+    }
+
+    fn from_journal_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(5, ',');
+        Some(Alert {
+            seq: fields.next()?.parse().ok()?,
+            timestamp: fields.next()?.parse().ok(),
+            kind: fields.next()?.to_string(),
+            level: fields.next()?.parse().ok()?,
+            replayed: true,
+            // Journals written before details existed end after the level
+            details: fields.next().unwrap_or("").to_string(),
+        })
+    }
+}
+
+// Alerts stay in the NVS journal until the broker acknowledges them (QoS 1), so that alerts raised
+// while offline or right before a reboot are delivered once the connection is back.
+pub struct AlertJournal {
+    nvs: EspNvs<NvsDefault>,
+    alerts: Vec<Alert>,
+    in_flight: HashMap<MessageId, u32>,
+    next_seq: u32,
+    // Acknowledgements not written yet while flash wear is throttled, a reboot just replays them
+    unsaved: bool,
+}
+
+impl AlertJournal {
+    pub fn new(nvs_partition: EspDefaultNvsPartition) -> Result<Self, EspError> {
+        let nvs = EspNvs::new(nvs_partition, NVS_ALERTS_NAMESPACE, true)?;
+        let mut buffer = vec![0u8; nvs.blob_len(NVS_JOURNAL_KEY)?.unwrap_or(0)];
+        let alerts: Vec<Alert> = match nvs.get_blob(NVS_JOURNAL_KEY, &mut buffer)? {
+            Some(blob) => String::from_utf8_lossy(blob)
+                .lines()
+                .filter_map(Alert::from_journal_line)
+                .collect(),
+            None => vec![],
+        };
+        if !alerts.is_empty() {
+            log::info!("{} unacknowledged alerts in journal", alerts.len());
+        }
+        let next_seq = nvs.get_u32(NVS_NEXT_SEQ_KEY)?.unwrap_or(0);
+        Ok(AlertJournal {
+            nvs,
+            alerts,
+            in_flight: HashMap::new(),
+            next_seq,
+            unsaved: false,
+        })
+    }
+
+    // `history` has the levels that go into the alert from before it was raised
+    pub fn raise(
+        &mut self,
+        mqtt_client: &mut EspMqttClient,
+        alerts_topic: &str,
+        history: &AlertBurst,
+        kind: &str,
+        level: Decibel,
+        timestamp: Option<u64>,
+    ) -> Option<u32> {
+        self.raise_with_details(
+            mqtt_client,
+            alerts_topic,
+            history,
+            kind,
+            level,
+            timestamp,
+            String::new(),
+        )
+    }
+
+    // `details` are extra JSON fields for the alert payload, without the surrounding braces.
+    // Returns the sequence number of the alert, unless maintenance mode held it back.
+    #[allow(clippy::too_many_arguments)]
+    pub fn raise_with_details(
+        &mut self,
+        mqtt_client: &mut EspMqttClient,
+        alerts_topic: &str,
+        history: &AlertBurst,
+        kind: &str,
+        level: Decibel,
+        timestamp: Option<u64>,
+        details: String,
+    ) -> Option<u32> {
+        if maintenance::in_mode() {
+            log::info!("Maintenance mode, not raising {} alert", kind);
+            return None;
+        }
+        if self.alerts.len() == MAX_JOURNAL_ENTRIES {
+            let dropped = self.alerts.remove(0);
+            log::warn!("Alert journal full, dropping alert {}", dropped.seq);
+        }
+        let context = history.context_fields(Instant::now());
+        // Kept in the journal, so replays still tell how good the timestamp was
+        let time_source = reporting::broker_time_fields(
+            clock::broker_accuracy()
+                .filter(|_| timestamp.is_some())
+                .map(reporting::accuracy_s),
+        );
+        let details = [details, context, time_source]
+            .into_iter()
+            .filter(|fields| !fields.is_empty())
+            .collect::<Vec<_>>()
+            .join(",");
+        let seq = self.next_seq;
+        let alert = Alert {
+            seq,
+            kind: kind.to_string(),
+            level: level.0,
+            timestamp,
+            replayed: false,
+            details,
+        };
+        self.next_seq = self.next_seq.wrapping_add(1);
+        match self.nvs.set_u32(NVS_NEXT_SEQ_KEY, self.next_seq) {
+            Ok(_) => wear::nvs_written(0),
+            Err(err) => log::error!("Unable to persist alert sequence: {}", err),
+        }
+        bus::post(BusEvent::AlertRaised {
+            seq: alert.seq,
+            level,
+        });
+        if let Ok(msg_id) = publish_alert(mqtt_client, alerts_topic, &alert) {
+            self.in_flight.insert(msg_id, alert.seq);
+        }
+        self.alerts.push(alert);
+        self.persist();
+        Some(seq)
+    }
+
+    // Everything not acknowledged yet is sent again, because PUBACKs from a previous session
+    // are never going to arrive.
+    pub fn replay(&mut self, mqtt_client: &mut EspMqttClient, alerts_topic: &str) {
+        self.in_flight.clear();
+        for alert in self.alerts.iter_mut() {
+            alert.replayed = true;
+            if let Ok(msg_id) = publish_alert(mqtt_client, alerts_topic, alert) {
+                self.in_flight.insert(msg_id, alert.seq);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.alerts.len()
+    }
+
+    pub fn has_in_flight(&self) -> bool {
+        !self.in_flight.is_empty()
+    }
+
+    pub fn acknowledge(&mut self, msg_id: MessageId) {
+        if let Some(seq) = self.in_flight.remove(&msg_id) {
+            self.alerts.retain(|alert| alert.seq != seq);
+            if wear::throttled() {
+                self.unsaved = true;
+            } else {
+                self.persist();
+            }
+        }
+    }
+
+    // Before a restart, writes what throttling held back
+    pub fn flush(&mut self) {
+        if self.unsaved {
+            self.persist();
+        }
+    }
+
+    fn persist(&mut self) {
+        let journal: String = self.alerts.iter().map(Alert::to_journal_line).collect();
+        match self.nvs.set_blob(NVS_JOURNAL_KEY, journal.as_bytes()) {
+            Ok(_) => {
+                self.unsaved = false;
+                wear::nvs_written(journal.len());
+            }
+            Err(err) => log::error!("Unable to persist alert journal: {}", err),
+        }
+    }
+}
+
+fn publish_alert(
+    mqtt_client: &mut EspMqttClient,
+    alerts_topic: &str,
+    alert: &Alert,
+) -> Result<MessageId, EspError> {
+    let payload = signing::sign(alerts_topic, alert.to_json());
+    payload_log::dump(Module::Alerts, alerts_topic, payload.as_bytes());
+    mqtt_client
+        .publish_tagged(
+            alerts_topic,
+            QoS::AtLeastOnce,
+            false,
+            &sealing::seal(alerts_topic, payload.as_bytes()),
+        )
+        .map_err(|err| {
+            log::error!("Unable to publish alert {}: {}", alert.seq, err);
+            err
+        })
+}
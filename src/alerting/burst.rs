@@ -0,0 +1,169 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::dsp::Decibel;
+
+struct Capture {
+    seq: u32,
+    alert: String,
+    // Unix time of the trigger, if the clock was set
+    ts: Option<u64>,
+    triggered: Instant,
+}
+
+// Keeps the last `window` of levels at `rate_hz` and, once an alert triggers a capture, collects
+// as much again before handing out the whole series. Alerts only carry their peak and the last
+// `context` of the same levels, the series shows how the event built up and faded.
+pub struct AlertBurst {
+    window: Duration,
+    context: Duration,
+    rate_hz: u32,
+    period: Duration,
+    levels: VecDeque<(Instant, Decibel)>,
+    capture: Option<Capture>,
+}
+
+impl AlertBurst {
+    pub fn new(window: Duration, rate_hz: u32) -> Self {
+        let mut burst = AlertBurst {
+            window: Duration::ZERO,
+            context: Duration::ZERO,
+            rate_hz: 1,
+            period: Duration::ZERO,
+            levels: VecDeque::new(),
+            capture: None,
+        };
+        burst.configure(window, rate_hz);
+        burst
+    }
+
+    // A zero window turns captures off, and frees the history without a context either
+    pub fn configure(&mut self, window: Duration, rate_hz: u32) {
+        self.window = window;
+        self.rate_hz = rate_hz.max(1);
+        self.period = Duration::from_secs(1) / self.rate_hz;
+        if window.is_zero() {
+            self.capture = None;
+        }
+        self.free_unused();
+    }
+
+    // How much of the history goes into every alert, none with a zero context
+    pub fn configure_context(&mut self, context: Duration) {
+        self.context = context;
+        self.free_unused();
+    }
+
+    fn free_unused(&mut self) {
+        if self.window.is_zero() && self.context.is_zero() {
+            self.levels = VecDeque::new();
+        }
+    }
+
+    // Every level as it is measured, faster ones are skipped down to the rate. Returns the series
+    // once a capture is complete.
+    pub fn add(&mut self, level: Decibel, now: Instant) -> Option<String> {
+        if self.window.is_zero() && self.context.is_zero() {
+            return None;
+        }
+        let due = self
+            .levels
+            .back()
+            .map_or(true, |(last, _)| now.duration_since(*last) >= self.period);
+        if due {
+            self.levels.push_back((now, level));
+        }
+        let before_capture = match self.capture.as_ref() {
+            Some(capture) => capture.triggered.checked_sub(self.window),
+            None => now.checked_sub(self.window),
+        };
+        let oldest_kept = before_capture.min(now.checked_sub(self.context));
+        if let Some(oldest_kept) = oldest_kept {
+            while self
+                .levels
+                .front()
+                .is_some_and(|(taken, _)| *taken < oldest_kept)
+            {
+                self.levels.pop_front();
+            }
+        }
+        let triggered = self.capture.as_ref()?.triggered;
+        if now.duration_since(triggered) < self.window {
+            return None;
+        }
+        let capture = self.capture.take()?;
+        Some(self.series(&capture))
+    }
+
+    // Alerts during a capture are part of the same event
+    pub fn trigger(&mut self, seq: u32, alert: &str, ts: Option<u64>, now: Instant) {
+        if self.window.is_zero() || self.capture.is_some() {
+            return;
+        }
+        self.capture = Some(Capture {
+            seq,
+            alert: alert.to_string(),
+            ts,
+            triggered: now,
+        });
+    }
+
+    // Extra alert fields with the levels of the last `context`, oldest first. Empty when off.
+    pub fn context_fields(&self, now: Instant) -> String {
+        if self.context.is_zero() {
+            return String::new();
+        }
+        let oldest = now.checked_sub(self.context);
+        let levels: Vec<String> = self
+            .levels
+            .iter()
+            .filter(|(taken, _)| oldest.map_or(true, |oldest| *taken > oldest))
+            .map(|(_, level)| format!("{:.1}", level.0))
+            .collect();
+        format!(
+            "\"context_step_ms\":{},\"context_db\":[{}]",
+            self.period.as_millis(),
+            levels.join(",")
+        )
+    }
+
+    // Offsets in milliseconds from the trigger, negative before it
+    fn series(&self, capture: &Capture) -> String {
+        // The history may reach further back for the context
+        let oldest = capture.triggered.checked_sub(self.window);
+        let levels: Vec<&(Instant, Decibel)> = self
+            .levels
+            .iter()
+            .filter(|(taken, _)| oldest.map_or(true, |oldest| *taken >= oldest))
+            .collect();
+        let offsets: Vec<String> = levels
+            .iter()
+            .map(|(taken, _)| {
+                let offset = if *taken >= capture.triggered {
+                    taken.duration_since(capture.triggered).as_millis() as i64
+                } else {
+                    -(capture.triggered.duration_since(*taken).as_millis() as i64)
+                };
+                offset.to_string()
+            })
+            .collect();
+        let levels: Vec<String> = levels
+            .iter()
+            .map(|(_, level)| format!("{:.1}", level.0))
+            .collect();
+        let ts = capture
+            .ts
+            .map_or_else(|| String::from("null"), |ts| ts.to_string());
+        format!(
+            "{{\"seq\":{},\"alert\":\"{}\",\"ts\":{},\"rate_hz\":{},\"offset_ms\":[{}],\"db\":[{}]}}",
+            capture.seq,
+            capture.alert,
+            ts,
+            self.rate_hz,
+            offsets.join(","),
+            levels.join(",")
+        )
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use crate::dsp::Decibel;
+
+// Raises once the level stayed at or above `trigger` for `trigger_dwell`, and re-arms only once
+// it stayed below `clear` for `clear_dwell`. The gap between the thresholds and the dwell times
+// keep a level hovering at the limit from raising alert after alert.
+pub struct AlertRule {
+    trigger: Decibel,
+    clear: Decibel,
+    trigger_dwell: Duration,
+    clear_dwell: Duration,
+    active: bool,
+    // When the level first crossed towards the other state
+    since: Option<Instant>,
+}
+
+impl AlertRule {
+    pub fn new(
+        trigger: Decibel,
+        clear: Decibel,
+        trigger_dwell: Duration,
+        clear_dwell: Duration,
+    ) -> Self {
+        AlertRule {
+            trigger,
+            clear,
+            trigger_dwell,
+            clear_dwell,
+            active: false,
+            since: None,
+        }
+    }
+
+    // Keeps whether the alert is active, so a change doesn't raise it again
+    pub fn configure(
+        &mut self,
+        trigger: Decibel,
+        clear: Decibel,
+        trigger_dwell: Duration,
+        clear_dwell: Duration,
+    ) {
+        self.trigger = trigger;
+        self.clear = clear;
+        self.trigger_dwell = trigger_dwell;
+        self.clear_dwell = clear_dwell;
+    }
+
+    // True when the alert is to be raised
+    pub fn update(&mut self, level: Decibel, now: Instant) -> bool {
+        let crossing = if self.active {
+            level < self.clear
+        } else {
+            level >= self.trigger
+        };
+        if !crossing {
+            self.since = None;
+            return false;
+        }
+        let since = *self.since.get_or_insert(now);
+        let dwell = if self.active {
+            self.clear_dwell
+        } else {
+            self.trigger_dwell
+        };
+        if now.duration_since(since) < dwell {
+            return false;
+        }
+        self.since = None;
+        self.active = !self.active;
+        if !self.active {
+            log::info!("Alert cleared at {} dB", level);
+        }
+        self.active
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use crate::{
+    config::{Config, ConfigStore, ConfigWatch},
+    dsp::Decibel,
+};
+
+// Where a channel's classes start and when its alert raises and clears
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    pub normal_from: Decibel,
+    pub loud_from: Decibel,
+    pub very_loud_from: Decibel,
+    pub alert_trigger: Decibel,
+    pub alert_clear: Decibel,
+    pub alert_trigger_dwell: Duration,
+    pub alert_clear_dwell: Duration,
+}
+
+impl Limits {
+    // Every level shifted, e.g. lowered at night by the outdoor profile
+    pub fn offset(&self, offset: f32) -> Self {
+        Limits {
+            normal_from: self.normal_from + offset,
+            loud_from: self.loud_from + offset,
+            very_loud_from: self.very_loud_from + offset,
+            alert_trigger: self.alert_trigger + offset,
+            alert_clear: self.alert_clear + offset,
+            ..*self
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thresholds {
+    pub level: Limits,
+    pub vibration: Limits,
+    pub hysteresis_db: f32,
+}
+
+impl Thresholds {
+    pub fn of(config: &Config) -> Self {
+        Thresholds {
+            level: Limits {
+                normal_from: Decibel(config.normal_from_db),
+                loud_from: Decibel(config.loud_from_db),
+                very_loud_from: Decibel(config.very_loud_from_db),
+                alert_trigger: Decibel(config.alert_trigger_db),
+                alert_clear: Decibel(config.alert_clear_db),
+                alert_trigger_dwell: Duration::from_secs(config.alert_trigger_s.into()),
+                alert_clear_dwell: Duration::from_secs(config.alert_clear_s.into()),
+            },
+            vibration: Limits {
+                normal_from: Decibel(config.vibration_normal_from_db),
+                loud_from: Decibel(config.vibration_loud_from_db),
+                very_loud_from: Decibel(config.vibration_very_loud_from_db),
+                alert_trigger: Decibel(config.vibration_alert_trigger_db),
+                alert_clear: Decibel(config.vibration_alert_clear_db),
+                alert_trigger_dwell: Duration::from_secs(config.vibration_alert_trigger_s.into()),
+                alert_clear_dwell: Duration::from_secs(config.vibration_alert_clear_s.into()),
+            },
+            hysteresis_db: config.class_hysteresis_db,
+        }
+    }
+}
+
+// Follows the shared settings, which the config topic updates from the MQTT callback, so the
+// sensor loop can check before every evaluation and new thresholds apply to the very next one
+pub struct ThresholdWatch {
+    config: ConfigWatch,
+    current: Thresholds,
+}
+
+impl ThresholdWatch {
+    pub fn new(store: &ConfigStore) -> Self {
+        ThresholdWatch {
+            config: store.subscribe(),
+            current: Thresholds::of(&store.get()),
+        }
+    }
+
+    pub fn current(&self) -> Thresholds {
+        self.current
+    }
+
+    // Returns the thresholds only when a change since the last call touched them
+    pub fn changed(&mut self) -> Option<Thresholds> {
+        let updated = Thresholds::of(&self.config.changed()?);
+        if updated == self.current {
+            return None;
+        }
+        self.current = updated;
+        Some(updated)
+    }
+}
@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use esp_idf_svc::{
+    http::{
+        client::{Configuration as HttpConfiguration, EspHttpConnection},
+        Method,
+    },
+    sys::{
+        esp_crt_bundle_attach, mbedtls_md_hmac, mbedtls_md_info_from_type,
+        mbedtls_md_type_t_MBEDTLS_MD_SHA256,
+    },
+};
+
+use crate::{clock, encoding::base64url};
+
+// Leaves time to reconnect with the new token before the broker drops the session
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+const MAX_TOKEN_LEN: usize = 2048;
+
+// Source of short-lived passwords for brokers that authenticate with tokens instead of static
+// credentials. Returns the token and how long it stays valid.
+pub trait TokenProvider {
+    fn token(&mut self) -> Result<(String, Duration)>;
+}
+
+// Returns a fresh token and when to replace it
+pub fn next_token(provider: &mut dyn TokenProvider) -> Result<(String, Instant)> {
+    let (token, valid_for) = provider.token()?;
+    let refresh_in = valid_for.saturating_sub(REFRESH_MARGIN).max(valid_for / 2);
+    log::info!("Got MQTT token, refreshing it in {:?}", refresh_in);
+    Ok((token, Instant::now() + refresh_in))
+}
+
+// HS256 JWT signed on the device with a key embedded in the configuration
+pub struct SignedJwt {
+    key: &'static str,
+    audience: &'static str,
+    subject: String,
+    lifetime: Duration,
+}
+
+impl SignedJwt {
+    pub fn new(
+        key: &'static str,
+        audience: &'static str,
+        subject: &str,
+        lifetime: Duration,
+    ) -> Self {
+        SignedJwt {
+            key,
+            audience,
+            subject: subject.to_string(),
+            lifetime,
+        }
+    }
+}
+
+impl TokenProvider for SignedJwt {
+    fn token(&mut self) -> Result<(String, Duration)> {
+        if self.key.is_empty() {
+            bail!("No JWT signing key configured");
+        }
+        // Issued/expiry times are meaningless until SNTP has set the clock
+        let Some(now) = clock::unix_time() else {
+            bail!("Clock not synchronized yet");
+        };
+        let header = base64url(br#"{"alg":"HS256","typ":"JWT"}"#);
+        let claims = base64url(
+            format!(
+                "{{\"sub\":\"{}\",\"aud\":\"{}\",\"iat\":{},\"exp\":{}}}",
+                self.subject,
+                self.audience,
+                now,
+                now + self.lifetime.as_secs()
+            )
+            .as_bytes(),
+        );
+        let signing_input = format!("{}.{}", header, claims);
+        let mut signature = [0u8; 32];
+        let result = unsafe {
+            mbedtls_md_hmac(
+                mbedtls_md_info_from_type(mbedtls_md_type_t_MBEDTLS_MD_SHA256),
+                self.key.as_ptr(),
+                self.key.len(),
+                signing_input.as_ptr(),
+                signing_input.len(),
+                signature.as_mut_ptr(),
+            )
+        };
+        if result != 0 {
+            bail!("Unable to sign JWT: mbedTLS error {}", result);
+        }
+        Ok((
+            format!("{}.{}", signing_input, base64url(&signature)),
+            self.lifetime,
+        ))
+    }
+}
+
+// Token issued by a backend, returned as the plain body of a GET over HTTPS
+pub struct FetchedToken {
+    url: &'static str,
+    lifetime: Duration,
+}
+
+impl FetchedToken {
+    pub fn new(url: &'static str, lifetime: Duration) -> Self {
+        FetchedToken { url, lifetime }
+    }
+}
+
+impl TokenProvider for FetchedToken {
+    fn token(&mut self) -> Result<(String, Duration)> {
+        if self.url.is_empty() {
+            bail!("No token URL configured");
+        }
+        let mut connection = EspHttpConnection::new(&HttpConfiguration {
+            crt_bundle_attach: Some(esp_crt_bundle_attach),
+            ..Default::default()
+        })
+        .context("Unable to create HTTPS connection")?;
+        connection
+            .initiate_request(Method::Get, self.url, &[("Accept", "text/plain")])
+            .context("Unable to request token")?;
+        connection
+            .initiate_response()
+            .context("No response from token endpoint")?;
+        if connection.status() != 200 {
+            bail!("Token endpoint answered {}", connection.status());
+        }
+        let mut body = vec![0u8; MAX_TOKEN_LEN];
+        let mut len = 0;
+        while len < body.len() {
+            match connection.read(&mut body[len..])? {
+                0 => break,
+                read => len += read,
+            }
+        }
+        let token = std::str::from_utf8(&body[..len])
+            .context("Token is not valid UTF-8")?
+            .trim();
+        if token.is_empty() {
+            bail!("Empty token");
+        }
+        Ok((token.to_string(), self.lifetime))
+    }
+}
@@ -0,0 +1,72 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use esp_idf_svc::hal::gpio::{AnyOutputPin, Output, PinDriver};
+
+use crate::rules::Action;
+
+// Set by rules, the LED thread shows it instead of the status sequence
+static LED_COLOR: Mutex<Option<[u8; 3]>> = Mutex::new(None);
+
+pub fn led_color() -> Option<[u8; 3]> {
+    *LED_COLOR.lock().unwrap()
+}
+
+// Carries out the actions of local rules that touch hardware. Pins are claimed on first use, the
+// rules only accept pins nothing else on the board drives.
+#[derive(Default)]
+pub struct Actuators {
+    outputs: HashMap<u8, PinDriver<'static, AnyOutputPin, Output>>,
+    // Buzzers to turn off again, checked every loop, which is about 50 ms
+    buzzers: Vec<(u8, Instant)>,
+}
+
+impl Actuators {
+    pub fn run(&mut self, action: &Action) {
+        match action {
+            Action::Gpio { pin, high } => self.set(*pin, *high),
+            Action::Led(color) => *LED_COLOR.lock().unwrap() = *color,
+            Action::Buzzer { pin, ms } => {
+                self.set(*pin, true);
+                self.buzzers.retain(|(buzzer, _)| buzzer != pin);
+                self.buzzers
+                    .push((*pin, Instant::now() + Duration::from_millis((*ms).into())));
+            }
+            // Needs the MQTT client, see the level loop
+            Action::Publish(_) => {}
+        }
+    }
+
+    pub fn poll(&mut self) {
+        let now = Instant::now();
+        let (done, pending) = std::mem::take(&mut self.buzzers)
+            .into_iter()
+            .partition(|(_, until)| now >= *until);
+        self.buzzers = pending;
+        for (pin, _) in done {
+            self.set(pin, false);
+        }
+    }
+
+    fn set(&mut self, pin: u8, high: bool) {
+        let output = match self.outputs.entry(pin) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                // Only reserved pins are in use elsewhere, and the rules reject those
+                match PinDriver::output(unsafe { AnyOutputPin::new(pin.into()) }) {
+                    Ok(output) => entry.insert(output),
+                    Err(err) => {
+                        log::error!("Unable to drive GPIO{}: {}", pin, err);
+                        return;
+                    }
+                }
+            }
+        };
+        if let Err(err) = output.set_level(high.into()) {
+            log::error!("Unable to set GPIO{}: {}", pin, err);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::time::{Duration, Instant};
+
+use crate::dsp::Decibel;
+
+pub const MIN_HOURS: u32 = 24;
+pub const MAX_HOURS: u32 = 48;
+// Half dB bins from the default plausibility floor to well above calibrated levels, 1 KiB of
+// counts
+const MIN_DB: f32 = -10.0;
+const BIN_DB: f32 = 0.5;
+const BINS: usize = 260;
+// A few minutes of levels, anything less says nothing about the room
+const MIN_LEVELS: u64 = 1000;
+// Above the percentile a threshold derives from, and between two thresholds at the least
+const MARGIN_DB: f32 = 3.0;
+// Like the default 50 and 47 dB
+const ALERT_CLEAR_BELOW_DB: f32 = 3.0;
+// 2 since levels leave out the DC bias, histograms from before are on another scale
+const STORED_VERSION: u8 = 2;
+
+// How often each level occurred, which is all percentiles need
+struct Histogram {
+    bins: Vec<u32>,
+    count: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            bins: vec![0; BINS],
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, level: Decibel) {
+        if !level.0.is_finite() {
+            return;
+        }
+        let bin = (((level.0 - MIN_DB).max(0.0) / BIN_DB) as usize).min(BINS - 1);
+        self.bins[bin] = self.bins[bin].saturating_add(1);
+        self.count += 1;
+    }
+
+    // The level exceeded `pct` percent of the time, the upper edge of its bin
+    fn exceeded(&self, pct: f32) -> Decibel {
+        let above = (self.count as f64 * f64::from(pct) / 100.0) as u64;
+        let mut seen = 0u64;
+        for (bin, count) in self.bins.iter().enumerate().rev() {
+            seen += u64::from(*count);
+            if seen > above {
+                return Decibel(MIN_DB + (bin + 1) as f32 * BIN_DB);
+            }
+        }
+        Decibel(MIN_DB)
+    }
+}
+
+// Thresholds for a room, from what it sounded like while it was observed: its background L90
+// stays quiet, what the L10 exceeds is loud and what even the L1 exceeds is very loud and alerts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Proposal {
+    pub l90: Decibel,
+    pub l50: Decibel,
+    pub l10: Decibel,
+    pub l1: Decibel,
+    pub normal_from_db: f32,
+    pub loud_from_db: f32,
+    pub very_loud_from_db: f32,
+    pub alert_trigger_db: f32,
+    pub alert_clear_db: f32,
+}
+
+impl Proposal {
+    fn from_histogram(histogram: &Histogram) -> Option<Self> {
+        if histogram.count < MIN_LEVELS {
+            return None;
+        }
+        let (l90, l50, l10, l1) = (
+            histogram.exceeded(90.0),
+            histogram.exceeded(50.0),
+            histogram.exceeded(10.0),
+            histogram.exceeded(1.0),
+        );
+        let normal_from_db = l90.0 + MARGIN_DB;
+        let loud_from_db = (l10.0 + MARGIN_DB).max(normal_from_db + MARGIN_DB);
+        let very_loud_from_db = (l1.0 + MARGIN_DB).max(loud_from_db + MARGIN_DB);
+        Some(Proposal {
+            l90,
+            l50,
+            l10,
+            l1,
+            normal_from_db,
+            loud_from_db,
+            very_loud_from_db,
+            alert_trigger_db: very_loud_from_db,
+            alert_clear_db: very_loud_from_db - ALERT_CLEAR_BELOW_DB,
+        })
+    }
+
+    // For the configuration, which validates and persists them like any other update
+    pub fn to_settings(self) -> String {
+        format!(
+            "normal_from_db={} loud_from_db={} very_loud_from_db={} alert_trigger_db={} alert_clear_db={}",
+            self.normal_from_db,
+            self.loud_from_db,
+            self.very_loud_from_db,
+            self.alert_trigger_db,
+            self.alert_clear_db
+        )
+    }
+}
+
+// Observes a site for a day or two. Survives restarts through `to_stored`, counting only the
+// time it was running.
+pub struct AutoTune {
+    duration: Duration,
+    observed_before: Duration,
+    resumed: Instant,
+    histogram: Histogram,
+}
+
+impl AutoTune {
+    pub fn new(hours: u32, now: Instant) -> Self {
+        AutoTune {
+            duration: Duration::from_secs(u64::from(hours.clamp(MIN_HOURS, MAX_HOURS)) * 3600),
+            observed_before: Duration::ZERO,
+            resumed: now,
+            histogram: Histogram::new(),
+        }
+    }
+
+    pub fn add(&mut self, level: Decibel) {
+        self.histogram.add(level);
+    }
+
+    pub fn observed(&self, now: Instant) -> Duration {
+        self.observed_before + now.duration_since(self.resumed)
+    }
+
+    pub fn is_complete(&self, now: Instant) -> bool {
+        self.observed(now) >= self.duration
+    }
+
+    // Once observed long enough and with enough levels
+    pub fn proposal(&self, now: Instant) -> Option<Proposal> {
+        if !self.is_complete(now) {
+            return None;
+        }
+        Proposal::from_histogram(&self.histogram)
+    }
+
+    pub fn to_json(&self, now: Instant) -> String {
+        let hours = |duration: Duration| duration.as_secs_f32() / 3600.0;
+        let observed = self.observed(now).min(self.duration);
+        let progress = format!(
+            "\"observed_h\":{:.1},\"duration_h\":{:.0},\"levels\":{}",
+            hours(observed),
+            hours(self.duration),
+            self.histogram.count
+        );
+        if !self.is_complete(now) {
+            return format!("{{\"state\":\"observing\",{}}}", progress);
+        }
+        let Some(proposal) = self.proposal(now) else {
+            return format!("{{\"state\":\"insufficient\",{}}}", progress);
+        };
+        format!(
+            "{{\"state\":\"proposed\",{},\"percentiles\":{{\"l90\":{:.1},\"l50\":{:.1},\"l10\":{:.1},\"l1\":{:.1}}},\"thresholds\":{{\"normal_from_db\":{:.1},\"loud_from_db\":{:.1},\"very_loud_from_db\":{:.1},\"alert_trigger_db\":{:.1},\"alert_clear_db\":{:.1}}}}}",
+            progress,
+            proposal.l90.0,
+            proposal.l50.0,
+            proposal.l10.0,
+            proposal.l1.0,
+            proposal.normal_from_db,
+            proposal.loud_from_db,
+            proposal.very_loud_from_db,
+            proposal.alert_trigger_db,
+            proposal.alert_clear_db
+        )
+    }
+
+    // Version, duration and observed seconds, then the bins, all little-endian
+    pub fn to_stored(&self, now: Instant) -> Vec<u8> {
+        let mut stored = Vec::with_capacity(9 + 4 * BINS);
+        stored.push(STORED_VERSION);
+        stored.extend_from_slice(&(self.duration.as_secs() as u32).to_le_bytes());
+        stored.extend_from_slice(&(self.observed(now).as_secs() as u32).to_le_bytes());
+        for count in &self.histogram.bins {
+            stored.extend_from_slice(&count.to_le_bytes());
+        }
+        stored
+    }
+
+    pub fn from_stored(stored: &[u8], now: Instant) -> Option<Self> {
+        if stored.len() != 9 + 4 * BINS || stored[0] != STORED_VERSION {
+            return None;
+        }
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([
+                stored[offset],
+                stored[offset + 1],
+                stored[offset + 2],
+                stored[offset + 3],
+            ])
+        };
+        let bins: Vec<u32> = (0..BINS).map(|bin| u32_at(9 + 4 * bin)).collect();
+        Some(AutoTune {
+            duration: Duration::from_secs(u32_at(1).into()),
+            observed_before: Duration::from_secs(u32_at(5).into()),
+            resumed: now,
+            histogram: Histogram {
+                count: bins.iter().map(|count| u64::from(*count)).sum(),
+                bins,
+            },
+        })
+    }
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+// How a retry loop waits between attempts. `jitter` is the fraction of each wait that is
+// randomized either way, so a fleet coming back from a site outage doesn't retry in lockstep.
+#[derive(Clone, Copy, Debug)]
+pub struct BackoffPolicy {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: u32,
+    pub jitter: f32,
+}
+
+pub struct Backoff {
+    policy: BackoffPolicy,
+    current: Duration,
+    // xorshift32, plenty for spreading retries and never zero
+    rng: u32,
+}
+
+impl Backoff {
+    // Seed with something that differs between devices, e.g. `esp_random()`
+    pub fn new(policy: BackoffPolicy, seed: u32) -> Self {
+        Backoff {
+            policy,
+            current: policy.initial,
+            rng: seed | 1,
+        }
+    }
+
+    // The wait before the next attempt, each one longer up to the maximum
+    pub fn next_delay(&mut self) -> Duration {
+        let base = self.current;
+        self.current = (self.current * self.policy.multiplier).min(self.policy.max);
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        let spread = self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0;
+        base.mul_f32((1.0 + self.policy.jitter * spread).max(0.0))
+            .min(self.policy.max)
+    }
+
+    // After a success, the next failure starts over from the initial wait
+    pub fn reset(&mut self) {
+        self.current = self.policy.initial;
+    }
+}
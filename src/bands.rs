@@ -0,0 +1,103 @@
+use std::ops::Range;
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use crate::{
+    dsp::{Decibel, RawAdc},
+    fft::Fft,
+};
+
+// Nominal centers of the octave bands as they are labeled, the exact ones are powers of two
+// from 1 kHz and the edges half an octave either side
+const CENTERS_HZ: [f32; 9] = [
+    31.5, 63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0,
+];
+const REFERENCE_HZ: f64 = 1000.0;
+const REFERENCE_BAND: i32 = 5;
+// Mean square of the Hann window, the share of the power it leaves
+const HANN_POWER: f64 = 0.375;
+// The FFT works on Q16 counts
+const COUNTS_SQUARED: f64 = (1u64 << 32) as f64;
+
+// Octave band levels of a window, like the overall level relative to ADC counts, unweighted and
+// without the DC bias
+pub struct OctaveAnalyzer {
+    fft: Fft,
+    // The bins of each band, bands without any or with their center at or above Nyquist are left
+    // out
+    bands: Vec<(f32, Range<usize>)>,
+}
+
+impl OctaveAnalyzer {
+    pub fn new(fft_len: usize, sample_rate_hz: f32) -> Option<Self> {
+        let fft = Fft::new(fft_len)?;
+        let bin_hz = f64::from(sample_rate_hz) / fft_len as f64;
+        let nyquist_bin = fft_len / 2;
+        let bands = CENTERS_HZ
+            .iter()
+            .enumerate()
+            .filter_map(|(index, nominal)| {
+                let center = REFERENCE_HZ * 2f64.powi(index as i32 - REFERENCE_BAND);
+                // First bin at or above each edge
+                let bin = |hz: f64| ((hz / bin_hz).ceil() as usize).max(1);
+                let bins =
+                    bin(center / 2f64.sqrt())..bin(center * 2f64.sqrt()).min(nyquist_bin + 1);
+                (center < f64::from(sample_rate_hz) / 2.0 && !bins.is_empty())
+                    .then_some((*nominal, bins))
+            })
+            .collect();
+        Some(OctaveAnalyzer { fft, bands })
+    }
+
+    // The power of consecutive blocks averaged over the window, None for less than one block
+    pub fn analyze(&self, samples: &[RawAdc]) -> Option<OctaveBands> {
+        let blocks: Vec<Vec<u64>> = samples
+            .chunks_exact(self.fft.block_len())
+            .map(|block| self.fft.power_spectrum(block))
+            .collect();
+        if blocks.is_empty() {
+            return None;
+        }
+        let levels = self
+            .bands
+            .iter()
+            .map(|(center, bins)| {
+                let power: u64 = blocks
+                    .iter()
+                    .map(|spectrum| spectrum[bins.clone()].iter().sum::<u64>())
+                    .sum();
+                let mean_square = power as f64 / blocks.len() as f64 / HANN_POWER / COUNTS_SQUARED;
+                (*center, Decibel((10.0 * mean_square.log10()) as f32))
+            })
+            .collect();
+        Some(OctaveBands(levels))
+    }
+}
+
+// Band centers with their levels, lowest first
+#[derive(Clone, Debug, PartialEq)]
+pub struct OctaveBands(pub Vec<(f32, Decibel)>);
+
+impl OctaveBands {
+    // Along with the overall level when that is calibrated
+    pub fn with_offset(self, offset_db: f32) -> Self {
+        OctaveBands(
+            self.0
+                .into_iter()
+                .map(|(center, level)| (center, level + offset_db))
+                .collect(),
+        )
+    }
+}
+
+// An object from the center in Hz to the level, `{"63":41.2,"125":44.0}`, silent bands are null
+impl Serialize for OctaveBands {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (center, level) in &self.0 {
+            let rounded = (level.0 * 10.0).round() / 10.0;
+            map.serialize_entry(&center.to_string(), &rounded)?;
+        }
+        map.end()
+    }
+}
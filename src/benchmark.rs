@@ -0,0 +1,84 @@
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    classification::Classifier,
+    dsp::{self, Decibel, LevelFilter, Plausibility, RawAdc},
+};
+
+// Short enough for the sensor watchdog, long enough to average out interrupts
+const DURATION: Duration = Duration::from_secs(2);
+// About a minute of levels, what one diagnostics report aggregates
+const AGGREGATION_WINDOW: usize = 1000;
+
+#[cfg(feature = "fixed-point")]
+const IMPLEMENTATION: &str = "fixed-point";
+#[cfg(not(feature = "fixed-point"))]
+const IMPLEMENTATION: &str = "f32";
+
+// Runs the measurement pipeline, level computation through classification, on synthetic blocks of
+// `block_len` samples without the ADC waits, then the interval aggregation. Blocks the caller for
+// a couple of seconds.
+pub fn run(block_len: usize, seed: u32) -> String {
+    log::info!("Benchmarking DSP for {:?}", DURATION);
+    let mut rng_state = seed | 1;
+    let mut next_sample = move || {
+        rng_state ^= rng_state << 13;
+        rng_state ^= rng_state >> 17;
+        rng_state ^= rng_state << 5;
+        RawAdc((rng_state >> 20) as u16)
+    };
+    let mut filter = LevelFilter::new(Decibel(-10.0), Decibel(70.0), 5, 30.0);
+    let mut classifier = Classifier::new(Decibel(40.0), Decibel(65.0), Decibel(80.0), 3.0);
+    let mut block = vec![RawAdc::default(); block_len];
+    let mut levels = Vec::with_capacity(AGGREGATION_WINDOW);
+    let mut blocks = 0u32;
+    let mut min_latency = Duration::MAX;
+    let mut max_latency = Duration::ZERO;
+    let mut busy = Duration::ZERO;
+
+    let started = Instant::now();
+    while started.elapsed() < DURATION {
+        block.iter_mut().for_each(|sample| *sample = next_sample());
+        let block_started = Instant::now();
+        if let Plausibility::Accepted(level) | Plausibility::Clamped(level) =
+            filter.check(dsp::rms_to_db(black_box(&block)))
+        {
+            black_box(classifier.update(level));
+            if levels.len() < AGGREGATION_WINDOW {
+                levels.push(level);
+            }
+        }
+        let latency = block_started.elapsed();
+        min_latency = min_latency.min(latency);
+        max_latency = max_latency.max(latency);
+        busy += latency;
+        blocks += 1;
+    }
+
+    let aggregation_started = Instant::now();
+    black_box((
+        dsp::leq(black_box(&levels)),
+        dsp::percentile(&levels, 90.0),
+        dsp::percentile(&levels, 10.0),
+    ));
+    let aggregation = aggregation_started.elapsed();
+
+    let samples_per_s = (blocks as u64 * block_len as u64) as f32 / busy.as_secs_f32();
+    let report = format!(
+        "{{\"implementation\":\"{}\",\"block_len\":{},\"blocks\":{},\"samples_per_s\":{:.0},\"block_us\":{{\"min\":{},\"avg\":{},\"max\":{}}},\"aggregation_us\":{},\"aggregated_levels\":{}}}",
+        IMPLEMENTATION,
+        block_len,
+        blocks,
+        samples_per_s,
+        min_latency.as_micros(),
+        (busy / blocks.max(1)).as_micros(),
+        max_latency.as_micros(),
+        aggregation.as_micros(),
+        levels.len()
+    );
+    log::info!("DSP benchmark: {}", report);
+    report
+}
@@ -0,0 +1,158 @@
+use esp_idf_svc::{
+    nvs::{EspDefaultNvsPartition, EspNvs},
+    sys::{
+        esp_reset_reason, esp_reset_reason_t, esp_reset_reason_t_ESP_RST_BROWNOUT,
+        esp_reset_reason_t_ESP_RST_CPU_LOCKUP, esp_reset_reason_t_ESP_RST_DEEPSLEEP,
+        esp_reset_reason_t_ESP_RST_EXT, esp_reset_reason_t_ESP_RST_INT_WDT,
+        esp_reset_reason_t_ESP_RST_PANIC, esp_reset_reason_t_ESP_RST_POWERON,
+        esp_reset_reason_t_ESP_RST_PWR_GLITCH, esp_reset_reason_t_ESP_RST_SW,
+        esp_reset_reason_t_ESP_RST_TASK_WDT, esp_reset_reason_t_ESP_RST_WDT,
+        esp_sleep_get_wakeup_cause, esp_sleep_source_t, esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT0,
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT1, esp_sleep_source_t_ESP_SLEEP_WAKEUP_GPIO,
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_TIMER, esp_sleep_source_t_ESP_SLEEP_WAKEUP_UART,
+    },
+};
+
+use crate::{watchdog, wear, NVS_NAMESPACE};
+
+const NVS_BOOT_COUNT_KEY: &str = "boot_count";
+
+// Coarse categories for fleet dashboards, the raw esp-idf reason goes along as `detail`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ResetCategory {
+    PowerOn,
+    Brownout,
+    Watchdog,
+    Panic,
+    Software,
+    DeepSleepWake,
+    External,
+    Unknown,
+}
+
+impl ResetCategory {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ResetCategory::PowerOn => "power_on",
+            ResetCategory::Brownout => "brownout",
+            ResetCategory::Watchdog => "watchdog",
+            ResetCategory::Panic => "panic",
+            ResetCategory::Software => "software",
+            ResetCategory::DeepSleepWake => "deep_sleep_wake",
+            ResetCategory::External => "external",
+            ResetCategory::Unknown => "unknown",
+        }
+    }
+}
+
+#[allow(non_upper_case_globals)]
+fn classify(reason: esp_reset_reason_t) -> (ResetCategory, &'static str) {
+    match reason {
+        esp_reset_reason_t_ESP_RST_POWERON => (ResetCategory::PowerOn, "power_on"),
+        esp_reset_reason_t_ESP_RST_BROWNOUT => (ResetCategory::Brownout, "brownout"),
+        esp_reset_reason_t_ESP_RST_PWR_GLITCH => (ResetCategory::Brownout, "power_glitch"),
+        esp_reset_reason_t_ESP_RST_INT_WDT => (ResetCategory::Watchdog, "interrupt_wdt"),
+        esp_reset_reason_t_ESP_RST_TASK_WDT => (ResetCategory::Watchdog, "task_wdt"),
+        esp_reset_reason_t_ESP_RST_WDT => (ResetCategory::Watchdog, "other_wdt"),
+        esp_reset_reason_t_ESP_RST_PANIC => (ResetCategory::Panic, "panic"),
+        esp_reset_reason_t_ESP_RST_CPU_LOCKUP => (ResetCategory::Panic, "cpu_lockup"),
+        esp_reset_reason_t_ESP_RST_SW => (ResetCategory::Software, "esp_restart"),
+        esp_reset_reason_t_ESP_RST_DEEPSLEEP => (ResetCategory::DeepSleepWake, "deep_sleep"),
+        esp_reset_reason_t_ESP_RST_EXT => (ResetCategory::External, "reset_pin"),
+        _ => (ResetCategory::Unknown, "unknown"),
+    }
+}
+
+#[allow(non_upper_case_globals)]
+fn wakeup_source(cause: esp_sleep_source_t) -> Option<&'static str> {
+    match cause {
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_TIMER => Some("timer"),
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT0 | esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT1 => {
+            Some("ext")
+        }
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_GPIO => Some("gpio"),
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_UART => Some("uart"),
+        _ => None,
+    }
+}
+
+#[derive(Clone)]
+pub struct BootReport {
+    category: ResetCategory,
+    detail: &'static str,
+    raw_reason: esp_reset_reason_t,
+    wakeup: Option<&'static str>,
+    boot_count: Option<u32>,
+    watchdog_task: Option<String>,
+}
+
+impl BootReport {
+    // Call once per boot, it counts boots in NVS
+    pub fn detect(nvs_partition: EspDefaultNvsPartition) -> Self {
+        let raw_reason = unsafe { esp_reset_reason() };
+        // The software watchdog restarts with esp_restart(), so it needs telling apart
+        let watchdog_task = watchdog::take_tripped();
+        let (category, detail) = match watchdog_task {
+            Some(_) => (ResetCategory::Watchdog, "task_watchdog"),
+            None => classify(raw_reason),
+        };
+        let wakeup = if category == ResetCategory::DeepSleepWake {
+            wakeup_source(unsafe { esp_sleep_get_wakeup_cause() })
+        } else {
+            None
+        };
+        let boot_count = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)
+            .and_then(|nvs| {
+                let count = nvs
+                    .get_u32(NVS_BOOT_COUNT_KEY)?
+                    .unwrap_or(0)
+                    .wrapping_add(1);
+                nvs.set_u32(NVS_BOOT_COUNT_KEY, count)?;
+                wear::nvs_written(0);
+                Ok(count)
+            })
+            .map_err(|err| log::error!("Unable to update boot count: {}", err))
+            .ok();
+        log::info!(
+            "Boot {:?} after {} ({})",
+            boot_count,
+            category.as_str(),
+            detail
+        );
+        BootReport {
+            category,
+            detail,
+            raw_reason,
+            wakeup,
+            boot_count,
+            watchdog_task,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        self.category.as_str()
+    }
+
+    pub fn to_json(&self) -> String {
+        let wakeup = self
+            .wakeup
+            .map_or_else(|| String::from("null"), |wakeup| format!("\"{}\"", wakeup));
+        let boot_count = self
+            .boot_count
+            .map_or_else(|| String::from("null"), |count| count.to_string());
+        let watchdog_task = self
+            .watchdog_task
+            .as_ref()
+            .map_or_else(|| String::from("null"), |task| format!("\"{}\"", task));
+        format!(
+            "{{\"reason\":\"{}\",\"detail\":\"{}\",\"raw_reason\":{},\"wakeup\":{},\"boot_count\":{},\"watchdog_task\":{},\"firmware\":\"{}\"}}",
+            self.category.as_str(),
+            self.detail,
+            self.raw_reason,
+            wakeup,
+            boot_count,
+            watchdog_task,
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+}
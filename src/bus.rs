@@ -0,0 +1,104 @@
+use std::{ffi::CStr, sync::OnceLock};
+
+use anyhow::{Context, Result};
+use esp_idf_svc::{
+    eventloop::{
+        BackgroundLoopConfiguration, EspBackgroundEventLoop, EspBackgroundSubscription, EspEvent,
+        EspEventDeserializer, EspEventPostData, EspEventSerializer, EspEventSource,
+    },
+    hal::delay::NON_BLOCK,
+    sys::EspError,
+};
+
+use crate::{classification::NoiseClass, dsp::Decibel};
+
+// Enough for a burst of readings while a subscriber formats a dashboard update
+const QUEUE_LEN: usize = 32;
+// Subscribers run on the bus task, and the dashboard broadcasts from there
+const TASK_STACK_SIZE: usize = 6144;
+
+// What happens in one module that others may care about. The event loop copies the payload into
+// its queue, hence Copy and nothing borrowed.
+#[derive(Clone, Copy, Debug)]
+pub enum BusEvent {
+    MeasurementReady {
+        level: Decibel,
+        class: Option<NoiseClass>,
+    },
+    ConnectivityChanged {
+        link: Link,
+        up: bool,
+    },
+    AlertRaised {
+        seq: u32,
+        level: Decibel,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Link {
+    Wifi,
+    Mqtt,
+}
+
+// A user event loop with its own task, so slow subscribers don't hold up the WiFi and IP events
+// on the system one
+static BUS: OnceLock<EspBackgroundEventLoop> = OnceLock::new();
+
+// SAFETY: only BusEvent is ever posted or read under this source
+unsafe impl EspEventSource for BusEvent {
+    fn source() -> Option<&'static CStr> {
+        CStr::from_bytes_with_nul(b"BZZZ_BUS\0").ok()
+    }
+}
+
+impl EspEventSerializer for BusEvent {
+    type Data<'a> = BusEvent;
+
+    fn serialize<F, R>(event: &Self::Data<'_>, f: F) -> R
+    where
+        F: FnOnce(&EspEventPostData) -> R,
+    {
+        let source = Self::source().unwrap();
+        // SAFETY: the event outlives the post, which copies it
+        f(&unsafe { EspEventPostData::new(source, Self::event_id(), event) })
+    }
+}
+
+impl EspEventDeserializer for BusEvent {
+    type Data<'a> = BusEvent;
+
+    fn deserialize<'a>(event: &EspEvent<'a>) -> Self::Data<'a> {
+        // SAFETY: see EspEventSource
+        *unsafe { event.as_payload::<BusEvent>() }
+    }
+}
+
+// Once at boot, before any producer runs
+pub fn start() -> Result<(), EspError> {
+    let bus = EspBackgroundEventLoop::new(&BackgroundLoopConfiguration {
+        queue_size: QUEUE_LEN,
+        task_name: "bus",
+        task_stack_size: TASK_STACK_SIZE,
+        ..Default::default()
+    })?;
+    let _ = BUS.set(bus);
+    Ok(())
+}
+
+// Never blocks the producer, events are dropped while the queue is full
+pub fn post(event: BusEvent) {
+    if let Some(bus) = BUS.get() {
+        let _ = bus.post::<BusEvent>(&event, NON_BLOCK);
+    }
+}
+
+// The callback runs on the bus task for every event until the subscription is dropped
+pub fn subscribe(
+    callback: impl FnMut(BusEvent) + Send + 'static,
+) -> Result<EspBackgroundSubscription<'static>> {
+    BUS.get()
+        .context("Event bus not started")?
+        .subscribe::<BusEvent, _>(callback)
+        .context("Unable to subscribe to the event bus")
+}
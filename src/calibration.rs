@@ -0,0 +1,103 @@
+use std::time::{Duration, Instant};
+
+use crate::dsp::{self, Decibel};
+
+// Long enough to average over a reference meter's display updates and the odd passing noise
+pub const SESSION: Duration = Duration::from_secs(5);
+// Any more and the reference or the mic is off, not the mic's sensitivity
+const MAX_OFFSET_DB: f32 = 150.0;
+
+// What turns the level relative to ADC counts into SPL, one offset in dB for the mic, its
+// amplifier and the ADC together
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Calibration {
+    pub offset_db: f32,
+    // What the reference meter read, if set against one rather than directly
+    pub reference_db: Option<f32>,
+}
+
+impl Calibration {
+    pub fn with_offset(offset_db: f32) -> Option<Self> {
+        (offset_db.is_finite() && offset_db.abs() <= MAX_OFFSET_DB).then_some(Calibration {
+            offset_db,
+            reference_db: None,
+        })
+    }
+
+    // With the uncalibrated level measured while the reference meter read `reference`
+    pub fn against(reference: Decibel, measured: Decibel) -> Option<Self> {
+        Self::with_offset(reference.0 - measured.0).map(|calibration| Calibration {
+            reference_db: Some(reference.0),
+            ..calibration
+        })
+    }
+
+    pub fn is_set(self) -> bool {
+        self.offset_db != 0.0
+    }
+
+    pub fn apply(self, level: Decibel) -> Decibel {
+        level + self.offset_db
+    }
+
+    // As kept in NVS, `<offset>` or `<offset>,<reference>`
+    pub fn to_stored(self) -> String {
+        match self.reference_db {
+            Some(reference_db) => format!("{},{}", self.offset_db, reference_db),
+            None => format!("{}", self.offset_db),
+        }
+    }
+
+    pub fn from_stored(stored: &str) -> Option<Self> {
+        let (offset, reference) = match stored.split_once(',') {
+            Some((offset, reference)) => (offset, Some(reference.parse().ok()?)),
+            None => (stored, None),
+        };
+        let calibration = Self::with_offset(offset.parse().ok()?)?;
+        Some(Calibration {
+            reference_db: reference,
+            ..calibration
+        })
+    }
+
+    pub fn to_json(self) -> String {
+        let reference = match self.reference_db {
+            Some(reference_db) => format!("{:.1}", reference_db),
+            None => String::from("null"),
+        };
+        format!(
+            "{{\"offset_db\":{:.2},\"reference_db\":{}}}",
+            self.offset_db, reference
+        )
+    }
+}
+
+// Collects the uncalibrated levels while the reference meter sits next to the mic
+pub struct Session {
+    reference: Decibel,
+    started: Instant,
+    levels: Vec<Decibel>,
+}
+
+impl Session {
+    pub fn new(reference: Decibel, now: Instant) -> Self {
+        Session {
+            reference,
+            started: now,
+            levels: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, level: Decibel) {
+        self.levels.push(level);
+    }
+
+    pub fn is_over(&self, now: Instant) -> bool {
+        now.duration_since(self.started) >= SESSION
+    }
+
+    // Against the Leq of the session, which is what a meter averaging over it shows
+    pub fn finish(self) -> Option<Calibration> {
+        Calibration::against(self.reference, dsp::leq(&self.levels)?)
+    }
+}
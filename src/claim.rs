@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+// The registration goes to `<claim_topic>/request`, the backend answers on
+// `<claim_topic>/<device_id>`
+pub struct ClaimTopics {
+    pub request: String,
+    pub response: String,
+}
+
+impl ClaimTopics {
+    // None when claiming is disabled
+    pub fn new(claim_topic: &str, device_id: &str) -> Option<Self> {
+        let claim_topic = claim_topic.trim_end_matches('/');
+        (!claim_topic.is_empty()).then(|| ClaimTopics {
+            request: format!("{}/request", claim_topic),
+            response: format!("{}/{}", claim_topic, device_id),
+        })
+    }
+}
+
+// A random (version 4) UUID, generated once and kept, so the backend recognizes the device even
+// if its id or MAC-based topics change
+pub fn uuid_from_random(mut bytes: [u8; 16]) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+pub fn request_json(uuid: &str, device_id: &str, mac: &str, firmware: &str) -> String {
+    format!(
+        "{{\"uuid\":\"{}\",\"device_id\":\"{}\",\"mac\":\"{}\",\"firmware\":\"{}\"}}",
+        uuid, device_id, mac, firmware
+    )
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Activation {
+    // The settings as `key=value` pairs, ready for `ConfigStore::update`
+    Activated(String),
+    Rejected(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Response {
+    status: String,
+    #[serde(default)]
+    settings: BTreeMap<String, Value>,
+    #[serde(default)]
+    reason: String,
+}
+
+impl Activation {
+    // `{"status":"activated","settings":{"alert_trigger_db":72.5}}` or
+    // `{"status":"rejected","reason":"unknown device"}`
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let response: Response =
+            serde_json::from_slice(payload).context("Invalid activation response")?;
+        match response.status.as_str() {
+            "activated" => {
+                let mut pairs = vec![];
+                for (key, value) in response.settings {
+                    let value = match value {
+                        Value::String(value) => value,
+                        value @ (Value::Number(_) | Value::Bool(_)) => value.to_string(),
+                        _ => bail!("Setting {} is not a string, number or boolean", key),
+                    };
+                    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == ',') {
+                        bail!("Invalid value for setting {}", key);
+                    }
+                    pairs.push(format!("{}={}", key, value));
+                }
+                Ok(Activation::Activated(pairs.join(",")))
+            }
+            "rejected" => Ok(Activation::Rejected(response.reason)),
+            status => bail!("Unknown activation status {:?}", status),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::dsp::Decibel;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum NoiseClass {
+    Quiet,
+    Normal,
+    Loud,
+    VeryLoud,
+}
+
+impl NoiseClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NoiseClass::Quiet => "quiet",
+            NoiseClass::Normal => "normal",
+            NoiseClass::Loud => "loud",
+            NoiseClass::VeryLoud => "very_loud",
+        }
+    }
+}
+
+// Moving up a class happens as soon as its threshold is reached, but moving down requires the
+// level to drop `hysteresis` dB below it, so a level hovering at a threshold doesn't flap.
+pub struct Classifier {
+    normal_from: Decibel,
+    loud_from: Decibel,
+    very_loud_from: Decibel,
+    hysteresis: f32,
+    current: Option<NoiseClass>,
+}
+
+impl Classifier {
+    pub fn new(
+        normal_from: Decibel,
+        loud_from: Decibel,
+        very_loud_from: Decibel,
+        hysteresis: f32,
+    ) -> Self {
+        Classifier {
+            normal_from,
+            loud_from,
+            very_loud_from,
+            hysteresis,
+            current: None,
+        }
+    }
+
+    pub fn set_thresholds(
+        &mut self,
+        normal_from: Decibel,
+        loud_from: Decibel,
+        very_loud_from: Decibel,
+    ) {
+        self.normal_from = normal_from;
+        self.loud_from = loud_from;
+        self.very_loud_from = very_loud_from;
+    }
+
+    pub fn set_hysteresis(&mut self, hysteresis: f32) {
+        self.hysteresis = hysteresis;
+    }
+
+    pub fn current(&self) -> Option<NoiseClass> {
+        self.current
+    }
+
+    // Returns the new class only when it changes
+    pub fn update(&mut self, level: Decibel) -> Option<NoiseClass> {
+        let next = match self.current {
+            None => self.class_of(level),
+            Some(current) => {
+                let rising = self.class_of(level);
+                let falling = self.class_of(level + self.hysteresis);
+                if rising > current {
+                    rising
+                } else if falling < current {
+                    falling
+                } else {
+                    current
+                }
+            }
+        };
+        if self.current == Some(next) {
+            None
+        } else {
+            self.current = Some(next);
+            Some(next)
+        }
+    }
+
+    fn class_of(&self, level: Decibel) -> NoiseClass {
+        if level >= self.very_loud_from {
+            NoiseClass::VeryLoud
+        } else if level >= self.loud_from {
+            NoiseClass::Loud
+        } else if level >= self.normal_from {
+            NoiseClass::Normal
+        } else {
+            NoiseClass::Quiet
+        }
+    }
+}
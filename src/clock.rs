@@ -0,0 +1,79 @@
+mod broker;
+
+use std::{
+    ffi::CString,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use esp_idf_svc::sys::{localtime_r, setenv, time_t, tm, tzset};
+
+use broker::BrokerClock;
+
+// 2024-01-01T00:00:00Z. Anything earlier means SNTP hasn't set the clock yet.
+const MIN_VALID_UNIX_TIME: u64 = 1_704_067_200;
+
+// Only with a `time_topic`, consulted while SNTP hasn't set the clock
+static BROKER: Mutex<Option<BrokerClock>> = Mutex::new(None);
+
+fn sntp_time() -> Option<u64> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    (secs >= MIN_VALID_UNIX_TIME).then_some(secs)
+}
+
+// From SNTP, or approximately from the broker where NTP doesn't get through
+pub fn unix_time() -> Option<u64> {
+    sntp_time().or_else(|| BROKER.lock().unwrap().as_ref()?.unix_time(Instant::now()))
+}
+
+// How far off the time may be while it comes from the broker, None while SNTP keeps it
+pub fn broker_accuracy() -> Option<Duration> {
+    if sntp_time().is_some() {
+        return None;
+    }
+    BROKER.lock().unwrap().as_ref()?.accuracy(Instant::now())
+}
+
+// Right before subscribing to the time topic published every `publish_interval`
+pub fn broker_subscribed(publish_interval: Duration) {
+    BROKER
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| BrokerClock::new(publish_interval))
+        .subscribed();
+}
+
+pub fn broker_time_received(payload: &[u8]) {
+    let Some(unix_ms) = broker::parse(payload).filter(|ms| ms / 1000 >= MIN_VALID_UNIX_TIME) else {
+        log::warn!(
+            "Ignoring broker time {:?}",
+            String::from_utf8_lossy(payload)
+        );
+        return;
+    };
+    if let Some(clock) = BROKER.lock().unwrap().as_mut() {
+        clock.received(unix_ms, Instant::now());
+    }
+}
+
+// Minutes since local midnight, None until SNTP has set the clock
+pub fn local_minute_of_day() -> Option<u16> {
+    let now = unix_time()? as time_t;
+    let mut local: tm = unsafe { std::mem::zeroed() };
+    if unsafe { localtime_r(&now, &mut local) }.is_null() {
+        return None;
+    }
+    Some((local.tm_hour * 60 + local.tm_min) as u16)
+}
+
+// Takes a POSIX TZ rule like `CET-1CEST,M3.5.0,M10.5.0/3` for the local time functions
+pub fn set_timezone(rule: &str) {
+    let Ok(rule) = CString::new(rule) else {
+        log::error!("Invalid timezone {:?}", rule);
+        return;
+    };
+    unsafe {
+        setenv(b"TZ\0".as_ptr() as _, rule.as_ptr(), 1);
+        tzset();
+    }
+}
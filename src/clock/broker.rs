@@ -0,0 +1,83 @@
+use std::time::{Duration, Instant};
+
+// What a cheap crystal drifts at most, so an estimate gets worse as it ages
+const DRIFT_PPM: u128 = 100;
+// A live message is whole seconds as published, plus a moment on its way
+const LIVE_ACCURACY: Duration = Duration::from_secs(2);
+
+struct Estimate {
+    unix_ms: u64,
+    at: Instant,
+    accuracy: Duration,
+}
+
+impl Estimate {
+    fn accuracy(&self, now: Instant) -> Duration {
+        let elapsed = now.duration_since(self.at).as_micros();
+        self.accuracy + Duration::from_micros((elapsed * DRIFT_PPM / 1_000_000) as u64)
+    }
+}
+
+// Wall-clock time from a time topic on the broker, for networks that block NTP. The first message
+// after subscribing may be the retained one, which can be as old as the interval it is published
+// at, later ones arrive as they are published. The best estimate so far is kept.
+pub struct BrokerClock {
+    retained_accuracy: Duration,
+    maybe_retained: bool,
+    estimate: Option<Estimate>,
+}
+
+impl BrokerClock {
+    pub fn new(publish_interval: Duration) -> Self {
+        BrokerClock {
+            retained_accuracy: publish_interval + LIVE_ACCURACY,
+            maybe_retained: true,
+            estimate: None,
+        }
+    }
+
+    // The broker delivers the retained message again
+    pub fn subscribed(&mut self) {
+        self.maybe_retained = true;
+    }
+
+    pub fn received(&mut self, unix_ms: u64, now: Instant) {
+        let accuracy = if std::mem::take(&mut self.maybe_retained) {
+            self.retained_accuracy
+        } else {
+            LIVE_ACCURACY
+        };
+        if self
+            .estimate
+            .as_ref()
+            .is_some_and(|estimate| estimate.accuracy(now) < accuracy)
+        {
+            return;
+        }
+        self.estimate = Some(Estimate {
+            unix_ms,
+            at: now,
+            accuracy,
+        });
+    }
+
+    pub fn unix_time(&self, now: Instant) -> Option<u64> {
+        let estimate = self.estimate.as_ref()?;
+        Some((estimate.unix_ms + now.duration_since(estimate.at).as_millis() as u64) / 1000)
+    }
+
+    pub fn accuracy(&self, now: Instant) -> Option<Duration> {
+        Some(self.estimate.as_ref()?.accuracy(now))
+    }
+}
+
+// Unix seconds as text, e.g. from `mosquitto_pub -r -t <time topic> -m $(date +%s.%N)`, in ms
+pub fn parse(payload: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(payload).ok()?.trim();
+    let (seconds, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if seconds.is_empty() || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let millis = format!("{:0<3}", &fraction[..fraction.len().min(3)]);
+    Some(seconds.parse::<u64>().ok()?.checked_mul(1000)? + millis.parse::<u64>().ok()?)
+}
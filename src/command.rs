@@ -0,0 +1,142 @@
+use crate::{autotune, encoding, payload_log::Module};
+
+// The counter and HMAC-SHA256 a command is signed with, checked by the signing module
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Signature {
+    pub counter: u64,
+    pub hmac: [u8; 32],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Pause,
+    Resume,
+    Restart,
+    Shutdown,
+    Profile,
+    Benchmark,
+    Identify,
+    // Publish the next level reading even if it didn't change
+    Read,
+    // Log the effective configuration, secrets redacted
+    ShowConfig,
+    // Check that readings make it through the broker and back
+    Verify,
+    // Publish logs, configuration, metrics and crash records as one compressed bundle
+    DiagBundle,
+    Led(bool),
+    // Against a reference meter reading this SPL, or straight to an offset in dB
+    Calibrate(f32),
+    SetCalibrationOffset(f32),
+    SetSampleInterval(u32),
+    // Observe the site for that many hours and propose thresholds, apply or drop the proposal
+    AutoTune(u32),
+    ApplyAutoTune,
+    CancelAutoTune,
+    Maintenance(bool),
+    // Signed, as it raises the log level and streams raw samples for a while
+    Support(Signature),
+    EndSupport,
+    SetFeatures(u32),
+    Dump(Module, bool),
+}
+
+impl TryFrom<&[u8]> for Command {
+    type Error = &'static str;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match std::str::from_utf8(value).map(str::trim) {
+            Ok("pause") => Ok(Command::Pause),
+            Ok("resume") => Ok(Command::Resume),
+            Ok("restart") => Ok(Command::Restart),
+            Ok("shutdown") => Ok(Command::Shutdown),
+            Ok("profile") => Ok(Command::Profile),
+            Ok("benchmark") => Ok(Command::Benchmark),
+            Ok("identify") => Ok(Command::Identify),
+            Ok("read") => Ok(Command::Read),
+            Ok("config") => Ok(Command::ShowConfig),
+            Ok("verify") => Ok(Command::Verify),
+            Ok("diag_bundle") => Ok(Command::DiagBundle),
+            Ok("autotune start") => Ok(Command::AutoTune(autotune::MIN_HOURS)),
+            Ok("autotune apply") => Ok(Command::ApplyAutoTune),
+            Ok("autotune cancel") => Ok(Command::CancelAutoTune),
+            Ok(command) => match command.split_once(' ') {
+                Some(("features", mask)) => parse_mask(mask.trim())
+                    .map(Command::SetFeatures)
+                    .ok_or("Invalid feature mask"),
+                Some(("dump", args)) => parse_dump(args.trim()).ok_or("Invalid dump command"),
+                Some(("maintenance", state)) => parse_state(state.trim())
+                    .map(Command::Maintenance)
+                    .ok_or("Expected maintenance on|off"),
+                Some(("support", state)) if state.trim() == "off" => Ok(Command::EndSupport),
+                Some(("support", signature)) => parse_signature(signature.trim())
+                    .map(Command::Support)
+                    .ok_or("Expected support <counter> <signature>|off"),
+                Some(("led", state)) => parse_state(state.trim())
+                    .map(Command::Led)
+                    .ok_or("Expected led on|off"),
+                Some(("calibrate", args)) => {
+                    parse_calibrate(args.trim()).ok_or("Expected calibrate <dB>|offset <dB>|reset")
+                }
+                Some(("autotune", args)) => args
+                    .trim()
+                    .strip_prefix("start ")
+                    .and_then(|hours| hours.trim().parse().ok())
+                    .filter(|hours| (autotune::MIN_HOURS..=autotune::MAX_HOURS).contains(hours))
+                    .map(Command::AutoTune)
+                    .ok_or("Expected autotune start [24-48]|apply|cancel"),
+                Some(("interval", ms)) => ms
+                    .trim()
+                    .parse()
+                    .map(Command::SetSampleInterval)
+                    .map_err(|_| "Expected interval <ms>"),
+                _ => Err("Unknown command"),
+            },
+            Err(_) => Err("Command is not valid UTF-8"),
+        }
+    }
+}
+
+// `dump <module> on|off`
+fn parse_dump(args: &str) -> Option<Command> {
+    let (module, state) = args.split_once(' ')?;
+    Some(Command::Dump(
+        Module::from_name(module)?,
+        parse_state(state.trim())?,
+    ))
+}
+
+// `calibrate <reference dB>`, `calibrate offset <dB>` or `calibrate reset`
+fn parse_calibrate(args: &str) -> Option<Command> {
+    let parse_db = |value: &str| value.trim().parse::<f32>().ok().filter(|db| db.is_finite());
+    match args.split_once(' ') {
+        Some(("offset", offset)) => parse_db(offset).map(Command::SetCalibrationOffset),
+        None if args == "reset" => Some(Command::SetCalibrationOffset(0.0)),
+        None => parse_db(args).map(Command::Calibrate),
+        _ => None,
+    }
+}
+
+// `<counter> <base64url HMAC>`
+fn parse_signature(args: &str) -> Option<Signature> {
+    let (counter, hmac) = args.split_once(' ')?;
+    Some(Signature {
+        counter: counter.parse().ok()?,
+        hmac: encoding::decode_base64url(hmac.trim())?.try_into().ok()?,
+    })
+}
+
+fn parse_state(state: &str) -> Option<bool> {
+    match state {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_mask(mask: &str) -> Option<u32> {
+    match mask.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => mask.parse().ok(),
+    }
+}
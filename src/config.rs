@@ -0,0 +1,74 @@
+use std::sync::OnceLock;
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+
+use crate::{
+    factory::{self, FactoryData},
+    wear,
+};
+
+mod settings;
+
+pub use settings::{Config, ConfigStore, ConfigWatch};
+
+const NVS_CONFIG_NAMESPACE: &str = "bzzz_cfg";
+const NVS_OVERRIDES_KEY: &str = "overrides";
+
+static FACTORY_DATA: OnceLock<Option<FactoryData>> = OnceLock::new();
+
+// The identity from manufacture, None on devices without a valid one
+pub fn factory_data() -> Option<&'static FactoryData> {
+    FACTORY_DATA.get().and_then(Option::as_ref)
+}
+
+fn load_factory_data(nvs_partition: EspDefaultNvsPartition) -> Option<&'static FactoryData> {
+    FACTORY_DATA
+        .get_or_init(|| {
+            // No namespace at all on devices flashed without one
+            let nvs = EspNvs::new(nvs_partition, factory::NVS_NAMESPACE, false).ok()?;
+            let mut buffer = vec![0u8; nvs.blob_len(factory::NVS_KEY).ok()??];
+            let blob = nvs.get_blob(factory::NVS_KEY, &mut buffer).ok()??;
+            FactoryData::parse(blob)
+                .map_err(|err| log::error!("Ignoring factory data: {:#}", err))
+                .ok()
+        })
+        .as_ref()
+}
+
+impl settings::OverrideStorage for EspNvs<NvsDefault> {
+    fn store(&mut self, blob: &str) {
+        match self.set_blob(NVS_OVERRIDES_KEY, blob.as_bytes()) {
+            Ok(_) => wear::nvs_written(blob.len()),
+            Err(err) => log::error!("Unable to store configuration: {}", err),
+        }
+    }
+}
+
+impl ConfigStore {
+    pub fn load(nvs_partition: EspDefaultNvsPartition) -> Self {
+        let mut defaults = Config::defaults();
+        if let Some(factory_data) = load_factory_data(nvs_partition.clone()) {
+            defaults.apply_factory_data(factory_data);
+        }
+        let nvs = EspNvs::new(nvs_partition, NVS_CONFIG_NAMESPACE, true)
+            .map_err(|err| log::error!("Unable to open configuration: {}", err))
+            .ok();
+        let stored = nvs.as_ref().and_then(|nvs| {
+            let mut buffer = vec![0u8; nvs.blob_len(NVS_OVERRIDES_KEY).ok()??];
+            let blob = nvs.get_blob(NVS_OVERRIDES_KEY, &mut buffer).ok()??;
+            Some(String::from_utf8_lossy(blob).into_owned())
+        });
+        let store = ConfigStore::new(
+            defaults,
+            nvs.map(|nvs| Box::new(nvs) as Box<dyn settings::OverrideStorage>),
+        );
+        if let Some(stored) = stored {
+            // Settings may have become invalid with a new firmware, defaults are safer then
+            match store.restore(&stored) {
+                Ok(_) => log::info!("Loaded configuration overrides"),
+                Err(err) => log::warn!("Ignoring stored configuration: {}", err),
+            }
+        }
+        store
+    }
+}
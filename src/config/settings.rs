@@ -0,0 +1,1113 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::{factory::FactoryData, weighting::Weighting};
+
+// Shown as set or not in the dump, never with their value
+const SECRETS: &[&str] = &[
+    "wifi_password",
+    "mqtt_password",
+    "jwt_key",
+    "est_password",
+    "web_token",
+    "diagnostics_ap_password",
+];
+// Several KiB of RAM with JSON readings
+const MAX_BATCH_SIZE: u32 = 100;
+// A logger reports at least hourly
+const MAX_LOGGER_INTERVAL_S: u32 = 3600;
+// Still a block of 512 samples for the octave bands
+const MIN_SAMPLE_WINDOW_MS: u32 = 50;
+// 8000 samples per input, 48 KiB of RAM with all three inputs
+const MAX_SAMPLE_WINDOW_MS: u32 = 500;
+// About 50 KiB of RAM with JSON readings
+const MAX_OFFLINE_QUEUE_LEN: u32 = 500;
+// Two windows at the top rate stay below 40 KiB of RAM
+const MAX_ALERT_BURST_S: u32 = 60;
+// About the rate of level readings, faster would repeat levels
+const MAX_ALERT_BURST_HZ: u32 = 20;
+// Keeps the 16 alerts of the journal within its NVS blob
+const MAX_ALERT_CONTEXT_S: u32 = 5;
+// Long enough to reproduce a problem, short enough that a forgotten session ends the same day
+const MIN_SUPPORT_SESSION_S: u32 = 60;
+const MAX_SUPPORT_SESSION_S: u32 = 4 * 3600;
+
+// Build-time defaults from cfg.toml. Everything else reads the settings through a ConfigStore.
+#[toml_cfg::toml_config]
+struct Configuration {
+    #[default("NotMyWifi")]
+    wifi_ssid: &'static str,
+    #[default("NotMyPassword")]
+    wifi_password: &'static str,
+    // How to get credentials without any: `softap` (the portal) or `dpp` (Wi-Fi Easy Connect)
+    #[default("softap")]
+    provisioning: &'static str,
+    #[default("mqttserver")]
+    mqtt_host: &'static str,
+    #[default("")]
+    mqtt_user: &'static str,
+    #[default("")]
+    mqtt_password: &'static str,
+    // mqtts:// on 8883, always on with an EST certificate
+    #[default(false)]
+    mqtt_use_tls: bool,
+    // PEM of the CA that signed the broker certificate, the public CA bundle when empty
+    #[default("")]
+    mqtt_ca_cert: &'static str,
+    // `tcp`, or `websocket` for networks that only let 80 and 443 out, `wss://` with TLS
+    #[default("tcp")]
+    mqtt_transport: &'static str,
+    // Where the broker's WebSocket listener answers
+    #[default("/mqtt")]
+    mqtt_ws_path: &'static str,
+    // Retained Home Assistant discovery documents for the level, class and telemetry state
+    #[default(false)]
+    ha_discovery: bool,
+    #[default("homeassistant")]
+    ha_discovery_prefix: &'static str,
+    // `3.1.1`, or `5` for user properties on every message, falling back to 3.1.1 when the broker
+    // refuses it
+    #[default("3.1.1")]
+    mqtt_protocol: &'static str,
+    // For the readings, 1 and 2 publish readings again until the broker acknowledges them
+    #[default(0)]
+    mqtt_qos: u8,
+    // `<mqtt_client_id_prefix>-<MAC>` when empty
+    #[default("")]
+    mqtt_client_id: &'static str,
+    #[default("bzzz")]
+    mqtt_client_id_prefix: &'static str,
+    // Pings when idle, lower for cellular links that drop quiet connections. 0 is esp-mqtt's 120.
+    #[default(120)]
+    mqtt_keep_alive_s: u32,
+    // False asks the broker to keep subscriptions and queued QoS 1 messages across reconnects
+    #[default(true)]
+    mqtt_clean_session: bool,
+    // How often esp-mqtt retries on its own, at least the longest backoff of 300 s
+    #[default(600)]
+    mqtt_reconnect_timeout_s: u32,
+    #[default("password")]
+    mqtt_auth: &'static str,
+    #[default("")]
+    jwt_key: &'static str,
+    #[default("")]
+    jwt_audience: &'static str,
+    #[default("")]
+    token_url: &'static str,
+    #[default(3600)]
+    token_lifetime_s: u32,
+    #[default("")]
+    est_url: &'static str,
+    #[default("")]
+    est_user: &'static str,
+    #[default("")]
+    est_password: &'static str,
+    #[default(30)]
+    cert_renew_before_days: u32,
+    #[default("home/noise sensor/{device_id}")]
+    topic_template: &'static str,
+    // Older name of `site_id`, used when that is empty
+    #[default("")]
+    site: &'static str,
+    #[default("")]
+    floor: &'static str,
+    // Prefixes every topic unless the template places it, see topics.rs
+    #[default("")]
+    tenant_id: &'static str,
+    #[default("")]
+    site_id: &'static str,
+    // Keeps the topics from before the tenant prefix working alongside the new ones
+    #[default(false)]
+    topic_migration: bool,
+    // Until the backend activates the device, no telemetry, see claim.rs. Empty for none.
+    #[default("")]
+    claim_topic: &'static str,
+    #[default(false)]
+    require_encrypted_secrets: bool,
+    // AES-GCM for every payload but availability, with the key from NVS, see sealing.rs
+    #[default(false)]
+    encrypt_payloads: bool,
+    // HMAC with a replay counter on measurements and alerts, see signing.rs
+    #[default(false)]
+    sign_payloads: bool,
+    #[default(false)]
+    demo_mode: bool,
+    // Only starts with a `web_token`, as it can reboot the sensor
+    #[default(true)]
+    web_dashboard: bool,
+    #[default("bzzz")]
+    web_user: &'static str,
+    #[default("")]
+    web_token: &'static str,
+    // How the dashboard spells out readings: the unit label, e.g. `dB(A)`, a decimal comma, the
+    // `24h` or `12h` clock and four class labels, quiet to very loud, comma separated
+    #[default("dB")]
+    display_unit: &'static str,
+    #[default(false)]
+    display_decimal_comma: bool,
+    #[default("24h")]
+    display_clock: &'static str,
+    #[default("")]
+    display_class_labels: &'static str,
+    // WPA2 password, 8 to 63 characters, of the access point opened after 10 minutes without WiFi.
+    // Empty for none.
+    #[default("")]
+    diagnostics_ap_password: &'static str,
+    // Levels are dB relative to one ADC count of RMS until calibrated, where the ADC's own noise
+    // is about -11 dB and a full-scale sine 63 dB. Whatever lies outside is clamped.
+    #[default(-10.0)]
+    level_floor_db: f32,
+    #[default(70.0)]
+    level_ceiling_db: f32,
+    #[default(5)]
+    outlier_window: usize,
+    #[default(30.0)]
+    outlier_max_deviation_db: f32,
+    // On the same scale, very loud is about 13 dB below clipping
+    #[default(10.0)]
+    normal_from_db: f32,
+    #[default(35.0)]
+    loud_from_db: f32,
+    #[default(50.0)]
+    very_loud_from_db: f32,
+    #[default(3.0)]
+    class_hysteresis_db: f32,
+    #[default(false)]
+    outdoor_profile: bool,
+    #[default(0.0)]
+    latitude: f32,
+    #[default(0.0)]
+    longitude: f32,
+    #[default(-10.0)]
+    night_threshold_offset_db: f32,
+    #[default(255)]
+    day_led_brightness: u8,
+    #[default(32)]
+    night_led_brightness: u8,
+    #[default(75.0)]
+    thermal_limit_c: f32,
+    // POSIX TZ rule, so local times follow daylight saving
+    #[default("UTC0")]
+    timezone: &'static str,
+    // A topic the broker side publishes Unix seconds to, retained, for networks that block NTP.
+    // Empty for none.
+    #[default("")]
+    time_topic: &'static str,
+    // How often it is published, which bounds how old the retained time can be
+    #[default(60)]
+    time_topic_interval_s: u32,
+    #[default(false)]
+    maintenance_reboot: bool,
+    // 0 is Sunday, 7 every day
+    #[default(0)]
+    maintenance_day: u8,
+    #[default(4)]
+    maintenance_hour: u8,
+    #[default(0)]
+    maintenance_minute: u8,
+    // `event:frequency_hz:min_level_db`, comma separated, see tone.rs
+    #[default("")]
+    tone_detectors: &'static str,
+    // A second microphone on GPIO1, to the right of the one on GPIO0 as seen from the sensor
+    #[default(false)]
+    direction_mic: bool,
+    // A piezo or accelerometer on GPIO2, for structure-borne noise
+    #[default(false)]
+    vibration_sensor: bool,
+    #[default(20.0)]
+    vibration_normal_from_db: f32,
+    #[default(35.0)]
+    vibration_loud_from_db: f32,
+    #[default(50.0)]
+    vibration_very_loud_from_db: f32,
+    // One document with every channel per interval, 0 to only publish per channel
+    #[default(60)]
+    fusion_interval_s: u32,
+    // Between two sample windows, up to 100 so tone cadences can still be timed
+    #[default(10)]
+    sample_interval_ms: u32,
+    // What a level reading covers, short for fast alerting, long for steadier levels and fewer
+    // readings
+    #[default(100)]
+    sample_window_ms: u32,
+    // `A` or `C` weight levels like a sound level meter, `Z` is the plain RMS the default
+    // thresholds are set for
+    #[default("Z")]
+    weighting: &'static str,
+    // `meter` publishes readings as they come and shows the noise class on the LED, `logger` one
+    // Leq every `logger_interval_s`, lets WiFi sleep and keeps the LED dark while all is well
+    #[default("meter")]
+    operating_mode: &'static str,
+    #[default(60)]
+    logger_interval_s: u32,
+    // Closes logger intervals on UTC boundaries instead, e.g. `*/5` or `0`, see schedule.rs.
+    // Empty for free-running intervals.
+    #[default("")]
+    logger_schedule: &'static str,
+    // Level readings per publish, as one JSON array, 0 or 1 publishes each on its own
+    #[default(0)]
+    batch_size: u32,
+    // A batch goes out after this at the latest, even if not full, 0 waits until it is
+    #[default(10)]
+    batch_interval_s: u32,
+    // The level topic gets a JSON document instead of a bare number
+    #[default(false)]
+    level_json: bool,
+    // Adds the raw RMS next to the dB, implies `level_json`
+    #[default(false)]
+    report_raw_rms: bool,
+    // FFT length for octave band levels next to the dB, 256 or 512, 0 for none. Implies
+    // `level_json`.
+    #[default(0)]
+    band_fft_len: u32,
+    // Readings kept while the broker is unreachable and published once it's back, 0 drops them
+    #[default(120)]
+    offline_queue_len: u32,
+    // Also keeps the newest queued readings in NVS, so they survive a reboot
+    #[default(false)]
+    offline_queue_flash: bool,
+    // What the flash is rated for, per sector
+    #[default(100000)]
+    flash_endurance_cycles: u32,
+    // Past this share of the rated cycles on any partition, writes that can wait are spaced out
+    #[default(80)]
+    flash_wear_throttle_pct: u32,
+    // How long a signed `support` command raises the log level and streams diagnostics
+    #[default(1800)]
+    support_session_s: u32,
+    // Level readings within this of the last published one are skipped, 0 publishes all
+    #[default(0.0)]
+    report_delta_db: f32,
+    // Publishes an unchanged level again after this long anyway
+    #[default(60)]
+    report_max_silence_s: u32,
+    // After this long without a level message a heartbeat goes out, 0 for none
+    #[default(300)]
+    heartbeat_interval_s: u32,
+    // Alerts raise at the trigger level once it held for the trigger time, and only raise again
+    // after the level stayed below the clear level for the clear time
+    #[default(50.0)]
+    alert_trigger_db: f32,
+    #[default(47.0)]
+    alert_clear_db: f32,
+    #[default(1)]
+    alert_trigger_s: u32,
+    #[default(30)]
+    alert_clear_s: u32,
+    #[default(50.0)]
+    vibration_alert_trigger_db: f32,
+    #[default(47.0)]
+    vibration_alert_clear_db: f32,
+    #[default(1)]
+    vibration_alert_trigger_s: u32,
+    #[default(30)]
+    vibration_alert_clear_s: u32,
+    // Levels kept before and collected after a loud or cadence alert, published as one series,
+    // 0 captures none
+    #[default(30)]
+    alert_burst_s: u32,
+    #[default(10)]
+    alert_burst_hz: u32,
+    // Seconds of the burst levels every alert carries from before it was raised, 0 for none
+    #[default(3)]
+    alert_context_s: u32,
+    // JSON array of local automation rules, see rules.rs
+    #[default("")]
+    rules: &'static str,
+}
+
+// Credentials, endpoints and topics only come from cfg.toml. The tuning settings can also be
+// changed at runtime, see `Config::set`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    pub wifi_ssid: &'static str,
+    pub wifi_password: &'static str,
+    pub provisioning: &'static str,
+    pub mqtt_host: &'static str,
+    pub mqtt_user: &'static str,
+    pub mqtt_password: &'static str,
+    pub mqtt_use_tls: bool,
+    pub mqtt_ca_cert: &'static str,
+    pub mqtt_transport: &'static str,
+    pub mqtt_ws_path: &'static str,
+    pub ha_discovery: bool,
+    pub ha_discovery_prefix: &'static str,
+    pub mqtt_protocol: &'static str,
+    pub mqtt_qos: u8,
+    pub mqtt_client_id: &'static str,
+    pub mqtt_client_id_prefix: &'static str,
+    pub mqtt_keep_alive_s: u32,
+    pub mqtt_clean_session: bool,
+    pub mqtt_reconnect_timeout_s: u32,
+    pub mqtt_auth: &'static str,
+    pub jwt_key: &'static str,
+    pub jwt_audience: &'static str,
+    pub token_url: &'static str,
+    pub token_lifetime_s: u32,
+    pub est_url: &'static str,
+    pub est_user: &'static str,
+    pub est_password: &'static str,
+    pub cert_renew_before_days: u32,
+    pub topic_template: &'static str,
+    pub site: &'static str,
+    pub floor: &'static str,
+    pub tenant_id: &'static str,
+    pub site_id: &'static str,
+    pub topic_migration: bool,
+    pub claim_topic: &'static str,
+    pub require_encrypted_secrets: bool,
+    pub encrypt_payloads: bool,
+    pub sign_payloads: bool,
+    pub demo_mode: bool,
+    pub web_dashboard: bool,
+    pub web_user: &'static str,
+    pub web_token: &'static str,
+    pub display_unit: &'static str,
+    pub display_decimal_comma: bool,
+    pub display_clock: &'static str,
+    pub display_class_labels: &'static str,
+    pub diagnostics_ap_password: &'static str,
+    pub level_floor_db: f32,
+    pub level_ceiling_db: f32,
+    pub outlier_window: usize,
+    pub outlier_max_deviation_db: f32,
+    pub normal_from_db: f32,
+    pub loud_from_db: f32,
+    pub very_loud_from_db: f32,
+    pub class_hysteresis_db: f32,
+    pub outdoor_profile: bool,
+    pub latitude: f32,
+    pub longitude: f32,
+    pub night_threshold_offset_db: f32,
+    pub day_led_brightness: u8,
+    pub night_led_brightness: u8,
+    pub thermal_limit_c: f32,
+    pub timezone: &'static str,
+    pub time_topic: &'static str,
+    pub time_topic_interval_s: u32,
+    pub maintenance_reboot: bool,
+    pub maintenance_day: u8,
+    pub maintenance_hour: u8,
+    pub maintenance_minute: u8,
+    pub tone_detectors: &'static str,
+    pub direction_mic: bool,
+    pub vibration_sensor: bool,
+    pub vibration_normal_from_db: f32,
+    pub vibration_loud_from_db: f32,
+    pub vibration_very_loud_from_db: f32,
+    pub fusion_interval_s: u32,
+    pub sample_interval_ms: u32,
+    pub sample_window_ms: u32,
+    pub weighting: Weighting,
+    pub operating_mode: &'static str,
+    pub logger_interval_s: u32,
+    pub logger_schedule: &'static str,
+    pub batch_size: u32,
+    pub batch_interval_s: u32,
+    pub level_json: bool,
+    pub report_raw_rms: bool,
+    pub band_fft_len: u32,
+    pub offline_queue_len: u32,
+    pub offline_queue_flash: bool,
+    pub flash_endurance_cycles: u32,
+    pub flash_wear_throttle_pct: u32,
+    pub support_session_s: u32,
+    pub report_delta_db: f32,
+    pub report_max_silence_s: u32,
+    pub heartbeat_interval_s: u32,
+    pub alert_trigger_db: f32,
+    pub alert_clear_db: f32,
+    pub alert_trigger_s: u32,
+    pub alert_clear_s: u32,
+    pub vibration_alert_trigger_db: f32,
+    pub vibration_alert_clear_db: f32,
+    pub vibration_alert_trigger_s: u32,
+    pub vibration_alert_clear_s: u32,
+    pub alert_burst_s: u32,
+    pub alert_burst_hz: u32,
+    pub alert_context_s: u32,
+    pub rules: &'static str,
+}
+
+impl Config {
+    pub fn defaults() -> Self {
+        let defaults = CONFIGURATION;
+        Config {
+            wifi_ssid: defaults.wifi_ssid,
+            wifi_password: defaults.wifi_password,
+            provisioning: defaults.provisioning,
+            mqtt_host: defaults.mqtt_host,
+            mqtt_user: defaults.mqtt_user,
+            mqtt_password: defaults.mqtt_password,
+            mqtt_use_tls: defaults.mqtt_use_tls,
+            mqtt_ca_cert: defaults.mqtt_ca_cert,
+            mqtt_transport: defaults.mqtt_transport,
+            mqtt_ws_path: defaults.mqtt_ws_path,
+            ha_discovery: defaults.ha_discovery,
+            ha_discovery_prefix: defaults.ha_discovery_prefix,
+            mqtt_protocol: defaults.mqtt_protocol,
+            mqtt_qos: defaults.mqtt_qos,
+            mqtt_client_id: defaults.mqtt_client_id,
+            mqtt_client_id_prefix: defaults.mqtt_client_id_prefix,
+            mqtt_keep_alive_s: defaults.mqtt_keep_alive_s,
+            mqtt_clean_session: defaults.mqtt_clean_session,
+            mqtt_reconnect_timeout_s: defaults.mqtt_reconnect_timeout_s,
+            mqtt_auth: defaults.mqtt_auth,
+            jwt_key: defaults.jwt_key,
+            jwt_audience: defaults.jwt_audience,
+            token_url: defaults.token_url,
+            token_lifetime_s: defaults.token_lifetime_s,
+            est_url: defaults.est_url,
+            est_user: defaults.est_user,
+            est_password: defaults.est_password,
+            cert_renew_before_days: defaults.cert_renew_before_days,
+            topic_template: defaults.topic_template,
+            site: defaults.site,
+            floor: defaults.floor,
+            tenant_id: defaults.tenant_id,
+            site_id: defaults.site_id,
+            topic_migration: defaults.topic_migration,
+            claim_topic: defaults.claim_topic,
+            require_encrypted_secrets: defaults.require_encrypted_secrets,
+            encrypt_payloads: defaults.encrypt_payloads,
+            sign_payloads: defaults.sign_payloads,
+            demo_mode: defaults.demo_mode,
+            web_dashboard: defaults.web_dashboard,
+            web_user: defaults.web_user,
+            web_token: defaults.web_token,
+            display_unit: defaults.display_unit,
+            display_decimal_comma: defaults.display_decimal_comma,
+            display_clock: defaults.display_clock,
+            display_class_labels: defaults.display_class_labels,
+            diagnostics_ap_password: defaults.diagnostics_ap_password,
+            level_floor_db: defaults.level_floor_db,
+            level_ceiling_db: defaults.level_ceiling_db,
+            outlier_window: defaults.outlier_window,
+            outlier_max_deviation_db: defaults.outlier_max_deviation_db,
+            normal_from_db: defaults.normal_from_db,
+            loud_from_db: defaults.loud_from_db,
+            very_loud_from_db: defaults.very_loud_from_db,
+            class_hysteresis_db: defaults.class_hysteresis_db,
+            outdoor_profile: defaults.outdoor_profile,
+            latitude: defaults.latitude,
+            longitude: defaults.longitude,
+            night_threshold_offset_db: defaults.night_threshold_offset_db,
+            day_led_brightness: defaults.day_led_brightness,
+            night_led_brightness: defaults.night_led_brightness,
+            thermal_limit_c: defaults.thermal_limit_c,
+            timezone: defaults.timezone,
+            time_topic: defaults.time_topic,
+            time_topic_interval_s: defaults.time_topic_interval_s,
+            maintenance_reboot: defaults.maintenance_reboot,
+            maintenance_day: defaults.maintenance_day,
+            maintenance_hour: defaults.maintenance_hour,
+            maintenance_minute: defaults.maintenance_minute,
+            tone_detectors: defaults.tone_detectors,
+            direction_mic: defaults.direction_mic,
+            vibration_sensor: defaults.vibration_sensor,
+            vibration_normal_from_db: defaults.vibration_normal_from_db,
+            vibration_loud_from_db: defaults.vibration_loud_from_db,
+            vibration_very_loud_from_db: defaults.vibration_very_loud_from_db,
+            fusion_interval_s: defaults.fusion_interval_s,
+            sample_interval_ms: defaults.sample_interval_ms,
+            sample_window_ms: defaults.sample_window_ms,
+            // cfg.toml can't be turned down, an unknown weighting there is the plain level
+            weighting: Weighting::parse(defaults.weighting).unwrap_or(Weighting::Z),
+            operating_mode: defaults.operating_mode,
+            logger_interval_s: defaults.logger_interval_s,
+            logger_schedule: defaults.logger_schedule,
+            batch_size: defaults.batch_size,
+            batch_interval_s: defaults.batch_interval_s,
+            level_json: defaults.level_json,
+            report_raw_rms: defaults.report_raw_rms,
+            band_fft_len: defaults.band_fft_len,
+            offline_queue_len: defaults.offline_queue_len,
+            offline_queue_flash: defaults.offline_queue_flash,
+            flash_endurance_cycles: defaults.flash_endurance_cycles,
+            flash_wear_throttle_pct: defaults.flash_wear_throttle_pct,
+            support_session_s: defaults.support_session_s,
+            report_delta_db: defaults.report_delta_db,
+            report_max_silence_s: defaults.report_max_silence_s,
+            heartbeat_interval_s: defaults.heartbeat_interval_s,
+            alert_trigger_db: defaults.alert_trigger_db,
+            alert_clear_db: defaults.alert_clear_db,
+            alert_trigger_s: defaults.alert_trigger_s,
+            alert_clear_s: defaults.alert_clear_s,
+            vibration_alert_trigger_db: defaults.vibration_alert_trigger_db,
+            vibration_alert_clear_db: defaults.vibration_alert_clear_db,
+            vibration_alert_trigger_s: defaults.vibration_alert_trigger_s,
+            vibration_alert_clear_s: defaults.vibration_alert_clear_s,
+            alert_burst_s: defaults.alert_burst_s,
+            alert_burst_hz: defaults.alert_burst_hz,
+            alert_context_s: defaults.alert_context_s,
+            rules: defaults.rules,
+        }
+    }
+
+    // What manufacture wrote replaces cfg.toml. The device id and the client certificate are
+    // read by the sensor worker directly.
+    pub fn apply_factory_data(&mut self, data: &'static FactoryData) {
+        if let Some(host) = data.mqtt_host.as_deref() {
+            self.mqtt_host = host;
+        }
+        if let Some(ca_cert) = data.ca_cert.as_deref() {
+            self.mqtt_ca_cert = ca_cert;
+            self.mqtt_use_tls = true;
+        }
+        if let Some(tenant_id) = data.tenant_id.as_deref() {
+            self.tenant_id = tenant_id;
+        }
+        if let Some(site_id) = data.site_id.as_deref() {
+            self.site_id = site_id;
+        }
+        if let Some(floor) = data.floor.as_deref() {
+            self.floor = floor;
+        }
+    }
+
+    // The settings that can be changed at runtime, by the name they have in cfg.toml
+    fn set(&mut self, key: &str, value: &str) -> Result<(), &'static str> {
+        match key {
+            "level_floor_db" => self.level_floor_db = parse_f32(value)?,
+            "level_ceiling_db" => self.level_ceiling_db = parse_f32(value)?,
+            "outlier_window" => {
+                self.outlier_window = value.parse().map_err(|_| "Invalid window")?
+            }
+            "outlier_max_deviation_db" => self.outlier_max_deviation_db = parse_f32(value)?,
+            "normal_from_db" => self.normal_from_db = parse_f32(value)?,
+            "loud_from_db" => self.loud_from_db = parse_f32(value)?,
+            "very_loud_from_db" => self.very_loud_from_db = parse_f32(value)?,
+            "class_hysteresis_db" => self.class_hysteresis_db = parse_f32(value)?,
+            "outdoor_profile" => {
+                self.outdoor_profile = value.parse().map_err(|_| "Invalid boolean")?
+            }
+            "latitude" => self.latitude = parse_f32(value)?,
+            "longitude" => self.longitude = parse_f32(value)?,
+            "night_threshold_offset_db" => self.night_threshold_offset_db = parse_f32(value)?,
+            "day_led_brightness" => {
+                self.day_led_brightness = value.parse().map_err(|_| "Invalid brightness")?
+            }
+            "night_led_brightness" => {
+                self.night_led_brightness = value.parse().map_err(|_| "Invalid brightness")?
+            }
+            "thermal_limit_c" => self.thermal_limit_c = parse_f32(value)?,
+            "maintenance_reboot" => {
+                self.maintenance_reboot = value.parse().map_err(|_| "Invalid boolean")?
+            }
+            "maintenance_day" => self.maintenance_day = value.parse().map_err(|_| "Invalid day")?,
+            "maintenance_hour" => {
+                self.maintenance_hour = value.parse().map_err(|_| "Invalid hour")?
+            }
+            "maintenance_minute" => {
+                self.maintenance_minute = value.parse().map_err(|_| "Invalid minute")?
+            }
+            "vibration_normal_from_db" => self.vibration_normal_from_db = parse_f32(value)?,
+            "vibration_loud_from_db" => self.vibration_loud_from_db = parse_f32(value)?,
+            "vibration_very_loud_from_db" => self.vibration_very_loud_from_db = parse_f32(value)?,
+            "fusion_interval_s" => {
+                self.fusion_interval_s = value.parse().map_err(|_| "Invalid interval")?
+            }
+            "sample_interval_ms" => {
+                self.sample_interval_ms = value.parse().map_err(|_| "Invalid interval")?
+            }
+            "sample_window_ms" => {
+                self.sample_window_ms = value.parse().map_err(|_| "Invalid window")?
+            }
+            "weighting" => {
+                self.weighting = Weighting::parse(value).ok_or("Weighting is Z, A or C")?
+            }
+            "operating_mode" => {
+                self.operating_mode = match value {
+                    "meter" => "meter",
+                    "logger" => "logger",
+                    _ => return Err("Mode is meter or logger"),
+                }
+            }
+            "logger_interval_s" => {
+                self.logger_interval_s = value.parse().map_err(|_| "Invalid interval")?
+            }
+            "batch_size" => self.batch_size = value.parse().map_err(|_| "Invalid batch size")?,
+            "batch_interval_s" => {
+                self.batch_interval_s = value.parse().map_err(|_| "Invalid interval")?
+            }
+            "level_json" => self.level_json = value.parse().map_err(|_| "Invalid boolean")?,
+            "report_raw_rms" => {
+                self.report_raw_rms = value.parse().map_err(|_| "Invalid boolean")?
+            }
+            "band_fft_len" => {
+                self.band_fft_len = value.parse().map_err(|_| "Invalid FFT length")?
+            }
+            "offline_queue_len" => {
+                self.offline_queue_len = value.parse().map_err(|_| "Invalid queue length")?
+            }
+            "offline_queue_flash" => {
+                self.offline_queue_flash = value.parse().map_err(|_| "Invalid boolean")?
+            }
+            "flash_endurance_cycles" => {
+                self.flash_endurance_cycles = value.parse().map_err(|_| "Invalid cycle count")?
+            }
+            "flash_wear_throttle_pct" => {
+                self.flash_wear_throttle_pct = value.parse().map_err(|_| "Invalid percentage")?
+            }
+            "support_session_s" => {
+                self.support_session_s = value.parse().map_err(|_| "Invalid duration")?
+            }
+            "report_delta_db" => self.report_delta_db = parse_f32(value)?,
+            "report_max_silence_s" => {
+                self.report_max_silence_s = value.parse().map_err(|_| "Invalid interval")?
+            }
+            "heartbeat_interval_s" => {
+                self.heartbeat_interval_s = value.parse().map_err(|_| "Invalid interval")?
+            }
+            "alert_trigger_db" => self.alert_trigger_db = parse_f32(value)?,
+            "alert_clear_db" => self.alert_clear_db = parse_f32(value)?,
+            "alert_trigger_s" => {
+                self.alert_trigger_s = value.parse().map_err(|_| "Invalid duration")?
+            }
+            "alert_clear_s" => {
+                self.alert_clear_s = value.parse().map_err(|_| "Invalid duration")?
+            }
+            "vibration_alert_trigger_db" => self.vibration_alert_trigger_db = parse_f32(value)?,
+            "vibration_alert_clear_db" => self.vibration_alert_clear_db = parse_f32(value)?,
+            "vibration_alert_trigger_s" => {
+                self.vibration_alert_trigger_s = value.parse().map_err(|_| "Invalid duration")?
+            }
+            "vibration_alert_clear_s" => {
+                self.vibration_alert_clear_s = value.parse().map_err(|_| "Invalid duration")?
+            }
+            "alert_burst_s" => {
+                self.alert_burst_s = value.parse().map_err(|_| "Invalid duration")?
+            }
+            "alert_burst_hz" => self.alert_burst_hz = value.parse().map_err(|_| "Invalid rate")?,
+            "alert_context_s" => {
+                self.alert_context_s = value.parse().map_err(|_| "Invalid duration")?
+            }
+            _ => return Err("Unknown or read-only setting"),
+        }
+        Ok(())
+    }
+
+    // Every setting with its value, in the order of the struct, for the dump
+    fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("wifi_ssid", format!("{:?}", self.wifi_ssid)),
+            ("wifi_password", format!("{:?}", self.wifi_password)),
+            ("provisioning", format!("{:?}", self.provisioning)),
+            ("mqtt_host", format!("{:?}", self.mqtt_host)),
+            ("mqtt_user", format!("{:?}", self.mqtt_user)),
+            ("mqtt_password", format!("{:?}", self.mqtt_password)),
+            ("mqtt_use_tls", format!("{:?}", self.mqtt_use_tls)),
+            ("mqtt_ca_cert", format!("{:?}", self.mqtt_ca_cert)),
+            ("mqtt_transport", format!("{:?}", self.mqtt_transport)),
+            ("mqtt_ws_path", format!("{:?}", self.mqtt_ws_path)),
+            ("ha_discovery", format!("{:?}", self.ha_discovery)),
+            (
+                "ha_discovery_prefix",
+                format!("{:?}", self.ha_discovery_prefix),
+            ),
+            ("mqtt_protocol", format!("{:?}", self.mqtt_protocol)),
+            ("mqtt_qos", format!("{:?}", self.mqtt_qos)),
+            ("mqtt_client_id", format!("{:?}", self.mqtt_client_id)),
+            (
+                "mqtt_client_id_prefix",
+                format!("{:?}", self.mqtt_client_id_prefix),
+            ),
+            ("mqtt_keep_alive_s", format!("{:?}", self.mqtt_keep_alive_s)),
+            (
+                "mqtt_clean_session",
+                format!("{:?}", self.mqtt_clean_session),
+            ),
+            (
+                "mqtt_reconnect_timeout_s",
+                format!("{:?}", self.mqtt_reconnect_timeout_s),
+            ),
+            ("mqtt_auth", format!("{:?}", self.mqtt_auth)),
+            ("jwt_key", format!("{:?}", self.jwt_key)),
+            ("jwt_audience", format!("{:?}", self.jwt_audience)),
+            ("token_url", format!("{:?}", self.token_url)),
+            ("token_lifetime_s", format!("{:?}", self.token_lifetime_s)),
+            ("est_url", format!("{:?}", self.est_url)),
+            ("est_user", format!("{:?}", self.est_user)),
+            ("est_password", format!("{:?}", self.est_password)),
+            (
+                "cert_renew_before_days",
+                format!("{:?}", self.cert_renew_before_days),
+            ),
+            ("topic_template", format!("{:?}", self.topic_template)),
+            ("site", format!("{:?}", self.site)),
+            ("floor", format!("{:?}", self.floor)),
+            ("tenant_id", format!("{:?}", self.tenant_id)),
+            ("site_id", format!("{:?}", self.site_id)),
+            ("topic_migration", format!("{:?}", self.topic_migration)),
+            ("claim_topic", format!("{:?}", self.claim_topic)),
+            (
+                "require_encrypted_secrets",
+                format!("{:?}", self.require_encrypted_secrets),
+            ),
+            ("encrypt_payloads", format!("{:?}", self.encrypt_payloads)),
+            ("sign_payloads", format!("{:?}", self.sign_payloads)),
+            ("demo_mode", format!("{:?}", self.demo_mode)),
+            ("web_dashboard", format!("{:?}", self.web_dashboard)),
+            ("web_user", format!("{:?}", self.web_user)),
+            ("web_token", format!("{:?}", self.web_token)),
+            ("display_unit", format!("{:?}", self.display_unit)),
+            (
+                "display_decimal_comma",
+                format!("{:?}", self.display_decimal_comma),
+            ),
+            ("display_clock", format!("{:?}", self.display_clock)),
+            (
+                "display_class_labels",
+                format!("{:?}", self.display_class_labels),
+            ),
+            (
+                "diagnostics_ap_password",
+                format!("{:?}", self.diagnostics_ap_password),
+            ),
+            ("level_floor_db", format!("{:?}", self.level_floor_db)),
+            ("level_ceiling_db", format!("{:?}", self.level_ceiling_db)),
+            ("outlier_window", format!("{:?}", self.outlier_window)),
+            (
+                "outlier_max_deviation_db",
+                format!("{:?}", self.outlier_max_deviation_db),
+            ),
+            ("normal_from_db", format!("{:?}", self.normal_from_db)),
+            ("loud_from_db", format!("{:?}", self.loud_from_db)),
+            ("very_loud_from_db", format!("{:?}", self.very_loud_from_db)),
+            (
+                "class_hysteresis_db",
+                format!("{:?}", self.class_hysteresis_db),
+            ),
+            ("outdoor_profile", format!("{:?}", self.outdoor_profile)),
+            ("latitude", format!("{:?}", self.latitude)),
+            ("longitude", format!("{:?}", self.longitude)),
+            (
+                "night_threshold_offset_db",
+                format!("{:?}", self.night_threshold_offset_db),
+            ),
+            (
+                "day_led_brightness",
+                format!("{:?}", self.day_led_brightness),
+            ),
+            (
+                "night_led_brightness",
+                format!("{:?}", self.night_led_brightness),
+            ),
+            ("thermal_limit_c", format!("{:?}", self.thermal_limit_c)),
+            ("timezone", format!("{:?}", self.timezone)),
+            ("time_topic", format!("{:?}", self.time_topic)),
+            (
+                "time_topic_interval_s",
+                format!("{:?}", self.time_topic_interval_s),
+            ),
+            (
+                "maintenance_reboot",
+                format!("{:?}", self.maintenance_reboot),
+            ),
+            ("maintenance_day", format!("{:?}", self.maintenance_day)),
+            ("maintenance_hour", format!("{:?}", self.maintenance_hour)),
+            (
+                "maintenance_minute",
+                format!("{:?}", self.maintenance_minute),
+            ),
+            ("tone_detectors", format!("{:?}", self.tone_detectors)),
+            ("direction_mic", format!("{:?}", self.direction_mic)),
+            ("vibration_sensor", format!("{:?}", self.vibration_sensor)),
+            (
+                "vibration_normal_from_db",
+                format!("{:?}", self.vibration_normal_from_db),
+            ),
+            (
+                "vibration_loud_from_db",
+                format!("{:?}", self.vibration_loud_from_db),
+            ),
+            (
+                "vibration_very_loud_from_db",
+                format!("{:?}", self.vibration_very_loud_from_db),
+            ),
+            ("fusion_interval_s", format!("{:?}", self.fusion_interval_s)),
+            (
+                "sample_interval_ms",
+                format!("{:?}", self.sample_interval_ms),
+            ),
+            ("sample_window_ms", format!("{:?}", self.sample_window_ms)),
+            ("weighting", format!("{:?}", self.weighting.as_str())),
+            ("operating_mode", format!("{:?}", self.operating_mode)),
+            ("logger_interval_s", format!("{:?}", self.logger_interval_s)),
+            ("logger_schedule", format!("{:?}", self.logger_schedule)),
+            ("batch_size", format!("{:?}", self.batch_size)),
+            ("batch_interval_s", format!("{:?}", self.batch_interval_s)),
+            ("level_json", format!("{:?}", self.level_json)),
+            ("report_raw_rms", format!("{:?}", self.report_raw_rms)),
+            ("band_fft_len", format!("{:?}", self.band_fft_len)),
+            ("offline_queue_len", format!("{:?}", self.offline_queue_len)),
+            (
+                "offline_queue_flash",
+                format!("{:?}", self.offline_queue_flash),
+            ),
+            (
+                "flash_endurance_cycles",
+                format!("{:?}", self.flash_endurance_cycles),
+            ),
+            (
+                "flash_wear_throttle_pct",
+                format!("{:?}", self.flash_wear_throttle_pct),
+            ),
+            ("support_session_s", format!("{:?}", self.support_session_s)),
+            ("report_delta_db", format!("{:?}", self.report_delta_db)),
+            (
+                "report_max_silence_s",
+                format!("{:?}", self.report_max_silence_s),
+            ),
+            (
+                "heartbeat_interval_s",
+                format!("{:?}", self.heartbeat_interval_s),
+            ),
+            ("alert_trigger_db", format!("{:?}", self.alert_trigger_db)),
+            ("alert_clear_db", format!("{:?}", self.alert_clear_db)),
+            ("alert_trigger_s", format!("{:?}", self.alert_trigger_s)),
+            ("alert_clear_s", format!("{:?}", self.alert_clear_s)),
+            (
+                "vibration_alert_trigger_db",
+                format!("{:?}", self.vibration_alert_trigger_db),
+            ),
+            (
+                "vibration_alert_clear_db",
+                format!("{:?}", self.vibration_alert_clear_db),
+            ),
+            (
+                "vibration_alert_trigger_s",
+                format!("{:?}", self.vibration_alert_trigger_s),
+            ),
+            (
+                "vibration_alert_clear_s",
+                format!("{:?}", self.vibration_alert_clear_s),
+            ),
+            ("alert_burst_s", format!("{:?}", self.alert_burst_s)),
+            ("alert_burst_hz", format!("{:?}", self.alert_burst_hz)),
+            ("alert_context_s", format!("{:?}", self.alert_context_s)),
+            ("rules", format!("{:?}", self.rules)),
+        ]
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.normal_from_db >= self.loud_from_db || self.loud_from_db >= self.very_loud_from_db {
+            return Err("Thresholds must be increasing");
+        }
+        if self.vibration_normal_from_db >= self.vibration_loud_from_db
+            || self.vibration_loud_from_db >= self.vibration_very_loud_from_db
+        {
+            return Err("Vibration thresholds must be increasing");
+        }
+        if self.level_floor_db >= self.level_ceiling_db {
+            return Err("Level floor must be below the ceiling");
+        }
+        if self.outlier_window == 0 {
+            return Err("Outlier window can't be empty");
+        }
+        if self.class_hysteresis_db < 0.0 || self.outlier_max_deviation_db <= 0.0 {
+            return Err("Hysteresis and deviation can't be negative");
+        }
+        if self.alert_clear_db > self.alert_trigger_db
+            || self.vibration_alert_clear_db > self.vibration_alert_trigger_db
+        {
+            return Err("Alerts must clear at or below their trigger level");
+        }
+        if !(1..=100).contains(&self.sample_interval_ms) {
+            return Err("Sample interval must be 1 to 100 ms");
+        }
+        if !(MIN_SAMPLE_WINDOW_MS..=MAX_SAMPLE_WINDOW_MS).contains(&self.sample_window_ms) {
+            return Err("Sample windows are 50 to 500 ms");
+        }
+        if !(1..=MAX_LOGGER_INTERVAL_S).contains(&self.logger_interval_s) {
+            return Err("Logger intervals are 1 s to an hour");
+        }
+        if self.batch_size > MAX_BATCH_SIZE {
+            return Err("Batches are up to 100 readings");
+        }
+        if self.offline_queue_len > MAX_OFFLINE_QUEUE_LEN {
+            return Err("The offline queue holds up to 500 readings");
+        }
+        if self.flash_endurance_cycles == 0 {
+            return Err("Flash endurance can't be zero");
+        }
+        if !(1..=100).contains(&self.flash_wear_throttle_pct) {
+            return Err("Wear throttling starts at 1 to 100 %");
+        }
+        if ![0, 256, 512].contains(&self.band_fft_len) {
+            return Err("Band FFTs are 256 or 512 points");
+        }
+        if !(MIN_SUPPORT_SESSION_S..=MAX_SUPPORT_SESSION_S).contains(&self.support_session_s) {
+            return Err("Support sessions last 1 minute to 4 hours");
+        }
+        if self.alert_burst_s > MAX_ALERT_BURST_S {
+            return Err("Alert bursts are up to 60 s each side");
+        }
+        if !(1..=MAX_ALERT_BURST_HZ).contains(&self.alert_burst_hz) {
+            return Err("Alert bursts are 1 to 20 Hz");
+        }
+        if self.alert_context_s > MAX_ALERT_CONTEXT_S {
+            return Err("Alerts carry up to 5 s of context");
+        }
+        if self.report_delta_db < 0.0 {
+            return Err("Report delta can't be negative");
+        }
+        if !(-90.0..=90.0).contains(&self.latitude) || !(-180.0..=180.0).contains(&self.longitude) {
+            return Err("Invalid coordinates");
+        }
+        if self.maintenance_day > 7 || self.maintenance_hour > 23 || self.maintenance_minute > 59 {
+            return Err("Invalid maintenance window");
+        }
+        Ok(())
+    }
+}
+
+fn parse_f32(value: &str) -> Result<f32, &'static str> {
+    value
+        .parse()
+        .ok()
+        .filter(|value: &f32| value.is_finite())
+        .ok_or("Invalid number")
+}
+
+// Splits `key=value` pairs separated by spaces, commas or newlines
+fn pairs(payload: &str) -> impl Iterator<Item = Result<(&str, &str), &'static str>> {
+    payload
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').ok_or("Expected key=value"))
+}
+
+// Where the overrides are kept between boots, NVS on the device
+pub trait OverrideStorage: Send {
+    fn store(&mut self, blob: &str);
+}
+
+struct State {
+    version: u32,
+    config: Config,
+    // What was changed at runtime, in the order it was first set, as stored
+    overrides: Vec<(String, String)>,
+}
+
+struct Shared {
+    storage: Mutex<Option<Box<dyn OverrideStorage>>>,
+    state: RwLock<State>,
+}
+
+// The settings every module reads, seeded from cfg.toml and the stored overrides.
+// Updates are validated as a whole, persisted and then seen by every subscriber.
+#[derive(Clone)]
+pub struct ConfigStore(Arc<Shared>);
+
+impl ConfigStore {
+    pub fn new(config: Config, storage: Option<Box<dyn OverrideStorage>>) -> Self {
+        ConfigStore(Arc::new(Shared {
+            storage: Mutex::new(storage),
+            state: RwLock::new(State {
+                version: 0,
+                config,
+                overrides: vec![],
+            }),
+        }))
+    }
+
+    pub fn get(&self) -> Config {
+        self.0.state.read().unwrap().config
+    }
+
+    // Applies all the `key=value` pairs of the payload or none of them
+    pub fn update(&self, payload: &[u8]) -> Result<Config, &'static str> {
+        let payload = std::str::from_utf8(payload).map_err(|_| "Config is not valid UTF-8")?;
+        self.apply(payload, true)
+    }
+
+    // The overrides as stored, applied without storing them again
+    pub fn restore(&self, stored: &str) -> Result<Config, &'static str> {
+        self.apply(stored, false)
+    }
+
+    // Every effective setting as `key = value`, with secrets redacted and the ones changed at
+    // runtime marked, for the serial log
+    pub fn dump(&self) -> String {
+        let state = self.0.state.read().unwrap();
+        state
+            .config
+            .entries()
+            .into_iter()
+            .map(|(key, value)| {
+                let value = if SECRETS.contains(&key) && value != "\"\"" {
+                    String::from("<redacted>")
+                } else {
+                    value
+                };
+                if state
+                    .overrides
+                    .iter()
+                    .any(|(overridden, _)| overridden == key)
+                {
+                    format!("{} = {} (runtime)", key, value)
+                } else {
+                    format!("{} = {}", key, value)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // Only changes made after this call are reported to the watch
+    pub fn subscribe(&self) -> ConfigWatch {
+        ConfigWatch {
+            seen: self.0.state.read().unwrap().version,
+            store: self.clone(),
+        }
+    }
+
+    fn apply(&self, payload: &str, persist: bool) -> Result<Config, &'static str> {
+        let mut state = self.0.state.write().unwrap();
+        let mut config = state.config;
+        let mut overrides = state.overrides.clone();
+        for pair in pairs(payload) {
+            let (key, value) = pair?;
+            config.set(key, value)?;
+            match overrides
+                .iter_mut()
+                .find(|(overridden, _)| overridden == key)
+            {
+                Some((_, overridden)) => *overridden = value.to_string(),
+                None => overrides.push((key.to_string(), value.to_string())),
+            }
+        }
+        config.validate()?;
+        // The retained config comes again on every connect, which needn't wear the flash
+        if persist && overrides != state.overrides {
+            let blob: String = overrides
+                .iter()
+                .map(|(key, value)| format!("{}={}\n", key, value))
+                .collect();
+            if let Some(storage) = self.0.storage.lock().unwrap().as_mut() {
+                storage.store(&blob);
+            }
+        }
+        state.version = state.version.wrapping_add(1);
+        state.config = config;
+        state.overrides = overrides;
+        Ok(config)
+    }
+}
+
+pub struct ConfigWatch {
+    store: ConfigStore,
+    seen: u32,
+}
+
+impl ConfigWatch {
+    // Returns the configuration only when it changed since the last call
+    pub fn changed(&mut self) -> Option<Config> {
+        let state = self.store.0.state.read().unwrap();
+        if state.version == self.seen {
+            return None;
+        }
+        self.seen = state.version;
+        Some(state.config)
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering::Relaxed},
+        mpsc, Mutex, OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use esp_idf_svc::{hal::task::thread::ThreadSpawnConfiguration, mqtt::client::EspMqttClient};
+
+use crate::watchdog::{self, Watchdog};
+
+// Below the sensor task, so the CPU-bound part of a TLS handshake waits for sampling on the
+// single core rather than the other way round
+pub const MQTT_TASK_PRIORITY: u8 = 3;
+const RETIRE_TASK_PRIORITY: u8 = 2;
+// Well beyond esp-mqtt's network timeout plus a slow handshake, so only a wedged one trips it
+const HANDSHAKE_WATCHDOG_TIMEOUT: Duration = Duration::from_secs(90);
+// Stopping a client waits for its task, which may be in the middle of a handshake
+const RETIRE_WATCHDOG_TIMEOUT: Duration = Duration::from_secs(90);
+
+static RETIRED: OnceLock<Mutex<mpsc::Sender<EspMqttClient<'static>>>> = OnceLock::new();
+static HANDSHAKE: Mutex<Option<Watchdog>> = Mutex::new(None);
+// Events of retired clients still come in while they stop, and are dropped by this
+static GENERATION: AtomicU32 = AtomicU32::new(0);
+
+// Once at boot, before the first client is retired
+pub fn start() {
+    let (retired_tx, retired_rx) = mpsc::channel::<EspMqttClient<'static>>();
+    let config = ThreadSpawnConfiguration {
+        name: Some(b"mqtt_conn\0"),
+        priority: RETIRE_TASK_PRIORITY,
+        ..Default::default()
+    };
+    if let Err(err) = config.set() {
+        log::warn!("Unable to lower connection task priority: {}", err);
+    }
+    let spawned = thread::Builder::new().stack_size(4096).spawn(move || {
+        for client in retired_rx {
+            let watchdog = watchdog::register("mqtt_conn", RETIRE_WATCHDOG_TIMEOUT);
+            drop(client);
+            drop(watchdog);
+        }
+    });
+    if let Err(err) = ThreadSpawnConfiguration::default().set() {
+        log::warn!("Unable to reset thread configuration: {}", err);
+    }
+    match spawned {
+        Ok(_) => {
+            let _ = RETIRED.set(Mutex::new(retired_tx));
+        }
+        Err(err) => log::error!("Unable to start connection task: {}", err),
+    }
+}
+
+// Stops the client off the sensor task. Without the connection task it is stopped right here.
+pub fn retire(client: EspMqttClient<'static>) {
+    GENERATION.fetch_add(1, Relaxed);
+    // The retire watchdog covers a handshake it interrupts
+    handshake_finished();
+    if let Some(retired_tx) = RETIRED.get() {
+        if let Err(mpsc::SendError(client)) = retired_tx.lock().unwrap().send(client) {
+            drop(client);
+        }
+    }
+}
+
+// For the callback of the next client, to tell its events from those of retired ones
+pub fn generation() -> u32 {
+    GENERATION.load(Relaxed)
+}
+
+pub fn is_current(generation: u32) -> bool {
+    GENERATION.load(Relaxed) == generation
+}
+
+// From the MQTT task: the handshake has its own watchdog while it runs
+pub fn handshake_started() {
+    *HANDSHAKE.lock().unwrap() = Some(watchdog::register("mqtt", HANDSHAKE_WATCHDOG_TIMEOUT));
+}
+
+pub fn handshake_finished() {
+    HANDSHAKE.lock().unwrap().take();
+}
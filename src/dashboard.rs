@@ -0,0 +1,456 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use esp_idf_svc::{
+    eventloop::EspBackgroundSubscription,
+    http::{
+        server::{
+            ws::EspHttpWsDetachedSender, Configuration as HttpConfiguration, EspHttpConnection,
+            EspHttpServer, Request,
+        },
+        Method,
+    },
+    io::{EspIOError, Write},
+    sys::{esp_timer_get_time, EspError},
+    ws::FrameType,
+};
+
+use crate::{
+    bus::{self, BusEvent, Link},
+    classification::NoiseClass,
+    clock,
+    command::Command,
+    display::DisplaySettings,
+    dsp::{self, Decibel},
+    maintenance, network, thermal,
+    web_auth::{Access, WebAuth},
+};
+
+const PAGE: &str = include_str!("dashboard.html");
+// Two minutes of one level per second, what the sparkline shows
+const HISTORY_LEN: usize = 120;
+const HISTORY_INTERVAL: Duration = Duration::from_secs(1);
+// A day of one Leq per minute for /history
+const MINUTES_LEN: usize = 24 * 60;
+// The server has 7 sockets by default, keep some for the page and the buttons
+const MAX_STREAM_CLIENTS: usize = 3;
+
+struct Readings {
+    level: Option<Decibel>,
+    class: Option<NoiseClass>,
+    // Local time of the level, in minutes of the day, None before the clock is set
+    minute_of_day: Option<u16>,
+    history: VecDeque<Decibel>,
+    last_history: Option<Instant>,
+    // Minutes of uptime, oldest first, None where no level was recorded (paused, worker restart)
+    minutes: VecDeque<Option<Decibel>>,
+    minute: u64,
+    // The history levels of the current minute
+    minute_levels: Vec<Decibel>,
+    wifi: bool,
+    mqtt: bool,
+    // Sequence number and level of the latest alert since boot
+    last_alert: Option<(u32, Decibel)>,
+}
+
+static READINGS: Mutex<Readings> = Mutex::new(Readings {
+    level: None,
+    class: None,
+    minute_of_day: None,
+    history: VecDeque::new(),
+    last_history: None,
+    minutes: VecDeque::new(),
+    minute: 0,
+    minute_levels: Vec::new(),
+    wifi: false,
+    mqtt: false,
+    last_alert: None,
+});
+
+// Set again by every start, so a dashboard restarted for new settings shows them
+static DISPLAY: RwLock<Option<DisplaySettings>> = RwLock::new(None);
+
+// While the diagnostics access point is up, whoever is near enough can reach the page
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+// Buttons pressed on the page, handled by the sensor loop like commands from the broker
+static PENDING_COMMANDS: Mutex<Vec<Command>> = Mutex::new(Vec::new());
+
+static STREAM_CLIENTS: Mutex<Vec<EspHttpWsDetachedSender>> = Mutex::new(Vec::new());
+// Connected, but yet to authenticate
+static STREAM_PENDING: Mutex<Vec<EspHttpWsDetachedSender>> = Mutex::new(Vec::new());
+
+// Keeps the latest level for the page. Once per second, the level also goes into the history
+// and out to the stream clients.
+fn record(level: Decibel, class: Option<NoiseClass>) {
+    let reading = {
+        let mut readings = READINGS.lock().unwrap();
+        readings.level = Some(level);
+        readings.class = class;
+        readings.minute_of_day = clock::local_minute_of_day();
+        if readings
+            .last_history
+            .is_some_and(|last| last.elapsed() < HISTORY_INTERVAL)
+        {
+            return;
+        }
+        readings.last_history = Some(Instant::now());
+        if readings.history.len() == HISTORY_LEN {
+            readings.history.pop_front();
+        }
+        readings.history.push_back(level);
+        let minute = uptime_s() / 60;
+        if minute != readings.minute {
+            close_minutes(&mut readings, minute);
+        }
+        readings.minute_levels.push(level);
+        // The page still polls, the stream is optional work while the chip is too hot
+        if thermal::is_throttled() {
+            return;
+        }
+        format!("{{{}}}", reading_fields(&readings))
+    };
+    broadcast(&reading);
+}
+
+fn close_minutes(readings: &mut Readings, minute: u64) {
+    let leq = dsp::leq(&readings.minute_levels);
+    readings.minute_levels.clear();
+    let skipped = minute
+        .saturating_sub(readings.minute + 1)
+        .min(MINUTES_LEN as u64);
+    let closed = std::iter::once(leq).chain((0..skipped).map(|_| None));
+    for level in closed {
+        if readings.minutes.len() == MINUTES_LEN {
+            readings.minutes.pop_front();
+        }
+        readings.minutes.push_back(level);
+    }
+    readings.minute = minute;
+}
+
+// Sending goes through the server task, which also runs the handler that adds and removes
+// clients, so the lock must not be held while sending
+fn broadcast(message: &str) {
+    let mut clients = std::mem::take(&mut *STREAM_CLIENTS.lock().unwrap());
+    if clients.is_empty() {
+        return;
+    }
+    clients.retain_mut(|client| {
+        !client.is_closed()
+            && client
+                .send(FrameType::Text(false), message.as_bytes())
+                .map_err(|err| log::warn!("Dropping stream client {}: {}", client.session(), err))
+                .is_ok()
+    });
+    STREAM_CLIENTS.lock().unwrap().append(&mut clients);
+}
+
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Relaxed);
+}
+
+pub fn take_commands() -> Vec<Command> {
+    std::mem::take(&mut *PENDING_COMMANDS.lock().unwrap())
+}
+
+fn reading_fields(readings: &Readings) -> String {
+    let level = readings
+        .level
+        .map_or_else(|| String::from("null"), |level| format!("{:.1}", level.0));
+    let class = readings.class.map_or_else(
+        || String::from("null"),
+        |class| format!("\"{}\"", class.as_str()),
+    );
+    let last_alert = readings.last_alert.map_or_else(
+        || String::from("null"),
+        |(seq, level)| format!("{{\"seq\":{},\"level_db\":{:.1}}}", seq, level.0),
+    );
+    // What the page shows, spelled out as configured. Labels may hold anything, hence serde.
+    let display = DISPLAY.read().unwrap().clone().unwrap_or_default();
+    let text = |text: &str| serde_json::to_string(text).unwrap_or_default();
+    format!(
+        "\"level_db\":{},\"class\":{},\"uptime_s\":{},\"maintenance\":{},\"wifi\":{},\"mqtt\":{},\"last_alert\":{},\"level_text\":{},\"class_text\":{},\"time_text\":{}",
+        level,
+        class,
+        uptime_s(),
+        maintenance::in_mode(),
+        readings.wifi,
+        readings.mqtt,
+        last_alert,
+        text(&display.level(readings.level)),
+        text(display.class(readings.class)),
+        text(&display.clock(readings.minute_of_day))
+    )
+}
+
+fn uptime_s() -> u64 {
+    (unsafe { esp_timer_get_time() } / 1_000_000) as u64
+}
+
+// `/history?window=1h&resolution=1m`, oldest level first. Resolutions under a minute come from
+// the last two minutes of levels, longer ones from the minute Leqs of the last day, and each
+// point is the Leq of the levels it covers.
+fn history_json(uri: &str) -> Result<String, &'static str> {
+    let window = query_param(uri, "window").map_or(Some(3600), parse_duration_s);
+    let resolution = query_param(uri, "resolution").map_or(Some(60), parse_duration_s);
+    let (Some(window), Some(resolution)) = (window, resolution) else {
+        return Err("window and resolution take durations like 90s, 15m or 1h");
+    };
+    if resolution == 0 || window % resolution != 0 {
+        return Err("window must be a multiple of resolution");
+    }
+    let (step, max_window) = if resolution < 60 {
+        (1, HISTORY_LEN as u64)
+    } else {
+        (60, MINUTES_LEN as u64 * 60)
+    };
+    if resolution % step != 0 || window > max_window {
+        return Err("at most 2m in seconds or 24h in minutes");
+    }
+    let readings = READINGS.lock().unwrap();
+    let source: Vec<Option<Decibel>> = if step == 1 {
+        readings.history.iter().copied().map(Some).collect()
+    } else {
+        readings.minutes.iter().copied().collect()
+    };
+    let count = ((window / step) as usize).min(source.len());
+    let levels: Vec<String> = source[source.len() - count..]
+        .rchunks((resolution / step) as usize)
+        .rev()
+        .map(|group| {
+            let group: Vec<Decibel> = group.iter().flatten().copied().collect();
+            dsp::leq(&group).map_or_else(|| String::from("null"), |level| format!("{:.1}", level.0))
+        })
+        .collect();
+    Ok(format!(
+        "{{\"window_s\":{},\"resolution_s\":{},\"levels\":[{}]}}",
+        window,
+        resolution,
+        levels.join(",")
+    ))
+}
+
+fn query_param<'a>(uri: &'a str, name: &str) -> Option<&'a str> {
+    let (_, query) = uri.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+fn parse_duration_s(text: &str) -> Option<u64> {
+    let unit = match text.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        _ => return None,
+    };
+    text[..text.len() - 1]
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(unit)
+}
+
+fn status_json(stream_ticket: &str) -> String {
+    let readings = READINGS.lock().unwrap();
+    let history: Vec<String> = readings
+        .history
+        .iter()
+        .map(|level| format!("{:.1}", level.0))
+        .collect();
+    format!(
+        "{{{},\"history\":[{}],\"stream_ticket\":\"{}\"}}",
+        reading_fields(&readings),
+        history.join(","),
+        stream_ticket
+    )
+}
+
+// Sends the error response and returns None unless the request may proceed
+fn authorized<'b, 'a>(
+    auth: &WebAuth,
+    req: Request<&'b mut EspHttpConnection<'a>>,
+) -> Result<Option<Request<&'b mut EspHttpConnection<'a>>>, EspIOError> {
+    match auth.check_header(req.header("Authorization")) {
+        Access::Granted => return Ok(Some(req)),
+        Access::Denied => {
+            req.into_response(
+                401,
+                None,
+                &[("WWW-Authenticate", "Basic realm=\"Mosquitto bzzz\"")],
+            )?
+            .flush()?;
+        }
+        Access::LockedOut(remaining) => {
+            let retry_after = (remaining.as_secs() + 1).to_string();
+            req.into_response(429, None, &[("Retry-After", retry_after.as_str())])?
+                .flush()?;
+        }
+    }
+    Ok(None)
+}
+
+// A page for installers to check the sensor before there is any backend. Stops serving when
+// dropped.
+pub struct Dashboard {
+    _server: EspHttpServer<'static>,
+    _bus: EspBackgroundSubscription<'static>,
+}
+
+impl Dashboard {
+    // `bundle` builds the diagnostic bundle, on the server's task
+    pub fn start(
+        auth: WebAuth,
+        display: DisplaySettings,
+        bundle: impl Fn() -> Vec<u8> + Send + 'static,
+    ) -> Result<Self> {
+        *DISPLAY.write().unwrap() = Some(display);
+        let bus = bus::subscribe(|event| match event {
+            BusEvent::MeasurementReady { level, class } => record(level, class),
+            BusEvent::ConnectivityChanged { link, up } => {
+                let mut readings = READINGS.lock().unwrap();
+                match link {
+                    Link::Wifi => readings.wifi = up,
+                    Link::Mqtt => readings.mqtt = up,
+                }
+            }
+            BusEvent::AlertRaised { seq, level } => {
+                READINGS.lock().unwrap().last_alert = Some((seq, level));
+            }
+        })?;
+        // WiFi usually came up before anyone listened
+        READINGS.lock().unwrap().wifi = network::sta_rssi().is_some();
+        let auth = Arc::new(auth);
+        let mut server = EspHttpServer::new(&HttpConfiguration::default())?;
+        let page_auth = auth.clone();
+        server.fn_handler("/", Method::Get, move |req| {
+            let Some(req) = authorized(&page_auth, req)? else {
+                return Ok(());
+            };
+            req.into_response(200, None, &[("Content-Type", "text/html")])?
+                .write_all(PAGE.as_bytes())
+        })?;
+        let status_auth = auth.clone();
+        server.fn_handler("/status", Method::Get, move |req| {
+            let Some(req) = authorized(&status_auth, req)? else {
+                return Ok(());
+            };
+            req.into_response(
+                200,
+                None,
+                &[
+                    ("Content-Type", "application/json"),
+                    ("Cache-Control", "no-store"),
+                ],
+            )?
+            .write_all(status_json(status_auth.stream_ticket()).as_bytes())
+        })?;
+        let history_auth = auth.clone();
+        server.fn_handler("/history", Method::Get, move |req| {
+            let Some(req) = authorized(&history_auth, req)? else {
+                return Ok(());
+            };
+            match history_json(req.uri()) {
+                Ok(history) => req
+                    .into_response(200, None, &[("Content-Type", "application/json")])?
+                    .write_all(history.as_bytes()),
+                Err(message) => req.into_status_response(400)?.write_all(message.as_bytes()),
+            }
+        })?;
+        let bundle_auth = auth.clone();
+        server.fn_handler("/diag_bundle", Method::Get, move |req| {
+            let Some(req) = authorized(&bundle_auth, req)? else {
+                return Ok(());
+            };
+            req.into_response(
+                200,
+                None,
+                &[
+                    ("Content-Type", "application/zlib"),
+                    (
+                        "Content-Disposition",
+                        "attachment; filename=\"bzzz-diag.zlib\"",
+                    ),
+                    ("Cache-Control", "no-store"),
+                ],
+            )?
+            .write_all(&bundle())
+        })?;
+        // One reading per second over a WebSocket, for the page and commissioning tools. Clients
+        // only get readings after sending the access token or the stream ticket of /status as
+        // their first frame.
+        let stream_auth = auth.clone();
+        server.ws_handler("/stream", move |ws| {
+            let session = ws.session();
+            if ws.is_new() {
+                let pending = STREAM_PENDING.lock().unwrap();
+                if pending.len() + STREAM_CLIENTS.lock().unwrap().len() >= MAX_STREAM_CLIENTS {
+                    log::warn!("Refusing stream client {}, too many", session);
+                    return ws.send(FrameType::Close, &[]);
+                }
+                drop(pending);
+                let sender = ws.create_detached_sender()?;
+                STREAM_PENDING.lock().unwrap().push(sender);
+                log::info!("Stream client {} connected", session);
+            } else if ws.is_closed() {
+                STREAM_PENDING
+                    .lock()
+                    .unwrap()
+                    .retain(|client| client.session() != session);
+                STREAM_CLIENTS
+                    .lock()
+                    .unwrap()
+                    .retain(|client| client.session() != session);
+            } else {
+                let mut frame = [0u8; 128];
+                let (_, len) = ws.recv(&mut frame)?;
+                let mut pending = STREAM_PENDING.lock().unwrap();
+                // Clients only listen once authenticated, drop whatever else they send
+                if let Some(index) = pending
+                    .iter()
+                    .position(|client| client.session() == session)
+                {
+                    let client = pending.remove(index);
+                    drop(pending);
+                    if stream_auth.check_stream_key(&frame[..len.min(frame.len())])
+                        == Access::Granted
+                    {
+                        STREAM_CLIENTS.lock().unwrap().push(client);
+                    } else {
+                        return ws.send(FrameType::Close, &[]);
+                    }
+                }
+            }
+            Ok::<(), EspError>(())
+        })?;
+        for (uri, command) in [
+            ("/identify", Command::Identify),
+            ("/reboot", Command::Restart),
+        ] {
+            let command_auth = auth.clone();
+            server.fn_handler(uri, Method::Post, move |req| {
+                let Some(req) = authorized(&command_auth, req)? else {
+                    return Ok(());
+                };
+                if READ_ONLY.load(Relaxed) {
+                    return req.into_status_response(403)?.flush();
+                }
+                PENDING_COMMANDS.lock().unwrap().push(command);
+                req.into_status_response(204)?.flush()
+            })?;
+        }
+        log::info!("Dashboard listening on port 80");
+        Ok(Dashboard {
+            _server: server,
+            _bus: bus,
+        })
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use esp_idf_svc::mqtt::client::{EspMqttClient, MessageId, QoS};
+
+use crate::mqtt5::Publish;
+
+// A PUBACK normally arrives within a round trip, this leaves room for a slow cellular link
+const ACK_TIMEOUT: Duration = Duration::from_secs(15);
+const MAX_ATTEMPTS: u32 = 3;
+// About two minutes of level readings at the default rate
+const MAX_IN_FLIGHT: usize = 32;
+
+struct Pending {
+    msg_id: MessageId,
+    topic: String,
+    // Already signed and sealed, so a retry is the very same message
+    payload: Vec<u8>,
+    sent: Instant,
+    attempts: u32,
+}
+
+// Keeps readings published with QoS 1 or 2 until the broker acknowledges them, and publishes
+// those without an acknowledgement within the timeout again. The client gets recreated on
+// reconnect, and PUBACKs for the old one's message ids never arrive.
+pub struct DeliveryTracker {
+    qos: QoS,
+    in_flight: VecDeque<Pending>,
+}
+
+impl DeliveryTracker {
+    pub fn new(qos: QoS) -> Self {
+        DeliveryTracker {
+            qos,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    pub fn qos(&self) -> QoS {
+        self.qos
+    }
+
+    pub fn sent(&mut self, msg_id: MessageId, topic: &str, payload: Vec<u8>) {
+        if self.qos == QoS::AtMostOnce {
+            return;
+        }
+        if self.in_flight.len() == MAX_IN_FLIGHT {
+            if let Some(oldest) = self.in_flight.pop_front() {
+                log::warn!(
+                    "Too many unacknowledged readings, dropping message {} to {}",
+                    oldest.msg_id,
+                    oldest.topic
+                );
+            }
+        }
+        self.in_flight.push_back(Pending {
+            msg_id,
+            topic: topic.to_string(),
+            payload,
+            sent: Instant::now(),
+            attempts: 1,
+        });
+    }
+
+    pub fn acknowledge(&mut self, msg_id: MessageId) {
+        self.in_flight.retain(|pending| pending.msg_id != msg_id);
+    }
+
+    // Publishes overdue readings again, and gives up on them after the last attempt
+    pub fn retry_overdue(&mut self, mqtt_client: &mut EspMqttClient) {
+        let now = Instant::now();
+        for pending in self.in_flight.iter_mut() {
+            if now.duration_since(pending.sent) < ACK_TIMEOUT {
+                continue;
+            }
+            if pending.attempts == MAX_ATTEMPTS {
+                log::error!(
+                    "Message {} to {} not acknowledged after {} attempts",
+                    pending.msg_id,
+                    pending.topic,
+                    MAX_ATTEMPTS
+                );
+                // Marked for removal below
+                pending.attempts += 1;
+                continue;
+            }
+            log::warn!(
+                "Message {} to {} not acknowledged, publishing it again",
+                pending.msg_id,
+                pending.topic
+            );
+            pending.sent = now;
+            pending.attempts += 1;
+            match mqtt_client.publish_tagged(&pending.topic, self.qos, false, &pending.payload) {
+                Ok(msg_id) => pending.msg_id = msg_id,
+                Err(err) => log::error!("Unable to publish {} again: {}", pending.topic, err),
+            }
+        }
+        self.in_flight
+            .retain(|pending| pending.attempts <= MAX_ATTEMPTS);
+    }
+}
@@ -0,0 +1,56 @@
+use std::{f32::consts::TAU, time::Instant};
+
+use crate::dsp::Decibel;
+
+const DAY_SECS: f32 = 24.0 * 60.0 * 60.0;
+// Around the default thresholds, mostly normal with loud and very loud events
+const BASE_LEVEL: f32 = 15.0;
+const DAY_SWING: f32 = 12.0;
+// Roughly one event every couple of minutes at the default publishing rate
+const EVENT_PROBABILITY: f32 = 0.0005;
+
+// Replaces the microphone with a synthetic day/night curve plus occasional loud events. There is
+// no wall-clock time at this point, so the boot instant is treated as midnight.
+pub struct NoiseSimulator {
+    started: Instant,
+    rng_state: u32,
+    event_steps_left: u32,
+    event_level: f32,
+}
+
+impl NoiseSimulator {
+    pub fn new(seed: u32) -> Self {
+        NoiseSimulator {
+            started: Instant::now(),
+            rng_state: seed | 1,
+            event_steps_left: 0,
+            event_level: 0.0,
+        }
+    }
+
+    pub fn next_level(&mut self) -> Decibel {
+        let day_phase = self.started.elapsed().as_secs_f32() / DAY_SECS;
+        // Quietest around 04:00, loudest around 16:00
+        let diurnal = BASE_LEVEL - DAY_SWING * (TAU * (day_phase - 1.0 / 6.0)).cos();
+        if self.event_steps_left == 0 && self.next_unit() < EVENT_PROBABILITY {
+            self.event_steps_left = 40 + (self.next_unit() * 200.0) as u32;
+            self.event_level = 30.0 + self.next_unit() * 25.0;
+        }
+        let event = if self.event_steps_left > 0 {
+            self.event_steps_left -= 1;
+            self.event_level
+        } else {
+            0.0
+        };
+        let jitter = (self.next_unit() - 0.5) * 4.0;
+        Decibel(diurnal.max(event) + jitter)
+    }
+
+    // xorshift32, plenty for demo noise and no extra dependency
+    fn next_unit(&mut self) -> f32 {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 17;
+        self.rng_state ^= self.rng_state << 5;
+        (self.rng_state >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
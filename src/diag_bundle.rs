@@ -0,0 +1,44 @@
+use miniz_oxide::deflate::compress_to_vec_zlib;
+
+// Small enough for the default MQTT buffer, a few for a typical bundle
+pub const CHUNK_LEN: usize = 1024;
+// zlib's default trade-off, the bundle is built once per request
+const COMPRESSION_LEVEL: u8 = 6;
+
+// Everything support asks for, as named text sections in one zlib stream: each section is a
+// `=== name ===` line followed by its text, so the unpacked bundle reads as a plain text file
+pub fn assemble(sections: &[(&str, String)]) -> Vec<u8> {
+    let mut text = String::new();
+    for (name, body) in sections {
+        text.push_str("=== ");
+        text.push_str(name);
+        text.push_str(" ===\n");
+        text.push_str(body.trim_end());
+        text.push('\n');
+    }
+    compress_to_vec_zlib(text.as_bytes(), COMPRESSION_LEVEL)
+}
+
+// Goes out ahead of the chunks, so a receiver knows when it has them all
+pub fn manifest_json(id: u32, bundle: &[u8]) -> String {
+    format!(
+        "{{\"id\":{},\"encoding\":\"zlib\",\"len\":{},\"chunks\":{},\"chunk_len\":{}}}",
+        id,
+        bundle.len(),
+        chunk_count(bundle),
+        CHUNK_LEN
+    )
+}
+
+// Under the bundle's id, so chunks of two requests in a row can't mix
+pub fn chunk_topic(bundle_topic: &str, id: u32, index: usize) -> String {
+    format!("{}/{}/{}", bundle_topic, id, index)
+}
+
+pub fn chunk_count(bundle: &[u8]) -> usize {
+    (bundle.len() + CHUNK_LEN - 1) / CHUNK_LEN
+}
+
+pub fn chunks(bundle: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    bundle.chunks(CHUNK_LEN).enumerate()
+}
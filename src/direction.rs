@@ -0,0 +1,97 @@
+use crate::dsp::{self, RawAdc};
+
+// Pairs of samples, 8 ms at the rate of the sample window
+pub const BURST_LEN: usize = 128;
+// About 17 cm of sound path at that rate, more than the spacing of two mics on one board
+const MAX_LAG: usize = 8;
+// Below this the mics hear mostly different sounds (or noise) and the lag means nothing
+const MIN_CORRELATION: f32 = 0.5;
+const LEVEL_MARGIN_DB: f32 = 3.0;
+
+// Both microphones over the same stretch of a sample window. The ADC converts the right one a
+// fraction of a sample later, which shifts the lag by less than the one sample it resolves.
+pub struct StereoBurst {
+    pub left: Vec<RawAdc>,
+    pub right: Vec<RawAdc>,
+    pub sample_rate_hz: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Left,
+    Right,
+    Ambiguous,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Left => "left",
+            Direction::Right => "right",
+            Direction::Ambiguous => "ambiguous",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirectionHint {
+    pub direction: Direction,
+    // Left minus right
+    pub level_difference_db: f32,
+    // Positive when the sound reached the left mic first
+    pub lag_samples: isize,
+    pub correlation: f32,
+    pub sample_rate_hz: f32,
+}
+
+impl DirectionHint {
+    // Timing and level each vote for a side when they are clear enough. If they disagree, the
+    // source is likely reflected or somewhere in between.
+    pub fn estimate(burst: &StereoBurst) -> Self {
+        // Not finite when a mic is silent, which says nothing about the source either
+        let level_difference_db =
+            Some(dsp::rms_to_db_f32(&burst.left).0 - dsp::rms_to_db_f32(&burst.right).0)
+                .filter(|difference| difference.is_finite())
+                .unwrap_or(0.0);
+        let (lag_samples, correlation) =
+            dsp::cross_correlation(&burst.left, &burst.right, MAX_LAG).unwrap_or((0, 0.0));
+        let by_timing = if correlation < MIN_CORRELATION || lag_samples == 0 {
+            None
+        } else if lag_samples > 0 {
+            Some(Direction::Left)
+        } else {
+            Some(Direction::Right)
+        };
+        let by_level = if level_difference_db >= LEVEL_MARGIN_DB {
+            Some(Direction::Left)
+        } else if level_difference_db <= -LEVEL_MARGIN_DB {
+            Some(Direction::Right)
+        } else {
+            None
+        };
+        let direction = match (by_timing, by_level) {
+            (Some(timing), Some(level)) if timing != level => Direction::Ambiguous,
+            (Some(side), _) | (None, Some(side)) => side,
+            (None, None) => Direction::Ambiguous,
+        };
+        DirectionHint {
+            direction,
+            level_difference_db,
+            lag_samples,
+            correlation,
+            sample_rate_hz: burst.sample_rate_hz,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let lag_us = self.lag_samples as f32 * 1e6 / self.sample_rate_hz;
+        format!(
+            "{{\"direction\":\"{}\",\"level_diff_db\":{:.1},\"lag_samples\":{},\"lag_us\":{:.0},\"correlation\":{:.2}}}",
+            self.direction.as_str(),
+            self.level_difference_db,
+            self.lag_samples,
+            lag_us,
+            self.correlation
+        )
+    }
+}
@@ -0,0 +1,52 @@
+use std::{net::IpAddr, thread, time::Duration};
+
+use esp_idf_svc::{
+    mdns::{EspMdns, QueryResult},
+    sys::esp_random,
+};
+
+use crate::backoff::{Backoff, BackoffPolicy};
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+// WiFi may still be connecting when the sensor worker starts
+const QUERY_ATTEMPTS: usize = 5;
+const RETRY_BACKOFF: BackoffPolicy = BackoffPolicy {
+    initial: Duration::from_secs(2),
+    max: Duration::from_secs(16),
+    multiplier: 2,
+    jitter: 0.2,
+};
+const MAX_RESULTS: usize = 4;
+
+// Looks for a broker advertising `_mqtt._tcp` on the local network, e.g. Mosquitto with an
+// Avahi service file, and returns it as `host:port`.
+pub fn discover_broker() -> Option<String> {
+    let mdns = EspMdns::take()
+        .map_err(|err| log::error!("Unable to start mDNS: {}", err))
+        .ok()?;
+    let mut results = vec![QueryResult::default(); MAX_RESULTS];
+    let mut backoff = Backoff::new(RETRY_BACKOFF, unsafe { esp_random() });
+    for attempt in 1..=QUERY_ATTEMPTS {
+        match mdns.query_ptr("_mqtt", "_tcp", QUERY_TIMEOUT, MAX_RESULTS, &mut results) {
+            Ok(found) => {
+                if let Some(broker) = results[..found].iter().find_map(broker_address) {
+                    log::info!("Discovered MQTT broker at {}", broker);
+                    return Some(broker);
+                }
+            }
+            Err(err) => log::warn!("DNS-SD query failed: {}", err),
+        }
+        log::info!("No MQTT broker found via DNS-SD (attempt {})", attempt);
+        thread::sleep(backoff.next_delay());
+    }
+    None
+}
+
+// An address is preferred over the hostname, lwIP can't resolve `.local` names by itself
+fn broker_address(result: &QueryResult) -> Option<String> {
+    let host = match result.addr.iter().find(|addr| addr.is_ipv4()) {
+        Some(IpAddr::V4(addr)) => addr.to_string(),
+        _ => format!("{}.local", result.hostname.as_ref()?),
+    };
+    Some(format!("{}:{}", host, result.port))
+}
@@ -0,0 +1,99 @@
+use anyhow::{bail, Result};
+
+use crate::{classification::NoiseClass, dsp::Decibel};
+
+// How the dashboard, and the OLED once it has a driver, spell out readings for the people in
+// front of the sensor. Published readings keep their fixed format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisplaySettings {
+    unit: String,
+    decimal_comma: bool,
+    clock_24h: bool,
+    // In NoiseClass order, quiet to very loud
+    class_labels: [String; 4],
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            unit: String::from("dB"),
+            decimal_comma: false,
+            clock_24h: true,
+            class_labels: [
+                NoiseClass::Quiet,
+                NoiseClass::Normal,
+                NoiseClass::Loud,
+                NoiseClass::VeryLoud,
+            ]
+            .map(|class| class.as_str().replace('_', " ")),
+        }
+    }
+}
+
+impl DisplaySettings {
+    // `clock` is `24h` or `12h`, `class_labels` four comma-separated labels or empty for the
+    // English ones
+    pub fn new(unit: &str, decimal_comma: bool, clock: &str, class_labels: &str) -> Result<Self> {
+        let mut settings = DisplaySettings {
+            unit: unit.to_string(),
+            decimal_comma,
+            ..Default::default()
+        };
+        settings.clock_24h = match clock {
+            "24h" => true,
+            "12h" => false,
+            other => bail!("Unknown clock {:?}, expected 24h or 12h", other),
+        };
+        if !class_labels.is_empty() {
+            let labels: Vec<&str> = class_labels.split(',').map(str::trim).collect();
+            if labels.len() != 4 || labels.iter().any(|label| label.is_empty()) {
+                bail!("Expected four class labels, quiet to very loud");
+            }
+            for (slot, label) in settings.class_labels.iter_mut().zip(labels) {
+                *slot = label.to_string();
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn level(&self, level: Option<Decibel>) -> String {
+        let value = level.map_or_else(|| String::from("--"), |level| format!("{:.1}", level.0));
+        let value = if self.decimal_comma {
+            value.replace('.', ",")
+        } else {
+            value
+        };
+        if self.unit.is_empty() {
+            value
+        } else {
+            format!("{} {}", value, self.unit)
+        }
+    }
+
+    pub fn class(&self, class: Option<NoiseClass>) -> &str {
+        match class {
+            Some(NoiseClass::Quiet) => &self.class_labels[0],
+            Some(NoiseClass::Normal) => &self.class_labels[1],
+            Some(NoiseClass::Loud) => &self.class_labels[2],
+            Some(NoiseClass::VeryLoud) => &self.class_labels[3],
+            None => "--",
+        }
+    }
+
+    // Local time of day, `--:--` until SNTP set the clock
+    pub fn clock(&self, minute_of_day: Option<u16>) -> String {
+        let Some(minute_of_day) = minute_of_day else {
+            return String::from("--:--");
+        };
+        let (hour, minute) = (minute_of_day / 60 % 24, minute_of_day % 60);
+        if self.clock_24h {
+            return format!("{:02}:{:02}", hour, minute);
+        }
+        let suffix = if hour < 12 { "AM" } else { "PM" };
+        let hour = match hour % 12 {
+            0 => 12,
+            hour => hour,
+        };
+        format!("{}:{:02} {}", hour, minute, suffix)
+    }
+}
@@ -0,0 +1,272 @@
+use std::{collections::VecDeque, fmt, ops::Add};
+
+// Nominal 12-bit range at 11 dB attenuation, without the per-chip eFuse calibration
+const FULL_SCALE_MV: f32 = 3300.0;
+const MAX_COUNT: f32 = 4095.0;
+
+// Counts as read from the ADC, before any scaling
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RawAdc(pub u16);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Millivolts(pub f32);
+
+impl fmt::Display for Millivolts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} mV", self.0)
+    }
+}
+
+// A sound level. Offsets and hysteresis are differences between levels and stay plain f32 dB,
+// so adding a level to a level doesn't compile.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Decibel(pub f32);
+
+impl Decibel {
+    fn is_nan(self) -> bool {
+        self.0.is_nan()
+    }
+
+    fn distance(self, other: Decibel) -> f32 {
+        (self.0 - other.0).abs()
+    }
+
+    fn clamp(self, min: Decibel, max: Decibel) -> Decibel {
+        Decibel(self.0.clamp(min.0, max.0))
+    }
+}
+
+impl Add<f32> for Decibel {
+    type Output = Decibel;
+
+    fn add(self, offset: f32) -> Decibel {
+        Decibel(self.0 + offset)
+    }
+}
+
+// Published as a bare number
+impl fmt::Display for Decibel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(not(feature = "fixed-point"))]
+pub use self::{leq_f32 as leq, leq_weighted_f32 as leq_weighted, rms_to_db_f32 as rms_to_db};
+#[cfg(feature = "fixed-point")]
+pub use crate::fixed_point::{leq, leq_weighted, rms_to_db};
+
+// Relative to one ADC count, the scale the default thresholds are set for
+pub fn rms_to_db_f32(samples: &[RawAdc]) -> Decibel {
+    Decibel(20.0f32 * rms_counts(samples).log10())
+}
+
+// Of the sound only. The microphone sits on a bias of about half the supply, which would
+// otherwise make up nearly all of the RMS, so the mean goes first.
+pub fn rms_counts(samples: &[RawAdc]) -> f32 {
+    let mean = mean(samples);
+    rms(samples.iter().map(|sample| sample.0 as f32 - mean))
+}
+
+pub fn rms_millivolts(samples: &[RawAdc]) -> Millivolts {
+    Millivolts(rms_counts(samples) * FULL_SCALE_MV / MAX_COUNT)
+}
+
+fn rms(values: impl ExactSizeIterator<Item = f32>) -> f32 {
+    let count = values.len() as f32;
+    (values.map(|value| value * value).sum::<f32>() / count).sqrt()
+}
+
+// Equivalent continuous level: the constant level carrying the same energy as the readings
+#[cfg_attr(feature = "fixed-point", allow(dead_code))]
+pub fn leq_f32(levels: &[Decibel]) -> Option<Decibel> {
+    leq_weighted_f32(levels.iter().map(|level| (*level, 1)))
+}
+
+// The same with each level standing for a number of readings, e.g. the Leq of a block of them
+#[cfg_attr(feature = "fixed-point", allow(dead_code))]
+pub fn leq_weighted_f32(levels: impl Iterator<Item = (Decibel, u32)> + Clone) -> Option<Decibel> {
+    let (energy, count) = levels.fold((0.0f32, 0u32), |(energy, count), (level, readings)| {
+        (
+            energy + readings as f32 * 10.0f32.powf(level.0 / 10.0),
+            count + readings,
+        )
+    });
+    (count > 0).then(|| Decibel(10.0 * (energy / count as f32).log10()))
+}
+
+// Nearest-rank percentile, `percent` from 0 to 100. L10, the level exceeded 10% of the time, is
+// the 90th percentile.
+pub fn percentile(levels: &[Decibel], percent: f32) -> Option<Decibel> {
+    if levels.is_empty() {
+        return None;
+    }
+    let mut sorted = levels.to_vec();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let rank = (percent.clamp(0.0, 100.0) / 100.0 * sorted.len() as f32).ceil() as usize;
+    Some(sorted[rank.saturating_sub(1)])
+}
+
+// Crossings of the mean per second. The mean goes first, the ADC always sees a DC offset.
+pub fn zero_crossing_rate(samples: &[RawAdc], sample_rate_hz: f32) -> f32 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(samples);
+    let crossings = samples
+        .windows(2)
+        .filter(|pair| (pair[0].0 as f32 >= mean) != (pair[1].0 as f32 >= mean))
+        .count();
+    crossings as f32 * sample_rate_hz / (samples.len() - 1) as f32
+}
+
+// Power at a single frequency, without the DC offset. For a handful of frequencies this is much
+// cheaper than an FFT.
+pub fn goertzel_power(samples: &[RawAdc], sample_rate_hz: f32, frequency_hz: f32) -> f32 {
+    let mean = mean(samples);
+    let coefficient = 2.0 * (2.0 * std::f32::consts::PI * frequency_hz / sample_rate_hz).cos();
+    let (mut previous, mut before_previous) = (0.0f32, 0.0f32);
+    for sample in samples {
+        let current = sample.0 as f32 - mean + coefficient * previous - before_previous;
+        before_previous = previous;
+        previous = current;
+    }
+    previous * previous + before_previous * before_previous
+        - coefficient * previous * before_previous
+}
+
+// RMS of the component at one frequency, on the same scale as `rms_to_db`
+pub fn tone_level(samples: &[RawAdc], sample_rate_hz: f32, frequency_hz: f32) -> Decibel {
+    let power = goertzel_power(samples, sample_rate_hz, frequency_hz);
+    let len = samples.len().max(1) as f32;
+    Decibel(10.0 * (2.0 * power / (len * len)).log10())
+}
+
+// How much of the power without DC sits at one frequency, about 1.0 for a pure tone
+pub fn tone_share(samples: &[RawAdc], sample_rate_hz: f32, frequency_hz: f32) -> f32 {
+    let mean = mean(samples);
+    let ac_power = samples
+        .iter()
+        .map(|sample| (sample.0 as f32 - mean).powi(2))
+        .sum::<f32>();
+    if ac_power == 0.0 {
+        return 0.0;
+    }
+    2.0 * goertzel_power(samples, sample_rate_hz, frequency_hz) / (samples.len() as f32 * ac_power)
+}
+
+// Power-weighted mean of the bin frequencies below Nyquist, None when there is no power in them
+pub fn spectral_centroid(samples: &[RawAdc], sample_rate_hz: f32, bins_hz: &[f32]) -> Option<f32> {
+    let (weighted, total) = bins_hz
+        .iter()
+        .filter(|frequency| **frequency < sample_rate_hz / 2.0)
+        .map(|frequency| {
+            let power = goertzel_power(samples, sample_rate_hz, *frequency);
+            (power * frequency, power)
+        })
+        .fold((0.0f32, 0.0f32), |(weighted, total), (w, p)| {
+            (weighted + w, total + p)
+        });
+    (total > 0.0).then(|| weighted / total)
+}
+
+// The lag in samples, up to `max_lag` either way, at which `b` best matches `a`, and the
+// normalized correlation there. A positive lag means `b` is late, the sound reached `a` first.
+// None for silence or bursts not longer than the lag.
+pub fn cross_correlation(a: &[RawAdc], b: &[RawAdc], max_lag: usize) -> Option<(isize, f32)> {
+    let len = a.len().min(b.len());
+    if len <= max_lag {
+        return None;
+    }
+    let without_mean = |samples: &[RawAdc]| {
+        let mean = mean(samples);
+        samples
+            .iter()
+            .map(|sample| sample.0 as f32 - mean)
+            .collect::<Vec<f32>>()
+    };
+    let (a, b) = (without_mean(&a[..len]), without_mean(&b[..len]));
+    let energy = |values: &[f32]| values.iter().map(|value| value * value).sum::<f32>();
+    let norm = (energy(&a) * energy(&b)).sqrt();
+    if norm == 0.0 {
+        return None;
+    }
+    let max_lag = max_lag as isize;
+    (-max_lag..=max_lag)
+        .map(|lag| {
+            let (a, b) = if lag >= 0 {
+                (&a[..len - lag as usize], &b[lag as usize..])
+            } else {
+                (&a[(-lag) as usize..], &b[..len - (-lag) as usize])
+            };
+            let sum = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+            (lag, sum / norm)
+        })
+        .max_by(|x, y| x.1.total_cmp(&y.1))
+}
+
+fn mean(samples: &[RawAdc]) -> f32 {
+    samples.iter().map(|sample| sample.0 as f32).sum::<f32>() / samples.len().max(1) as f32
+}
+
+pub enum Plausibility {
+    Accepted(Decibel),
+    Clamped(Decibel),
+    Rejected,
+}
+
+// Drops levels that jump too far away from the median of the last readings (electrical spikes)
+// and clamps whatever survives to the configured floor/ceiling.
+pub struct LevelFilter {
+    floor: Decibel,
+    ceiling: Decibel,
+    max_deviation: f32,
+    window_len: usize,
+    recent: VecDeque<Decibel>,
+}
+
+impl LevelFilter {
+    pub fn new(floor: Decibel, ceiling: Decibel, window_len: usize, max_deviation: f32) -> Self {
+        LevelFilter {
+            floor,
+            ceiling,
+            max_deviation,
+            window_len,
+            recent: VecDeque::with_capacity(window_len),
+        }
+    }
+
+    pub fn check(&mut self, level: Decibel) -> Plausibility {
+        if level.is_nan() {
+            return Plausibility::Rejected;
+        }
+        if self.window_len > 1 {
+            let median = self.median();
+            if self.recent.len() == self.window_len {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(level);
+            if let Some(median) = median {
+                if level.distance(median) > self.max_deviation {
+                    return Plausibility::Rejected;
+                }
+            }
+        }
+        let clamped = level.clamp(self.floor, self.ceiling);
+        if clamped == level {
+            Plausibility::Accepted(level)
+        } else {
+            Plausibility::Clamped(clamped)
+        }
+    }
+
+    // Only meaningful once the window is full, otherwise the first readings after boot decide
+    fn median(&self) -> Option<Decibel> {
+        if self.recent.len() < self.window_len {
+            return None;
+        }
+        let mut sorted: Vec<Decibel> = self.recent.iter().copied().collect();
+        sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Some(sorted[sorted.len() / 2])
+    }
+}
@@ -0,0 +1,57 @@
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64URL: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// Padded, as expected by HTTP and PEM
+pub fn base64(data: &[u8]) -> String {
+    let mut encoded = encode(data, BASE64);
+    while encoded.len() % 4 != 0 {
+        encoded.push('=');
+    }
+    encoded
+}
+
+// Unpadded, as used by JWTs
+pub fn base64url(data: &[u8]) -> String {
+    encode(data, BASE64URL)
+}
+
+// Skips whitespace, so line-wrapped payloads decode as well
+pub fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    decode(text, BASE64)
+}
+
+pub fn decode_base64url(text: &str) -> Option<Vec<u8>> {
+    decode(text, BASE64URL)
+}
+
+fn decode(text: &str, alphabet: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for byte in text.bytes().filter(|byte| !byte.is_ascii_whitespace()) {
+        if byte == b'=' {
+            break;
+        }
+        let value = alphabet.iter().position(|c| *c == byte)? as u32;
+        bits = bits << 6 | value;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+fn encode(data: &[u8], alphabet: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 4 + 2) / 3);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..=chunk.len() {
+            encoded.push(alphabet[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
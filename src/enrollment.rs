@@ -0,0 +1,422 @@
+use std::{
+    ffi::{c_int, c_void, CString},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use esp_idf_svc::{
+    http::{
+        client::{Configuration as HttpConfiguration, EspHttpConnection},
+        Method,
+    },
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::{
+        esp_crt_bundle_attach, esp_fill_random, mbedtls_md_type_t_MBEDTLS_MD_SHA256,
+        mbedtls_pk_context, mbedtls_pk_free, mbedtls_pk_init, mbedtls_pk_write_key_pem,
+        mbedtls_x509write_csr, mbedtls_x509write_csr_der, mbedtls_x509write_csr_free,
+        mbedtls_x509write_csr_init, mbedtls_x509write_csr_set_key,
+        mbedtls_x509write_csr_set_md_alg, mbedtls_x509write_csr_set_subject_name,
+    },
+    tls::X509,
+};
+
+#[cfg(not(feature = "secure-element"))]
+use crate::security;
+use crate::{
+    clock,
+    encoding::{base64, decode_base64},
+    wear,
+};
+
+const NVS_CERT_NAMESPACE: &str = "bzzz_cert";
+const NVS_CERT_KEY: &str = "cert";
+const NVS_PRIVATE_KEY_KEY: &str = "key";
+const MAX_RESPONSE_LEN: usize = 4096;
+const KEY_PEM_LEN: usize = 512;
+const CSR_DER_LEN: usize = 512;
+
+// The MQTT client only takes 'static certificates, so each one is leaked exactly once: when it is
+// loaded at boot or when a new one is enrolled. Worker restarts reuse it from here.
+static CURRENT: Mutex<Option<Identity>> = Mutex::new(None);
+
+#[derive(Clone, Copy)]
+pub struct Identity {
+    pub certificate: X509<'static>,
+    pub private_key: X509<'static>,
+    // Unix time
+    pub not_after: u64,
+}
+
+// Gets an operational certificate for mTLS from an EST (RFC 7030) server. The first enrollment
+// authenticates with the bootstrap credentials; renewals use the current certificate instead.
+pub struct Enrollment {
+    nvs: EspNvs<NvsDefault>,
+    est_url: &'static str,
+    bootstrap_user: &'static str,
+    bootstrap_password: &'static str,
+    subject: String,
+    renew_before: Duration,
+}
+
+impl Enrollment {
+    pub fn new(
+        nvs_partition: EspDefaultNvsPartition,
+        est_url: &'static str,
+        bootstrap_user: &'static str,
+        bootstrap_password: &'static str,
+        device_id: &str,
+        renew_before: Duration,
+    ) -> Result<Self> {
+        let nvs = EspNvs::new(nvs_partition, NVS_CERT_NAMESPACE, true)
+            .context("Unable to open certificate storage")?;
+        let mut current = CURRENT.lock().unwrap();
+        if current.is_none() {
+            *current = load_identity(&nvs);
+        }
+        Ok(Enrollment {
+            nvs,
+            est_url,
+            bootstrap_user,
+            bootstrap_password,
+            subject: format!("CN={}", device_id),
+            renew_before,
+        })
+    }
+
+    pub fn identity(&self) -> Option<Identity> {
+        *CURRENT.lock().unwrap()
+    }
+
+    // Without a synchronized clock there is no telling, so only a missing certificate is due
+    pub fn renewal_due(&self) -> bool {
+        match (self.identity(), clock::unix_time()) {
+            (None, _) => true,
+            (Some(identity), Some(now)) => {
+                identity.not_after.saturating_sub(now) < self.renew_before.as_secs()
+            }
+            (Some(_), None) => false,
+        }
+    }
+
+    pub fn enroll(&mut self) -> Result<Identity> {
+        // Only a reference to the key slot is stored with a secure element
+        #[cfg(not(feature = "secure-element"))]
+        security::check_secret_storage("a private key")?;
+        let (key_pem, csr_der) =
+            generate_key_and_csr(&self.subject).context("Unable to create CSR")?;
+        let current = self.identity();
+        let operation = if current.is_some() {
+            "simplereenroll"
+        } else {
+            "simpleenroll"
+        };
+        log::info!(
+            "Requesting certificate for {} ({})",
+            self.subject,
+            operation
+        );
+        let response = self.est_request(operation, &csr_der, current)?;
+        let pkcs7 = decode_base64(&response).context("EST response is not base64")?;
+        let cert_der = first_certificate(&pkcs7).context("No certificate in EST response")?;
+        let not_after = not_after(cert_der).context("Unable to read certificate expiry")?;
+        let cert_pem = to_pem("CERTIFICATE", cert_der);
+
+        // Stored before switching, a reboot right now must not lose the new key
+        self.nvs
+            .set_blob(NVS_CERT_KEY, cert_pem.as_bytes())
+            .context("Unable to store certificate")?;
+        self.nvs
+            .set_blob(NVS_PRIVATE_KEY_KEY, &key_pem)
+            .context("Unable to store private key")?;
+        wear::nvs_written(cert_pem.len());
+        wear::nvs_written(key_pem.len());
+        let identity = leak_identity(cert_pem.into_bytes(), key_pem, not_after);
+        *CURRENT.lock().unwrap() = Some(identity);
+        log::info!("Enrolled certificate valid until {}", not_after);
+        Ok(identity)
+    }
+
+    fn est_request(
+        &self,
+        operation: &str,
+        csr_der: &[u8],
+        current: Option<Identity>,
+    ) -> Result<String> {
+        let mut connection = EspHttpConnection::new(&HttpConfiguration {
+            crt_bundle_attach: Some(esp_crt_bundle_attach),
+            client_certificate: current.map(|identity| identity.certificate),
+            private_key: current.map(|identity| identity.private_key),
+            ..Default::default()
+        })
+        .context("Unable to create HTTPS connection")?;
+        let url = format!(
+            "{}/.well-known/est/{}",
+            self.est_url.trim_end_matches('/'),
+            operation
+        );
+        let body = base64(csr_der);
+        let content_length = body.len().to_string();
+        let authorization = format!(
+            "Basic {}",
+            base64(format!("{}:{}", self.bootstrap_user, self.bootstrap_password).as_bytes())
+        );
+        let mut headers = vec![
+            ("Content-Type", "application/pkcs10"),
+            ("Content-Transfer-Encoding", "base64"),
+            ("Content-Length", content_length.as_str()),
+        ];
+        if current.is_none() {
+            headers.push(("Authorization", authorization.as_str()));
+        }
+        connection
+            .initiate_request(Method::Post, &url, &headers)
+            .context("Unable to send EST request")?;
+        let mut written = 0;
+        while written < body.len() {
+            written += connection.write(&body.as_bytes()[written..])?;
+        }
+        connection
+            .initiate_response()
+            .context("No response from EST server")?;
+        if connection.status() != 200 {
+            bail!("EST server answered {}", connection.status());
+        }
+        let mut response = vec![0u8; MAX_RESPONSE_LEN];
+        let mut len = 0;
+        while len < response.len() {
+            match connection.read(&mut response[len..])? {
+                0 => break,
+                read => len += read,
+            }
+        }
+        response.truncate(len);
+        String::from_utf8(response).context("EST response is not valid UTF-8")
+    }
+}
+
+fn load_identity(nvs: &EspNvs<NvsDefault>) -> Option<Identity> {
+    let cert_pem = read_blob(nvs, NVS_CERT_KEY)?;
+    let key_pem = read_blob(nvs, NVS_PRIVATE_KEY_KEY)?;
+    let cert_der = pem_body(&cert_pem)?;
+    let not_after = not_after(&cert_der)?;
+    log::info!("Stored certificate valid until {}", not_after);
+    Some(leak_identity(cert_pem, key_pem, not_after))
+}
+
+// For a certificate provisioned at manufacture instead of enrolled, never renewed
+pub fn identity_from_pem(cert_pem: &str, key_pem: &str) -> Option<Identity> {
+    let not_after = not_after(&pem_body(cert_pem.as_bytes())?)?;
+    Some(leak_identity(
+        cert_pem.as_bytes().to_vec(),
+        key_pem.as_bytes().to_vec(),
+        not_after,
+    ))
+}
+
+fn read_blob(nvs: &EspNvs<NvsDefault>, key: &str) -> Option<Vec<u8>> {
+    let mut buffer = vec![0u8; nvs.blob_len(key).ok()??];
+    let len = nvs.get_blob(key, &mut buffer).ok()??.len();
+    buffer.truncate(len);
+    Some(buffer)
+}
+
+fn leak_identity(mut cert_pem: Vec<u8>, mut key_pem: Vec<u8>, not_after: u64) -> Identity {
+    for pem in [&mut cert_pem, &mut key_pem] {
+        if pem.last() != Some(&0) {
+            pem.push(0);
+        }
+    }
+    Identity {
+        certificate: X509::pem_until_nul(Box::leak(cert_pem.into_boxed_slice())),
+        private_key: X509::pem_until_nul(Box::leak(key_pem.into_boxed_slice())),
+        not_after,
+    }
+}
+
+unsafe extern "C" fn fill_random(_: *mut c_void, output: *mut u8, len: usize) -> c_int {
+    esp_fill_random(output as *mut c_void, len);
+    0
+}
+
+#[cfg(not(feature = "secure-element"))]
+unsafe fn create_key(key: &mut mbedtls_pk_context) -> Result<()> {
+    use esp_idf_svc::sys::{
+        mbedtls_ecp_gen_key, mbedtls_ecp_group_id_MBEDTLS_ECP_DP_SECP256R1, mbedtls_ecp_keypair,
+        mbedtls_pk_info_from_type, mbedtls_pk_setup, mbedtls_pk_type_t_MBEDTLS_PK_ECKEY,
+    };
+
+    check(mbedtls_pk_setup(
+        key,
+        mbedtls_pk_info_from_type(mbedtls_pk_type_t_MBEDTLS_PK_ECKEY),
+    ))?;
+    check(mbedtls_ecp_gen_key(
+        mbedtls_ecp_group_id_MBEDTLS_ECP_DP_SECP256R1,
+        key.private_pk_ctx as *mut mbedtls_ecp_keypair,
+        Some(fill_random),
+        std::ptr::null_mut(),
+    ))
+}
+
+// Renewals keep reusing the key in the chip
+#[cfg(feature = "secure-element")]
+unsafe fn create_key(key: &mut mbedtls_pk_context) -> Result<()> {
+    crate::secure_element::bind_key(key)
+}
+
+// A fresh P-256 key for every enrollment (unless it lives in the secure element), returned as a
+// NUL terminated PEM along with the DER CSR
+fn generate_key_and_csr(subject: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+    let subject = CString::new(subject)?;
+    let mut key_pem = vec![0u8; KEY_PEM_LEN];
+    let mut csr_der = vec![0u8; CSR_DER_LEN];
+    unsafe {
+        let mut key: mbedtls_pk_context = std::mem::zeroed();
+        let mut csr: mbedtls_x509write_csr = std::mem::zeroed();
+        mbedtls_pk_init(&mut key);
+        mbedtls_x509write_csr_init(&mut csr);
+        let result: Result<usize> = (|| {
+            create_key(&mut key)?;
+            check(mbedtls_pk_write_key_pem(
+                &key,
+                key_pem.as_mut_ptr(),
+                key_pem.len(),
+            ))?;
+            mbedtls_x509write_csr_set_key(&mut csr, &mut key);
+            mbedtls_x509write_csr_set_md_alg(&mut csr, mbedtls_md_type_t_MBEDTLS_MD_SHA256);
+            check(mbedtls_x509write_csr_set_subject_name(
+                &mut csr,
+                subject.as_ptr(),
+            ))?;
+            // The DER is written at the end of the buffer
+            let len = mbedtls_x509write_csr_der(
+                &mut csr,
+                csr_der.as_mut_ptr(),
+                csr_der.len(),
+                Some(fill_random),
+                std::ptr::null_mut(),
+            );
+            check(len)?;
+            Ok(len as usize)
+        })();
+        mbedtls_x509write_csr_free(&mut csr);
+        mbedtls_pk_free(&mut key);
+        let csr_len = result?;
+        csr_der.drain(..csr_der.len() - csr_len);
+    }
+    let pem_len = key_pem
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(key_pem.len());
+    key_pem.truncate(pem_len + 1);
+    Ok((key_pem, csr_der))
+}
+
+fn check(result: c_int) -> Result<()> {
+    if result < 0 {
+        bail!("mbedTLS error -0x{:04x}", -result);
+    }
+    Ok(())
+}
+
+fn to_pem(label: &str, der: &[u8]) -> String {
+    let encoded = base64(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+fn pem_body(pem: &[u8]) -> Option<Vec<u8>> {
+    let pem = std::str::from_utf8(pem).ok()?.trim_end_matches('\0');
+    let body: String = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    decode_base64(&body)
+}
+
+// Splits a DER element into its tag, its content and whatever follows it
+fn der_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&first, mut data) = data.split_first()?;
+    let len = if first < 0x80 {
+        first as usize
+    } else {
+        let len_bytes = (first & 0x7f) as usize;
+        if len_bytes == 0 || len_bytes > 4 || data.len() < len_bytes {
+            return None;
+        }
+        let len = data[..len_bytes]
+            .iter()
+            .fold(0usize, |len, byte| len << 8 | *byte as usize);
+        data = &data[len_bytes..];
+        len
+    };
+    if data.len() < len {
+        return None;
+    }
+    Some((tag, &data[..len], &data[len..]))
+}
+
+// EST answers with a certs-only PKCS#7 SignedData; the first certificate is ours
+fn first_certificate(pkcs7: &[u8]) -> Option<&[u8]> {
+    let (_, content_info, _) = der_element(pkcs7)?;
+    let (_, _content_type, rest) = der_element(content_info)?;
+    let (_, explicit_content, _) = der_element(rest)?;
+    let (_, signed_data, _) = der_element(explicit_content)?;
+    let (_, _version, rest) = der_element(signed_data)?;
+    let (_, _digest_algorithms, rest) = der_element(rest)?;
+    let (_, _encap_content_info, rest) = der_element(rest)?;
+    let (tag, certificates, _) = der_element(rest)?;
+    if tag != 0xa0 {
+        return None;
+    }
+    let (_, _, after) = der_element(certificates)?;
+    Some(&certificates[..certificates.len() - after.len()])
+}
+
+fn not_after(cert_der: &[u8]) -> Option<u64> {
+    let (_, certificate, _) = der_element(cert_der)?;
+    let (_, tbs_certificate, _) = der_element(certificate)?;
+    let (tag, _, mut rest) = der_element(tbs_certificate)?;
+    // The version is optional, the serial number that follows it is not
+    if tag == 0xa0 {
+        rest = der_element(rest)?.2;
+    }
+    let (_, _signature, rest) = der_element(rest)?;
+    let (_, _issuer, rest) = der_element(rest)?;
+    let (_, validity, _) = der_element(rest)?;
+    let (_, _not_before, rest) = der_element(validity)?;
+    let (tag, time, _) = der_element(rest)?;
+    parse_time(tag, std::str::from_utf8(time).ok()?)
+}
+
+// UTCTime (YYMMDDHHMMSSZ) or GeneralizedTime (YYYYMMDDHHMMSSZ) to Unix time
+fn parse_time(tag: u8, time: &str) -> Option<u64> {
+    let (year, rest) = match tag {
+        0x17 => {
+            let year: i64 = time.get(..2)?.parse().ok()?;
+            (
+                if year < 50 { 2000 + year } else { 1900 + year },
+                &time[2..],
+            )
+        }
+        0x18 => (time.get(..4)?.parse().ok()?, &time[4..]),
+        _ => return None,
+    };
+    let field = |i: usize| -> Option<i64> { rest.get(i * 2..i * 2 + 2)?.parse().ok() };
+    let (month, day) = (field(0)?, field(1)?);
+    let seconds = field(2)? * 3600 + field(3)? * 60 + field(4)?;
+    // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    u64::try_from(days * 86400 + seconds).ok()
+}
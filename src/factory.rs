@@ -0,0 +1,82 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+// Where the firmware reads the blob and bzzz-provision writes it
+pub const NVS_NAMESPACE: &str = "bzzz_factory";
+pub const NVS_KEY: &str = "data";
+// The firmware's own namespace, where the captive portal stores WiFi credentials too
+pub const NVS_DEVICE_NAMESPACE: &str = "bzzz";
+pub const NVS_WIFI_SSID_KEY: &str = "wifi_ssid";
+pub const NVS_WIFI_PASSWORD_KEY: &str = "wifi_pass";
+
+const FORMAT_VERSION: u32 = 1;
+const MAX_DEVICE_ID_LEN: usize = 64;
+
+// The identity written at manufacture, separately from the firmware, as one JSON blob, e.g.
+// `{"version":1,"device_id":"bzzz-0042","mqtt_host":"broker.example.com","client_cert":"-----BEGIN CERTIFICATE-----\n...","client_key":"...","tenant_id":"acme"}`.
+// Everything but the version is optional and falls back to cfg.toml.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FactoryData {
+    version: u32,
+    pub device_id: Option<String>,
+    pub mqtt_host: Option<String>,
+    // PEM
+    pub ca_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub tenant_id: Option<String>,
+    pub site_id: Option<String>,
+    pub floor: Option<String>,
+}
+
+impl FactoryData {
+    // Empty but for the version, for bzzz-provision, the firmware only reads blobs
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        FactoryData {
+            version: FORMAT_VERSION,
+            ..Default::default()
+        }
+    }
+
+    pub fn parse(blob: &[u8]) -> Result<Self> {
+        let data: FactoryData = serde_json::from_slice(blob).context("Invalid factory data")?;
+        if data.version != FORMAT_VERSION {
+            bail!("Unsupported factory data version {}", data.version);
+        }
+        if let Some(device_id) = data.device_id.as_deref() {
+            if device_id.is_empty()
+                || device_id.len() > MAX_DEVICE_ID_LEN
+                || !device_id
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+            {
+                bail!("Device ids are up to 64 letters, digits, dashes and underscores");
+            }
+        }
+        if data
+            .mqtt_host
+            .as_deref()
+            .is_some_and(|host| host.is_empty() || host.contains(['/', ' ']))
+        {
+            bail!("Invalid MQTT host");
+        }
+        if data.client_cert.is_some() != data.client_key.is_some() {
+            bail!("A client certificate needs its key and the other way around");
+        }
+        for (name, pem) in [
+            ("CA certificate", &data.ca_cert),
+            ("client certificate", &data.client_cert),
+            ("client key", &data.client_key),
+        ] {
+            if pem
+                .as_deref()
+                .is_some_and(|pem| !pem.trim_start().starts_with("-----BEGIN "))
+            {
+                bail!("The {} is not PEM", name);
+            }
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,65 @@
+use esp_idf_svc::nvs::{EspNvs, NvsDefault};
+
+use crate::wear;
+
+const NVS_FEATURES_KEY: &str = "features";
+
+#[derive(Clone, Copy, Debug)]
+pub enum Feature {
+    Classification = 0,
+    Alerts = 1,
+    OutdoorProfile = 2,
+    ThermalMonitor = 3,
+    Diagnostics = 4,
+    SpectralFeatures = 5,
+}
+
+const ALL_FEATURES: [Feature; 6] = [
+    Feature::Classification,
+    Feature::Alerts,
+    Feature::OutdoorProfile,
+    Feature::ThermalMonitor,
+    Feature::Diagnostics,
+    Feature::SpectralFeatures,
+];
+
+// Subsystems that can be switched off per device without reflashing. The mask is read once at
+// boot, so changes take effect after the next restart.
+#[derive(Clone, Copy)]
+pub struct Features(u32);
+
+impl Features {
+    pub fn load(nvs: &EspNvs<NvsDefault>) -> Self {
+        let mask = match nvs.get_u32(NVS_FEATURES_KEY) {
+            Ok(Some(mask)) => mask,
+            _ => u32::MAX,
+        };
+        let features = Features(mask);
+        for feature in ALL_FEATURES {
+            log::info!(
+                "Feature {:?}: {}",
+                feature,
+                if features.is_enabled(feature) {
+                    "enabled"
+                } else {
+                    "disabled"
+                }
+            );
+        }
+        features
+    }
+
+    pub fn store(nvs: &EspNvs<NvsDefault>, mask: u32) {
+        match nvs.set_u32(NVS_FEATURES_KEY, mask) {
+            Ok(()) => {
+                wear::nvs_written(0);
+                log::info!("Feature mask {:#x} stored, applies after restart", mask)
+            }
+            Err(err) => log::error!("Unable to persist feature mask: {}", err),
+        }
+    }
+
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        self.0 & (1 << feature as u32) != 0
+    }
+}
@@ -0,0 +1,118 @@
+use std::f64::consts::PI;
+
+use crate::dsp::RawAdc;
+
+// Samples go in as Q16 counts: 12 bits and the sign leave 3 bits of headroom, which the halving
+// after every stage keeps from overflowing
+const SAMPLE_SHIFT: u32 = 16;
+const TWIDDLE_BITS: u32 = 15;
+const MIN_LEN: usize = 16;
+const MAX_LEN: usize = 1024;
+
+// Radix-2 FFT in integer arithmetic, like `fixed_point` the ESP32-C6 has no FPU. The twiddles and
+// the Hann window are worked out once, every block is then integer multiplies and shifts.
+pub struct Fft {
+    len: usize,
+    // cos and -sin of 2πk/len for the first half of the circle, Q1.15
+    twiddles: Vec<(i32, i32)>,
+    window: Vec<i32>,
+}
+
+impl Fft {
+    // A power of two from 16 to 1024
+    pub fn new(len: usize) -> Option<Self> {
+        if !len.is_power_of_two() || !(MIN_LEN..=MAX_LEN).contains(&len) {
+            return None;
+        }
+        let q15 = |value: f64| (value * f64::from(1 << TWIDDLE_BITS)).round() as i32;
+        let twiddles = (0..len / 2)
+            .map(|k| {
+                let angle = 2.0 * PI * k as f64 / len as f64;
+                (q15(angle.cos()), q15(-angle.sin()))
+            })
+            .collect();
+        let window = (0..len)
+            .map(|n| q15(0.5 - 0.5 * (2.0 * PI * n as f64 / len as f64).cos()))
+            .collect();
+        Some(Fft {
+            len,
+            twiddles,
+            window,
+        })
+    }
+
+    pub fn block_len(&self) -> usize {
+        self.len
+    }
+
+    // Of one block of `len` samples, without its mean and through the Hann window: the power of
+    // the bins from DC to Nyquist, scaled so the bins of the one-sided spectrum add up to the
+    // mean square of the windowed block in Q16 counts squared
+    pub fn power_spectrum(&self, samples: &[RawAdc]) -> Vec<u64> {
+        let samples = &samples[..self.len];
+        let mean = samples
+            .iter()
+            .map(|sample| i64::from(sample.0))
+            .sum::<i64>()
+            / self.len as i64;
+        let mut bins: Vec<(i32, i32)> = samples
+            .iter()
+            .zip(&self.window)
+            .map(|(sample, window)| {
+                let centered = (i64::from(sample.0) - mean) << SAMPLE_SHIFT;
+                (((centered * i64::from(*window)) >> TWIDDLE_BITS) as i32, 0)
+            })
+            .collect();
+        self.transform(&mut bins);
+        let power = |(re, im): (i32, i32)| {
+            (i64::from(re) * i64::from(re) + i64::from(im) * i64::from(im)) as u64
+        };
+        // Everything but DC and Nyquist has its mirror image above Nyquist
+        (0..=self.len / 2)
+            .map(|k| {
+                let bin = power(bins[k]);
+                if k == 0 || k == self.len / 2 {
+                    bin
+                } else {
+                    2 * bin
+                }
+            })
+            .collect()
+    }
+
+    // In place, decimation in time, halving after every stage so the result is the DFT over len
+    fn transform(&self, bins: &mut [(i32, i32)]) {
+        let bits = self.len.trailing_zeros();
+        for i in 0..self.len {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                bins.swap(i, j);
+            }
+        }
+        let mut half = 1;
+        while half < self.len {
+            let step = self.len / (2 * half);
+            for start in (0..self.len).step_by(2 * half) {
+                for k in 0..half {
+                    let (cos, sin) = self.twiddles[k * step];
+                    let (re, im) = bins[start + k + half];
+                    let odd_re = (i64::from(re) * i64::from(cos) - i64::from(im) * i64::from(sin))
+                        >> TWIDDLE_BITS;
+                    let odd_im = (i64::from(re) * i64::from(sin) + i64::from(im) * i64::from(cos))
+                        >> TWIDDLE_BITS;
+                    let (even_re, even_im) = bins[start + k];
+                    let (even_re, even_im) = (i64::from(even_re), i64::from(even_im));
+                    bins[start + k] = (
+                        ((even_re + odd_re) >> 1) as i32,
+                        ((even_im + odd_im) >> 1) as i32,
+                    );
+                    bins[start + k + half] = (
+                        ((even_re - odd_re) >> 1) as i32,
+                        ((even_im - odd_im) >> 1) as i32,
+                    );
+                }
+            }
+            half *= 2;
+        }
+    }
+}
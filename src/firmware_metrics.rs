@@ -0,0 +1,153 @@
+use std::{ffi::CStr, fmt::Write};
+
+use esp_idf_svc::{
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::{esp_app_get_description, esp_get_minimum_free_heap_size, esp_timer_get_time},
+};
+
+use crate::wear;
+
+const NVS_FW_METRICS_NAMESPACE: &str = "bzzz_fw";
+const NVS_BASELINE_KEY: &str = "baseline";
+const WINDOW_US: i64 = 3600 * 1_000_000;
+
+#[derive(Clone, Default)]
+struct Metrics {
+    firmware: String,
+    connect_time_ms: Option<u64>,
+    min_free_heap: u32,
+    published: u32,
+    failed: u32,
+}
+
+impl Metrics {
+    fn success_rate(&self) -> Option<f32> {
+        let attempts = self.published + self.failed;
+        (attempts > 0).then(|| self.published as f32 / attempts as f32)
+    }
+
+    fn to_json(&self) -> String {
+        let connect_time = self
+            .connect_time_ms
+            .map_or_else(|| String::from("null"), |ms| ms.to_string());
+        let success_rate = self
+            .success_rate()
+            .map_or_else(|| String::from("null"), |rate| format!("{:.4}", rate));
+        format!(
+            "{{\"firmware\":\"{}\",\"connect_time_ms\":{},\"min_free_heap\":{},\"publish_success_rate\":{}}}",
+            self.firmware, connect_time, self.min_free_heap, success_rate
+        )
+    }
+
+    fn to_line(&self) -> String {
+        let connect_time = self
+            .connect_time_ms
+            .map_or_else(String::new, |ms| ms.to_string());
+        format!(
+            "{},{},{},{},{}",
+            self.firmware, connect_time, self.min_free_heap, self.published, self.failed
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.split(',');
+        Some(Metrics {
+            firmware: fields.next()?.to_string(),
+            connect_time_ms: fields.next()?.parse().ok(),
+            min_free_heap: fields.next()?.parse().ok()?,
+            published: fields.next()?.parse().ok()?,
+            failed: fields.next()?.parse().ok()?,
+        })
+    }
+}
+
+// Version plus the start of the ELF hash, so rebuilds without a version bump count as updates
+fn firmware_id() -> String {
+    let description = unsafe { &*esp_app_get_description() };
+    let version = unsafe { CStr::from_ptr(description.version.as_ptr()) }.to_string_lossy();
+    description.app_elf_sha256[..4]
+        .iter()
+        .fold(format!("{}-", version), |mut id, byte| {
+            let _ = write!(id, "{byte:02x}");
+            id
+        })
+}
+
+// Collects the first hour of metrics of every boot and keeps them as the baseline for the next
+// one. When that next boot runs a different firmware, both are reported side by side so an
+// update that made things worse stands out.
+pub struct FirmwareMetrics {
+    nvs: Option<EspNvs<NvsDefault>>,
+    current: Metrics,
+    baseline: Option<Metrics>,
+    done: bool,
+}
+
+impl FirmwareMetrics {
+    pub fn new(nvs_partition: EspDefaultNvsPartition) -> Self {
+        let nvs = EspNvs::new(nvs_partition, NVS_FW_METRICS_NAMESPACE, true)
+            .map_err(|err| log::error!("Unable to open firmware metrics: {}", err))
+            .ok();
+        let baseline = nvs.as_ref().and_then(|nvs| {
+            let mut buffer = [0u8; 96];
+            Metrics::from_line(nvs.get_str(NVS_BASELINE_KEY, &mut buffer).ok()??)
+        });
+        FirmwareMetrics {
+            nvs,
+            current: Metrics {
+                firmware: firmware_id(),
+                ..Default::default()
+            },
+            baseline,
+            done: false,
+        }
+    }
+
+    pub fn connected(&mut self) {
+        if self.current.connect_time_ms.is_none() {
+            self.current.connect_time_ms = Some(unsafe { esp_timer_get_time() } as u64 / 1000);
+        }
+    }
+
+    pub fn published(&mut self, success: bool) {
+        if self.done {
+            return;
+        }
+        if success {
+            self.current.published = self.current.published.saturating_add(1);
+        } else {
+            self.current.failed = self.current.failed.saturating_add(1);
+        }
+    }
+
+    // Once the first hour is over, stores this boot as the new baseline and returns the
+    // comparison if the firmware changed since the previous one
+    pub fn poll(&mut self) -> Option<String> {
+        if self.done || unsafe { esp_timer_get_time() } < WINDOW_US {
+            return None;
+        }
+        self.done = true;
+        self.current.min_free_heap = unsafe { esp_get_minimum_free_heap_size() };
+        if let Some(nvs) = self.nvs.as_mut() {
+            let line = self.current.to_line();
+            match nvs.set_str(NVS_BASELINE_KEY, &line) {
+                Ok(_) => wear::nvs_written(line.len()),
+                Err(err) => log::error!("Unable to store firmware metrics: {}", err),
+            }
+        }
+        let baseline = self.baseline.take()?;
+        if baseline.firmware == self.current.firmware {
+            return None;
+        }
+        log::info!(
+            "First hour of {} done, comparing with {}",
+            self.current.firmware,
+            baseline.firmware
+        );
+        Some(format!(
+            "{{\"current\":{},\"previous\":{}}}",
+            self.current.to_json(),
+            baseline.to_json()
+        ))
+    }
+}
@@ -0,0 +1,114 @@
+// Integer versions of the level math in `dsp`. The ESP32-C6 has no FPU, so every f32 log10, sqrt
+// and powf is a soft-float library call; here only the final conversion to `Decibel` is one.
+// Levels and exponents are Q16.16, energies and mantissas Q1.31.
+use crate::dsp::{Decibel, RawAdc};
+
+const FRAC_BITS: u32 = 16;
+const ONE_Q31: u64 = 1 << 31;
+// 10 * log10(2) and log2(10) / 10 as Q8.24
+const DB_PER_OCTAVE_Q24: i64 = 50_504_453;
+const OCTAVES_PER_DB_Q24: i64 = 5_573_271;
+// 2^(2^-(i + 1)) as Q1.31
+const EXP2_STEPS_Q31: [u64; FRAC_BITS as usize] = [
+    3_037_000_500,
+    2_553_802_834,
+    2_341_847_524,
+    2_242_560_872,
+    2_194_507_417,
+    2_170_868_212,
+    2_159_144_272,
+    2_153_306_067,
+    2_150_392_887,
+    2_148_937_775,
+    2_148_210_589,
+    2_147_847_087,
+    2_147_665_360,
+    2_147_574_502,
+    2_147_529_075,
+    2_147_506_361,
+];
+
+// log2 of a nonzero integer, one fractional bit per squaring of the mantissa
+pub fn log2_q16(value: u64) -> i64 {
+    let int_part = 63 - value.leading_zeros();
+    let mut mantissa = (value << (63 - int_part)) >> 32;
+    let mut log2 = i64::from(int_part) << FRAC_BITS;
+    for bit in (0..FRAC_BITS).rev() {
+        mantissa = (mantissa * mantissa) >> 31;
+        if mantissa >= 2 * ONE_Q31 {
+            mantissa >>= 1;
+            log2 |= 1 << bit;
+        }
+    }
+    log2
+}
+
+// 2 to the power of a non-positive exponent
+pub fn exp2_q31(exponent_q16: i64) -> u64 {
+    let shift = (-exponent_q16 + (1 << FRAC_BITS) - 1) >> FRAC_BITS;
+    if shift > 31 {
+        return 0;
+    }
+    let fraction = exponent_q16 + (shift << FRAC_BITS);
+    let mut value = ONE_Q31;
+    for (bit, step) in EXP2_STEPS_Q31.iter().enumerate() {
+        if fraction & (1 << (FRAC_BITS as usize - 1 - bit)) != 0 {
+            value = (value * step) >> 31;
+        }
+    }
+    value >> shift
+}
+
+fn to_q16(level: Decibel) -> i64 {
+    i64::from((level.0 * (1 << FRAC_BITS) as f32) as i32)
+}
+
+fn from_octaves(octaves_q16: i64) -> Decibel {
+    Decibel(((octaves_q16 * DB_PER_OCTAVE_Q24) >> 24) as f32 / (1 << FRAC_BITS) as f32)
+}
+
+// 20 log10(rms) is 10 log10(mean square), so no square root is needed. Without the mean like
+// `dsp::rms_counts`: len² times the mean square is len Σx² - (Σx)², exact in integers.
+pub fn rms_to_db(samples: &[RawAdc]) -> Decibel {
+    if samples.is_empty() {
+        return Decibel(f32::NAN);
+    }
+    let (sum, sum_of_squares) = samples.iter().fold((0u64, 0u64), |(sum, squares), sample| {
+        let value = u64::from(sample.0);
+        (sum + value, squares + value * value)
+    });
+    let len = samples.len() as u64;
+    let scaled = u128::from(len) * u128::from(sum_of_squares) - u128::from(sum) * u128::from(sum);
+    if scaled == 0 {
+        return Decibel(f32::NEG_INFINITY);
+    }
+    // Long windows overflow u64, the bits shifted out are far below the precision of the log
+    let shift = (u128::BITS - scaled.leading_zeros()).saturating_sub(u64::BITS);
+    from_octaves(
+        log2_q16((scaled >> shift) as u64) + (i64::from(shift) << FRAC_BITS) - 2 * log2_q16(len),
+    )
+}
+
+// Energies are taken relative to the loudest level, so they fit whatever the absolute levels
+pub fn leq(levels: &[Decibel]) -> Option<Decibel> {
+    leq_weighted(levels.iter().map(|level| (*level, 1)))
+}
+
+// With each level standing for a number of readings
+pub fn leq_weighted(levels: impl Iterator<Item = (Decibel, u32)> + Clone) -> Option<Decibel> {
+    let exponent = |level: Decibel| (to_q16(level) * OCTAVES_PER_DB_Q24) >> 24;
+    let loudest = levels
+        .clone()
+        .filter(|(_, readings)| *readings > 0)
+        .map(|(level, _)| exponent(level))
+        .max()?;
+    let (energy, count) = levels.fold((0u64, 0u64), |(energy, count), (level, readings)| {
+        (
+            energy + u64::from(readings) * exp2_q31(exponent(level) - loudest),
+            count + u64::from(readings),
+        )
+    });
+    Some(from_octaves(
+        loudest + log2_q16(energy) - (31 << FRAC_BITS) - log2_q16(count),
+    ))
+}
@@ -0,0 +1,74 @@
+use crate::dsp::RawAdc;
+
+// The inputs the continuous ADC converts in turn, in the order of its pattern
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    Mic,
+    SecondMic,
+    Vibration,
+}
+
+// One window of every input, sorted out of the interleaved DMA conversions
+pub struct Frame {
+    len: usize,
+    pub sample_rate_hz: f32,
+    pub mic: Vec<RawAdc>,
+    pub second_mic: Vec<RawAdc>,
+    pub vibration: Vec<RawAdc>,
+}
+
+impl Frame {
+    pub fn new(len: usize, sample_rate_hz: f32) -> Self {
+        Frame {
+            len,
+            sample_rate_hz,
+            mic: Vec::with_capacity(len),
+            second_mic: Vec::with_capacity(len),
+            vibration: Vec::with_capacity(len),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.mic.clear();
+        self.second_mic.clear();
+        self.vibration.clear();
+    }
+
+    // Conversions past a full window are dropped, the next window starts with fresh ones
+    pub fn push(&mut self, input: Input, sample: RawAdc) {
+        let samples = match input {
+            Input::Mic => &mut self.mic,
+            Input::SecondMic => &mut self.second_mic,
+            Input::Vibration => &mut self.vibration,
+        };
+        if samples.len() < self.len {
+            samples.push(sample);
+        }
+    }
+
+    // The mic comes first in the pattern, the other inputs are at most a sample behind
+    pub fn is_full(&self) -> bool {
+        self.mic.len() >= self.len
+    }
+
+    // `len` mic samples starting every `step`, with their offset into the window in samples
+    pub fn mic_bursts(&self, len: usize, step: usize) -> impl Iterator<Item = (usize, &[RawAdc])> {
+        let starts = if len == 0 {
+            0
+        } else {
+            (self.mic.len() + 1).saturating_sub(len)
+        };
+        (0..starts)
+            .step_by(step.max(1))
+            .map(move |offset| (offset, &self.mic[offset..offset + len]))
+    }
+
+    // The vibration input averaged over blocks of `block` samples, which also filters out what
+    // a slower rate couldn't represent
+    pub fn vibration_decimated(&self, block: usize) -> impl Iterator<Item = RawAdc> + '_ {
+        self.vibration.chunks_exact(block.max(1)).map(|chunk| {
+            let sum: u32 = chunk.iter().map(|sample| u32::from(sample.0)).sum();
+            RawAdc((sum / chunk.len() as u32) as u16)
+        })
+    }
+}
@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    classification::NoiseClass,
+    dsp::{self, Decibel},
+    reporting,
+};
+
+// Every enabled channel over one interval in a single document, so ingestion is one insert per
+// device and interval instead of a join across topics. Channels without readings are left out.
+pub struct FusedInterval {
+    started: Instant,
+    levels: Vec<Decibel>,
+    noise_class: Option<NoiseClass>,
+    vibration_levels: Vec<Decibel>,
+    vibration_peak: Option<Decibel>,
+    vibration_class: Option<NoiseClass>,
+}
+
+impl Default for FusedInterval {
+    fn default() -> Self {
+        FusedInterval {
+            started: Instant::now(),
+            levels: vec![],
+            noise_class: None,
+            vibration_levels: vec![],
+            vibration_peak: None,
+            vibration_class: None,
+        }
+    }
+}
+
+impl FusedInterval {
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn add_level(&mut self, level: Decibel, class: Option<NoiseClass>) {
+        self.levels.push(level);
+        self.noise_class = class;
+    }
+
+    pub fn add_vibration(&mut self, level: Decibel, peak: Decibel, class: Option<NoiseClass>) {
+        self.vibration_levels.push(level);
+        if self.vibration_peak.map_or(true, |max| peak > max) {
+            self.vibration_peak = Some(peak);
+        }
+        self.vibration_class = class;
+    }
+
+    // The document for the interval so far, then starts the next one. None while nothing was
+    // measured, e.g. paused.
+    pub fn take_json(
+        &mut self,
+        metadata: &str,
+        timestamp: Option<u64>,
+        ts_accuracy_s: Option<u64>,
+        chip_temp_c: Option<f32>,
+    ) -> Option<String> {
+        let interval = std::mem::take(self);
+        let leq = dsp::leq(&interval.levels)?;
+        let max = interval
+            .levels
+            .iter()
+            .copied()
+            .fold(leq, |max, level| if level > max { level } else { max });
+        let class = |class: Option<NoiseClass>| {
+            class.map_or_else(
+                || String::from("null"),
+                |class| format!("\"{}\"", class.as_str()),
+            )
+        };
+        let mut time_source = reporting::broker_time_fields(timestamp.and(ts_accuracy_s));
+        if !time_source.is_empty() {
+            time_source.insert(0, ',');
+        }
+        let timestamp = timestamp.map_or_else(|| String::from("null"), |ts| ts.to_string());
+        let mut json = format!(
+            "{{{},\"timestamp\":{}{},\"interval_s\":{},\"noise\":{{\"leq_db\":{:.1},\"max_db\":{:.1},\"class\":{}}}",
+            metadata,
+            timestamp,
+            time_source,
+            interval.started.elapsed().as_secs(),
+            leq.0,
+            max.0,
+            class(interval.noise_class)
+        );
+        if let (Some(level), Some(peak)) = (
+            dsp::leq(&interval.vibration_levels),
+            interval.vibration_peak,
+        ) {
+            json.push_str(&format!(
+                ",\"vibration\":{{\"level_db\":{:.1},\"peak_db\":{:.1},\"class\":{}}}",
+                level.0,
+                peak.0,
+                class(interval.vibration_class)
+            ));
+        }
+        if let Some(celsius) = chip_temp_c {
+            json.push_str(&format!(",\"temperature\":{{\"chip_c\":{:.1}}}", celsius));
+        }
+        json.push('}');
+        Some(json)
+    }
+}
@@ -0,0 +1,144 @@
+use serde::Serialize;
+
+use crate::topics::Topics;
+
+// How level readings look on the level topic, which decides how Home Assistant gets the dB out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LevelFormat {
+    Number,
+    Document,
+    // Batches, of numbers or documents, of which the last reading is shown
+    Batch { documents: bool },
+}
+
+impl LevelFormat {
+    fn value_template(self) -> Option<&'static str> {
+        match self {
+            LevelFormat::Number => None,
+            LevelFormat::Document => Some("{{ value_json.db }}"),
+            LevelFormat::Batch { documents: false } => Some("{{ value_json[-1] }}"),
+            LevelFormat::Batch { documents: true } => Some("{{ value_json[-1].db }}"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Device<'a> {
+    identifiers: [String; 1],
+    connections: [[&'a str; 2]; 1],
+    name: String,
+    manufacturer: &'static str,
+    model: &'static str,
+    sw_version: &'static str,
+}
+
+// One entity of the discovery scheme, see https://www.home-assistant.io/integrations/sensor.mqtt/
+#[derive(Serialize)]
+struct Sensor<'a> {
+    name: &'static str,
+    unique_id: String,
+    object_id: String,
+    state_topic: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_template: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_measurement: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<&'static [&'static str]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entity_category: Option<&'static str>,
+    availability_topic: &'a str,
+    payload_available: &'static str,
+    payload_not_available: &'static str,
+    device: &'a Device<'a>,
+}
+
+const NOISE_CLASSES: [&str; 4] = ["quiet", "normal", "loud", "very_loud"];
+
+// The retained config documents, as topic and payload, under `<prefix>/sensor/<node id>/`. `mac`
+// is colon separated.
+pub fn documents(
+    prefix: &str,
+    device_id: &str,
+    mac: &str,
+    topics: &Topics,
+    level_format: LevelFormat,
+) -> Vec<(String, String)> {
+    // Home Assistant only takes these in node and object ids
+    let node_id: String = device_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let device = Device {
+        identifiers: [format!("bzzz_{}", node_id)],
+        connections: [["mac", mac]],
+        name: format!("Bzzz {}", device_id),
+        manufacturer: "MongoDB",
+        model: "Mosquitto Bzzz",
+        sw_version: env!("CARGO_PKG_VERSION"),
+    };
+    let sensor = |object: &str, name, state_topic| Sensor {
+        name,
+        unique_id: format!("{}_{}", node_id, object),
+        object_id: format!("bzzz_{}_{}", node_id, object),
+        state_topic,
+        value_template: None,
+        device_class: None,
+        unit_of_measurement: None,
+        state_class: None,
+        options: None,
+        entity_category: None,
+        availability_topic: &topics.availability,
+        payload_available: "online",
+        payload_not_available: "offline",
+        device: &device,
+    };
+    let sensors = [
+        (
+            "level",
+            Sensor {
+                value_template: level_format.value_template(),
+                device_class: Some("sound_pressure"),
+                unit_of_measurement: Some("dB"),
+                state_class: Some("measurement"),
+                ..sensor("level", "Noise level", &topics.level)
+            },
+        ),
+        (
+            "noise_class",
+            Sensor {
+                device_class: Some("enum"),
+                options: Some(&NOISE_CLASSES),
+                ..sensor("noise_class", "Noise class", &topics.classification)
+            },
+        ),
+        (
+            "telemetry",
+            Sensor {
+                value_template: Some("{{ value_json.telemetry }}"),
+                entity_category: Some("diagnostic"),
+                ..sensor("telemetry", "Telemetry", &topics.state)
+            },
+        ),
+    ];
+    sensors
+        .iter()
+        .map(|(object, sensor)| {
+            (
+                format!("{}/sensor/{}/{}/config", prefix, node_id, object),
+                // Nothing in here fails to serialize
+                serde_json::to_string(sensor).unwrap_or_default(),
+            )
+        })
+        .collect()
+}
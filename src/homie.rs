@@ -0,0 +1,91 @@
+use crate::{classification::NoiseClass, dsp::Decibel};
+
+const ROOT: &str = "homie";
+
+// The device in the Homie 4.0 convention, see https://homieiot.github.io/specification/spec-core-v4_0_0/,
+// with a single `noise` node of a `level` and a `class` property. Alongside the usual topics, so
+// Homie controllers find the sensor without being told about it.
+pub struct Homie {
+    base: String,
+}
+
+impl Homie {
+    pub fn new(device_id: &str) -> Self {
+        // Device ids are lowercase letters, digits and hyphens, not starting or ending with one
+        let id: String = device_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        Homie {
+            base: format!("{}/{}", ROOT, id.trim_matches('-')),
+        }
+    }
+
+    pub fn state_topic(&self) -> String {
+        format!("{}/$state", self.base)
+    }
+
+    // The retained attributes, as topic and payload, `$state` first and last so controllers only
+    // read the description once it's complete
+    pub fn description(&self, name: &str) -> Vec<(String, String)> {
+        let attribute =
+            |path: &str, value: &str| (format!("{}/{}", self.base, path), value.to_string());
+        let classes: Vec<&str> = [
+            NoiseClass::Quiet,
+            NoiseClass::Normal,
+            NoiseClass::Loud,
+            NoiseClass::VeryLoud,
+        ]
+        .iter()
+        .map(NoiseClass::as_str)
+        .collect();
+        vec![
+            attribute("$state", "init"),
+            attribute("$homie", "4.0"),
+            attribute("$name", name),
+            attribute("$nodes", "noise"),
+            attribute("$extensions", ""),
+            attribute("noise/$name", "Noise"),
+            attribute("noise/$type", "sound-level-meter"),
+            attribute("noise/$properties", "level,class"),
+            attribute("noise/level/$name", "Noise level"),
+            attribute("noise/level/$datatype", "float"),
+            attribute("noise/level/$unit", "dB"),
+            attribute("noise/level/$retained", "false"),
+            attribute("noise/class/$name", "Noise class"),
+            attribute("noise/class/$datatype", "enum"),
+            attribute("noise/class/$format", &classes.join(",")),
+            attribute("noise/class/$retained", "false"),
+            attribute("$state", "ready"),
+        ]
+    }
+
+    pub fn level(&self, level: Decibel) -> (String, String) {
+        (
+            format!("{}/noise/level", self.base),
+            format!("{:.1}", level.0),
+        )
+    }
+
+    pub fn class(&self, class: NoiseClass) -> (String, String) {
+        (
+            format!("{}/noise/class", self.base),
+            class.as_str().to_string(),
+        )
+    }
+}
+
+// Whether a topic is one of the device's retained `$` attributes, `$state` included, rather than
+// a property value
+pub fn is_attribute(topic: &str) -> bool {
+    topic
+        .rsplit('/')
+        .next()
+        .is_some_and(|last| last.starts_with('$'))
+}
@@ -0,0 +1,20 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// While set, the status LED blinks a pattern no status uses, so an installer can tell which of
+// several sensors is the one they are looking at
+static UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+
+pub fn start(duration: Duration) {
+    log::info!("Identifying for {:?}", duration);
+    *UNTIL.lock().unwrap() = Some(Instant::now() + duration);
+}
+
+pub fn is_active() -> bool {
+    UNTIL
+        .lock()
+        .unwrap()
+        .is_some_and(|until| Instant::now() < until)
+}
@@ -0,0 +1,329 @@
+use crate::classification::NoiseClass;
+
+// While waiting for the next reconnection attempt the dark steps of a fault shrink down to this
+const MIN_RETRY_PAUSE_PCT: u32 = 20;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum DeviceStatus {
+    Ok,
+    WifiError,
+    // Broker unreachable or unavailable
+    MqttError,
+    WorkerError,
+    MqttBadCredentials,
+    MqttNotAuthorized,
+}
+
+impl DeviceStatus {
+    const ALL: [DeviceStatus; 6] = [
+        DeviceStatus::Ok,
+        DeviceStatus::WifiError,
+        DeviceStatus::MqttError,
+        DeviceStatus::WorkerError,
+        DeviceStatus::MqttBadCredentials,
+        DeviceStatus::MqttNotAuthorized,
+    ];
+
+    pub fn light_sequence(&self) -> Vec<ColorStep> {
+        match self {
+            DeviceStatus::Ok => vec![ColorStep::new(0, 255, 0, 500), ColorStep::new(0, 0, 0, 500)],
+            DeviceStatus::WifiError => {
+                vec![ColorStep::new(255, 0, 0, 200), ColorStep::new(0, 0, 0, 100)]
+            }
+            DeviceStatus::MqttError => vec![
+                ColorStep::new(255, 0, 255, 100),
+                ColorStep::new(0, 0, 0, 300),
+            ],
+            DeviceStatus::WorkerError => vec![
+                ColorStep::new(255, 160, 0, 300),
+                ColorStep::new(0, 0, 0, 300),
+            ],
+            // Magenta like the other MQTT errors, but in patterns an installer can tell apart
+            DeviceStatus::MqttBadCredentials => vec![
+                ColorStep::new(255, 0, 255, 100),
+                ColorStep::new(0, 0, 0, 100),
+                ColorStep::new(255, 0, 255, 100),
+                ColorStep::new(0, 0, 0, 700),
+            ],
+            DeviceStatus::MqttNotAuthorized => vec![
+                ColorStep::new(255, 0, 255, 700),
+                ColorStep::new(0, 0, 0, 300),
+            ],
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            DeviceStatus::Ok => "ok",
+            DeviceStatus::WifiError => "wifi_error",
+            DeviceStatus::MqttError => "mqtt_error",
+            DeviceStatus::WorkerError => "worker_error",
+            DeviceStatus::MqttBadCredentials => "mqtt_bad_credentials",
+            DeviceStatus::MqttNotAuthorized => "mqtt_not_authorized",
+        }
+    }
+
+    // English, Spanish and German, like `MEANINGS`
+    fn meaning(&self) -> [&'static str; 3] {
+        match self {
+            DeviceStatus::Ok => [
+                "Running and connected",
+                "Funcionando y conectado",
+                "In Betrieb und verbunden",
+            ],
+            DeviceStatus::WifiError => [
+                "No WiFi connection",
+                "Sin conexión WiFi",
+                "Keine WLAN-Verbindung",
+            ],
+            DeviceStatus::MqttError => [
+                "Broker unreachable or unavailable",
+                "Broker inalcanzable o no disponible",
+                "Broker nicht erreichbar oder nicht verfügbar",
+            ],
+            DeviceStatus::WorkerError => [
+                "A task failed and is being restarted",
+                "Una tarea falló y se está reiniciando",
+                "Ein Task ist ausgefallen und wird neu gestartet",
+            ],
+            DeviceStatus::MqttBadCredentials => [
+                "Broker refused the username or password",
+                "El broker rechazó el usuario o la contraseña",
+                "Broker hat Benutzername oder Passwort abgelehnt",
+            ],
+            DeviceStatus::MqttNotAuthorized => [
+                "Broker says the device is not authorized",
+                "El broker indica que el dispositivo no está autorizado",
+                "Broker meldet, dass das Gerät nicht berechtigt ist",
+            ],
+        }
+    }
+
+    fn is_fault(&self) -> bool {
+        *self != DeviceStatus::Ok
+    }
+}
+
+impl TryFrom<u8> for DeviceStatus {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0u8 => Ok(DeviceStatus::Ok),
+            1u8 => Ok(DeviceStatus::WifiError),
+            2u8 => Ok(DeviceStatus::MqttError),
+            3u8 => Ok(DeviceStatus::WorkerError),
+            4u8 => Ok(DeviceStatus::MqttBadCredentials),
+            5u8 => Ok(DeviceStatus::MqttNotAuthorized),
+            _ => Err("Unknown status"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorStep {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub duration: u64,
+}
+
+impl ColorStep {
+    pub const fn new(red: u8, green: u8, blue: u8, duration: u64) -> Self {
+        ColorStep {
+            red,
+            green,
+            blue,
+            duration,
+        }
+    }
+
+    pub fn is_dark(&self) -> bool {
+        self.red == 0 && self.green == 0 && self.blue == 0
+    }
+
+    fn to_json(self) -> String {
+        format!(
+            "{{\"color\":\"#{:02x}{:02x}{:02x}\",\"ms\":{}}}",
+            self.red, self.green, self.blue, self.duration
+        )
+    }
+}
+
+pub fn identify_sequence() -> Vec<ColorStep> {
+    vec![
+        ColorStep::new(255, 255, 255, 100),
+        ColorStep::new(0, 0, 255, 100),
+    ]
+}
+
+pub fn maintenance_sequence() -> Vec<ColorStep> {
+    vec![
+        ColorStep::new(0, 255, 255, 1000),
+        ColorStep::new(0, 0, 0, 1000),
+    ]
+}
+
+// Dark to save power, faults still show
+pub fn logger_sequence() -> Vec<ColorStep> {
+    vec![ColorStep::new(0, 0, 0, 1000)]
+}
+
+// A steady color, of a rule or the noise class
+pub fn steady_sequence([red, green, blue]: [u8; 3]) -> Vec<ColorStep> {
+    vec![ColorStep::new(red, green, blue, 500)]
+}
+
+// Green to red like the dashboard
+pub fn class_color(class: NoiseClass) -> [u8; 3] {
+    match class {
+        NoiseClass::Quiet | NoiseClass::Normal => [0, 255, 0],
+        NoiseClass::Loud => [255, 144, 0],
+        NoiseClass::VeryLoud => [255, 0, 0],
+    }
+}
+
+const LANGUAGES: [&str; 3] = ["en", "es", "de"];
+const MEANINGS: [(&str, [&str; 3]); 7] = [
+    (
+        "identify",
+        [
+            "Identifying itself on request",
+            "Identificándose a petición",
+            "Identifiziert sich auf Anfrage",
+        ],
+    ),
+    (
+        "maintenance",
+        ["Maintenance mode", "Modo de mantenimiento", "Wartungsmodus"],
+    ),
+    (
+        "rule",
+        [
+            "Color set by a local rule",
+            "Color fijado por una regla local",
+            "Farbe von einer lokalen Regel gesetzt",
+        ],
+    ),
+    (
+        "logger",
+        [
+            "Logger mode, dark while all is well",
+            "Modo registrador, apagado mientras todo va bien",
+            "Logger-Modus, dunkel solange alles in Ordnung ist",
+        ],
+    ),
+    (
+        "class_normal",
+        [
+            "Quiet or normal noise",
+            "Ruido bajo o normal",
+            "Leise oder normale Lautstärke",
+        ],
+    ),
+    ("class_loud", ["Loud noise", "Ruido alto", "Laut"]),
+    (
+        "class_very_loud",
+        ["Very loud noise", "Ruido muy alto", "Sehr laut"],
+    ),
+];
+
+fn meaning_of(code: &str) -> [&'static str; 3] {
+    MEANINGS
+        .iter()
+        .find(|(name, _)| *name == code)
+        .map_or(["", "", ""], |(_, meaning)| *meaning)
+}
+
+fn pattern_json(
+    code: &str,
+    status: Option<DeviceStatus>,
+    steps: Option<&[ColorStep]>,
+    meaning: [&str; 3],
+) -> String {
+    let status = status.map_or_else(|| String::from("null"), |status| (status as u8).to_string());
+    let steps = steps.map_or_else(
+        || String::from("null"),
+        |steps| {
+            let steps: Vec<String> = steps.iter().map(|step| step.to_json()).collect();
+            format!("[{}]", steps.join(","))
+        },
+    );
+    let meaning: Vec<String> = LANGUAGES
+        .iter()
+        .zip(meaning)
+        .map(|(language, text)| format!("\"{}\":\"{}\"", language, text))
+        .collect();
+    format!(
+        "{{\"code\":\"{}\",\"status\":{},\"steps\":{},\"meaning\":{{{}}}}}",
+        code,
+        status,
+        steps,
+        meaning.join(",")
+    )
+}
+
+// Every pattern the LED shows, in the order the LED task picks them, the first that applies wins.
+// `status` is the status code of faults, `steps` null for the colors rules choose.
+pub fn codes_json() -> String {
+    let mut patterns = vec![
+        pattern_json(
+            "identify",
+            None,
+            Some(&identify_sequence()),
+            meaning_of("identify"),
+        ),
+        pattern_json(
+            "maintenance",
+            None,
+            Some(&maintenance_sequence()),
+            meaning_of("maintenance"),
+        ),
+        pattern_json("rule", None, None, meaning_of("rule")),
+    ];
+    for status in DeviceStatus::ALL.iter().filter(|status| status.is_fault()) {
+        patterns.push(pattern_json(
+            status.code(),
+            Some(*status),
+            Some(&status.light_sequence()),
+            status.meaning(),
+        ));
+    }
+    patterns.push(pattern_json(
+        "logger",
+        None,
+        Some(&logger_sequence()),
+        meaning_of("logger"),
+    ));
+    for (code, class) in [
+        ("class_normal", NoiseClass::Normal),
+        ("class_loud", NoiseClass::Loud),
+        ("class_very_loud", NoiseClass::VeryLoud),
+    ] {
+        patterns.push(pattern_json(
+            code,
+            None,
+            Some(&steady_sequence(class_color(class))),
+            meaning_of(code),
+        ));
+    }
+    let ok = DeviceStatus::Ok;
+    patterns.push(pattern_json(
+        ok.code(),
+        Some(ok),
+        Some(&ok.light_sequence()),
+        ok.meaning(),
+    ));
+    format!(
+        "{{\"languages\":[\"en\",\"es\",\"de\"],\"retry_pause_min_pct\":{},\"patterns\":[{}]}}",
+        MIN_RETRY_PAUSE_PCT,
+        patterns.join(",")
+    )
+}
+
+// How much of a dark step is left while a fault waits to reconnect, with `remaining` of the wait
+pub fn retry_pause_scale(remaining: f32) -> f32 {
+    let min = MIN_RETRY_PAUSE_PCT as f32 / 100.0;
+    min + (1.0 - min) * remaining
+}
@@ -0,0 +1,75 @@
+use std::{collections::VecDeque, fmt::Write, sync::Mutex};
+
+use esp_idf_svc::{log::EspLogger, sys::esp_log_timestamp};
+use log::{Level, Log, Metadata, Record};
+
+// About the last minute of a busy log
+const TAIL_LEN: usize = 8192;
+
+static ESP_LOGGER: EspLogger = EspLogger;
+static LOGGER: TailLogger = TailLogger;
+
+struct Tail {
+    lines: VecDeque<String>,
+    len: usize,
+}
+
+static TAIL: Mutex<Tail> = Mutex::new(Tail {
+    lines: VecDeque::new(),
+    len: 0,
+});
+
+// Logs like the default ESP-IDF logger and keeps the latest lines from info up in RAM, for the
+// diagnostic bundle. Only the firmware's own log, not the C components printing straight to the
+// console.
+struct TailLogger;
+
+impl Log for TailLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        ESP_LOGGER.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        ESP_LOGGER.log(record);
+        if record.level() > Level::Info || !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut line = String::new();
+        let _ = write!(
+            line,
+            "{} ({}) {}: {}",
+            record.level(),
+            unsafe { esp_log_timestamp() },
+            record.target(),
+            record.args()
+        );
+        let mut tail = TAIL.lock().unwrap();
+        tail.len += line.len();
+        tail.lines.push_back(line);
+        while tail.len > TAIL_LEN {
+            let Some(oldest) = tail.lines.pop_front() else {
+                break;
+            };
+            tail.len -= oldest.len();
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// In place of EspLogger::initialize_default()
+pub fn init() {
+    log::set_logger(&LOGGER)
+        .map(|()| ESP_LOGGER.initialize())
+        .unwrap();
+}
+
+pub fn recent() -> String {
+    let tail = TAIL.lock().unwrap();
+    let mut text = String::with_capacity(tail.len + tail.lines.len());
+    for line in &tail.lines {
+        text.push_str(line);
+        text.push('\n');
+    }
+    text
+}
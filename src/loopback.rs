@@ -0,0 +1,96 @@
+use std::time::{Duration, Instant};
+
+struct Probe {
+    // As published, sealed payloads included, so whatever the broker delivers back is compared
+    // byte for byte
+    payload: Vec<u8>,
+    qos: u8,
+    msg_id: Option<u32>,
+    started: Instant,
+    acknowledged: Option<Duration>,
+}
+
+// One end-to-end check at a time: the device subscribes to its own level topic, publishes a
+// marker there and waits for the broker to deliver it back. That covers WiFi, the broker's ACLs
+// for publishing and subscribing, and the QoS the readings use.
+pub struct Loopback {
+    timeout: Duration,
+    probe: Option<Probe>,
+}
+
+impl Loopback {
+    pub fn new(timeout: Duration) -> Self {
+        Loopback {
+            timeout,
+            probe: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.probe.is_some()
+    }
+
+    // What backends reading the level topic see, and can skip by its `loopback` field
+    pub fn marker(nonce: u32) -> String {
+        format!("{{\"loopback\":{}}}", nonce)
+    }
+
+    // After publishing the marker, without a message id for QoS 0
+    pub fn start(&mut self, payload: Vec<u8>, qos: u8, msg_id: Option<u32>, now: Instant) {
+        self.probe = Some(Probe {
+            payload,
+            qos,
+            msg_id: msg_id.filter(|msg_id| *msg_id != 0),
+            started: now,
+            acknowledged: None,
+        });
+    }
+
+    pub fn acknowledge(&mut self, msg_id: u32, now: Instant) {
+        if let Some(probe) = self.probe.as_mut() {
+            if probe.msg_id == Some(msg_id) && probe.acknowledged.is_none() {
+                probe.acknowledged = Some(now.duration_since(probe.started));
+            }
+        }
+    }
+
+    // Every message on the level topic while the check runs, returns the result once the marker
+    // came back
+    pub fn received(&mut self, payload: &[u8], now: Instant) -> Option<String> {
+        if self.probe.as_ref()?.payload != payload {
+            return None;
+        }
+        let probe = self.probe.take()?;
+        Some(result(
+            true,
+            probe.qos,
+            probe.acknowledged,
+            Some(now.duration_since(probe.started)),
+        ))
+    }
+
+    pub fn take_timed_out(&mut self, now: Instant) -> Option<String> {
+        if now.duration_since(self.probe.as_ref()?.started) < self.timeout {
+            return None;
+        }
+        let probe = self.probe.take()?;
+        Some(result(false, probe.qos, probe.acknowledged, None))
+    }
+}
+
+// Latencies in milliseconds, `null` for what didn't happen. A PUBACK without the round trip
+// points at the subscription side.
+pub fn result(ok: bool, qos: u8, puback: Option<Duration>, round_trip: Option<Duration>) -> String {
+    let millis = |latency: Option<Duration>| {
+        latency.map_or(String::from("null"), |latency| {
+            latency.as_millis().to_string()
+        })
+    };
+    format!(
+        "{{\"ok\":{},\"qos\":{},\"puback_ms\":{},\"round_trip_ms\":{}}}",
+        ok,
+        qos,
+        millis(puback),
+        millis(round_trip)
+    )
+}
@@ -1,97 +1,233 @@
 use std::{
+    ffi::c_void,
     fmt::Write,
-    sync::atomic::{AtomicU8, Ordering::Relaxed},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering::Relaxed},
+        mpsc, OnceLock,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, Context, Result};
 use esp_idf_svc::{
-    eventloop::EspSystemEventLoop,
     hal::{
-        adc::{self, attenuation, AdcChannelDriver, AdcDriver, ADC1},
+        adc::ADC1,
         gpio::{ADCPin, OutputPin},
-        modem,
         peripheral::Peripheral,
         peripherals::Peripherals,
         rmt::RmtChannel,
+        task::thread::ThreadSpawnConfiguration,
+    },
+    mqtt::client::{
+        EspMqttClient, EventPayload, LwtConfiguration, MessageId, MqttClientConfiguration,
+        MqttProtocolVersion, QoS,
+    },
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sntp::EspSntp,
+    sys::{
+        esp_base_mac_addr_get, esp_crt_bundle_attach, esp_deep_sleep_start, esp_fill_random,
+        esp_random, esp_restart, esp_timer_get_time, EspError, ESP_OK,
     },
-    mqtt::client::{EspMqttClient, MqttClientConfiguration, QoS},
-    nvs::EspDefaultNvsPartition,
-    sys::{esp_base_mac_addr_get, ESP_OK},
-    wifi::{self, AuthMethod, BlockingWifi, EspWifi},
+    tls::X509,
 };
 use ws2812_esp32_rmt_driver::{
     driver::color::{LedPixelColor, LedPixelColorGrb24},
     Ws2812Esp32RmtDriver,
 };
 
-#[repr(u8)]
-#[derive(Clone, Copy, PartialEq)]
-enum DeviceStatus {
-    Ok,
-    WifiError,
-    MqttError,
-}
-
-impl DeviceStatus {
-    fn light_sequence(&self) -> Vec<ColorStep> {
-        match self {
-            DeviceStatus::Ok => vec![ColorStep::new(0, 255, 0, 500), ColorStep::new(0, 0, 0, 500)],
-            DeviceStatus::WifiError => {
-                vec![ColorStep::new(255, 0, 0, 200), ColorStep::new(0, 0, 0, 100)]
-            }
-            DeviceStatus::MqttError => vec![
-                ColorStep::new(255, 0, 255, 100),
-                ColorStep::new(0, 0, 0, 300),
-            ],
-        }
-    }
-}
+mod alerting;
+mod auth;
+mod automation;
+mod autotune;
+mod backoff;
+mod bands;
+mod benchmark;
+mod boot;
+mod bus;
+mod calibration;
+mod claim;
+mod classification;
+mod clock;
+mod command;
+mod config;
+mod connection;
+mod dashboard;
+mod delivery;
+mod demo;
+mod diag_bundle;
+mod direction;
+mod discovery;
+mod display;
+mod dsp;
+mod encoding;
+mod enrollment;
+mod factory;
+mod features;
+mod fft;
+mod firmware_metrics;
+#[cfg(feature = "fixed-point")]
+mod fixed_point;
+mod frame;
+mod fusion;
+mod home_assistant;
+#[cfg(feature = "homie")]
+mod homie;
+mod identify;
+mod led;
+mod log_tail;
+mod loopback;
+mod maintenance;
+mod metrics;
+mod mode;
+mod mqtt5;
+mod network;
+mod offline;
+mod ota;
+mod outage;
+mod payload_log;
+mod profiling;
+mod provisioning;
+mod reporting;
+mod rules;
+mod sampler;
+mod schedule;
+mod sealing;
+#[cfg(feature = "secure-element")]
+mod secure_element;
+mod security;
+mod sequence;
+mod signing;
+mod solar;
+mod spectrum;
+mod supervisor;
+mod support;
+mod thermal;
+mod tone;
+mod topics;
+mod vibration;
+mod watchdog;
+mod wear;
+mod web_auth;
+mod weighting;
 
-impl TryFrom<u8> for DeviceStatus {
-    type Error = &'static str;
+use alerting::{AlertBurst, AlertJournal, AlertRule, Limits, ThresholdWatch, Thresholds};
+use auth::{FetchedToken, SignedJwt, TokenProvider};
+use automation::Actuators;
+use autotune::AutoTune;
+use backoff::{Backoff, BackoffPolicy};
+use bands::OctaveAnalyzer;
+use boot::BootReport;
+use bus::{BusEvent, Link};
+use calibration::Calibration;
+use claim::{Activation, ClaimTopics};
+use classification::{Classifier, NoiseClass};
+use command::Command;
+use config::{Config, ConfigStore};
+use dashboard::Dashboard;
+use delivery::DeliveryTracker;
+use demo::NoiseSimulator;
+use direction::{DirectionHint, StereoBurst};
+use display::DisplaySettings;
+use dsp::{Decibel, LevelFilter, Plausibility};
+use enrollment::{Enrollment, Identity};
+use features::{Feature, Features};
+use firmware_metrics::FirmwareMetrics;
+use fusion::FusedInterval;
+use home_assistant::LevelFormat;
+use led::{ColorStep, DeviceStatus};
+use loopback::Loopback;
+use mode::OperatingMode;
+use mqtt5::Publish;
+use network::{MqttBackoff, RetryCountdown};
+use offline::OfflineQueue;
+use outage::OutageTracker;
+use payload_log::Module;
+use profiling::Profiler;
+use reporting::{IntervalLeq, LevelBatch, LevelReading, Reporter};
+use rules::{Action, RuleEngine};
+use sampler::Sampler;
+use schedule::Schedule;
+use security::SecurityState;
+use sequence::Counter;
+use spectrum::{Burst, SpectralStats};
+use thermal::ChipTemperature;
+use tone::ToneEvent;
+use topics::{LegacyTopics, Namespace, Topics};
+use vibration::VibrationMeter;
+use web_auth::WebAuth;
+use weighting::WeightingFilter;
 
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            0u8 => Ok(DeviceStatus::Ok),
-            1u8 => Ok(DeviceStatus::WifiError),
-            2u8 => Ok(DeviceStatus::MqttError),
-            _ => Err("Unknown status"),
-        }
-    }
-}
-
-#[toml_cfg::toml_config]
-struct Configuration {
-    #[default("NotMyWifi")]
-    wifi_ssid: &'static str,
-    #[default("NotMyPassword")]
-    wifi_password: &'static str,
-    #[default("mqttserver")]
-    mqtt_host: &'static str,
-    #[default("")]
-    mqtt_user: &'static str,
-    #[default("")]
-    mqtt_password: &'static str,
-}
+const NVS_NAMESPACE: &str = factory::NVS_DEVICE_NAMESPACE;
+const NVS_PAUSED_KEY: &str = "paused";
+const NVS_CLAIMED_KEY: &str = "claimed";
+const NVS_UUID_KEY: &str = "uuid";
+const NVS_OFFLINE_KEY: &str = "offline";
+// Renamed when levels started leaving out the DC bias, offsets from before are about 40 dB off
+const NVS_CALIBRATION_KEY: &str = "calibration_ac";
+const NVS_OLD_CALIBRATION_KEY: &str = "calibration";
+const NVS_AUTOTUNE_KEY: &str = "autotune";
+// Keeps the blob within a couple of NVS pages
+const MAX_PERSISTED_READINGS: usize = 32;
+// Bounds the flash wear while readings pile up
+const OFFLINE_PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+// Once the flash is worn, queued readings risk more on a power loss rather than using it up faster
+const OFFLINE_PERSIST_INTERVAL_THROTTLED: Duration = Duration::from_secs(600);
+// A restart loses at most this much of a threshold learning observation
+const AUTOTUNE_SAVE_INTERVAL: Duration = Duration::from_secs(3600);
+const AUTOTUNE_SAVE_INTERVAL_THROTTLED: Duration = Duration::from_secs(4 * 3600);
+// Queued readings published per loop iteration, so the delivery tracker keeps up
+const OFFLINE_FLUSH_LEN: usize = 4;
+// The base MAC address in hex, the rest of the id is padding
+const MAC_HEX_LEN: usize = 12;
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(60);
+const PROFILE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// Whether diagnostics are published or not, the die takes minutes to heat up
+const THERMAL_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+const TOKEN_RETRY_BACKOFF: BackoffPolicy = BackoffPolicy {
+    initial: Duration::from_secs(30),
+    max: Duration::from_secs(300),
+    multiplier: 2,
+    jitter: 0.2,
+};
+// The backend may not be listening yet when the device first connects
+const CLAIM_BACKOFF: BackoffPolicy = BackoffPolicy {
+    initial: Duration::from_secs(60),
+    max: Duration::from_secs(3600),
+    multiplier: 2,
+    jitter: 0.2,
+};
+// Token refreshes and certificate renewals happen inline and may take a few HTTP timeouts
+const SENSOR_WATCHDOG_TIMEOUT: Duration = Duration::from_secs(60);
+const LED_WATCHDOG_TIMEOUT: Duration = Duration::from_secs(10);
+const CERT_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+const IDENTIFY_DURATION: Duration = Duration::from_secs(30);
+// A healthy broker echoes within a second, this leaves room for a slow link and a retry
+const LOOPBACK_TIMEOUT: Duration = Duration::from_secs(10);
+const MAINTENANCE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const BURST_INTERVAL: Duration = Duration::from_secs(1);
+const DIRECTION_INTERVAL: Duration = Duration::from_secs(10);
+const VIBRATION_INTERVAL: Duration = Duration::from_secs(1);
+// Down to the 100 Hz the vibration meter filters for
+const VIBRATION_DECIMATION: usize = sampler::SAMPLE_RATE_HZ as usize / 100;
+// 10 ms of the sample window between two tone bursts
+const TONE_BURST_STEP: usize = sampler::SAMPLE_RATE_HZ as usize / 100;
+// Without a broker at boot the sensor starts with the settings it has
+const BOOT_CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+// The broker sends a retained message right after the subscription, if there is one
+const RETAINED_CONFIG_TIMEOUT: Duration = Duration::from_secs(3);
 
-struct ColorStep {
-    red: u8,
-    green: u8,
-    blue: u8,
-    duration: u64,
-}
-
-impl ColorStep {
-    fn new(red: u8, green: u8, blue: u8, duration: u64) -> Self {
-        ColorStep {
-            red,
-            green,
-            blue,
-            duration,
-        }
-    }
+enum MqttNotification {
+    BeforeConnect,
+    Connected,
+    Disconnected,
+    Published(MessageId),
+    Command(Command),
+    Activation(Activation),
+    ConfigApplied,
+    // Anything on the level topic, only subscribed to during a loopback check
+    Telemetry(Vec<u8>),
 }
 
 fn main() {
@@ -100,93 +236,2496 @@ fn main() {
     esp_idf_svc::sys::link_patches();
 
     // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
+    log_tail::init();
 
     log::info!("Hello, world!");
+    let nvs_partition =
+        EspDefaultNvsPartition::take().expect("Unable to access default NVS partition");
+    let config = ConfigStore::load(nvs_partition.clone());
+    log::info!("Configuration:\n{}", config.dump());
+    let app_config = config.get();
+    security::require_encryption_for_secrets(app_config.require_encrypted_secrets);
+    // Never falls back to clear text for a broker that isn't trusted
+    if app_config.encrypt_payloads {
+        sealing::load_key(nvs_partition.clone()).expect("Unable to load payload key");
+    }
+    if app_config.sign_payloads {
+        signing::load_key(nvs_partition.clone()).expect("Unable to load signing key");
+    }
+    if let Err(err) = wear::init(
+        nvs_partition.clone(),
+        app_config.flash_endurance_cycles,
+        app_config.flash_wear_throttle_pct,
+    ) {
+        log::error!("Flash wear not tracked: {:#}", err);
+    }
+    if let Err(err) = sequence::init(nvs_partition.clone()) {
+        log::error!("Sequences start over: {:#}", err);
+    }
+    clock::set_timezone(app_config.timezone);
 
     let status = &AtomicU8::new(0u8);
+    let led_brightness = &AtomicU8::new(app_config.day_led_brightness);
+    let wifi_retry = &RetryCountdown::new();
+    let mqtt_retry = &RetryCountdown::new();
     let peripherals = Peripherals::take().expect("Unable to access device peripherals");
-    let rmt_channel = peripherals.rmt.channel0;
-    let led_pin = peripherals.pins.gpio8;
-    let adc = peripherals.adc1;
-    let adc_pin = peripherals.pins.gpio0;
+    let mut rmt_channel = peripherals.rmt.channel0;
+    let mut led_pin = peripherals.pins.gpio8;
+    let mut adc = peripherals.adc1;
+    let mut adc_pin = peripherals.pins.gpio0;
+    let mut second_mic_pin = peripherals.pins.gpio1;
+    let mut vibration_pin = peripherals.pins.gpio2;
     let modem = peripherals.modem;
+    let wifi_nvs_partition = nvs_partition.clone();
+    watchdog::start(nvs_partition.clone());
+    connection::start();
+    if let Err(err) = bus::start() {
+        log::error!("Unable to start event bus: {}", err);
+    }
+    let boot_report = BootReport::detect(nvs_partition.clone());
+    // Outlives sensor worker restarts, the comparison covers the first hour since boot
+    let mut firmware_metrics = FirmwareMetrics::new(nvs_partition.clone());
     thread::scope(|scope| {
-        scope.spawn(|| report_status(status, rmt_channel, led_pin));
+        name_next_thread(b"led\0");
+        scope.spawn(|| {
+            supervisor::supervise("LED", status, || {
+                report_status(
+                    status,
+                    led_brightness,
+                    wifi_retry,
+                    mqtt_retry,
+                    &mut rmt_channel,
+                    &mut led_pin,
+                )
+            })
+        });
+        name_next_thread(b"wifi_sup\0");
+        thread::Builder::new()
+            .stack_size(6144)
+            .spawn_scoped(scope, || {
+                network::supervise_wifi(
+                    status,
+                    wifi_retry,
+                    app_config.wifi_ssid,
+                    app_config.wifi_password,
+                    app_config.provisioning == "dpp",
+                    app_config.diagnostics_ap_password,
+                    modem,
+                    wifi_nvs_partition,
+                )
+            })
+            .unwrap();
+        name_next_thread(b"sensor\0");
         thread::Builder::new()
             .stack_size(6144)
-            .spawn_scoped(scope, || read_noise_level(status, adc, adc_pin, modem))
+            .spawn_scoped(scope, || {
+                supervisor::supervise("Sensor", status, || {
+                    read_noise_level(
+                        status,
+                        led_brightness,
+                        mqtt_retry,
+                        &mut adc,
+                        &mut adc_pin,
+                        &mut second_mic_pin,
+                        &mut vibration_pin,
+                        nvs_partition.clone(),
+                        config.clone(),
+                        boot_report.clone(),
+                        &mut firmware_metrics,
+                    )
+                })
+            })
             .unwrap();
     });
 }
 
-fn read_noise_level<GPIO>(
+// Names the FreeRTOS task behind the next thread spawned from this one, so that profiling can
+// tell them apart
+fn name_next_thread(name: &'static [u8]) {
+    let config = ThreadSpawnConfiguration {
+        name: Some(name),
+        ..Default::default()
+    };
+    if let Err(err) = config.set() {
+        log::warn!("Unable to name thread: {}", err);
+    }
+}
+
+// Once per boot, outside the sensor worker so a restart by the supervisor doesn't report it again
+static BOOT_REPORTED: AtomicBool = AtomicBool::new(false);
+
+#[allow(clippy::too_many_arguments)]
+fn read_noise_level<GPIO, MIC2, VIB>(
     status: &AtomicU8,
-    adc1: ADC1,
-    adc1_pin: GPIO,
-    modem: impl Peripheral<P = modem::Modem> + 'static,
-) -> !
+    led_brightness: &AtomicU8,
+    mqtt_retry: &RetryCountdown,
+    adc1: impl Peripheral<P = ADC1>,
+    adc1_pin: impl Peripheral<P = GPIO>,
+    second_mic_pin: impl Peripheral<P = MIC2>,
+    vibration_pin: impl Peripheral<P = VIB>,
+    nvs_partition: EspDefaultNvsPartition,
+    config: ConfigStore,
+    boot_report: BootReport,
+    firmware_metrics: &mut FirmwareMetrics,
+) -> Result<()>
 where
     GPIO: ADCPin<Adc = ADC1>,
+    MIC2: ADCPin<Adc = ADC1>,
+    VIB: ADCPin<Adc = ADC1>,
 {
-    const LEN: usize = 5;
-    let mut sample_buffer = [0u16; LEN];
-    let app_config = CONFIGURATION;
-    let mut adc =
-        AdcDriver::new(adc1, &adc::config::Config::default()).expect("Unable to initialze ADC1");
-    let mut adc_channel: AdcChannelDriver<{ attenuation::DB_11 }, _> =
-        AdcChannelDriver::new(adc1_pin).expect("Unable to access ADC1 channel 0");
-    let _wifi = match connect_to_wifi(app_config.wifi_ssid, app_config.wifi_password, modem) {
-        Ok(wifi) => Some(wifi),
-        Err(err) => {
-            log::error!("Connect to WiFi: {}", err);
-            status.store(DeviceStatus::WifiError as u8, Relaxed);
-            None
-        }
-    };
-    let sensor_id = get_sensor_id();
-    let topic = format!("home/noise sensor/{sensor_id}");
-    let mqtt_url = if app_config.mqtt_user.is_empty() || app_config.mqtt_password.is_empty() {
-        format!("mqtt://{}/", app_config.mqtt_host)
+    let mut app_config = config.get();
+    let mut sampler = Sampler::new(
+        adc1,
+        adc1_pin,
+        app_config.direction_mic.then_some(second_mic_pin),
+        app_config.vibration_sensor.then_some(vibration_pin),
+    )?;
+    let mut frame = sampler.frame(sample_window(&app_config));
+    let mut weighting = WeightingFilter::new(app_config.weighting, frame.sample_rate_hz);
+    let mut octave_analyzer =
+        OctaveAnalyzer::new(app_config.band_fft_len as usize, frame.sample_rate_hz);
+    let mut nvs = EspNvs::new(nvs_partition.clone(), NVS_NAMESPACE, true)
+        .context("Unable to open NVS namespace")?;
+    let mut paused = matches!(nvs.get_u8(NVS_PAUSED_KEY), Ok(Some(1u8)));
+    let mut calibration = load_calibration(&mut nvs);
+    let mut calibration_session: Option<calibration::Session> = None;
+    let mut autotune = load_autotune(&mut nvs);
+    let mut autotune_saved = Instant::now();
+    let mut autotune_proposed = false;
+    let features = Features::load(&nvs);
+    let mut alert_journal =
+        AlertJournal::new(nvs_partition.clone()).context("Unable to open alert journal")?;
+    let chip_temperature = if features.is_enabled(Feature::ThermalMonitor) {
+        ChipTemperature::new()
+            .map_err(|err| log::error!("Unable to start temperature sensor: {}", err))
+            .ok()
     } else {
+        None
+    };
+    let _sntp = EspSntp::new_default()
+        .map_err(|err| log::error!("Unable to start SNTP: {}", err))
+        .ok();
+    let mac = get_sensor_id();
+    let sensor_id = config::factory_data()
+        .and_then(|data| data.device_id.clone())
+        .unwrap_or_else(|| mac.clone());
+    #[cfg(feature = "homie")]
+    let homie = homie::Homie::new(&sensor_id);
+    let security_state = SecurityState::detect();
+    if !security_state.flash_encryption || !security_state.secure_boot {
+        log::warn!(
+            "Flash encryption {}, secure boot {}",
+            if security_state.flash_encryption {
+                "on"
+            } else {
+                "off"
+            },
+            if security_state.secure_boot {
+                "on"
+            } else {
+                "off"
+            }
+        );
+    }
+    let namespace = Namespace {
+        tenant_id: app_config.tenant_id,
+        site_id: if app_config.site_id.is_empty() {
+            app_config.site
+        } else {
+            app_config.site_id
+        },
+        floor: app_config.floor,
+    };
+    let topics = Topics::new(
+        app_config.topic_template,
+        &sensor_id,
+        &mac[..MAC_HEX_LEN],
+        &namespace,
+        app_config.topic_migration,
+    )
+    .context("Invalid topic template")?;
+    // The whole MAC, where esp-mqtt's default only has its last bytes and sensors collide,
+    // kicking each other off the broker
+    let mqtt_client_id = if app_config.mqtt_client_id.is_empty() {
         format!(
-            "mqtt://{}:{}@{}/",
-            app_config.mqtt_user, app_config.mqtt_password, app_config.mqtt_host
+            "{}-{}",
+            app_config.mqtt_client_id_prefix,
+            &mac[..MAC_HEX_LEN]
         )
+    } else {
+        app_config.mqtt_client_id.to_string()
+    };
+    let use_mqtt5 = match app_config.mqtt_protocol {
+        "3.1.1" => false,
+        "5" => true,
+        other => bail!("Unknown MQTT protocol {:?}", other),
+    };
+    mqtt5::init(use_mqtt5, &sensor_id);
+    let claim_topics = ClaimTopics::new(app_config.claim_topic, &sensor_id);
+    // Claimed once, for good
+    let mut claimed =
+        claim_topics.is_none() || matches!(nvs.get_u8(NVS_CLAIMED_KEY), Ok(Some(1u8)));
+    let mut next_claim_request: Option<Instant> = None;
+    let mut claim_backoff = Backoff::new(CLAIM_BACKOFF, unsafe { esp_random() });
+    let mqtt_host = if app_config.mqtt_host.is_empty() {
+        discovery::discover_broker().context("No MQTT broker host configured or discovered")?
+    } else {
+        app_config.mqtt_host.to_string()
+    };
+    let mut enrollment = if app_config.est_url.is_empty() {
+        None
+    } else {
+        Some(Enrollment::new(
+            nvs_partition,
+            app_config.est_url,
+            app_config.est_user,
+            app_config.est_password,
+            &sensor_id,
+            Duration::from_secs(u64::from(app_config.cert_renew_before_days) * 86400),
+        )?)
+    };
+    let mut identity = match enrollment.as_mut() {
+        Some(enrollment) if enrollment.renewal_due() => match enrollment.enroll() {
+            Ok(identity) => Some(identity),
+            // A certificate close to expiry still works, retry later
+            Err(err) if enrollment.identity().is_some() => {
+                log::error!("Unable to renew certificate: {:#}", err);
+                enrollment.identity()
+            }
+            Err(err) => return Err(err.context("Unable to enroll device certificate")),
+        },
+        Some(enrollment) => enrollment.identity(),
+        None => factory_identity(),
+    };
+    let mut last_cert_check = Instant::now();
+    // mTLS needs the TLS transport, which esp-mqtt runs on 8883 by default
+    let use_tls = app_config.mqtt_use_tls || identity.is_some();
+    let ca_certificate = mqtt_ca_certificate(app_config.mqtt_ca_cert);
+    // esp-mqtt defaults to port 80 for ws:// and 443 for wss://
+    let mqtt_url = match (app_config.mqtt_transport, use_tls) {
+        ("tcp", false) => format!("mqtt://{}/", mqtt_host),
+        ("tcp", true) => format!("mqtts://{}/", mqtt_host),
+        ("websocket", tls) => format!(
+            "{}://{}/{}",
+            if tls { "wss" } else { "ws" },
+            mqtt_host,
+            app_config.mqtt_ws_path.trim_start_matches('/')
+        ),
+        (other, _) => bail!("Unknown MQTT transport {:?}", other),
+    };
+    let lifetime = Duration::from_secs(app_config.token_lifetime_s.into());
+    let mut token_provider: Option<Box<dyn TokenProvider>> = match app_config.mqtt_auth {
+        "password" => None,
+        "jwt" => Some(Box::new(SignedJwt::new(
+            app_config.jwt_key,
+            app_config.jwt_audience,
+            &sensor_id,
+            lifetime,
+        ))),
+        "token_url" => Some(Box::new(FetchedToken::new(app_config.token_url, lifetime))),
+        other => bail!("Unknown MQTT auth method {:?}", other),
+    };
+    let (mut mqtt_password, mut token_refresh) = match token_provider.as_mut() {
+        Some(provider) => {
+            let (token, refresh_at) =
+                auth::next_token(provider.as_mut()).context("Unable to get MQTT token")?;
+            (token, Some(refresh_at))
+        }
+        None => (app_config.mqtt_password.to_string(), None),
     };
 
-    let mut mqtt_client =
-        EspMqttClient::new_cb(&mqtt_url, &MqttClientConfiguration::default(), |_| {
-            log::info!("MQTT client callback")
-        })
-        .expect("Unable to initialize MQTT client");
-    let mut mqtt_msg: String;
+    // There is one will per connection. With Homie it's the device's `$state`, so controllers see a
+    // lost sensor, and the status topic keeps its last word.
+    #[cfg(not(feature = "homie"))]
+    let last_will = (topics.availability.as_str(), b"offline".as_slice());
+    #[cfg(feature = "homie")]
+    let homie_state_topic = homie.state_topic();
+    #[cfg(feature = "homie")]
+    let last_will = (homie_state_topic.as_str(), b"lost".as_slice());
+
+    let (notification_tx, notification_rx) = mpsc::channel();
+    let mut mqtt_client = connect_mqtt(
+        &mqtt_url,
+        app_config.mqtt_user,
+        &mqtt_password,
+        &mqtt_client_id,
+        use_tls,
+        ca_certificate,
+        identity,
+        &topics,
+        last_will,
+        claim_topics.as_ref(),
+        config.clone(),
+        notification_tx.clone(),
+    )?;
+    // Back from a restart by the supervisor
+    let _ = status.compare_exchange(
+        DeviceStatus::WorkerError as u8,
+        DeviceStatus::Ok as u8,
+        Relaxed,
+        Relaxed,
+    );
+    // Settings retained on the config topic apply from the first reading on
+    let mut config_subscribed = apply_retained_config(
+        &mut mqtt_client,
+        &topics.config,
+        &notification_tx,
+        &notification_rx,
+    );
+    app_config = config.get();
+    // The retained settings may ask for another window
+    frame = sampler.frame(sample_window(&app_config));
+    let mut config_watch = config.subscribe();
+    let mut threshold_watch = ThresholdWatch::new(&config);
+    // Reboot and the diagnostic bundle are a POST and a GET away, never without a token
+    let _dashboard = if app_config.web_dashboard && app_config.web_token.is_empty() {
+        log::warn!("Not starting the dashboard, web_token is empty");
+        None
+    } else if app_config.web_dashboard {
+        let display = DisplaySettings::new(
+            app_config.display_unit,
+            app_config.display_decimal_comma,
+            app_config.display_clock,
+            app_config.display_class_labels,
+        )
+        .unwrap_or_else(|err| {
+            log::error!("Invalid display settings: {}", err);
+            DisplaySettings::default()
+        });
+        let bundle_config = config.clone();
+        let bundle_boot_report = boot_report.clone();
+        Dashboard::start(
+            WebAuth::new(app_config.web_user, app_config.web_token),
+            display,
+            move || diagnostic_bundle(&bundle_config, &bundle_boot_report),
+        )
+        .map_err(|err| log::error!("Unable to start dashboard: {}", err))
+        .ok()
+    } else {
+        None
+    };
+    let mut simulator = if app_config.demo_mode {
+        log::info!("Demo mode: publishing simulated noise levels");
+        Some(NoiseSimulator::new(unsafe { esp_random() }))
+    } else {
+        None
+    };
+    let mut level_filter = LevelFilter::new(
+        Decibel(app_config.level_floor_db),
+        Decibel(app_config.level_ceiling_db),
+        app_config.outlier_window,
+        app_config.outlier_max_deviation_db,
+    );
+    let mut classifier = Classifier::new(
+        Decibel(app_config.normal_from_db),
+        Decibel(app_config.loud_from_db),
+        Decibel(app_config.very_loud_from_db),
+        app_config.class_hysteresis_db,
+    );
+    let mut level_alert = AlertRule::new(
+        Decibel(app_config.alert_trigger_db),
+        Decibel(app_config.alert_clear_db),
+        Duration::from_secs(app_config.alert_trigger_s.into()),
+        Duration::from_secs(app_config.alert_clear_s.into()),
+    );
+    let mut is_daytime = true;
+    let mut last_profile_check: Option<Instant> = None;
+    let mut last_maintenance_check = Instant::now();
+    let mut rejected_samples = 0u32;
+    let mut clamped_samples = 0u32;
+    let mut last_diagnostics = Instant::now();
+    let mut last_thermal_check: Option<Instant> = None;
+    let mut chip_temp: Option<f32> = None;
+    // Accepted levels since the last diagnostics report
+    let mut interval_levels: Vec<Decibel> = vec![];
+    let mut outage = OutageTracker::default();
+    let mut delivery = DeliveryTracker::new(readings_qos(app_config.mqtt_qos));
+    let mut loopback = Loopback::new(LOOPBACK_TIMEOUT);
+    let mut profiler = Profiler::default();
+    let mut spectral_stats = SpectralStats::default();
+    let mut last_burst = Instant::now();
+    let mut last_direction = Instant::now();
+    let mut vibration_meter = VibrationMeter::default();
+    let mut vibration_classifier = Classifier::new(
+        Decibel(app_config.vibration_normal_from_db),
+        Decibel(app_config.vibration_loud_from_db),
+        Decibel(app_config.vibration_very_loud_from_db),
+        app_config.class_hysteresis_db,
+    );
+    let mut vibration_alert = AlertRule::new(
+        Decibel(app_config.vibration_alert_trigger_db),
+        Decibel(app_config.vibration_alert_clear_db),
+        Duration::from_secs(app_config.vibration_alert_trigger_s.into()),
+        Duration::from_secs(app_config.vibration_alert_clear_s.into()),
+    );
+    let mut last_vibration = Instant::now();
+    let mut last_adc_stream = Instant::now();
+    let mut last_memory_report: Option<Instant> = None;
+    let mut fused_interval = FusedInterval::default();
+    let mut rule_engine = RuleEngine::parse(app_config.rules).unwrap_or_else(|err| {
+        log::error!("{:#}", err);
+        RuleEngine::default()
+    });
+    let mut actuators = Actuators::default();
+    let mut reporter = Reporter::new(
+        app_config.report_delta_db,
+        Duration::from_secs(app_config.report_max_silence_s.into()),
+    );
+    reporter.resume_heartbeats(sequence::last(Counter::Heartbeat));
+    if OperatingMode::parse(app_config.operating_mode).is_none() {
+        log::error!(
+            "Unknown operating mode {:?}, running as a meter",
+            app_config.operating_mode
+        );
+    }
+    let mut operating_mode = OperatingMode::of(&app_config);
+    apply_operating_mode(operating_mode, None);
+    let logger_schedule = (!app_config.logger_schedule.is_empty())
+        .then(|| Schedule::parse(app_config.logger_schedule))
+        .transpose()
+        .unwrap_or_else(|err| {
+            log::error!("Invalid logger schedule, running free: {}", err);
+            None
+        });
+    let mut logger_interval = IntervalLeq::new(
+        Duration::from_secs(app_config.logger_interval_s.into()),
+        logger_schedule,
+    );
+    let mut level_batch = LevelBatch::new(
+        app_config.batch_size as usize,
+        Duration::from_secs(app_config.batch_interval_s.into()),
+    );
+    let mut alert_burst = AlertBurst::new(
+        Duration::from_secs(app_config.alert_burst_s.into()),
+        app_config.alert_burst_hz,
+    );
+    alert_burst.configure_context(Duration::from_secs(app_config.alert_context_s.into()));
+    let mut offline = OfflineQueue::new(app_config.offline_queue_len as usize);
+    if app_config.offline_queue_flash {
+        restore_offline(&nvs, &mut offline);
+    }
+    // Something to write to NVS, restored readings included so the blob goes once they are out
+    let mut offline_dirty = !offline.is_empty();
+    let mut offline_persisted = Instant::now();
+    let mut mqtt_connected = false;
+    let mut tone_detectors =
+        tone::parse_detectors(app_config.tone_detectors).unwrap_or_else(|err| {
+            log::error!("Invalid tone detectors: {}", err);
+            vec![]
+        });
+    let mut inventory_reported = false;
+    let mut led_codes_reported = false;
+    let mut firmware_confirmed = false;
+    let mut mqtt_backoff = MqttBackoff::default();
+    let mut token_backoff = Backoff::new(TOKEN_RETRY_BACKOFF, unsafe { esp_random() });
+    let watchdog = watchdog::register("sensor", SENSOR_WATCHDOG_TIMEOUT);
 
     loop {
-        let mut sum = 0.0f32;
-        for i in 0..LEN {
-            thread::sleep(Duration::from_millis(10));
-            if let Ok(sample) = adc.read(&mut adc_channel) {
-                sample_buffer[i] = sample;
-                sum += (sample as f32) * (sample as f32);
-            } else {
-                sample_buffer[i] = 0u16;
+        watchdog.feed();
+        let mut reconnect = mqtt_backoff.reconnect_due();
+        if token_refresh.is_some_and(|refresh_at| Instant::now() >= refresh_at) {
+            if let Some(provider) = token_provider.as_mut() {
+                match auth::next_token(provider.as_mut()) {
+                    Ok((token, refresh_at)) => {
+                        mqtt_password = token;
+                        token_refresh = Some(refresh_at);
+                        token_backoff.reset();
+                        reconnect = true;
+                    }
+                    Err(err) => {
+                        log::error!("Unable to refresh MQTT token: {:#}", err);
+                        token_refresh = Some(Instant::now() + token_backoff.next_delay());
+                    }
+                }
+            }
+        }
+        if last_cert_check.elapsed() >= CERT_CHECK_INTERVAL {
+            last_cert_check = Instant::now();
+            if let Some(enrollment) = enrollment.as_mut().filter(|e| e.renewal_due()) {
+                match enrollment.enroll() {
+                    Ok(renewed) => {
+                        identity = Some(renewed);
+                        reconnect = true;
+                    }
+                    Err(err) => log::error!("Unable to renew certificate: {:#}", err),
+                }
+            }
+        }
+        if reconnect {
+            log::info!("Reconnecting to MQTT");
+            connection::retire(mqtt_client);
+            // Anything still queued refers to the old session. The new one replays the alert
+            // journal on connection anyway.
+            while notification_rx.try_recv().is_ok() {}
+            mqtt_client = connect_mqtt(
+                &mqtt_url,
+                app_config.mqtt_user,
+                &mqtt_password,
+                &mqtt_client_id,
+                use_tls,
+                ca_certificate,
+                identity,
+                &topics,
+                last_will,
+                claim_topics.as_ref(),
+                config.clone(),
+                notification_tx.clone(),
+            )?;
+        }
+        if app_config.outdoor_profile
+            && features.is_enabled(Feature::OutdoorProfile)
+            && last_profile_check.map_or(true, |check| check.elapsed() >= PROFILE_CHECK_INTERVAL)
+        {
+            last_profile_check = Some(Instant::now());
+            if let Some(now) = clock::unix_time() {
+                let daytime = solar::is_daytime(now, app_config.latitude, app_config.longitude);
+                if daytime != is_daytime {
+                    is_daytime = daytime;
+                    log::info!(
+                        "Switching to {} profile",
+                        if daytime { "day" } else { "night" }
+                    );
+                    apply_profile(
+                        &mut classifier,
+                        &mut level_alert,
+                        led_brightness,
+                        &app_config,
+                        &threshold_watch.current(),
+                        daytime,
+                    );
+                }
+            }
+        }
+        if let Some(sensor) = chip_temperature.as_ref().filter(|_| {
+            last_thermal_check.map_or(true, |check| check.elapsed() >= THERMAL_CHECK_INTERVAL)
+        }) {
+            last_thermal_check = Some(Instant::now());
+            chip_temp = sensor.celsius().ok();
+            if let Some(celsius) = chip_temp {
+                if thermal::update_throttle(celsius, app_config.thermal_limit_c) {
+                    log::warn!(
+                        "Chip at {} °C, throttling {}",
+                        celsius,
+                        if thermal::is_throttled() { "on" } else { "off" }
+                    );
+                }
+            }
+        }
+        if claimed
+            && features.is_enabled(Feature::Diagnostics)
+            && last_diagnostics.elapsed() >= DIAGNOSTICS_INTERVAL
+        {
+            last_diagnostics = Instant::now();
+            let chip_temp = chip_temp.map_or_else(|| String::from("null"), |c| c.to_string());
+            let cert_not_after = identity.map_or_else(
+                || String::from("null"),
+                |identity| identity.not_after.to_string(),
+            );
+            let level_stat = |level: Option<Decibel>| {
+                level.map_or_else(|| String::from("null"), |level| format!("{:.1}", level.0))
+            };
+            let leq = level_stat(dsp::leq(&interval_levels));
+            let l10 = level_stat(dsp::percentile(&interval_levels, 90.0));
+            let l90 = level_stat(dsp::percentile(&interval_levels, 10.0));
+            interval_levels.clear();
+            let spectral = spectral_stats.take_json_fields();
+            let metrics = metrics::json_fields();
+            let flash_wear = wear::json_fields();
+            let diagnostics_msg = maintenance::mark(format!(
+                "{{\"rejected_samples\":{rejected_samples},\"clamped_samples\":{clamped_samples},\"chip_temp_c\":{chip_temp},\"throttled\":{},\"cert_not_after\":{cert_not_after},\"leq_db\":{leq},\"l10_db\":{l10},\"l90_db\":{l90},{spectral},{metrics},{flash_wear}}}",
+                thermal::is_throttled()
+            ));
+            payload_log::dump(
+                Module::Diagnostics,
+                &topics.diagnostics,
+                diagnostics_msg.as_bytes(),
+            );
+            if mqtt_client
+                .publish_tagged(
+                    &topics.diagnostics,
+                    QoS::AtMostOnce,
+                    false,
+                    &sealing::seal(&topics.diagnostics, diagnostics_msg.as_bytes()),
+                )
+                .is_err()
+            {
+                log::error!("Unable to publish diagnostics");
+            }
+        }
+        for command in dashboard::take_commands() {
+            let _ = notification_tx.send(MqttNotification::Command(command));
+        }
+        while let Ok(notification) = notification_rx.try_recv() {
+            match notification {
+                MqttNotification::BeforeConnect => outage.attempt(),
+                MqttNotification::Disconnected => {
+                    bus::post(BusEvent::ConnectivityChanged {
+                        link: Link::Mqtt,
+                        up: false,
+                    });
+                    mqtt_connected = false;
+                    outage.disconnected();
+                    mqtt_backoff.disconnected(status, mqtt_retry);
+                }
+                MqttNotification::Connected => {
+                    bus::post(BusEvent::ConnectivityChanged {
+                        link: Link::Mqtt,
+                        up: true,
+                    });
+                    mqtt_connected = true;
+                    mqtt_backoff.connected(status, mqtt_retry);
+                    firmware_metrics.connected();
+                    if !firmware_confirmed {
+                        ota::confirm_running();
+                        firmware_confirmed = true;
+                    }
+                    if mqtt_client
+                        .subscribe(&topics.cmd, QoS::AtLeastOnce)
+                        .is_err()
+                    {
+                        log::error!("Unable to subscribe to {}", topics.cmd);
+                    }
+                    // Unless it happened before the loop, which would get the retained settings
+                    // stored again
+                    if !std::mem::take(&mut config_subscribed)
+                        && mqtt_client
+                            .subscribe(&topics.config, QoS::AtLeastOnce)
+                            .is_err()
+                    {
+                        log::error!("Unable to subscribe to {}", topics.config);
+                    }
+                    if let Some(legacy) = topics.legacy.as_ref() {
+                        subscribe_legacy(&mut mqtt_client, legacy, &topics.level);
+                    }
+                    if !app_config.time_topic.is_empty() {
+                        clock::broker_subscribed(Duration::from_secs(
+                            app_config.time_topic_interval_s.into(),
+                        ));
+                        if mqtt_client
+                            .subscribe(app_config.time_topic, QoS::AtMostOnce)
+                            .is_err()
+                        {
+                            log::error!("Unable to subscribe to {}", app_config.time_topic);
+                        }
+                    }
+                    if let Some(claim_topics) = claim_topics.as_ref().filter(|_| !claimed) {
+                        if mqtt_client
+                            .subscribe(&claim_topics.response, QoS::AtLeastOnce)
+                            .is_err()
+                        {
+                            log::error!("Unable to subscribe to {}", claim_topics.response);
+                        }
+                        // Asks again right away on the new session
+                        next_claim_request = None;
+                    }
+                    if let Some(class) = classifier.current() {
+                        publish_class(&mut mqtt_client, &topics.classification, class);
+                    }
+                    alert_journal.replay(&mut mqtt_client, &topics.alerts);
+                    publish_availability(&mut mqtt_client, &topics.availability, true);
+                    publish_info(&mut mqtt_client, &topics, security_state);
+                    publish_birth(&mut mqtt_client, &topics, &mac, boot_report.reason());
+                    if app_config.ha_discovery {
+                        publish_ha_discovery(
+                            &mut mqtt_client,
+                            &app_config,
+                            &sensor_id,
+                            &mac,
+                            &topics,
+                        );
+                    }
+                    #[cfg(feature = "homie")]
+                    {
+                        for attribute in homie.description(&format!("Bzzz {}", sensor_id)) {
+                            publish_homie(&mut mqtt_client, attribute);
+                        }
+                        if let Some(class) = classifier.current() {
+                            publish_homie(&mut mqtt_client, homie.class(class));
+                        }
+                    }
+                    if !BOOT_REPORTED.load(Relaxed) {
+                        let report = boot_report.to_json();
+                        payload_log::dump(Module::Boot, &topics.boot, report.as_bytes());
+                        let published = mqtt_client
+                            .publish_tagged(
+                                &topics.boot,
+                                QoS::AtLeastOnce,
+                                true,
+                                &sealing::seal(&topics.boot, report.as_bytes()),
+                            )
+                            .is_ok();
+                        BOOT_REPORTED.store(published, Relaxed);
+                    }
+                    // After confirming the firmware above, so the state says valid
+                    if !inventory_reported {
+                        inventory_reported = publish_inventory(&mut mqtt_client, &topics.fw);
+                    }
+                    if !led_codes_reported {
+                        led_codes_reported = publish_led_codes(&mut mqtt_client, &topics.led_codes);
+                    }
+                    publish_state(&mut mqtt_client, &topics.state, paused);
+                    publish_calibration(&mut mqtt_client, &topics.calibration, &calibration);
+                    if let Some(observation) = autotune.as_ref() {
+                        publish_autotune(
+                            &mut mqtt_client,
+                            &topics.autotune,
+                            observation.to_json(Instant::now()),
+                        );
+                    }
+                    if let Some(summary) = outage.recovered(alert_journal.len() + offline.len()) {
+                        log::info!("Recovered from {:?} outage", summary.duration);
+                        let summary = summary.to_json();
+                        payload_log::dump(Module::Diagnostics, &topics.outage, summary.as_bytes());
+                        if mqtt_client
+                            .publish_tagged(
+                                &topics.outage,
+                                QoS::AtLeastOnce,
+                                false,
+                                &sealing::seal(&topics.outage, summary.as_bytes()),
+                            )
+                            .is_err()
+                        {
+                            log::error!("Unable to publish outage summary");
+                        }
+                    }
+                }
+                MqttNotification::Published(msg_id) => {
+                    alert_journal.acknowledge(msg_id);
+                    delivery.acknowledge(msg_id);
+                    loopback.acknowledge(msg_id, Instant::now());
+                }
+                MqttNotification::Telemetry(payload) => {
+                    if let Some(result) = loopback.received(&payload, Instant::now()) {
+                        finish_loopback(&mut mqtt_client, &topics, &result);
+                    }
+                }
+                MqttNotification::Command(Command::Verify) if loopback.is_running() => {
+                    log::warn!("Loopback check already running");
+                }
+                MqttNotification::Command(Command::Verify) => {
+                    start_loopback(&mut mqtt_client, &topics, &mut loopback, delivery.qos());
+                }
+                MqttNotification::Command(command @ (Command::Pause | Command::Resume)) => {
+                    paused = command == Command::Pause;
+                    store_paused(&nvs, paused);
+                    publish_state(&mut mqtt_client, &topics.state, paused);
+                }
+                MqttNotification::Command(Command::Profile) => profiler.start(),
+                MqttNotification::Command(Command::Benchmark) => {
+                    let report =
+                        benchmark::run(sampler::window_len(sample_window(&app_config)), unsafe {
+                            esp_random()
+                        });
+                    payload_log::dump(Module::Diagnostics, &topics.benchmark, report.as_bytes());
+                    if mqtt_client
+                        .publish_tagged(
+                            &topics.benchmark,
+                            QoS::AtMostOnce,
+                            false,
+                            &sealing::seal(&topics.benchmark, report.as_bytes()),
+                        )
+                        .is_err()
+                    {
+                        log::error!("Unable to publish benchmark");
+                    }
+                }
+                MqttNotification::Command(Command::DiagBundle) => {
+                    publish_diag_bundle(
+                        &mut mqtt_client,
+                        &topics.diag_bundle,
+                        &diagnostic_bundle(&config, &boot_report),
+                    );
+                }
+                // Unsigned, the broker's ACL keeps others off the response topic, see the README
+                MqttNotification::Activation(Activation::Activated(settings)) if !claimed => {
+                    match config.update(settings.as_bytes()) {
+                        Ok(_) => {
+                            log::info!("Activated by the backend");
+                            claimed = true;
+                            match nvs.set_u8(NVS_CLAIMED_KEY, 1) {
+                                Ok(_) => wear::nvs_written(0),
+                                Err(err) => log::error!("Unable to persist claim: {}", err),
+                            }
+                            if let Some(claim_topics) = claim_topics.as_ref() {
+                                let _ = mqtt_client.unsubscribe(&claim_topics.response);
+                            }
+                        }
+                        Err(err) => log::error!("Ignoring activation: {}", err),
+                    }
+                }
+                MqttNotification::Activation(Activation::Activated(_)) => {}
+                MqttNotification::Activation(Activation::Rejected(reason)) => {
+                    log::warn!("Claim rejected by the backend: {}", reason);
+                }
+                MqttNotification::ConfigApplied => {}
+                MqttNotification::Command(Command::Identify) => identify::start(IDENTIFY_DURATION),
+                MqttNotification::Command(Command::Read) => reporter.publish_next(),
+                MqttNotification::Command(Command::Led(enabled)) => {
+                    log::info!("Status LED {}", if enabled { "on" } else { "off" });
+                    LED_ENABLED.store(enabled, Relaxed);
+                }
+                MqttNotification::Command(Command::Calibrate(_)) if simulator.is_some() => {
+                    log::warn!("Not calibrating simulated levels");
+                }
+                MqttNotification::Command(Command::Calibrate(reference_db)) => {
+                    log::info!(
+                        "Calibrating against {} dB for {:?}",
+                        reference_db,
+                        calibration::SESSION
+                    );
+                    calibration_session = Some(calibration::Session::new(
+                        Decibel(reference_db),
+                        Instant::now(),
+                    ));
+                }
+                MqttNotification::Command(Command::SetCalibrationOffset(offset_db)) => {
+                    match Calibration::with_offset(offset_db) {
+                        Some(updated) => {
+                            calibration = updated;
+                            store_calibration(&mut nvs, &calibration);
+                            publish_calibration(
+                                &mut mqtt_client,
+                                &topics.calibration,
+                                &calibration,
+                            );
+                        }
+                        None => log::warn!("Ignoring calibration offset {} dB", offset_db),
+                    }
+                }
+                MqttNotification::Command(Command::AutoTune(hours)) => {
+                    log::info!("Learning thresholds over {} h", hours);
+                    let observation = AutoTune::new(hours, Instant::now());
+                    persist_autotune(&mut nvs, Some(&observation));
+                    publish_autotune(
+                        &mut mqtt_client,
+                        &topics.autotune,
+                        observation.to_json(Instant::now()),
+                    );
+                    autotune = Some(observation);
+                    autotune_saved = Instant::now();
+                    autotune_proposed = false;
+                }
+                // Through the configuration, so it is validated and persisted like any update
+                MqttNotification::Command(Command::ApplyAutoTune) => {
+                    match autotune
+                        .as_ref()
+                        .and_then(|observation| observation.proposal(Instant::now()))
+                    {
+                        Some(proposal) => match config.update(proposal.to_settings().as_bytes()) {
+                            Ok(_) => {
+                                log::info!("Applied learned thresholds");
+                                autotune = None;
+                                persist_autotune(&mut nvs, None);
+                                publish_autotune(
+                                    &mut mqtt_client,
+                                    &topics.autotune,
+                                    String::from("{\"state\":\"applied\"}"),
+                                );
+                            }
+                            Err(err) => log::warn!("Unable to apply learned thresholds: {}", err),
+                        },
+                        None => log::warn!("No learned thresholds to apply"),
+                    }
+                }
+                MqttNotification::Command(Command::CancelAutoTune) => {
+                    if autotune.take().is_some() {
+                        log::info!("Threshold learning cancelled");
+                        persist_autotune(&mut nvs, None);
+                        publish_autotune(
+                            &mut mqtt_client,
+                            &topics.autotune,
+                            String::from("{\"state\":\"cancelled\"}"),
+                        );
+                    }
+                }
+                // Through the configuration, so it is validated, persisted and seen everywhere
+                MqttNotification::Command(Command::SetSampleInterval(ms)) => {
+                    if let Err(err) = config.update(format!("sample_interval_ms={}", ms).as_bytes())
+                    {
+                        log::warn!("Ignoring sample interval: {}", err);
+                    }
+                }
+                MqttNotification::Command(Command::Maintenance(active)) => {
+                    maintenance::set_mode(active);
+                    publish_state(&mut mqtt_client, &topics.state, paused);
+                }
+                MqttNotification::Command(Command::Support(signature)) => {
+                    if signing::verify_command("support", signature.counter, &signature.hmac) {
+                        support::start(Duration::from_secs(app_config.support_session_s.into()));
+                        last_memory_report = None;
+                        publish_state(&mut mqtt_client, &topics.state, paused);
+                    } else {
+                        log::warn!("Ignoring support command");
+                    }
+                }
+                MqttNotification::Command(Command::EndSupport) => {
+                    support::end();
+                    publish_state(&mut mqtt_client, &topics.state, paused);
+                }
+                MqttNotification::Command(Command::SetFeatures(mask)) => {
+                    Features::store(&nvs, mask);
+                }
+                MqttNotification::Command(Command::ShowConfig) => {
+                    log::info!("Configuration:\n{}", config.dump());
+                }
+                MqttNotification::Command(Command::Dump(module, enabled)) => {
+                    payload_log::set_enabled(module, enabled);
+                }
+                MqttNotification::Command(command @ (Command::Restart | Command::Shutdown)) => {
+                    if offline_dirty && app_config.offline_queue_flash {
+                        persist_offline(&mut nvs, &offline);
+                    }
+                    if let Some(observation) = autotune.as_ref() {
+                        persist_autotune(&mut nvs, Some(observation));
+                    }
+                    #[cfg(feature = "homie")]
+                    publish_homie(
+                        &mut mqtt_client,
+                        (homie.state_topic(), "disconnected".into()),
+                    );
+                    shut_down(
+                        &mut mqtt_client,
+                        &notification_rx,
+                        &mut alert_journal,
+                        &topics.availability,
+                        command == Command::Restart,
+                    );
+                }
+            }
+        }
+        delivery.retry_overdue(&mut mqtt_client);
+        if mqtt_connected && !offline.is_empty() {
+            flush_offline(
+                &mut mqtt_client,
+                &mut delivery,
+                &topics.replay,
+                &mut offline,
+            );
+            offline_dirty = true;
+        }
+        if offline_dirty
+            && app_config.offline_queue_flash
+            && (offline.is_empty() || offline_persisted.elapsed() >= offline_persist_interval())
+        {
+            persist_offline(&mut nvs, &offline);
+            offline_dirty = false;
+            offline_persisted = Instant::now();
+        }
+        if let Some(result) = loopback.take_timed_out(Instant::now()) {
+            finish_loopback(&mut mqtt_client, &topics, &result);
+        }
+        if let Some(claim_topics) = claim_topics.as_ref().filter(|_| !claimed) {
+            if next_claim_request.map_or(true, |due| Instant::now() >= due) {
+                next_claim_request = Some(Instant::now() + claim_backoff.next_delay());
+                request_claim(&mut mqtt_client, &mut nvs, claim_topics, &sensor_id, &mac);
+            }
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+        if last_maintenance_check.elapsed() >= MAINTENANCE_CHECK_INTERVAL {
+            last_maintenance_check = Instant::now();
+            let uptime = Duration::from_micros(unsafe { esp_timer_get_time() } as u64);
+            if !ota::in_progress()
+                && clock::unix_time()
+                    .is_some_and(|now| maintenance::reboot_due(&app_config, now, uptime))
+            {
+                log::info!("Scheduled maintenance reboot after {:?} up", uptime);
+                if offline_dirty && app_config.offline_queue_flash {
+                    persist_offline(&mut nvs, &offline);
+                }
+                if let Some(observation) = autotune.as_ref() {
+                    persist_autotune(&mut nvs, Some(observation));
+                }
+                #[cfg(feature = "homie")]
+                publish_homie(
+                    &mut mqtt_client,
+                    (homie.state_topic(), "disconnected".into()),
+                );
+                shut_down(
+                    &mut mqtt_client,
+                    &notification_rx,
+                    &mut alert_journal,
+                    &topics.availability,
+                    true,
+                );
             }
         }
-        let d_b = 20.0f32 * (sum / LEN as f32).sqrt().log10();
-        mqtt_msg = format!("{}", d_b);
-        if let Ok(msg_id) = mqtt_client.publish(&topic, QoS::AtMostOnce, false, mqtt_msg.as_bytes())
+        if support::expire() {
+            publish_state(&mut mqtt_client, &topics.state, paused);
+        }
+        if support::active()
+            && last_memory_report.map_or(true, |last| last.elapsed() >= support::MEMORY_INTERVAL)
         {
-            println!(
-                "MSG ID: {}, ADC values: {:?}, sum: {}, and dB: {} ",
-                msg_id, sample_buffer, sum, d_b
+            last_memory_report = Some(Instant::now());
+            profiler.start();
+        }
+        if let Some(report) = profiler.poll() {
+            payload_log::dump(Module::Diagnostics, &topics.profile, report.as_bytes());
+            if mqtt_client
+                .publish_tagged(
+                    &topics.profile,
+                    QoS::AtMostOnce,
+                    false,
+                    &sealing::seal(&topics.profile, report.as_bytes()),
+                )
+                .is_err()
+            {
+                log::error!("Unable to publish profile");
+            }
+        }
+        if let Some(report) = firmware_metrics.poll() {
+            payload_log::dump(Module::Diagnostics, &topics.firmware, report.as_bytes());
+            if mqtt_client
+                .publish_tagged(
+                    &topics.firmware,
+                    QoS::AtLeastOnce,
+                    true,
+                    &sealing::seal(&topics.firmware, report.as_bytes()),
+                )
+                .is_err()
+            {
+                log::error!("Unable to publish firmware comparison");
+            }
+        }
+        let heartbeat_interval = Duration::from_secs(app_config.heartbeat_interval_s.into());
+        if let Some(heartbeat_msg) = reporter
+            .heartbeat(heartbeat_interval, Instant::now(), paused)
+            .map(maintenance::mark)
+        {
+            sequence::record(Counter::Heartbeat, reporter.heartbeat_seq());
+            payload_log::dump(
+                Module::Diagnostics,
+                &topics.heartbeat,
+                heartbeat_msg.as_bytes(),
             );
+            if mqtt_client
+                .publish_tagged(
+                    &topics.heartbeat,
+                    QoS::AtMostOnce,
+                    false,
+                    &sealing::seal(&topics.heartbeat, heartbeat_msg.as_bytes()),
+                )
+                .is_err()
+            {
+                log::error!("Unable to publish heartbeat");
+            }
+        }
+        if paused {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+        let tone_checks = simulator.is_none()
+            && !tone_detectors.is_empty()
+            && features.is_enabled(Feature::Alerts);
+        let mut tone_events = vec![];
+        thread::sleep(Duration::from_millis(app_config.sample_interval_ms.into()));
+        let window_start = Instant::now();
+        if simulator.is_some() {
+            thread::sleep(sample_window(&app_config));
+        } else {
+            sampler.read(&mut frame)?;
+            for sample in frame.vibration_decimated(VIBRATION_DECIMATION) {
+                vibration_meter.add(sample);
+            }
+            if support::active() && last_adc_stream.elapsed() >= support::ADC_STREAM_INTERVAL {
+                last_adc_stream = Instant::now();
+                let len = support::ADC_STREAM_LEN.min(frame.mic.len());
+                let samples = support::adc_json(&frame.mic[..len], frame.sample_rate_hz);
+                if mqtt_client
+                    .publish_tagged(
+                        &topics.adc_stream,
+                        QoS::AtMostOnce,
+                        false,
+                        &sealing::seal(&topics.adc_stream, samples.as_bytes()),
+                    )
+                    .is_err()
+                {
+                    log::error!("Unable to publish ADC samples");
+                }
+            }
+        }
+        // Every 10 ms of the window, often enough to time the 0.1 s pulses of alarm cadences
+        if tone_checks {
+            for (offset, samples) in frame.mic_bursts(tone::BURST_LEN, TONE_BURST_STEP) {
+                let burst = Burst {
+                    samples: samples.to_vec(),
+                    sample_rate_hz: frame.sample_rate_hz,
+                };
+                let at =
+                    window_start + Duration::from_secs_f32(offset as f32 / frame.sample_rate_hz);
+                for (index, detector) in tone_detectors.iter_mut().enumerate() {
+                    if let Some(event) = detector.update(&burst, at) {
+                        tone_events.push((index, event));
+                    }
+                }
+            }
+        }
+        for (index, event) in tone_events {
+            let detector = &tone_detectors[index];
+            match event {
+                ToneEvent::Tone(level) => {
+                    alert_journal.raise(
+                        &mut mqtt_client,
+                        &topics.alerts,
+                        &alert_burst,
+                        detector.event(),
+                        level,
+                        clock::unix_time(),
+                    );
+                }
+                ToneEvent::Cadence(found) => {
+                    log::warn!(
+                        "{} cadence of {} for {:?}",
+                        found.cadence.as_str(),
+                        detector.event(),
+                        found.duration
+                    );
+                    let raised = alert_journal.raise_with_details(
+                        &mut mqtt_client,
+                        &topics.alerts,
+                        &alert_burst,
+                        found.cadence.alert(),
+                        found.level,
+                        clock::unix_time(),
+                        format!(
+                            "\"priority\":\"high\",\"pattern\":\"{}\",\"tone\":\"{}\",\"confidence\":{:.2},\"duration_s\":{:.1}",
+                            found.cadence.as_str(),
+                            detector.event(),
+                            found.confidence,
+                            found.duration.as_secs_f32()
+                        ),
+                    );
+                    if let Some(seq) = raised {
+                        alert_burst.trigger(
+                            seq,
+                            found.cadence.alert(),
+                            clock::unix_time(),
+                            Instant::now(),
+                        );
+                    }
+                }
+            }
+        }
+        if simulator.is_none()
+            && features.is_enabled(Feature::SpectralFeatures)
+            && features.is_enabled(Feature::Diagnostics)
+            && !thermal::is_throttled()
+            && last_burst.elapsed() >= BURST_INTERVAL
+        {
+            last_burst = Instant::now();
+            let burst = Burst {
+                samples: frame
+                    .mic
+                    .iter()
+                    .copied()
+                    .take(spectrum::BURST_LEN)
+                    .collect(),
+                sample_rate_hz: frame.sample_rate_hz,
+            };
+            spectral_stats.add(&burst);
+        }
+        // Only sampled along with the mic if enabled at start
+        if !frame.second_mic.is_empty()
+            && simulator.is_none()
+            && last_direction.elapsed() >= DIRECTION_INTERVAL
+        {
+            last_direction = Instant::now();
+            let len = direction::BURST_LEN.min(frame.second_mic.len());
+            let burst = StereoBurst {
+                left: frame.mic[..len].to_vec(),
+                right: frame.second_mic[..len].to_vec(),
+                sample_rate_hz: frame.sample_rate_hz,
+            };
+            let hint = signing::sign(
+                &topics.direction,
+                maintenance::mark(DirectionHint::estimate(&burst).to_json()),
+            );
+            payload_log::dump(Module::Diagnostics, &topics.direction, hint.as_bytes());
+            if publish_reading(
+                &mut mqtt_client,
+                &mut delivery,
+                &topics.direction,
+                hint.as_bytes(),
+            )
+            .is_err()
+            {
+                log::error!("Unable to publish direction hint");
+            }
+        }
+        if last_vibration.elapsed() >= VIBRATION_INTERVAL {
+            last_vibration = Instant::now();
+            if let Some((level, peak)) = vibration_meter.take() {
+                vibration_classifier.update(level);
+                fused_interval.add_vibration(level, peak, vibration_classifier.current());
+                // Always set once the classifier has seen a level
+                let class = vibration_classifier
+                    .current()
+                    .unwrap_or(NoiseClass::Quiet)
+                    .as_str();
+                let vibration_msg = signing::sign(
+                    &topics.vibration,
+                    maintenance::mark(format!(
+                        "{{\"level_db\":{:.1},\"peak_db\":{:.1},\"class\":\"{}\"}}",
+                        level.0, peak.0, class
+                    )),
+                );
+                payload_log::dump(
+                    Module::Diagnostics,
+                    &topics.vibration,
+                    vibration_msg.as_bytes(),
+                );
+                if publish_reading(
+                    &mut mqtt_client,
+                    &mut delivery,
+                    &topics.vibration,
+                    vibration_msg.as_bytes(),
+                )
+                .is_err()
+                {
+                    log::error!("Unable to publish vibration level");
+                }
+                if vibration_alert.update(level, Instant::now())
+                    && features.is_enabled(Feature::Alerts)
+                {
+                    alert_journal.raise(
+                        &mut mqtt_client,
+                        &topics.alerts,
+                        &alert_burst,
+                        "vibration_very_loud",
+                        level,
+                        clock::unix_time(),
+                    );
+                }
+            }
+        }
+        let raw_d_b = match simulator.as_mut() {
+            Some(simulator) => simulator.next_level(),
+            None => {
+                let uncalibrated = weighting.level(&frame.mic);
+                if let Some(session) = calibration_session.as_mut() {
+                    session.add(uncalibrated);
+                }
+                calibration.apply(uncalibrated)
+            }
+        };
+        if calibration_session
+            .as_ref()
+            .is_some_and(|session| session.is_over(Instant::now()))
+        {
+            match calibration_session
+                .take()
+                .and_then(calibration::Session::finish)
+            {
+                Some(updated) => {
+                    log::info!("Calibrated with {:+.2} dB", updated.offset_db);
+                    calibration = updated;
+                    store_calibration(&mut nvs, &calibration);
+                    publish_calibration(&mut mqtt_client, &topics.calibration, &calibration);
+                }
+                None => log::warn!(
+                    "Calibration failed, keeping {:+.2} dB",
+                    calibration.offset_db
+                ),
+            }
+        }
+        let d_b = match level_filter.check(raw_d_b) {
+            Plausibility::Accepted(d_b) => d_b,
+            Plausibility::Clamped(d_b) => {
+                clamped_samples = clamped_samples.wrapping_add(1);
+                d_b
+            }
+            Plausibility::Rejected => {
+                rejected_samples = rejected_samples.wrapping_add(1);
+                log::warn!("Rejected implausible level: {} dB", raw_d_b);
+                continue;
+            }
+        };
+        if features.is_enabled(Feature::Diagnostics) {
+            interval_levels.push(d_b);
+        }
+        if let Some(observation) = autotune.as_mut() {
+            let now = Instant::now();
+            if !observation.is_complete(now) {
+                // Vacuuming next to the sensor says nothing about the room
+                if !maintenance::in_mode() {
+                    observation.add(d_b);
+                }
+                let save_interval = if wear::throttled() {
+                    AUTOTUNE_SAVE_INTERVAL_THROTTLED
+                } else {
+                    AUTOTUNE_SAVE_INTERVAL
+                };
+                if autotune_saved.elapsed() >= save_interval {
+                    autotune_saved = now;
+                    persist_autotune(&mut nvs, Some(observation));
+                    publish_autotune(&mut mqtt_client, &topics.autotune, observation.to_json(now));
+                }
+            } else if !autotune_proposed {
+                autotune_proposed = true;
+                match observation.proposal(now) {
+                    Some(proposal) => log::info!(
+                        "Learned thresholds: {}, waiting for autotune apply",
+                        proposal.to_settings()
+                    ),
+                    None => log::warn!("Too few levels to learn thresholds from"),
+                }
+                persist_autotune(&mut nvs, Some(observation));
+                publish_autotune(&mut mqtt_client, &topics.autotune, observation.to_json(now));
+            }
+        }
+        if let Some(updated) = config_watch.changed() {
+            log::info!("Configuration updated");
+            let filter_settings = |config: &Config| {
+                (
+                    config.level_floor_db,
+                    config.level_ceiling_db,
+                    config.outlier_window,
+                    config.outlier_max_deviation_db,
+                )
+            };
+            if filter_settings(&updated) != filter_settings(&app_config) {
+                level_filter = LevelFilter::new(
+                    Decibel(updated.level_floor_db),
+                    Decibel(updated.level_ceiling_db),
+                    updated.outlier_window,
+                    updated.outlier_max_deviation_db,
+                );
+            }
+            if updated.weighting != weighting.weighting() {
+                weighting = WeightingFilter::new(updated.weighting, frame.sample_rate_hz);
+            }
+            if updated.sample_window_ms != app_config.sample_window_ms {
+                frame = sampler.frame(sample_window(&updated));
+            }
+            if updated.band_fft_len != app_config.band_fft_len {
+                octave_analyzer =
+                    OctaveAnalyzer::new(updated.band_fft_len as usize, frame.sample_rate_hz);
+            }
+            let format_changed = level_format(&updated) != level_format(&app_config);
+            app_config = updated;
+            // Home Assistant needs another template for the new readings
+            if format_changed && app_config.ha_discovery {
+                publish_ha_discovery(&mut mqtt_client, &app_config, &sensor_id, &mac, &topics);
+            }
+            wear::configure(
+                app_config.flash_endurance_cycles,
+                app_config.flash_wear_throttle_pct,
+            );
+            reporter.configure(
+                app_config.report_delta_db,
+                Duration::from_secs(app_config.report_max_silence_s.into()),
+            );
+            level_batch.configure(
+                app_config.batch_size as usize,
+                Duration::from_secs(app_config.batch_interval_s.into()),
+            );
+            offline.configure(app_config.offline_queue_len as usize);
+            logger_interval.configure(Duration::from_secs(app_config.logger_interval_s.into()));
+            if OperatingMode::of(&app_config) != operating_mode {
+                operating_mode = OperatingMode::of(&app_config);
+                log::info!("Running as a {}", app_config.operating_mode);
+                apply_operating_mode(operating_mode, classifier.current());
+            }
+            alert_burst.configure(
+                Duration::from_secs(app_config.alert_burst_s.into()),
+                app_config.alert_burst_hz,
+            );
+            alert_burst.configure_context(Duration::from_secs(app_config.alert_context_s.into()));
+            if !app_config.outdoor_profile {
+                is_daytime = true;
+            }
+            // Coordinates may have changed as well
+            last_profile_check = None;
+            apply_profile(
+                &mut classifier,
+                &mut level_alert,
+                led_brightness,
+                &app_config,
+                &threshold_watch.current(),
+                is_daytime,
+            );
+        }
+        // Checked before every evaluation, so new thresholds judge the very next level
+        if let Some(thresholds) = threshold_watch.changed() {
+            log::info!("Thresholds updated");
+            apply_profile(
+                &mut classifier,
+                &mut level_alert,
+                led_brightness,
+                &app_config,
+                &thresholds,
+                is_daytime,
+            );
+            classifier.set_hysteresis(thresholds.hysteresis_db);
+            apply_limits(
+                &mut vibration_classifier,
+                &mut vibration_alert,
+                &thresholds.vibration,
+            );
+            vibration_classifier.set_hysteresis(thresholds.hysteresis_db);
+        }
+        let class_change = if features.is_enabled(Feature::Classification) {
+            classifier.update(d_b)
+        } else {
+            None
+        };
+        if let Some(class) = class_change {
+            publish_class(&mut mqtt_client, &topics.classification, class);
+            #[cfg(feature = "homie")]
+            publish_homie(&mut mqtt_client, homie.class(class));
+            mode::show_class(Some(class));
+        }
+        // Before the alert, which carries the latest levels
+        if let Some(series) = alert_burst.add(d_b, Instant::now()) {
+            publish_alert_burst(&mut mqtt_client, &topics.alert_burst, series);
+        }
+        if level_alert.update(d_b, Instant::now()) && features.is_enabled(Feature::Alerts) {
+            if let Some(seq) = alert_journal.raise(
+                &mut mqtt_client,
+                &topics.alerts,
+                &alert_burst,
+                NoiseClass::VeryLoud.as_str(),
+                d_b,
+                clock::unix_time(),
+            ) {
+                alert_burst.trigger(
+                    seq,
+                    NoiseClass::VeryLoud.as_str(),
+                    clock::unix_time(),
+                    Instant::now(),
+                );
+            }
+        }
+        bus::post(BusEvent::MeasurementReady {
+            level: d_b,
+            class: classifier.current(),
+        });
+        fused_interval.add_level(d_b, classifier.current());
+        if !rule_engine.is_empty() {
+            for (rule, action) in
+                rule_engine.update(d_b, clock::local_minute_of_day(), Instant::now())
+            {
+                match action {
+                    Action::Publish(payload) => {
+                        let topic = format!("{}/{}", topics.rules, rule);
+                        payload_log::dump(Module::Mqtt, &topic, payload.as_bytes());
+                        if mqtt_client
+                            .publish_tagged(
+                                &topic,
+                                QoS::AtLeastOnce,
+                                false,
+                                &sealing::seal(&topic, payload.as_bytes()),
+                            )
+                            .is_err()
+                        {
+                            log::error!("Unable to publish for rule {}", rule);
+                        }
+                    }
+                    action => actuators.run(&action),
+                }
+            }
+            actuators.poll();
+        }
+        let mut outgoing = None;
+        // A logger reports the interval, for which the samples of the last reading say nothing
+        let (level, samples) = match operating_mode {
+            OperatingMode::Meter => (Some(d_b), simulator.is_none().then_some(&frame.mic[..])),
+            OperatingMode::Logger => (
+                logger_interval.add(d_b, Instant::now(), clock::unix_time()),
+                None,
+            ),
+        };
+        if let Some(level) = level.filter(|level| reporter.should_publish(*level, Instant::now())) {
+            #[cfg(feature = "homie")]
+            publish_homie(&mut mqtt_client, homie.level(level));
+            // A scheduled logger interval is stamped with the boundary that closed it
+            let ts = match operating_mode {
+                OperatingMode::Meter => clock::unix_time(),
+                OperatingMode::Logger => logger_interval.closed_at().or_else(clock::unix_time),
+            };
+            let reading = if app_config.level_json
+                || app_config.report_raw_rms
+                || app_config.band_fft_len > 0
+            {
+                let reading = LevelReading::new(
+                    &sensor_id,
+                    ts,
+                    level,
+                    samples,
+                    network::sta_rssi(),
+                    app_config.report_raw_rms,
+                )
+                .with_seq(sequence::next(Counter::Reading))
+                .with_broker_time(clock::broker_accuracy())
+                .with_weighting(weighting.weighting())
+                // Optional work, left out while the chip is too hot
+                .with_bands(
+                    samples
+                        .filter(|_| !thermal::is_throttled())
+                        .zip(octave_analyzer.as_ref())
+                        .and_then(|(samples, analyzer)| analyzer.analyze(samples))
+                        .map(|bands| bands.with_offset(calibration.offset_db)),
+                )
+                .with_maintenance(maintenance::in_mode())
+                .to_json();
+                // Each document on its own, so a batch is an array of signed readings
+                signing::sign(&topics.level, reading)
+            } else {
+                format!("{}", level)
+            };
+            outgoing = level_batch.add(reading, Instant::now());
+        }
+        if let Some(mqtt_msg) = outgoing.or_else(|| level_batch.take_due(Instant::now())) {
+            let published = publish_reading(
+                &mut mqtt_client,
+                &mut delivery,
+                &topics.level,
+                mqtt_msg.as_bytes(),
+            );
+            if let Some(legacy) = topics.legacy.as_ref() {
+                let _ = publish_reading(
+                    &mut mqtt_client,
+                    &mut delivery,
+                    &legacy.level,
+                    mqtt_msg.as_bytes(),
+                );
+            }
+            firmware_metrics.published(published.is_ok());
+            if let Ok(msg_id) = published {
+                println!(
+                    "MSG ID: {}, ADC samples: {}, RMS: {}, and dB: {} ",
+                    msg_id,
+                    frame.mic.len(),
+                    dsp::rms_millivolts(&frame.mic),
+                    d_b
+                );
+            } else {
+                println!("Unable to send MQTT msg");
+                if offline.push(
+                    mqtt_msg,
+                    clock::unix_time(),
+                    clock::broker_accuracy().map(reporting::accuracy_s),
+                ) {
+                    offline_dirty = true;
+                } else {
+                    outage.dropped();
+                }
+            }
+        }
+        if app_config.fusion_interval_s > 0
+            && fused_interval.elapsed() >= Duration::from_secs(app_config.fusion_interval_s.into())
+        {
+            if let Some(fused_msg) = fused_interval
+                .take_json(
+                    &topics.metadata,
+                    clock::unix_time(),
+                    clock::broker_accuracy().map(reporting::accuracy_s),
+                    chip_temp,
+                )
+                .map(maintenance::mark)
+                .map(|fused_msg| signing::sign(&topics.fused, fused_msg))
+            {
+                payload_log::dump(Module::Diagnostics, &topics.fused, fused_msg.as_bytes());
+                if mqtt_client
+                    .publish_tagged(
+                        &topics.fused,
+                        QoS::AtLeastOnce,
+                        false,
+                        &sealing::seal(&topics.fused, fused_msg.as_bytes()),
+                    )
+                    .is_err()
+                {
+                    log::error!("Unable to publish fused document");
+                }
+            }
+        }
+    }
+}
+
+// The client certificate from manufacture, leaked once like the enrolled ones
+fn factory_identity() -> Option<Identity> {
+    static IDENTITY: OnceLock<Option<Identity>> = OnceLock::new();
+    *IDENTITY.get_or_init(|| {
+        let data = config::factory_data()?;
+        let identity = enrollment::identity_from_pem(
+            data.client_cert.as_deref()?,
+            data.client_key.as_deref()?,
+        );
+        if identity.is_none() {
+            log::error!("Unable to read the factory client certificate");
+        }
+        identity
+    })
+}
+
+// The MQTT client only takes 'static certificates, NUL terminated for PEM, so the configured one
+// is leaked exactly once and worker restarts reuse it
+fn mqtt_ca_certificate(pem: &'static str) -> Option<X509<'static>> {
+    static CA_CERTIFICATE: OnceLock<Option<X509<'static>>> = OnceLock::new();
+    *CA_CERTIFICATE.get_or_init(|| {
+        (!pem.is_empty()).then(|| {
+            let mut pem = pem.as_bytes().to_vec();
+            pem.push(0);
+            X509::pem_until_nul(Box::leak(pem.into_boxed_slice()))
+        })
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn connect_mqtt(
+    url: &str,
+    user: &str,
+    password: &str,
+    client_id: &str,
+    use_tls: bool,
+    ca_certificate: Option<X509<'static>>,
+    identity: Option<Identity>,
+    topics: &Topics,
+    (will_topic, will_payload): (&str, &[u8]),
+    claim_topics: Option<&ClaimTopics>,
+    config: ConfigStore,
+    notification_tx: mpsc::Sender<MqttNotification>,
+) -> Result<EspMqttClient<'static>> {
+    let callback_cmd_topic = topics.cmd.clone();
+    let callback_config_topic = topics.config.clone();
+    let callback_level_topic = topics.level.clone();
+    let claim_response_topic = claim_topics.map(|claim_topics| claim_topics.response.clone());
+    let session = config.get();
+    let time_topic = (!session.time_topic.is_empty()).then_some(session.time_topic);
+    let generation = connection::generation();
+    let (legacy_cmd_topic, legacy_config_topic) = match topics.legacy.as_ref() {
+        Some(legacy) => (Some(legacy.cmd.clone()), Some(legacy.config.clone())),
+        None => (None, None),
+    };
+    let mqtt_client = EspMqttClient::new_cb(
+        url,
+        &MqttClientConfiguration {
+            username: Some(user).filter(|user| !user.is_empty()),
+            password: Some(password).filter(|password| !password.is_empty()),
+            client_certificate: identity.map(|identity| identity.certificate),
+            private_key: identity.map(|identity| identity.private_key),
+            protocol_version: mqtt5::wanted().then_some(MqttProtocolVersion::V5),
+            client_id: Some(client_id),
+            keep_alive_interval: (session.mqtt_keep_alive_s > 0)
+                .then(|| Duration::from_secs(session.mqtt_keep_alive_s.into())),
+            disable_clean_session: !session.mqtt_clean_session,
+            reconnect_timeout: Some(network::mqtt_reconnect_timeout(Duration::from_secs(
+                session.mqtt_reconnect_timeout_s.into(),
+            ))),
+            // The broker says so on the device's behalf when it drops off without a goodbye
+            lwt: Some(LwtConfiguration {
+                topic: will_topic,
+                payload: will_payload,
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            }),
+            // Public CAs from the bundle unless the broker's own CA is configured
+            server_certificate: ca_certificate.filter(|_| use_tls),
+            crt_bundle_attach: (use_tls && ca_certificate.is_none())
+                .then_some(esp_crt_bundle_attach as _),
+            task_prio: connection::MQTT_TASK_PRIORITY,
+            ..Default::default()
+        },
+        move |event| {
+            if !connection::is_current(generation) {
+                return;
+            }
+            match event.payload() {
+                EventPayload::BeforeConnect => {
+                    connection::handshake_started();
+                    let _ = notification_tx.send(MqttNotification::BeforeConnect);
+                }
+                EventPayload::Connected(_) => {
+                    connection::handshake_finished();
+                    let _ = notification_tx.send(MqttNotification::Connected);
+                }
+                EventPayload::Disconnected => {
+                    connection::handshake_finished();
+                    let _ = notification_tx.send(MqttNotification::Disconnected);
+                }
+                EventPayload::Published(msg_id) => {
+                    let _ = notification_tx.send(MqttNotification::Published(msg_id));
+                }
+                // The MQTT task has one of the smallest stacks, which is why dumps are chunked
+                EventPayload::Received {
+                    topic: Some(topic),
+                    data,
+                    ..
+                } if topic == callback_cmd_topic || legacy_cmd_topic.as_deref() == Some(topic) => {
+                    payload_log::dump(Module::Mqtt, topic, data);
+                    match Command::try_from(data) {
+                        Ok(command) => {
+                            let _ = notification_tx.send(MqttNotification::Command(command));
+                        }
+                        Err(err) => log::warn!("Ignoring command: {}", err),
+                    }
+                }
+                // Applied right here so subscribers pick the change up on their next iteration
+                EventPayload::Received {
+                    topic: Some(topic),
+                    data,
+                    ..
+                } if topic == callback_config_topic
+                    || legacy_config_topic.as_deref() == Some(topic) =>
+                {
+                    payload_log::dump(Module::Mqtt, topic, data);
+                    match config.update(data) {
+                        Ok(_) => {
+                            log::info!("Received configuration update");
+                            let _ = notification_tx.send(MqttNotification::ConfigApplied);
+                        }
+                        Err(err) => log::warn!("Ignoring config: {}", err),
+                    }
+                }
+                EventPayload::Received {
+                    topic: Some(topic),
+                    data,
+                    ..
+                } if topic == callback_level_topic => {
+                    let _ = notification_tx.send(MqttNotification::Telemetry(data.to_vec()));
+                }
+                EventPayload::Received {
+                    topic: Some(topic),
+                    data,
+                    ..
+                } if time_topic == Some(topic) => clock::broker_time_received(data),
+                EventPayload::Received {
+                    topic: Some(topic),
+                    data,
+                    ..
+                } if claim_response_topic.as_deref() == Some(topic) => {
+                    payload_log::dump(Module::Mqtt, topic, data);
+                    match Activation::parse(data) {
+                        Ok(activation) => {
+                            let _ = notification_tx.send(MqttNotification::Activation(activation));
+                        }
+                        Err(err) => log::warn!("Ignoring activation response: {:#}", err),
+                    }
+                }
+                _ => log::info!("MQTT client callback"),
+            }
+        },
+    )
+    .context("Unable to initialize MQTT client")?;
+    network::watch_mqtt_errors(&mqtt_client);
+    Ok(mqtt_client)
+}
+
+// Waits for pending alerts to be acknowledged and says goodbye before restarting or
+// powering down. Deep sleep without wakeup sources is as close to off as the chip gets.
+fn shut_down(
+    mqtt_client: &mut EspMqttClient,
+    notification_rx: &mpsc::Receiver<MqttNotification>,
+    alert_journal: &mut AlertJournal,
+    availability_topic: &str,
+    restart: bool,
+) -> ! {
+    log::info!("{} requested", if restart { "Restart" } else { "Shutdown" });
+    alert_journal.flush();
+    wear::save();
+    let deadline = Instant::now() + SHUTDOWN_FLUSH_TIMEOUT;
+    let mut offline_msg_id = None;
+    loop {
+        if !alert_journal.has_in_flight() && offline_msg_id.is_none() {
+            offline_msg_id = publish_availability(mqtt_client, availability_topic, false);
+            if offline_msg_id.is_none() {
+                break;
+            }
+        }
+        let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+            log::warn!("Timed out flushing messages before shutdown");
+            break;
+        };
+        match notification_rx.recv_timeout(remaining) {
+            Ok(MqttNotification::Published(msg_id)) if Some(msg_id) == offline_msg_id => break,
+            Ok(MqttNotification::Published(msg_id)) => alert_journal.acknowledge(msg_id),
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    unsafe {
+        if restart {
+            esp_restart()
         } else {
-            println!("Unable to send MQTT msg");
+            esp_deep_sleep_start()
+        }
+    }
+}
+
+fn apply_profile(
+    classifier: &mut Classifier,
+    level_alert: &mut AlertRule,
+    led_brightness: &AtomicU8,
+    app_config: &Config,
+    thresholds: &Thresholds,
+    daytime: bool,
+) {
+    let (offset, brightness) = if daytime {
+        (0.0, app_config.day_led_brightness)
+    } else {
+        (
+            app_config.night_threshold_offset_db,
+            app_config.night_led_brightness,
+        )
+    };
+    apply_limits(classifier, level_alert, &thresholds.level.offset(offset));
+    led_brightness.store(brightness, Relaxed);
+}
+
+fn apply_limits(classifier: &mut Classifier, alert: &mut AlertRule, limits: &Limits) {
+    classifier.set_thresholds(limits.normal_from, limits.loud_from, limits.very_loud_from);
+    alert.configure(
+        limits.alert_trigger,
+        limits.alert_clear,
+        limits.alert_trigger_dwell,
+        limits.alert_clear_dwell,
+    );
+}
+
+// Meters keep WiFi awake for the lowest latency, loggers let it sleep between their reports
+fn apply_operating_mode(mode: OperatingMode, class: Option<NoiseClass>) {
+    mode::set_current(mode);
+    mode::show_class(class);
+    network::set_power_save(mode == OperatingMode::Logger);
+}
+
+fn store_paused(nvs: &EspNvs<NvsDefault>, paused: bool) {
+    match nvs.set_u8(NVS_PAUSED_KEY, paused as u8) {
+        Ok(_) => wear::nvs_written(0),
+        Err(err) => log::error!("Unable to persist paused state: {}", err),
+    }
+}
+
+fn load_calibration(nvs: &mut EspNvs<NvsDefault>) -> Calibration {
+    match nvs.remove(NVS_OLD_CALIBRATION_KEY) {
+        Ok(true) => log::warn!("Dropped the calibration made with the DC bias, calibrate again"),
+        Ok(false) => {}
+        Err(err) => log::error!("Unable to drop the old calibration: {}", err),
+    }
+    let mut buffer = [0u8; 32];
+    let calibration = match nvs.get_str(NVS_CALIBRATION_KEY, &mut buffer) {
+        Ok(Some(stored)) => Calibration::from_stored(stored).unwrap_or_else(|| {
+            log::warn!("Ignoring invalid calibration {:?}", stored);
+            Calibration::default()
+        }),
+        Ok(None) => Calibration::default(),
+        Err(err) => {
+            log::error!("Unable to read calibration: {}", err);
+            Calibration::default()
+        }
+    };
+    if calibration.is_set() {
+        log::info!("Levels calibrated with {:+.2} dB", calibration.offset_db);
+    }
+    calibration
+}
+
+fn store_calibration(nvs: &mut EspNvs<NvsDefault>, calibration: &Calibration) {
+    let stored = calibration.to_stored();
+    match nvs.set_str(NVS_CALIBRATION_KEY, &stored) {
+        Ok(_) => wear::nvs_written(stored.len()),
+        Err(err) => log::error!("Unable to persist calibration: {}", err),
+    }
+}
+
+// Retained, so the backend knows which levels are SPL and which are relative to ADC counts
+fn publish_calibration(
+    mqtt_client: &mut EspMqttClient,
+    calibration_topic: &str,
+    calibration: &Calibration,
+) {
+    let calibration_msg = calibration.to_json();
+    payload_log::dump(
+        Module::Diagnostics,
+        calibration_topic,
+        calibration_msg.as_bytes(),
+    );
+    if mqtt_client
+        .publish_tagged(
+            calibration_topic,
+            QoS::AtLeastOnce,
+            true,
+            &sealing::seal(calibration_topic, calibration_msg.as_bytes()),
+        )
+        .is_err()
+    {
+        log::error!("Unable to publish calibration");
+    }
+}
+
+fn publish_state(mqtt_client: &mut EspMqttClient, state_topic: &str, paused: bool) {
+    let telemetry = if paused { "paused" } else { "running" };
+    let state_msg = format!(
+        "{{\"telemetry\":\"{telemetry}\",\"maintenance\":{},\"support\":{}}}",
+        maintenance::in_mode(),
+        support::active()
+    );
+    if mqtt_client
+        .publish_tagged(
+            state_topic,
+            QoS::AtLeastOnce,
+            true,
+            &sealing::seal(state_topic, state_msg.as_bytes()),
+        )
+        .is_err()
+    {
+        log::error!("Unable to publish state");
+    }
+}
+
+fn publish_availability(
+    mqtt_client: &mut EspMqttClient,
+    availability_topic: &str,
+    online: bool,
+) -> Option<MessageId> {
+    let availability_msg = if online { "online" } else { "offline" };
+    mqtt_client
+        .publish_tagged(
+            availability_topic,
+            QoS::AtLeastOnce,
+            true,
+            availability_msg.as_bytes(),
+        )
+        .map_err(|err| log::error!("Unable to publish availability: {}", err))
+        .ok()
+}
+
+fn publish_info(mqtt_client: &mut EspMqttClient, topics: &Topics, security: SecurityState) {
+    let info_msg = format!(
+        "{{{},\"firmware\":\"{}\",\"flash_encryption\":{},\"secure_boot\":{}}}",
+        topics.metadata,
+        env!("CARGO_PKG_VERSION"),
+        security.flash_encryption,
+        security.secure_boot
+    );
+    if mqtt_client
+        .publish_tagged(
+            &topics.info,
+            QoS::AtLeastOnce,
+            true,
+            &sealing::seal(&topics.info, info_msg.as_bytes()),
+        )
+        .is_err()
+    {
+        log::error!("Unable to publish device info");
+    }
+}
+
+fn publish_birth(mqtt_client: &mut EspMqttClient, topics: &Topics, mac: &str, reset_reason: &str) {
+    let ip = network::sta_ip().map_or_else(|| String::from("null"), |ip| format!("\"{}\"", ip));
+    let mac = mac_with_colons(mac);
+    let birth_msg = format!(
+        "{{{},\"firmware\":\"{}\",\"ip\":{},\"mac\":\"{}\",\"reset_reason\":\"{}\"}}",
+        topics.metadata,
+        env!("CARGO_PKG_VERSION"),
+        ip,
+        mac,
+        reset_reason
+    );
+    if mqtt_client
+        .publish_tagged(
+            &topics.birth,
+            QoS::AtLeastOnce,
+            true,
+            &sealing::seal(&topics.birth, birth_msg.as_bytes()),
+        )
+        .is_err()
+    {
+        log::error!("Unable to publish birth message");
+    }
+}
+
+// Once per boot, so also right after a firmware update. False if it's worth another try.
+fn publish_inventory(mqtt_client: &mut EspMqttClient, fw_topic: &str) -> bool {
+    let inventory = match ota::inventory_json() {
+        Ok(inventory) => inventory,
+        Err(err) => {
+            log::error!("{:#}", err);
+            return true;
+        }
+    };
+    let published = mqtt_client
+        .publish_tagged(
+            fw_topic,
+            QoS::AtLeastOnce,
+            true,
+            &sealing::seal(fw_topic, inventory.as_bytes()),
+        )
+        .is_ok();
+    if !published {
+        log::error!("Unable to publish firmware inventory");
+    }
+    published
+}
+
+// Once per boot, they only change with the firmware
+fn publish_led_codes(mqtt_client: &mut EspMqttClient, topic: &str) -> bool {
+    let published = mqtt_client
+        .publish_tagged(
+            topic,
+            QoS::AtLeastOnce,
+            true,
+            &sealing::seal(topic, led::codes_json().as_bytes()),
+        )
+        .is_ok();
+    if !published {
+        log::error!("Unable to publish the LED codes");
+    }
+    published
+}
+
+// Waits for the first connection, subscribes to the config topic and gives the broker a moment
+// to deliver the retained settings. Everything else received meanwhile is queued again for the
+// main loop, which then finds the connection already subscribed to the config topic if this
+// returns true.
+fn apply_retained_config(
+    mqtt_client: &mut EspMqttClient,
+    config_topic: &str,
+    notification_tx: &mpsc::Sender<MqttNotification>,
+    notification_rx: &mpsc::Receiver<MqttNotification>,
+) -> bool {
+    let mut held = vec![];
+    let mut deadline = Instant::now() + BOOT_CONNECT_TIMEOUT;
+    let mut subscribed = false;
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        match notification_rx.recv_timeout(timeout) {
+            Ok(MqttNotification::Connected) => {
+                held.push(MqttNotification::Connected);
+                if mqtt_client
+                    .subscribe(config_topic, QoS::AtLeastOnce)
+                    .is_err()
+                {
+                    log::error!("Unable to subscribe to {}", config_topic);
+                    break;
+                }
+                subscribed = true;
+                deadline = Instant::now() + RETAINED_CONFIG_TIMEOUT;
+            }
+            Ok(MqttNotification::ConfigApplied) => break,
+            // Then the main loop subscribes on the next connection
+            Ok(MqttNotification::Disconnected) if subscribed => {
+                held.push(MqttNotification::Disconnected);
+                subscribed = false;
+                break;
+            }
+            Ok(notification) => held.push(notification),
+            Err(_) => break,
+        }
+    }
+    if !subscribed {
+        log::warn!("No connection to the broker, starting with the stored configuration");
+    }
+    for notification in held {
+        let _ = notification_tx.send(notification);
+    }
+    subscribed
+}
+
+// Commands and configuration still work on the old topics, and a retained message there points
+// to the new ones
+fn subscribe_legacy(mqtt_client: &mut EspMqttClient, legacy: &LegacyTopics, base_topic: &str) {
+    for topic in [&legacy.cmd, &legacy.config] {
+        if mqtt_client.subscribe(topic, QoS::AtLeastOnce).is_err() {
+            log::error!("Unable to subscribe to {}", topic);
+        }
+    }
+    let moved_msg = format!("{{\"moved_to\":\"{}\"}}", base_topic);
+    if mqtt_client
+        .publish_tagged(
+            &legacy.moved,
+            QoS::AtLeastOnce,
+            true,
+            &sealing::seal(&legacy.moved, moved_msg.as_bytes()),
+        )
+        .is_err()
+    {
+        log::error!("Unable to publish topic migration notice");
+    }
+}
+
+fn mac_with_colons(mac: &str) -> String {
+    (0..MAC_HEX_LEN)
+        .step_by(2)
+        .map(|i| &mac[i..i + 2])
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn sample_window(config: &Config) -> Duration {
+    Duration::from_millis(config.sample_window_ms.into())
+}
+
+fn level_format(config: &Config) -> LevelFormat {
+    let documents = config.level_json || config.report_raw_rms || config.band_fft_len > 0;
+    match (config.batch_size > 1, documents) {
+        (true, documents) => LevelFormat::Batch { documents },
+        (false, true) => LevelFormat::Document,
+        (false, false) => LevelFormat::Number,
+    }
+}
+
+// Clear text only, Home Assistant can't read sealed payloads
+fn publish_ha_discovery(
+    mqtt_client: &mut EspMqttClient,
+    config: &Config,
+    device_id: &str,
+    mac: &str,
+    topics: &Topics,
+) {
+    for (topic, document) in home_assistant::documents(
+        config.ha_discovery_prefix,
+        device_id,
+        &mac_with_colons(mac),
+        topics,
+        level_format(config),
+    ) {
+        payload_log::dump(Module::Mqtt, &topic, document.as_bytes());
+        if mqtt_client
+            .publish_tagged(&topic, QoS::AtLeastOnce, true, document.as_bytes())
+            .is_err()
+        {
+            log::error!("Unable to publish {}", topic);
+        }
+    }
+}
+
+// Clear text, retained and at least once, as the convention wants every attribute and value
+#[cfg(feature = "homie")]
+fn publish_homie(mqtt_client: &mut EspMqttClient, (topic, payload): (String, String)) {
+    payload_log::dump(Module::Mqtt, &topic, payload.as_bytes());
+    // Values come too often to retain, and a lost one is followed by the next
+    let (qos, retain) = if homie::is_attribute(&topic) {
+        (QoS::AtLeastOnce, true)
+    } else {
+        (QoS::AtMostOnce, false)
+    };
+    if mqtt_client
+        .publish_tagged(&topic, qos, retain, payload.as_bytes())
+        .is_err()
+    {
+        log::error!("Unable to publish {}", topic);
+    }
+}
+
+fn publish_class(mqtt_client: &mut EspMqttClient, class_topic: &str, class: NoiseClass) {
+    if mqtt_client
+        .publish_tagged(
+            class_topic,
+            QoS::AtLeastOnce,
+            true,
+            &sealing::seal(class_topic, class.as_str().as_bytes()),
+        )
+        .is_err()
+    {
+        log::error!("Unable to publish noise classification");
+    }
+}
+
+// Registers the device under its UUID, generated on the first claim and kept in NVS
+fn request_claim(
+    mqtt_client: &mut EspMqttClient,
+    nvs: &mut EspNvs<NvsDefault>,
+    claim_topics: &ClaimTopics,
+    device_id: &str,
+    mac: &str,
+) {
+    let mut buffer = [0u8; 37];
+    let uuid = match nvs.get_str(NVS_UUID_KEY, &mut buffer) {
+        Ok(Some(uuid)) => uuid.to_string(),
+        _ => {
+            let mut random = [0u8; 16];
+            unsafe { esp_fill_random(random.as_mut_ptr() as *mut c_void, random.len()) };
+            let uuid = claim::uuid_from_random(random);
+            match nvs.set_str(NVS_UUID_KEY, &uuid) {
+                Ok(_) => wear::nvs_written(uuid.len()),
+                Err(err) => log::error!("Unable to persist device UUID: {}", err),
+            }
+            uuid
         }
+    };
+    let request = claim::request_json(
+        &uuid,
+        device_id,
+        &mac[..MAC_HEX_LEN],
+        env!("CARGO_PKG_VERSION"),
+    );
+    log::info!("Requesting activation as {}", uuid);
+    payload_log::dump(Module::Mqtt, &claim_topics.request, request.as_bytes());
+    if mqtt_client
+        .publish_tagged(
+            &claim_topics.request,
+            QoS::AtLeastOnce,
+            false,
+            &sealing::seal(&claim_topics.request, request.as_bytes()),
+        )
+        .is_err()
+    {
+        log::error!("Unable to publish claim request");
     }
 }
 
+fn readings_qos(level: u8) -> QoS {
+    match level {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        2 => QoS::ExactlyOnce,
+        level => {
+            log::warn!("Invalid MQTT QoS {}, using 0", level);
+            QoS::AtMostOnce
+        }
+    }
+}
+
+// Subscribes to the level topic and publishes a marker there with the readings' QoS, outside
+// the delivery tracker so a retry can't hide a lost message. The broker handles the subscribe
+// before the publish on the same connection.
+fn start_loopback(
+    mqtt_client: &mut EspMqttClient,
+    topics: &Topics,
+    loopback: &mut Loopback,
+    qos: QoS,
+) {
+    log::info!("Starting loopback check on {}", topics.level);
+    let qos_level = match qos {
+        QoS::AtMostOnce => 0,
+        QoS::AtLeastOnce => 1,
+        QoS::ExactlyOnce => 2,
+    };
+    if let Err(err) = mqtt_client.subscribe(&topics.level, qos) {
+        log::error!("Unable to subscribe to {}: {}", topics.level, err);
+        publish_loopback_result(
+            mqtt_client,
+            &topics.loopback,
+            &loopback::result(false, qos_level, None, None),
+        );
+        return;
+    }
+    let marker = Loopback::marker(unsafe { esp_random() });
+    let sealed = sealing::seal(&topics.level, marker.as_bytes());
+    match mqtt_client.publish_tagged(&topics.level, qos, false, &sealed) {
+        Ok(msg_id) => loopback.start(sealed, qos_level, Some(msg_id), Instant::now()),
+        Err(err) => {
+            log::error!("Unable to publish loopback marker: {}", err);
+            finish_loopback(
+                mqtt_client,
+                topics,
+                &loopback::result(false, qos_level, None, None),
+            );
+        }
+    }
+}
+
+fn finish_loopback(mqtt_client: &mut EspMqttClient, topics: &Topics, result: &str) {
+    log::info!("Loopback check: {}", result);
+    if mqtt_client.unsubscribe(&topics.level).is_err() {
+        log::error!("Unable to unsubscribe from {}", topics.level);
+    }
+    publish_loopback_result(mqtt_client, &topics.loopback, result);
+}
+
+fn publish_loopback_result(mqtt_client: &mut EspMqttClient, loopback_topic: &str, result: &str) {
+    payload_log::dump(Module::Diagnostics, loopback_topic, result.as_bytes());
+    if mqtt_client
+        .publish_tagged(
+            loopback_topic,
+            QoS::AtLeastOnce,
+            false,
+            &sealing::seal(loopback_topic, result.as_bytes()),
+        )
+        .is_err()
+    {
+        log::error!("Unable to publish loopback result");
+    }
+}
+
+// Signed like the alert it belongs to
+fn publish_alert_burst(mqtt_client: &mut EspMqttClient, burst_topic: &str, series: String) {
+    let payload = signing::sign(burst_topic, series);
+    payload_log::dump(Module::Alerts, burst_topic, payload.as_bytes());
+    if mqtt_client
+        .publish_tagged(
+            burst_topic,
+            QoS::AtLeastOnce,
+            false,
+            &sealing::seal(burst_topic, payload.as_bytes()),
+        )
+        .is_err()
+    {
+        log::error!("Unable to publish alert burst");
+    }
+}
+
+// Oldest first, stopping at the first failure so the order is kept
+fn flush_offline(
+    mqtt_client: &mut EspMqttClient,
+    delivery: &mut DeliveryTracker,
+    replay_topic: &str,
+    offline: &mut OfflineQueue,
+) {
+    for _ in 0..OFFLINE_FLUSH_LEN {
+        let Some(payload) = offline.peek_replay() else {
+            log::info!("Offline queue flushed");
+            return;
+        };
+        // A new counter on every attempt, the queued reading inside keeps its own signature
+        let payload = signing::sign(replay_topic, payload);
+        if publish_reading(mqtt_client, delivery, replay_topic, payload.as_bytes()).is_err() {
+            return;
+        }
+        offline.pop();
+    }
+}
+
+fn restore_offline(nvs: &EspNvs<NvsDefault>, offline: &mut OfflineQueue) {
+    let mut buffer = vec![0u8; nvs.blob_len(NVS_OFFLINE_KEY).ok().flatten().unwrap_or(0)];
+    match nvs.get_blob(NVS_OFFLINE_KEY, &mut buffer) {
+        Ok(Some(blob)) => {
+            offline.restore(&String::from_utf8_lossy(blob));
+            log::info!("{} queued readings restored", offline.len());
+        }
+        Ok(None) => {}
+        Err(err) => log::error!("Unable to read queued readings: {}", err),
+    }
+}
+
+// What support needs in one go: the log tail, the configuration without secrets, the metrics
+// of the diagnostics report and how the last reset came about
+fn diagnostic_bundle(config: &ConfigStore, boot_report: &BootReport) -> Vec<u8> {
+    diag_bundle::assemble(&[
+        ("log", log_tail::recent()),
+        ("config", config.dump()),
+        (
+            "metrics",
+            format!("{{{},{}}}", metrics::json_fields(), wear::json_fields()),
+        ),
+        ("boot", boot_report.to_json()),
+    ])
+}
+
+fn publish_diag_bundle(mqtt_client: &mut EspMqttClient, topic: &str, bundle: &[u8]) {
+    let id = unsafe { esp_random() };
+    let manifest = diag_bundle::manifest_json(id, bundle);
+    payload_log::dump(Module::Diagnostics, topic, manifest.as_bytes());
+    let mut failed = mqtt_client
+        .publish_tagged(
+            topic,
+            QoS::AtLeastOnce,
+            false,
+            &sealing::seal(topic, manifest.as_bytes()),
+        )
+        .is_err();
+    for (index, chunk) in diag_bundle::chunks(bundle) {
+        let chunk_topic = diag_bundle::chunk_topic(topic, id, index);
+        failed |= mqtt_client
+            .publish_tagged(
+                &chunk_topic,
+                QoS::AtLeastOnce,
+                false,
+                &sealing::seal(&chunk_topic, chunk),
+            )
+            .is_err();
+    }
+    if failed {
+        log::error!("Unable to publish diagnostic bundle");
+    } else {
+        log::info!(
+            "Published {} byte diagnostic bundle in {} chunks",
+            bundle.len(),
+            diag_bundle::chunk_count(bundle)
+        );
+    }
+}
+
+fn offline_persist_interval() -> Duration {
+    if wear::throttled() {
+        OFFLINE_PERSIST_INTERVAL_THROTTLED
+    } else {
+        OFFLINE_PERSIST_INTERVAL
+    }
+}
+
+fn persist_offline(nvs: &mut EspNvs<NvsDefault>, offline: &OfflineQueue) {
+    let result = if offline.is_empty() {
+        nvs.remove(NVS_OFFLINE_KEY).map(|_| ())
+    } else {
+        let blob = offline.to_blob(MAX_PERSISTED_READINGS);
+        nvs.set_blob(NVS_OFFLINE_KEY, blob.as_bytes())
+            .map(|_| wear::nvs_written(blob.len()))
+    };
+    if let Err(err) = result {
+        log::error!("Unable to persist queued readings: {}", err);
+    }
+}
+
+fn load_autotune(nvs: &mut EspNvs<NvsDefault>) -> Option<AutoTune> {
+    let mut buffer = vec![0u8; 2048];
+    match nvs.get_blob(NVS_AUTOTUNE_KEY, &mut buffer) {
+        Ok(Some(stored)) => {
+            let observation = AutoTune::from_stored(stored, Instant::now());
+            match observation.as_ref() {
+                Some(observation) => log::info!(
+                    "Resuming threshold learning after {:?}",
+                    observation.observed(Instant::now())
+                ),
+                // Also what an older firmware left, learned on another scale
+                None => {
+                    log::warn!("Dropping invalid threshold learning state");
+                    persist_autotune(nvs, None);
+                }
+            }
+            observation
+        }
+        Ok(None) => None,
+        Err(err) => {
+            log::error!("Unable to read threshold learning state: {}", err);
+            None
+        }
+    }
+}
+
+fn persist_autotune(nvs: &mut EspNvs<NvsDefault>, autotune: Option<&AutoTune>) {
+    let result = match autotune {
+        Some(observation) => {
+            let blob = observation.to_stored(Instant::now());
+            nvs.set_blob(NVS_AUTOTUNE_KEY, &blob)
+                .map(|_| wear::nvs_written(blob.len()))
+        }
+        None => nvs.remove(NVS_AUTOTUNE_KEY).map(|_| ()),
+    };
+    if let Err(err) = result {
+        log::error!("Unable to persist threshold learning: {}", err);
+    }
+}
+
+// Retained, so the operator finds the proposal whenever they look
+fn publish_autotune(mqtt_client: &mut EspMqttClient, autotune_topic: &str, autotune_msg: String) {
+    payload_log::dump(Module::Diagnostics, autotune_topic, autotune_msg.as_bytes());
+    if mqtt_client
+        .publish_tagged(
+            autotune_topic,
+            QoS::AtLeastOnce,
+            true,
+            &sealing::seal(autotune_topic, autotune_msg.as_bytes()),
+        )
+        .is_err()
+    {
+        log::error!("Unable to publish threshold learning");
+    }
+}
+
+// Sealed once, so a retry from the delivery tracker sends the same bytes
+fn publish_reading(
+    mqtt_client: &mut EspMqttClient,
+    delivery: &mut DeliveryTracker,
+    topic: &str,
+    payload: &[u8],
+) -> Result<MessageId, EspError> {
+    let sealed = sealing::seal(topic, payload);
+    let msg_id = mqtt_client.publish_tagged(topic, delivery.qos(), false, &sealed)?;
+    delivery.sent(msg_id, topic, sealed);
+    Ok(msg_id)
+}
+
 fn get_sensor_id() -> String {
     let mut mac_addr = [0u8; 8];
     unsafe {
@@ -207,65 +2746,93 @@ fn get_sensor_id() -> String {
     }
 }
 
+// Off on request from the cmd topic, until the next restart. Identifying still lights it up.
+static LED_ENABLED: AtomicBool = AtomicBool::new(true);
+
 fn report_status(
     status: &AtomicU8,
+    led_brightness: &AtomicU8,
+    wifi_retry: &RetryCountdown,
+    mqtt_retry: &RetryCountdown,
     rmt_channel: impl Peripheral<P = impl RmtChannel>,
     led_pin: impl Peripheral<P = impl OutputPin>,
-) -> ! {
+) -> Result<()> {
     let mut neopixel =
-        Ws2812Esp32RmtDriver::new(rmt_channel, led_pin).expect("Unable to talk to ws2812");
+        Ws2812Esp32RmtDriver::new(rmt_channel, led_pin).context("Unable to talk to ws2812")?;
     let mut prev_status = DeviceStatus::WifiError; // Anything but Ok
+    let mut prev_identifying = false;
+    let mut prev_maintaining = false;
+    let mut prev_rule_color = None;
+    let mut prev_mode = OperatingMode::Meter;
+    let mut prev_level_color = None;
     let mut sequence: Vec<ColorStep> = vec![];
+    let watchdog = watchdog::register("led", LED_WATCHDOG_TIMEOUT);
     loop {
+        watchdog.feed();
         if let Ok(status) = DeviceStatus::try_from(status.load(Relaxed)) {
-            if status != prev_status {
+            let identifying = identify::is_active();
+            let maintaining = maintenance::in_mode();
+            let rule_color = automation::led_color();
+            let operating_mode = mode::current();
+            let level_color = mode::led_color();
+            if status != prev_status
+                || identifying != prev_identifying
+                || maintaining != prev_maintaining
+                || rule_color != prev_rule_color
+                || operating_mode != prev_mode
+                || level_color != prev_level_color
+            {
                 prev_status = status;
-                sequence = status.light_sequence();
+                prev_identifying = identifying;
+                prev_maintaining = maintaining;
+                prev_rule_color = rule_color;
+                prev_mode = operating_mode;
+                prev_level_color = level_color;
+                sequence = if identifying {
+                    led::identify_sequence()
+                } else if maintaining {
+                    led::maintenance_sequence()
+                } else if let Some(color) = rule_color {
+                    led::steady_sequence(color)
+                } else if status != DeviceStatus::Ok {
+                    status.light_sequence()
+                } else if operating_mode == OperatingMode::Logger {
+                    led::logger_sequence()
+                } else if let Some(color) = level_color {
+                    led::steady_sequence(color)
+                } else {
+                    status.light_sequence()
+                };
             }
+            // Blink faster and faster while waiting for the next reconnection attempt
+            let pause_scale = match wifi_retry
+                .remaining_fraction()
+                .or_else(|| mqtt_retry.remaining_fraction())
+            {
+                Some(remaining) if status != DeviceStatus::Ok && !identifying && !maintaining => {
+                    led::retry_pause_scale(remaining)
+                }
+                _ => 1.0,
+            };
             for step in sequence.iter() {
-                let color = LedPixelColorGrb24::new_with_rgb(step.red, step.green, step.blue);
+                let color = LedPixelColorGrb24::new_with_rgb(step.red, step.green, step.blue)
+                    .brightness(if !LED_ENABLED.load(Relaxed) && !identifying {
+                        0
+                    } else if thermal::is_throttled() {
+                        led_brightness.load(Relaxed) / 4
+                    } else {
+                        led_brightness.load(Relaxed)
+                    });
                 neopixel
                     .write_blocking(color.as_ref().iter().cloned())
-                    .expect("Error writing to neopixel");
-                thread::sleep(Duration::from_millis(step.duration));
+                    .context("Error writing to neopixel")?;
+                let duration = if step.is_dark() {
+                    (step.duration as f32 * pause_scale) as u64
+                } else {
+                    step.duration
+                };
+                thread::sleep(Duration::from_millis(duration));
             }
         }
     }
 }
-
-fn connect_to_wifi(
-    ssid: &str,
-    passwd: &str,
-    modem: impl Peripheral<P = modem::Modem> + 'static,
-) -> Result<Box<EspWifi<'static>>> {
-    if ssid.is_empty() {
-        bail!("No SSID defined");
-    }
-    let auth_method = if passwd.is_empty() {
-        AuthMethod::None
-    } else {
-        AuthMethod::WPA2Personal
-    };
-    let sys_loop = EspSystemEventLoop::take().context("Unable to access system event loop.")?;
-    let nvs = EspDefaultNvsPartition::take().context("Unable to access default NVS partition")?;
-    let mut esp_wifi = EspWifi::new(modem, sys_loop.clone(), Some(nvs))?;
-    let mut wifi = BlockingWifi::wrap(&mut esp_wifi, sys_loop)?;
-    wifi.set_configuration(&wifi::Configuration::Client(wifi::ClientConfiguration {
-        ssid: ssid
-            .try_into()
-            .map_err(|_| anyhow::Error::msg("Failed to use SSID"))?,
-        password: passwd
-            .try_into()
-            .map_err(|_| anyhow::Error::msg("Failed to use password"))?,
-        auth_method,
-        ..Default::default()
-    }))?;
-    wifi.start()?;
-    wifi.connect()?;
-    wifi.wait_netif_up()?;
-
-    let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
-    log::info!("DHCP info: {:?}", ip_info);
-
-    Ok(Box::new(esp_wifi))
-}
@@ -0,0 +1,60 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering::Relaxed},
+    time::Duration,
+};
+
+use esp_idf_svc::sys::{localtime_r, mktime, time_t, tm};
+
+use crate::config::Config;
+
+// A device that just booted gains nothing from a reboot
+const MIN_UPTIME: Duration = Duration::from_secs(3600);
+const EVERY_DAY: u8 = 7;
+
+// Maintenance mode, for calibration sessions or vacuuming next to the sensor: telemetry is
+// marked, alerts are dropped and the LED shows it. Ends with a restart at the latest.
+static MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_mode(active: bool) {
+    log::info!("Maintenance mode {}", if active { "on" } else { "off" });
+    MODE.store(active, Relaxed);
+}
+
+pub fn in_mode() -> bool {
+    MODE.load(Relaxed)
+}
+
+// Adds `"maintenance":true` to a JSON object published in maintenance mode, so analytics can
+// leave it out
+pub fn mark(json: String) -> String {
+    match json.strip_suffix('}') {
+        Some(fields) if in_mode() => format!("{},\"maintenance\":true}}", fields),
+        _ => json,
+    }
+}
+
+// Whether the scheduled reboot time of today, local time, passed while the device was up. The
+// schedule is turned into a point in time by mktime, with daylight saving worked out from the
+// timezone rule, so a time skipped in spring still happens, and one repeated in autumn happens
+// only once: after the reboot, the device is up since after it.
+pub fn reboot_due(config: &Config, unix_time: u64, uptime: Duration) -> bool {
+    if !config.maintenance_reboot || uptime < MIN_UPTIME {
+        return false;
+    }
+    let now = unix_time as time_t;
+    let mut local: tm = unsafe { std::mem::zeroed() };
+    if unsafe { localtime_r(&now, &mut local) }.is_null() {
+        return false;
+    }
+    if config.maintenance_day != EVERY_DAY && local.tm_wday != config.maintenance_day as i32 {
+        return false;
+    }
+    local.tm_hour = config.maintenance_hour as i32;
+    local.tm_min = config.maintenance_minute as i32;
+    local.tm_sec = 0;
+    // Let mktime find out whether daylight saving applies at that time
+    local.tm_isdst = -1;
+    let scheduled = unsafe { mktime(&mut local) };
+    let booted = unix_time.saturating_sub(uptime.as_secs()) as time_t;
+    scheduled >= 0 && booted < scheduled && scheduled <= now
+}
@@ -0,0 +1,34 @@
+use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+
+use esp_idf_svc::sys::{
+    esp_get_free_heap_size, esp_get_minimum_free_heap_size, esp_timer_get_time,
+};
+
+use crate::network;
+
+// Counted by whichever task sees the failure, the sensor task for publishes it couldn't queue and
+// the MQTT task for transport errors, and read by the diagnostics report
+static PUBLISH_ERRORS: AtomicU32 = AtomicU32::new(0);
+static MQTT_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+pub fn publish_failed() {
+    PUBLISH_ERRORS.fetch_add(1, Relaxed);
+}
+
+pub fn mqtt_error() {
+    MQTT_ERRORS.fetch_add(1, Relaxed);
+}
+
+// Extra diagnostics fields, the counters since boot
+pub fn json_fields() -> String {
+    let rssi = network::sta_rssi().map_or_else(|| String::from("null"), |rssi| rssi.to_string());
+    format!(
+        "\"free_heap\":{},\"min_free_heap\":{},\"rssi\":{},\"uptime_s\":{},\"publish_errors\":{},\"mqtt_errors\":{}",
+        unsafe { esp_get_free_heap_size() },
+        unsafe { esp_get_minimum_free_heap_size() },
+        rssi,
+        unsafe { esp_timer_get_time() } / 1_000_000,
+        PUBLISH_ERRORS.load(Relaxed),
+        MQTT_ERRORS.load(Relaxed)
+    )
+}
@@ -0,0 +1,59 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering::Relaxed},
+    Mutex,
+};
+
+use crate::{classification::NoiseClass, config::Config, led};
+
+static CURRENT: AtomicU8 = AtomicU8::new(OperatingMode::Meter as u8);
+// The class the LED shows in meter mode while all is well
+static LED_CLASS: Mutex<Option<NoiseClass>> = Mutex::new(None);
+
+// What the sensor is set up for. Both run the same pipeline, wired differently: a meter publishes
+// readings as they come and shows the noise class on the LED, a logger publishes one Leq per
+// interval, batched and queued while offline like readings, and lets WiFi sleep in between.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OperatingMode {
+    Meter,
+    Logger,
+}
+
+impl OperatingMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "meter" => Some(OperatingMode::Meter),
+            "logger" => Some(OperatingMode::Logger),
+            _ => None,
+        }
+    }
+
+    // Runtime changes only take the known modes, an unknown one from cfg.toml is a meter
+    pub fn of(config: &Config) -> Self {
+        OperatingMode::parse(config.operating_mode).unwrap_or(OperatingMode::Meter)
+    }
+}
+
+// For the LED thread
+pub fn set_current(mode: OperatingMode) {
+    CURRENT.store(mode as u8, Relaxed);
+}
+
+pub fn current() -> OperatingMode {
+    if CURRENT.load(Relaxed) == OperatingMode::Logger as u8 {
+        OperatingMode::Logger
+    } else {
+        OperatingMode::Meter
+    }
+}
+
+pub fn show_class(class: Option<NoiseClass>) {
+    *LED_CLASS.lock().unwrap() = class;
+}
+
+// The class color, None in logger mode
+pub fn led_color() -> Option<[u8; 3]> {
+    if current() == OperatingMode::Logger {
+        return None;
+    }
+    (*LED_CLASS.lock().unwrap()).map(led::class_color)
+}
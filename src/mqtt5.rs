@@ -0,0 +1,118 @@
+use std::{
+    ffi::{c_char, CString},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        OnceLock,
+    },
+};
+
+use esp_idf_svc::{
+    handle::RawHandle,
+    mqtt::client::{EspMqttClient, MessageId, QoS},
+    sys::{
+        esp_mqtt5_client_delete_user_property, esp_mqtt5_client_set_publish_property,
+        esp_mqtt5_client_set_user_property, esp_mqtt5_publish_property_config_t,
+        esp_mqtt5_user_property_item_t, mqtt5_user_property_handle_t, EspError, ESP_OK,
+    },
+};
+
+use crate::metrics;
+
+const FIRMWARE: &[u8] = concat!(env!("CARGO_PKG_VERSION"), "\0").as_bytes();
+
+// Asked for in cfg.toml, until the broker turns it down
+static WANTED: AtomicBool = AtomicBool::new(false);
+static DEVICE_ID: OnceLock<CString> = OnceLock::new();
+
+pub fn init(wanted: bool, device_id: &str) {
+    WANTED.store(wanted, Relaxed);
+    let _ = DEVICE_ID.set(CString::new(device_id).unwrap_or_default());
+}
+
+// Whether the next client should connect with MQTT 5
+pub fn wanted() -> bool {
+    WANTED.load(Relaxed)
+}
+
+// The broker only speaks 3.1.1, which it says by refusing the protocol version
+pub fn refused() {
+    if WANTED.swap(false, Relaxed) {
+        log::warn!("Broker doesn't support MQTT 5, falling back to 3.1.1");
+    }
+}
+
+pub trait Publish {
+    // Like `publish`, with the firmware version and device id as user properties and the content
+    // type on MQTT 5
+    fn publish_tagged(
+        &mut self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: &[u8],
+    ) -> Result<MessageId, EspError>;
+}
+
+impl Publish for EspMqttClient<'_> {
+    fn publish_tagged(
+        &mut self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: &[u8],
+    ) -> Result<MessageId, EspError> {
+        if wanted() {
+            set_publish_properties(self, payload);
+        }
+        let published = self.publish(topic, qos, retain, payload);
+        if published.is_err() {
+            metrics::publish_failed();
+        }
+        published
+    }
+}
+
+// Also covers sealed payloads, which are binary
+fn content_type(payload: &[u8]) -> &'static [u8] {
+    match payload.first() {
+        Some(b'{' | b'[') => b"application/json\0",
+        _ if std::str::from_utf8(payload).is_ok() => b"text/plain\0",
+        _ => b"application/octet-stream\0",
+    }
+}
+
+// esp-mqtt applies the properties to the next publish only and frees the user properties then.
+// Publishing happens from the sensor task, so nothing comes in between.
+fn set_publish_properties(mqtt_client: &EspMqttClient, payload: &[u8]) {
+    let device_id = DEVICE_ID.get_or_init(CString::default);
+    let mut items = [
+        esp_mqtt5_user_property_item_t {
+            key: b"firmware\0".as_ptr() as *const c_char,
+            value: FIRMWARE.as_ptr() as *const c_char,
+        },
+        esp_mqtt5_user_property_item_t {
+            key: b"device_id\0".as_ptr() as *const c_char,
+            value: device_id.as_ptr(),
+        },
+    ];
+    let mut user_property: mqtt5_user_property_handle_t = ptr::null_mut();
+    let result = unsafe {
+        let result = esp_mqtt5_client_set_user_property(&mut user_property, items.as_mut_ptr(), 2);
+        if result != ESP_OK {
+            result
+        } else {
+            let mut property: esp_mqtt5_publish_property_config_t = std::mem::zeroed();
+            property.content_type = content_type(payload).as_ptr() as *const c_char;
+            property.user_property = user_property;
+            let result = esp_mqtt5_client_set_publish_property(mqtt_client.handle(), &property);
+            if result != ESP_OK {
+                esp_mqtt5_client_delete_user_property(user_property);
+            }
+            result
+        }
+    };
+    if result != ESP_OK {
+        log::warn!("Unable to set MQTT 5 properties: {}", result);
+    }
+}
@@ -0,0 +1,440 @@
+use std::{
+    ffi::c_void,
+    net::Ipv4Addr,
+    ptr,
+    sync::{
+        atomic::{AtomicU8, Ordering::Relaxed},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    hal::{modem, peripheral::Peripheral},
+    handle::RawHandle,
+    mqtt::client::EspMqttClient,
+    nvs::{EspDefaultNvsPartition, EspNvs},
+    sys::{
+        esp_event_base_t, esp_mqtt_client_register_event,
+        esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_BAD_USERNAME,
+        esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_NOT_AUTHORIZED,
+        esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_PROTOCOL,
+        esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_SERVER_UNAVAILABLE,
+        esp_mqtt_error_type_t_MQTT_ERROR_TYPE_CONNECTION_REFUSED,
+        esp_mqtt_event_id_t_MQTT_EVENT_ERROR, esp_mqtt_event_t, esp_random, esp_restart,
+        esp_wifi_set_ps, esp_wifi_sta_get_ap_info, wifi_ap_record_t,
+        wifi_ps_type_t_WIFI_PS_MAX_MODEM, wifi_ps_type_t_WIFI_PS_NONE, ESP_OK,
+    },
+    wifi::{self, AccessPointConfiguration, AuthMethod, BlockingWifi, EspWifi},
+};
+
+use crate::{
+    backoff::{Backoff, BackoffPolicy},
+    bus::{self, BusEvent, Link},
+    dashboard, get_sensor_id,
+    led::DeviceStatus,
+    metrics, mqtt5, provisioning, watchdog, NVS_NAMESPACE,
+};
+
+const WIFI_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// For WiFi and the broker alike
+const RETRY_BACKOFF: BackoffPolicy = BackoffPolicy {
+    initial: Duration::from_secs(2),
+    max: Duration::from_secs(300),
+    multiplier: 2,
+    jitter: 0.2,
+};
+// What an MQTT 5 broker answers to a protocol version it doesn't support
+const MQTT5_UNSUPPORTED_PROTOCOL_VERSION: u32 = 0x84;
+// Covers the longest backoff plus a connection attempt
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(420);
+// After this long without WiFi, a technician on site gets the dashboard over a SoftAP for a while
+const DIAGNOSTICS_AP_AFTER: Duration = Duration::from_secs(600);
+const DIAGNOSTICS_AP_DURATION: Duration = Duration::from_secs(900);
+
+// When the next reconnection attempt is due, so the LED can show how close it is
+pub struct RetryCountdown(Mutex<Option<(Instant, Duration)>>);
+
+impl RetryCountdown {
+    pub const fn new() -> Self {
+        RetryCountdown(Mutex::new(None))
+    }
+
+    pub fn schedule(&self, delay: Duration) {
+        *self.0.lock().unwrap() = Some((Instant::now(), delay));
+    }
+
+    pub fn clear(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    // From 1.0 right after a failed attempt down to 0.0 when the next one is due
+    pub fn remaining_fraction(&self) -> Option<f32> {
+        let (scheduled_at, delay) = (*self.0.lock().unwrap())?;
+        let remaining = delay.saturating_sub(scheduled_at.elapsed());
+        Some(remaining.as_secs_f32() / delay.as_secs_f32())
+    }
+}
+
+// esp-mqtt retries on its own at a fixed interval. The sensor loop reconnects with a fresh client
+// before that, so the interval has to outlast the longest backoff.
+pub fn mqtt_reconnect_timeout(configured: Duration) -> Duration {
+    configured.max(RETRY_BACKOFF.max)
+}
+
+// Why the last attempt failed, as the status to show until the broker is back. esp-mqtt reports
+// the error right before the disconnection.
+static MQTT_FAILURE: AtomicU8 = AtomicU8::new(DeviceStatus::MqttError as u8);
+
+// From the last time WiFi came up, for the birth message
+static STA_IP: Mutex<Option<Ipv4Addr>> = Mutex::new(None);
+
+pub fn sta_ip() -> Option<Ipv4Addr> {
+    *STA_IP.lock().unwrap()
+}
+
+// Of the access point, None while not associated. Safe to call from any task.
+pub fn sta_rssi() -> Option<i8> {
+    let mut ap_info: wifi_ap_record_t = unsafe { std::mem::zeroed() };
+    (unsafe { esp_wifi_sta_get_ap_info(&mut ap_info) } == ESP_OK).then_some(ap_info.rssi)
+}
+
+// The radio sleeps between beacons with power saving, which adds up to a beacon interval of latency
+// to every publish
+pub fn set_power_save(power_save: bool) {
+    let mode = if power_save {
+        wifi_ps_type_t_WIFI_PS_MAX_MODEM
+    } else {
+        wifi_ps_type_t_WIFI_PS_NONE
+    };
+    let result = unsafe { esp_wifi_set_ps(mode) };
+    if result != ESP_OK {
+        log::warn!("Unable to set WiFi power saving: {}", result);
+    }
+}
+
+// esp-idf-svc passes errors on without the CONNACK return code, so this listens to the raw event
+pub fn watch_mqtt_errors(mqtt_client: &EspMqttClient) {
+    let result = unsafe {
+        esp_mqtt_client_register_event(
+            mqtt_client.handle(),
+            esp_mqtt_event_id_t_MQTT_EVENT_ERROR,
+            Some(on_mqtt_error),
+            ptr::null_mut(),
+        )
+    };
+    if result != ESP_OK {
+        log::error!("Unable to watch MQTT errors: {}", result);
+    }
+}
+
+#[allow(non_upper_case_globals)]
+unsafe extern "C" fn on_mqtt_error(
+    _: *mut c_void,
+    _: esp_event_base_t,
+    _: i32,
+    event_data: *mut c_void,
+) {
+    let Some(codes) = (event_data as *const esp_mqtt_event_t)
+        .as_ref()
+        .and_then(|event| event.error_handle.as_ref())
+    else {
+        return;
+    };
+    let failure = if codes.error_type == esp_mqtt_error_type_t_MQTT_ERROR_TYPE_CONNECTION_REFUSED {
+        match codes.connect_return_code {
+            esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_BAD_USERNAME => {
+                log::error!("Broker refused the MQTT username or password");
+                DeviceStatus::MqttBadCredentials
+            }
+            esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_NOT_AUTHORIZED => {
+                log::error!("Broker says the device is not authorized");
+                DeviceStatus::MqttNotAuthorized
+            }
+            // 3.1.1 brokers answer an MQTT 5 CONNECT like this
+            esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_PROTOCOL
+            | MQTT5_UNSUPPORTED_PROTOCOL_VERSION => {
+                mqtt5::refused();
+                DeviceStatus::MqttError
+            }
+            esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_SERVER_UNAVAILABLE => {
+                log::error!("Broker unavailable");
+                DeviceStatus::MqttError
+            }
+            code => {
+                log::error!("Broker refused the connection with return code {}", code);
+                DeviceStatus::MqttError
+            }
+        }
+    } else {
+        log::warn!(
+            "MQTT transport error {}, socket errno {}",
+            codes.esp_tls_last_esp_err,
+            codes.esp_transport_sock_errno
+        );
+        DeviceStatus::MqttError
+    };
+    metrics::mqtt_error();
+    MQTT_FAILURE.store(failure as u8, Relaxed);
+}
+
+// Reconnections to the broker, doubling the wait after each failed attempt. Driven from the
+// sensor loop, so measuring never waits for the broker.
+pub struct MqttBackoff {
+    backoff: Backoff,
+    reconnect_at: Option<Instant>,
+}
+
+impl Default for MqttBackoff {
+    fn default() -> Self {
+        MqttBackoff {
+            backoff: Backoff::new(RETRY_BACKOFF, unsafe { esp_random() }),
+            reconnect_at: None,
+        }
+    }
+}
+
+impl MqttBackoff {
+    // Also what esp-mqtt reports when an attempt fails
+    pub fn disconnected(&mut self, status: &AtomicU8, retry: &RetryCountdown) {
+        let failure = MQTT_FAILURE.load(Relaxed);
+        let _ = status.fetch_update(Relaxed, Relaxed, |current| {
+            (current == DeviceStatus::Ok as u8 || is_mqtt_error(current)).then_some(failure)
+        });
+        if self.reconnect_at.is_none() {
+            let delay = self.backoff.next_delay();
+            log::warn!("Disconnected from MQTT, reconnecting in {:?}", delay);
+            self.reconnect_at = Some(Instant::now() + delay);
+            retry.schedule(delay);
+        }
+    }
+
+    pub fn connected(&mut self, status: &AtomicU8, retry: &RetryCountdown) {
+        let _ = status.fetch_update(Relaxed, Relaxed, |current| {
+            is_mqtt_error(current).then_some(DeviceStatus::Ok as u8)
+        });
+        MQTT_FAILURE.store(DeviceStatus::MqttError as u8, Relaxed);
+        self.backoff.reset();
+        self.reconnect_at = None;
+        retry.clear();
+    }
+
+    // Whether to replace the client now
+    pub fn reconnect_due(&mut self) -> bool {
+        if self
+            .reconnect_at
+            .is_some_and(|reconnect_at| Instant::now() >= reconnect_at)
+        {
+            self.reconnect_at = None;
+            return true;
+        }
+        false
+    }
+}
+
+fn is_mqtt_error(status: u8) -> bool {
+    DeviceStatus::try_from(status).is_ok_and(|status| {
+        matches!(
+            status,
+            DeviceStatus::MqttError
+                | DeviceStatus::MqttBadCredentials
+                | DeviceStatus::MqttNotAuthorized
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn supervise_wifi(
+    status: &AtomicU8,
+    retry: &RetryCountdown,
+    ssid: &str,
+    passwd: &str,
+    use_dpp: bool,
+    diagnostics_ap_password: &str,
+    modem: impl Peripheral<P = modem::Modem> + 'static,
+    nvs_partition: EspDefaultNvsPartition,
+) -> ! {
+    let mut wifi = match start_or_provision(ssid, passwd, use_dpp, modem, nvs_partition) {
+        Ok(wifi) => wifi,
+        Err(err) => {
+            log::error!("Start WiFi: {}", err);
+            status.store(DeviceStatus::WifiError as u8, Relaxed);
+            loop {
+                thread::sleep(Duration::from_secs(3600));
+            }
+        }
+    };
+    let mut backoff = Backoff::new(RETRY_BACKOFF, unsafe { esp_random() });
+    let mut failing_since = None;
+    let mut was_connected = false;
+    let watchdog = watchdog::register("wifi_sup", WATCHDOG_TIMEOUT);
+    loop {
+        watchdog.feed();
+        if wifi.is_connected().unwrap_or(false) {
+            thread::sleep(WIFI_CHECK_INTERVAL);
+            continue;
+        }
+        if std::mem::take(&mut was_connected) {
+            bus::post(BusEvent::ConnectivityChanged {
+                link: Link::Wifi,
+                up: false,
+            });
+        }
+        match connect(&mut wifi) {
+            Ok(()) => {
+                was_connected = true;
+                bus::post(BusEvent::ConnectivityChanged {
+                    link: Link::Wifi,
+                    up: true,
+                });
+                retry.clear();
+                backoff.reset();
+                failing_since = None;
+                let _ = status.compare_exchange(
+                    DeviceStatus::WifiError as u8,
+                    DeviceStatus::Ok as u8,
+                    Relaxed,
+                    Relaxed,
+                );
+            }
+            Err(err) => {
+                let delay = backoff.next_delay();
+                log::error!("Connect to WiFi: {}, retrying in {:?}", err, delay);
+                status.store(DeviceStatus::WifiError as u8, Relaxed);
+                let since = *failing_since.get_or_insert_with(Instant::now);
+                if !diagnostics_ap_password.is_empty() && since.elapsed() >= DIAGNOSTICS_AP_AFTER {
+                    retry.clear();
+                    if let Err(err) =
+                        run_diagnostics_ap(&mut wifi, diagnostics_ap_password, &watchdog)
+                    {
+                        log::error!("Diagnostics access point: {}", err);
+                    }
+                    failing_since = None;
+                    backoff.reset();
+                    continue;
+                }
+                retry.schedule(delay);
+                thread::sleep(delay);
+            }
+        }
+    }
+}
+
+// Keeps the client configuration and adds an access point serving the dashboard, read-only, then
+// goes back to client mode. Reconnection attempts stop meanwhile, their channel scans would keep
+// dropping whoever joined.
+fn run_diagnostics_ap(
+    wifi: &mut BlockingWifi<EspWifi<'static>>,
+    password: &str,
+    watchdog: &watchdog::Watchdog,
+) -> Result<()> {
+    let wifi::Configuration::Client(client) = wifi.get_configuration()? else {
+        bail!("Not in client mode");
+    };
+    let sensor_id = get_sensor_id();
+    let ap_ssid = format!("bzzz-{}-diag", &sensor_id[6..12]);
+    wifi.set_configuration(&wifi::Configuration::Mixed(
+        client.clone(),
+        AccessPointConfiguration {
+            ssid: ap_ssid
+                .as_str()
+                .try_into()
+                .map_err(|_| anyhow::Error::msg("Failed to use AP SSID"))?,
+            password: password
+                .try_into()
+                .map_err(|_| anyhow::Error::msg("Failed to use AP password"))?,
+            auth_method: AuthMethod::WPA2Personal,
+            ..Default::default()
+        },
+    ))?;
+    dashboard::set_read_only(true);
+    let ip = wifi.wifi().ap_netif().get_ip_info().map(|info| info.ip);
+    log::warn!(
+        "No WiFi for {:?}, diagnostics on WiFi {:?} at http://{}/ for {:?}",
+        DIAGNOSTICS_AP_AFTER,
+        ap_ssid,
+        ip.map_or_else(|_| String::from("?"), |ip| ip.to_string()),
+        DIAGNOSTICS_AP_DURATION
+    );
+    let started = Instant::now();
+    while started.elapsed() < DIAGNOSTICS_AP_DURATION {
+        watchdog.feed();
+        thread::sleep(WIFI_CHECK_INTERVAL);
+    }
+    dashboard::set_read_only(false);
+    log::info!(
+        "Diagnostics access point off, back to WiFi {:?}",
+        client.ssid
+    );
+    wifi.set_configuration(&wifi::Configuration::Client(client))?;
+    Ok(())
+}
+
+// Credentials stored through the provisioning portal take precedence over the configured ones.
+// Without either, the device stays in provisioning mode until it gets some and restarts.
+fn start_or_provision(
+    ssid: &str,
+    passwd: &str,
+    use_dpp: bool,
+    modem: impl Peripheral<P = modem::Modem> + 'static,
+    nvs_partition: EspDefaultNvsPartition,
+) -> Result<BlockingWifi<EspWifi<'static>>> {
+    let nvs = EspNvs::new(nvs_partition.clone(), NVS_NAMESPACE, true)
+        .context("Unable to open NVS namespace")?;
+    match provisioning::stored_credentials(&nvs) {
+        Some((ssid, passwd)) => start_wifi(&ssid, &passwd, modem, nvs_partition),
+        None if ssid.is_empty() => {
+            if use_dpp {
+                provisioning::provision_with_dpp(&get_sensor_id(), modem, nvs_partition, nvs)?;
+            } else {
+                provisioning::provision(&get_sensor_id(), modem, nvs_partition, nvs)?;
+            }
+            unsafe { esp_restart() }
+        }
+        None => start_wifi(ssid, passwd, modem, nvs_partition),
+    }
+}
+
+fn start_wifi(
+    ssid: &str,
+    passwd: &str,
+    modem: impl Peripheral<P = modem::Modem> + 'static,
+    nvs: EspDefaultNvsPartition,
+) -> Result<BlockingWifi<EspWifi<'static>>> {
+    if ssid.is_empty() {
+        bail!("No SSID defined");
+    }
+    let auth_method = if passwd.is_empty() {
+        AuthMethod::None
+    } else {
+        AuthMethod::WPA2Personal
+    };
+    let sys_loop = EspSystemEventLoop::take().context("Unable to access system event loop.")?;
+    let esp_wifi = EspWifi::new(modem, sys_loop.clone(), Some(nvs))?;
+    let mut wifi = BlockingWifi::wrap(esp_wifi, sys_loop)?;
+    wifi.set_configuration(&wifi::Configuration::Client(wifi::ClientConfiguration {
+        ssid: ssid
+            .try_into()
+            .map_err(|_| anyhow::Error::msg("Failed to use SSID"))?,
+        password: passwd
+            .try_into()
+            .map_err(|_| anyhow::Error::msg("Failed to use password"))?,
+        auth_method,
+        ..Default::default()
+    }))?;
+    wifi.start()?;
+    Ok(wifi)
+}
+
+fn connect(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Result<()> {
+    wifi.connect()?;
+    wifi.wait_netif_up()?;
+
+    let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
+    log::info!("DHCP info: {:?}", ip_info);
+    *STA_IP.lock().unwrap() = Some(ip_info.ip);
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::collections::VecDeque;
+
+use crate::reporting;
+
+struct Queued {
+    // When the reading was taken, if the clock was set by then
+    ts: Option<u64>,
+    // How far off `ts` may be when it came from the broker's time topic
+    ts_accuracy_s: Option<u64>,
+    // As it would have been published: a number, a JSON document or a batch array
+    reading: String,
+}
+
+// Level readings that couldn't be published, oldest first, replayed once the broker is back.
+// When full the oldest reading makes room, the latest ones being what people look at first.
+pub struct OfflineQueue {
+    capacity: usize,
+    readings: VecDeque<Queued>,
+}
+
+impl OfflineQueue {
+    pub fn new(capacity: usize) -> Self {
+        OfflineQueue {
+            capacity,
+            readings: VecDeque::new(),
+        }
+    }
+
+    pub fn configure(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.readings.len() > capacity {
+            self.readings.pop_front();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.readings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.readings.is_empty()
+    }
+
+    // False when the reading or an older one was dropped to respect the capacity
+    pub fn push(&mut self, reading: String, ts: Option<u64>, ts_accuracy_s: Option<u64>) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let evicted = self.readings.len() == self.capacity;
+        if evicted {
+            self.readings.pop_front();
+        }
+        self.readings.push_back(Queued {
+            ts,
+            ts_accuracy_s: ts.and(ts_accuracy_s),
+            reading,
+        });
+        !evicted
+    }
+
+    // The oldest reading wrapped with its timestamp, so backends file it under the right time,
+    // for the replay topic. It stays queued until `pop` says it was published.
+    pub fn peek_replay(&self) -> Option<String> {
+        let queued = self.readings.front()?;
+        let ts = queued
+            .ts
+            .map_or_else(|| String::from("null"), |ts| ts.to_string());
+        let mut time_source = reporting::broker_time_fields(queued.ts_accuracy_s);
+        if !time_source.is_empty() {
+            time_source.insert(0, ',');
+        }
+        Some(format!(
+            "{{\"ts\":{}{},\"queued\":true,\"reading\":{}}}",
+            ts, time_source, queued.reading
+        ))
+    }
+
+    pub fn pop(&mut self) {
+        self.readings.pop_front();
+    }
+
+    // The newest `max_len` readings as `<ts>,<reading>` lines, for keeping them across a reboot.
+    // A broker time is `<ts>:<accuracy_s>`, which blobs from before still parse without.
+    pub fn to_blob(&self, max_len: usize) -> String {
+        let skip = self.readings.len().saturating_sub(max_len);
+        self.readings
+            .iter()
+            .skip(skip)
+            .map(|queued| {
+                let ts = match (queued.ts, queued.ts_accuracy_s) {
+                    (Some(ts), Some(accuracy_s)) => format!("{}:{}", ts, accuracy_s),
+                    (Some(ts), None) => ts.to_string(),
+                    (None, _) => String::new(),
+                };
+                format!("{},{}\n", ts, queued.reading)
+            })
+            .collect()
+    }
+
+    // Readings from a blob go before anything queued since boot
+    pub fn restore(&mut self, blob: &str) {
+        let restored: Vec<Queued> = blob
+            .lines()
+            .filter_map(|line| {
+                let (ts, reading) = line.split_once(',')?;
+                let (ts, accuracy_s) = ts.split_once(':').unwrap_or((ts, ""));
+                (!reading.is_empty()).then(|| Queued {
+                    ts: ts.parse().ok(),
+                    ts_accuracy_s: accuracy_s.parse().ok(),
+                    reading: reading.to_string(),
+                })
+            })
+            .collect();
+        for queued in restored.into_iter().rev() {
+            self.readings.push_front(queued);
+        }
+        self.configure(self.capacity);
+    }
+}
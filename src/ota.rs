@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+use anyhow::{bail, Context, Result};
+use esp_idf_svc::{
+    io::{Read, Write},
+    ota::{EspOta, SlotState},
+};
+
+use crate::wear;
+
+// Flash writes happen in pages, anything larger only costs stack of the HTTP server task
+const CHUNK_LEN: usize = 1024;
+
+static IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+// Whether an image is being written, so nothing restarts the device halfway through
+pub fn in_progress() -> bool {
+    IN_PROGRESS.load(Relaxed)
+}
+
+// Writes the image to the passive slot and boots from it next time. The image header, checksum
+// and hash, plus the signature with secure boot, are verified once the whole image is written.
+// Returns the size of the image.
+pub fn install(image: &mut impl Read) -> Result<usize> {
+    if IN_PROGRESS.swap(true, Relaxed) {
+        bail!("Another firmware update is in progress");
+    }
+    let result = write_image(image);
+    IN_PROGRESS.store(false, Relaxed);
+    result
+}
+
+fn write_image(image: &mut impl Read) -> Result<usize> {
+    let mut ota = EspOta::new().context("Unable to access OTA slots")?;
+    let mut update = ota
+        .initiate_update()
+        .context("Unable to start firmware update")?;
+    let mut chunk = [0u8; CHUNK_LEN];
+    let mut len = 0;
+    loop {
+        let read = match image.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) => {
+                let _ = update.abort();
+                bail!("Unable to receive firmware image: {:?}", err);
+            }
+        };
+        if let Err(err) = update.write_all(&chunk[..read]) {
+            let _ = update.abort();
+            bail!("Unable to write firmware image: {}", err);
+        }
+        len += read;
+    }
+    update.complete().context("Invalid firmware image")?;
+    wear::ota_written();
+    log::info!(
+        "Installed {} byte firmware image, active after restart",
+        len
+    );
+    Ok(len)
+}
+
+// A new image boots on probation: unless it confirms itself before the next reset, the
+// bootloader goes back to the previous one. Call once the firmware has proven it can be updated
+// again, i.e. it reaches the network.
+pub fn confirm_running() {
+    match EspOta::new().and_then(|mut ota| ota.mark_running_slot_valid()) {
+        Ok(()) => log::info!("Running firmware confirmed"),
+        Err(err) => log::error!("Unable to confirm running firmware: {}", err),
+    }
+}
+
+// What runs from which slot, and where the next boot goes, for `<topic>/fw`. The build hash is the
+// ELF SHA-256 esp-idf puts into the image, so two builds of the same version differ.
+pub fn inventory_json() -> Result<String> {
+    let ota = EspOta::new().context("Unable to access OTA slots")?;
+    let running = ota
+        .get_running_slot()
+        .context("Unable to read running slot")?;
+    let boot = ota.get_boot_slot().context("Unable to read boot slot")?;
+    let build = running
+        .firmware
+        .as_ref()
+        .and_then(|firmware| firmware.signature.as_deref())
+        .map_or_else(
+            || String::from("null"),
+            |hash| {
+                let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("\"{}\"", hex)
+            },
+        );
+    let state = match running.state {
+        SlotState::Factory => "factory",
+        SlotState::Valid => "valid",
+        SlotState::Invalid => "invalid",
+        SlotState::Unverified => "unverified",
+        SlotState::Unknown => "unknown",
+    };
+    Ok(format!(
+        "{{\"version\":\"{}\",\"build\":{},\"slot\":\"{}\",\"state\":\"{}\",\"next_boot\":\"{}\"}}",
+        env!("CARGO_PKG_VERSION"),
+        build,
+        running.label.as_str(),
+        state,
+        boot.label.as_str()
+    ))
+}
@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+pub struct OutageSummary {
+    pub duration: Duration,
+    pub attempts: u32,
+    pub buffered: usize,
+    pub dropped: u32,
+}
+
+impl OutageSummary {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"duration_s\":{},\"attempts\":{},\"buffered\":{},\"dropped\":{}}}",
+            self.duration.as_secs(),
+            self.attempts,
+            self.buffered,
+            self.dropped
+        )
+    }
+}
+
+// Counts what happened between losing the broker and getting it back
+#[derive(Default)]
+pub struct OutageTracker {
+    started: Option<Instant>,
+    attempts: u32,
+    dropped: u32,
+}
+
+impl OutageTracker {
+    pub fn disconnected(&mut self) {
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+            self.attempts = 0;
+            self.dropped = 0;
+        }
+    }
+
+    pub fn attempt(&mut self) {
+        if self.started.is_some() {
+            self.attempts += 1;
+        }
+    }
+
+    pub fn dropped(&mut self) {
+        if self.started.is_some() {
+            self.dropped += 1;
+        }
+    }
+
+    pub fn recovered(&mut self, buffered: usize) -> Option<OutageSummary> {
+        let started = self.started.take()?;
+        Some(OutageSummary {
+            duration: started.elapsed(),
+            attempts: self.attempts,
+            buffered,
+            dropped: self.dropped,
+        })
+    }
+}
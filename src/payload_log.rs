@@ -0,0 +1,111 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering::Relaxed},
+        Mutex,
+    },
+    time::Instant,
+};
+
+// Log lines are formatted on the stack of whatever task logs them, some of which only have a
+// few KiB, so payloads go out in small slices
+const CHUNK_LEN: usize = 128;
+// Serial output at 115200 baud is about 11 KiB/s, stay well below it
+const BURST_BYTES: u32 = 4096;
+const BYTES_PER_S: u32 = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Module {
+    // Payloads received from the broker
+    Mqtt = 0,
+    Alerts = 1,
+    Diagnostics = 2,
+    Boot = 3,
+}
+
+impl Module {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mqtt" => Some(Module::Mqtt),
+            "alerts" => Some(Module::Alerts),
+            "diagnostics" => Some(Module::Diagnostics),
+            "boot" => Some(Module::Boot),
+            _ => None,
+        }
+    }
+}
+
+// Everything off after boot, dumps are for a debugging session
+static ENABLED: AtomicU32 = AtomicU32::new(0);
+
+struct Budget {
+    bytes: u32,
+    refilled: Option<Instant>,
+    suppressed: u32,
+}
+
+static BUDGET: Mutex<Budget> = Mutex::new(Budget {
+    bytes: BURST_BYTES,
+    refilled: None,
+    suppressed: 0,
+});
+
+pub fn set_enabled(module: Module, enabled: bool) {
+    if enabled {
+        ENABLED.fetch_or(1 << module as u32, Relaxed);
+    } else {
+        ENABLED.fetch_and(!(1 << module as u32), Relaxed);
+    }
+    log::info!(
+        "Payload dumps for {:?} {}",
+        module,
+        if enabled { "enabled" } else { "disabled" }
+    );
+}
+
+// Logs the payload in chunks if dumps are enabled for the module. Payloads over the remaining
+// byte budget are dropped whole rather than cut, and counted instead. A full budget lets any
+// payload through, so even the largest ones can be seen once in a while.
+pub fn dump(module: Module, label: &str, payload: &[u8]) {
+    if ENABLED.load(Relaxed) & (1 << module as u32) == 0 {
+        return;
+    }
+    let suppressed = {
+        let mut budget = BUDGET.lock().unwrap();
+        let now = Instant::now();
+        if let Some(refilled) = budget.refilled {
+            let earned = (refilled.elapsed().as_millis() as u64 * BYTES_PER_S as u64 / 1000)
+                .min(BURST_BYTES as u64) as u32;
+            if earned > 0 {
+                budget.bytes = (budget.bytes + earned).min(BURST_BYTES);
+                budget.refilled = Some(now);
+            }
+        } else {
+            budget.refilled = Some(now);
+        }
+        let len = payload.len().min(u32::MAX as usize) as u32;
+        if len > budget.bytes && budget.bytes < BURST_BYTES {
+            budget.suppressed = budget.suppressed.saturating_add(1);
+            return;
+        }
+        budget.bytes = budget.bytes.saturating_sub(len);
+        std::mem::take(&mut budget.suppressed)
+    };
+    if suppressed > 0 {
+        log::warn!("{} payload dumps suppressed by rate limit", suppressed);
+    }
+    if payload.is_empty() {
+        log::info!("{}: empty", label);
+        return;
+    }
+    let chunks = payload.chunks(CHUNK_LEN);
+    let count = chunks.len();
+    for (index, chunk) in chunks.enumerate() {
+        log::info!(
+            "{} [{}/{}]: {}",
+            label,
+            index + 1,
+            count,
+            String::from_utf8_lossy(chunk)
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    ffi::CStr,
+    time::{Duration, Instant},
+};
+
+use esp_idf_svc::sys::{
+    heap_caps_get_free_size, heap_caps_get_per_task_info, heap_task_info_params_t,
+    heap_task_totals_t, uxTaskGetNumberOfTasks, uxTaskGetSystemState, TaskHandle_t, TaskStatus_t,
+    MALLOC_CAP_8BIT,
+};
+
+const WINDOW: Duration = Duration::from_secs(5);
+// Room for tasks created while the window is open
+const EXTRA_TASKS: usize = 4;
+
+struct TaskSample {
+    name: String,
+    handle: TaskHandle_t,
+    run_time: u32,
+    stack_free: u32,
+}
+
+struct Snapshot {
+    tasks: Vec<TaskSample>,
+    total_run_time: u32,
+}
+
+impl Snapshot {
+    // Needs CONFIG_FREERTOS_USE_TRACE_FACILITY and CONFIG_FREERTOS_GENERATE_RUN_TIME_STATS
+    fn take() -> Self {
+        let capacity = unsafe { uxTaskGetNumberOfTasks() } as usize + EXTRA_TASKS;
+        let mut statuses: Vec<TaskStatus_t> = Vec::with_capacity(capacity);
+        let mut total_run_time = 0;
+        unsafe {
+            let count =
+                uxTaskGetSystemState(statuses.as_mut_ptr(), capacity as _, &mut total_run_time);
+            statuses.set_len(count as usize);
+        }
+        let tasks = statuses
+            .iter()
+            .map(|status| TaskSample {
+                name: unsafe { CStr::from_ptr(status.pcTaskName) }
+                    .to_string_lossy()
+                    .into_owned(),
+                handle: status.xHandle,
+                run_time: status.ulRunTimeCounter,
+                stack_free: status.usStackHighWaterMark,
+            })
+            .collect();
+        Snapshot {
+            tasks,
+            total_run_time,
+        }
+    }
+}
+
+// Maps the tasks we know about to the part of the firmware that owns them, everything else
+// counts as `system`
+fn subsystem(task_name: &str) -> &'static str {
+    match task_name {
+        "mqtt_task" => "mqtt_tls",
+        "led" => "ws2812",
+        "sensor" => "dsp",
+        "wifi_sup" | "wifi" | "tiT" | "sys_evt" => "network",
+        name if name.starts_with("IDLE") => "idle",
+        _ => "system",
+    }
+}
+
+// Heap currently held by each task. Needs CONFIG_HEAP_TASK_TRACKING.
+fn heap_per_task(max_tasks: usize) -> Vec<(TaskHandle_t, usize)> {
+    let mut totals: Vec<heap_task_totals_t> = Vec::with_capacity(max_tasks);
+    let mut num_totals = 0usize;
+    unsafe {
+        let mut params: heap_task_info_params_t = std::mem::zeroed();
+        params.caps[0] = MALLOC_CAP_8BIT;
+        params.mask[0] = MALLOC_CAP_8BIT;
+        params.totals = totals.as_mut_ptr();
+        params.num_totals = &mut num_totals;
+        params.max_totals = max_tasks;
+        heap_caps_get_per_task_info(&mut params);
+        totals.set_len(num_totals.min(max_tasks));
+    }
+    totals
+        .iter()
+        .map(|totals| (totals.task, totals.size[0]))
+        .collect()
+}
+
+// CPU usage is measured between `start` and the end of the window, heap at the end of it
+#[derive(Default)]
+pub struct Profiler {
+    started: Option<(Instant, Snapshot)>,
+}
+
+impl Profiler {
+    pub fn start(&mut self) {
+        if self.started.is_none() {
+            log::info!("Profiling for {:?}", WINDOW);
+            self.started = Some((Instant::now(), Snapshot::take()));
+        }
+    }
+
+    // The report once the window is over
+    pub fn poll(&mut self) -> Option<String> {
+        if self.started.as_ref()?.0.elapsed() < WINDOW {
+            return None;
+        }
+        let (_, start) = self.started.take()?;
+        let end = Snapshot::take();
+        let heap = heap_per_task(end.tasks.len() + EXTRA_TASKS);
+        let elapsed = end.total_run_time.wrapping_sub(start.total_run_time).max(1) as f32;
+
+        let mut tasks = Vec::with_capacity(end.tasks.len());
+        let mut subsystems: Vec<(&'static str, f32, usize)> = vec![];
+        for task in end.tasks.iter() {
+            let previous = start
+                .tasks
+                .iter()
+                .find(|previous| previous.handle == task.handle)
+                .map_or(0, |previous| previous.run_time);
+            let cpu = 100.0 * task.run_time.wrapping_sub(previous) as f32 / elapsed;
+            let task_heap = heap
+                .iter()
+                .find(|(handle, _)| *handle == task.handle)
+                .map_or(0, |(_, size)| *size);
+            let subsystem = subsystem(&task.name);
+            tasks.push(format!(
+                "{{\"name\":\"{}\",\"subsystem\":\"{}\",\"cpu_pct\":{:.1},\"heap\":{},\"stack_free\":{}}}",
+                task.name, subsystem, cpu, task_heap, task.stack_free
+            ));
+            match subsystems
+                .iter_mut()
+                .find(|(name, _, _)| *name == subsystem)
+            {
+                Some((_, total_cpu, total_heap)) => {
+                    *total_cpu += cpu;
+                    *total_heap += task_heap;
+                }
+                None => subsystems.push((subsystem, cpu, task_heap)),
+            }
+        }
+        let subsystems: Vec<String> = subsystems
+            .iter()
+            .map(|(name, cpu, heap)| {
+                format!("\"{}\":{{\"cpu_pct\":{:.1},\"heap\":{}}}", name, cpu, heap)
+            })
+            .collect();
+        Some(format!(
+            "{{\"window_s\":{},\"free_heap\":{},\"subsystems\":{{{}}},\"tasks\":[{}]}}",
+            WINDOW.as_secs(),
+            unsafe { heap_caps_get_free_size(MALLOC_CAP_8BIT) },
+            subsystems.join(","),
+            tasks.join(",")
+        ))
+    }
+}
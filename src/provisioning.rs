@@ -0,0 +1,240 @@
+mod dpp;
+
+use std::{sync::mpsc, thread, time::Duration};
+
+use anyhow::{Context, Result};
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    hal::{modem, peripheral::Peripheral},
+    http::{
+        server::{Configuration as HttpConfiguration, EspHttpServer},
+        Method,
+    },
+    io::Write,
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::esp_random,
+    wifi::{self, AccessPointConfiguration, AuthMethod, BlockingWifi, EspWifi},
+};
+use qrcodegen::{QrCode, QrCodeEcc};
+
+use crate::{
+    factory::{NVS_WIFI_PASSWORD_KEY, NVS_WIFI_SSID_KEY},
+    ota, security, wear,
+};
+
+pub use dpp::provision_with_dpp;
+
+const AP_PASSWORD_LEN: usize = 12;
+// No 0/O or 1/l/I, installers may have to type it
+const AP_PASSWORD_CHARS: &[u8] = b"abcdefghijkmnpqrstuvwxyzACDEFGHJKLMNPQRSTUVWXYZ23456789";
+// Gives the browser time to get the response before the AP goes away
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+const PORTAL_FORM: &str = "<!DOCTYPE html><html><head><title>Mosquitto bzzz</title>\
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"></head><body>\
+<h1>Mosquitto bzzz</h1><form method=\"post\" action=\"/wifi\">\
+<p><label>WiFi SSID <input name=\"ssid\" maxlength=\"32\" required></label></p>\
+<p><label>Password <input name=\"password\" type=\"password\" maxlength=\"64\"></label></p>\
+<p><input type=\"submit\" value=\"Save\"></p></form>\
+<h2>Firmware</h2><p><input type=\"file\" id=\"image\" accept=\".bin\"> \
+<button onclick=\"upload()\">Install</button></p><p id=\"result\"></p><script>\
+function upload() { const image = document.getElementById(\"image\").files[0]; if (!image) return; \
+const result = document.getElementById(\"result\"); result.textContent = \"Installing...\"; \
+fetch(\"/firmware\", { method: \"POST\", body: image }).then(response => response.text()) \
+.then(text => result.textContent = text, () => result.textContent = \"Upload failed\"); }\
+</script></body></html>";
+
+enum PortalResult {
+    Credentials(String, String),
+    FirmwareInstalled,
+}
+
+pub fn stored_credentials(nvs: &EspNvs<NvsDefault>) -> Option<(String, String)> {
+    let mut ssid = [0u8; 33];
+    let mut password = [0u8; 65];
+    let ssid = nvs.get_str(NVS_WIFI_SSID_KEY, &mut ssid).ok()??;
+    let password = nvs
+        .get_str(NVS_WIFI_PASSWORD_KEY, &mut password)
+        .ok()?
+        .unwrap_or("");
+    Some((ssid.to_string(), password.to_string()))
+}
+
+// Brings up a SoftAP with a random password, shows how to join it as a QR code and serves a form
+// to enter the WiFi credentials, as well as a firmware upload for sites without an update server.
+// Returns once the credentials are stored or a firmware installed; the caller restarts the device.
+pub fn provision(
+    sensor_id: &str,
+    modem: impl Peripheral<P = modem::Modem> + 'static,
+    nvs_partition: EspDefaultNvsPartition,
+    mut nvs: EspNvs<NvsDefault>,
+) -> Result<()> {
+    security::check_secret_storage("WiFi credentials")?;
+    let ap_ssid = format!("bzzz-{}", &sensor_id[6..12]);
+    let ap_password = random_password();
+
+    let sys_loop = EspSystemEventLoop::take().context("Unable to access system event loop.")?;
+    let esp_wifi = EspWifi::new(modem, sys_loop.clone(), Some(nvs_partition))?;
+    let mut wifi = BlockingWifi::wrap(esp_wifi, sys_loop)?;
+    wifi.set_configuration(&wifi::Configuration::AccessPoint(
+        AccessPointConfiguration {
+            ssid: ap_ssid
+                .as_str()
+                .try_into()
+                .map_err(|_| anyhow::Error::msg("Failed to use AP SSID"))?,
+            password: ap_password
+                .as_str()
+                .try_into()
+                .map_err(|_| anyhow::Error::msg("Failed to use AP password"))?,
+            auth_method: AuthMethod::WPA2Personal,
+            ..Default::default()
+        },
+    ))?;
+    wifi.start()?;
+    wifi.wait_netif_up()?;
+    let portal_ip = wifi.wifi().ap_netif().get_ip_info()?.ip;
+
+    let (portal_tx, portal_rx) = mpsc::channel();
+    let mut server = EspHttpServer::new(&HttpConfiguration::default())?;
+    server.fn_handler("/", Method::Get, |req| {
+        req.into_ok_response()?.write_all(PORTAL_FORM.as_bytes())
+    })?;
+    let credentials_tx = portal_tx.clone();
+    server.fn_handler("/wifi", Method::Post, move |mut req| {
+        let mut body = [0u8; 256];
+        let mut len = 0;
+        while len < body.len() {
+            match req.read(&mut body[len..])? {
+                0 => break,
+                read => len += read,
+            }
+        }
+        match parse_form(&body[..len]) {
+            Some((ssid, password)) => {
+                let _ = credentials_tx.send(PortalResult::Credentials(ssid, password));
+                req.into_ok_response()?
+                    .write_all(b"Saved, the sensor restarts now.")
+            }
+            None => req
+                .into_status_response(400)?
+                .write_all(b"A valid SSID and a password of 8 to 64 characters are required."),
+        }
+    })?;
+    // The raw image as body, the page sends the file as is
+    server.fn_handler(
+        "/firmware",
+        Method::Post,
+        move |mut req| match ota::install(&mut req) {
+            Ok(_) => {
+                let _ = portal_tx.send(PortalResult::FirmwareInstalled);
+                req.into_ok_response()?
+                    .write_all(b"Installed, the sensor restarts now.")
+            }
+            Err(err) => {
+                log::error!("Firmware upload failed: {:#}", err);
+                req.into_status_response(400)?
+                    .write_all(format!("{:#}", err).as_bytes())
+            }
+        },
+    )?;
+    // Up to here is enough to get another firmware on the device
+    ota::confirm_running();
+
+    log::info!(
+        "Provisioning mode: join WiFi {:?} (password {:?}) and open http://{}/",
+        ap_ssid,
+        ap_password,
+        portal_ip
+    );
+    // The optional OLED has no driver yet, so the QR code only goes to the serial console
+    print_qr_code(&format!("WIFI:S:{};T:WPA;P:{};;", ap_ssid, ap_password));
+
+    let (ssid, password) = match portal_rx.recv().context("Provisioning portal stopped")? {
+        PortalResult::Credentials(ssid, password) => (ssid, password),
+        PortalResult::FirmwareInstalled => {
+            thread::sleep(RESTART_DELAY);
+            return Ok(());
+        }
+    };
+    store_credentials(&mut nvs, &ssid, &password)?;
+    thread::sleep(RESTART_DELAY);
+    Ok(())
+}
+
+fn store_credentials(nvs: &mut EspNvs<NvsDefault>, ssid: &str, password: &str) -> Result<()> {
+    nvs.set_str(NVS_WIFI_SSID_KEY, ssid)
+        .context("Unable to store WiFi SSID")?;
+    nvs.set_str(NVS_WIFI_PASSWORD_KEY, password)
+        .context("Unable to store WiFi password")?;
+    wear::nvs_written(ssid.len());
+    wear::nvs_written(password.len());
+    log::info!("Stored credentials for WiFi {:?}", ssid);
+    Ok(())
+}
+
+fn random_password() -> String {
+    (0..AP_PASSWORD_LEN)
+        .map(|_| {
+            let index = unsafe { esp_random() } as usize % AP_PASSWORD_CHARS.len();
+            AP_PASSWORD_CHARS[index] as char
+        })
+        .collect()
+}
+
+// Light modules are drawn as blocks, so the code reads correctly on the usual dark serial
+// terminal. Each text line holds two rows of modules.
+fn print_qr_code(payload: &str) {
+    const QUIET_ZONE: i32 = 2;
+    let qr = match QrCode::encode_text(payload, QrCodeEcc::Low) {
+        Ok(qr) => qr,
+        Err(err) => {
+            log::error!("Unable to encode QR code: {}", err);
+            return;
+        }
+    };
+    let light = |x: i32, y: i32| !qr.get_module(x, y);
+    for y in (-QUIET_ZONE..qr.size() + QUIET_ZONE).step_by(2) {
+        let line: String = (-QUIET_ZONE..qr.size() + QUIET_ZONE)
+            .map(|x| match (light(x, y), light(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            })
+            .collect();
+        println!("{}", line);
+    }
+}
+
+fn parse_form(body: &[u8]) -> Option<(String, String)> {
+    let body = std::str::from_utf8(body).ok()?;
+    let mut ssid = None;
+    let mut password = String::new();
+    for field in body.split('&') {
+        match field.split_once('=') {
+            Some(("ssid", value)) => ssid = Some(url_decode(value)?),
+            Some(("password", value)) => password = url_decode(value)?,
+            _ => {}
+        }
+    }
+    let ssid = ssid.filter(|ssid| !ssid.is_empty() && ssid.len() <= 32)?;
+    if !password.is_empty() && !(8..=64).contains(&password.len()) {
+        return None;
+    }
+    Some((ssid, password))
+}
+
+fn url_decode(value: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            _ => decoded.push(byte),
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
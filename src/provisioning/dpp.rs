@@ -0,0 +1,128 @@
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    ptr,
+    sync::{mpsc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    hal::{modem, peripheral::Peripheral},
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::{esp, esp_err_t, wifi_config_t},
+    wifi::{self, BlockingWifi, EspWifi},
+};
+
+use super::{print_qr_code, store_credentials};
+use crate::security;
+
+// Where the configurator finds the device, the usual non-overlapping 2.4 GHz channels
+const LISTEN_CHANNELS: &[u8] = b"1,6,11\0";
+
+// From esp_dpp.h, needs CONFIG_ESP_WIFI_DPP_SUPPORT
+const ESP_SUPP_DPP_URI_READY: c_int = 0;
+const ESP_SUPP_DPP_CFG_RECVD: c_int = 1;
+const ESP_SUPP_DPP_FAIL: c_int = 2;
+const DPP_BOOTSTRAP_QR_CODE: c_int = 0;
+
+extern "C" {
+    fn esp_supp_dpp_init(evt_cb: unsafe extern "C" fn(c_int, *mut c_void)) -> esp_err_t;
+    fn esp_supp_dpp_deinit();
+    fn esp_supp_dpp_bootstrap_gen(
+        chan_list: *const c_char,
+        bootstrap_type: c_int,
+        key: *const c_char,
+        info: *const c_char,
+    ) -> esp_err_t;
+    fn esp_supp_dpp_start_listen() -> esp_err_t;
+}
+
+enum DppEvent {
+    Uri(String),
+    Credentials(String, String),
+    Failed(isize),
+}
+
+// The supplicant calls back from its own task
+static EVENTS: Mutex<Option<mpsc::Sender<DppEvent>>> = Mutex::new(None);
+
+unsafe extern "C" fn on_dpp_event(event: c_int, data: *mut c_void) {
+    let event = match event {
+        ESP_SUPP_DPP_URI_READY if !data.is_null() => DppEvent::Uri(
+            CStr::from_ptr(data as *const c_char)
+                .to_string_lossy()
+                .into_owned(),
+        ),
+        ESP_SUPP_DPP_CFG_RECVD if !data.is_null() => {
+            let sta = (*(data as *const wifi_config_t)).sta;
+            DppEvent::Credentials(until_nul(&sta.ssid), until_nul(&sta.password))
+        }
+        // The reason comes as the pointer itself
+        ESP_SUPP_DPP_FAIL => DppEvent::Failed(data as isize),
+        _ => return,
+    };
+    if let Some(events) = EVENTS.lock().unwrap().as_ref() {
+        let _ = events.send(event);
+    }
+}
+
+fn until_nul(bytes: &[u8]) -> String {
+    let len = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+// Wi-Fi Easy Connect: shows the bootstrapping QR code of the device and waits for a configurator,
+// usually a phone, to push the credentials after scanning it. Returns once they are stored; the
+// caller restarts the device.
+pub fn provision_with_dpp(
+    sensor_id: &str,
+    modem: impl Peripheral<P = modem::Modem> + 'static,
+    nvs_partition: EspDefaultNvsPartition,
+    mut nvs: EspNvs<NvsDefault>,
+) -> Result<()> {
+    security::check_secret_storage("WiFi credentials")?;
+    let sys_loop = EspSystemEventLoop::take().context("Unable to access system event loop.")?;
+    let esp_wifi = EspWifi::new(modem, sys_loop.clone(), Some(nvs_partition))?;
+    let mut wifi = BlockingWifi::wrap(esp_wifi, sys_loop)?;
+    // DPP runs on the station interface, before it knows any network
+    wifi.set_configuration(&wifi::Configuration::Client(Default::default()))?;
+    wifi.start()?;
+
+    let (events_tx, events_rx) = mpsc::channel();
+    *EVENTS.lock().unwrap() = Some(events_tx);
+    let info = CString::new(sensor_id)?;
+    unsafe {
+        esp!(esp_supp_dpp_init(on_dpp_event)).context("Unable to start DPP")?;
+        esp!(esp_supp_dpp_bootstrap_gen(
+            LISTEN_CHANNELS.as_ptr() as *const c_char,
+            DPP_BOOTSTRAP_QR_CODE,
+            ptr::null(),
+            info.as_ptr(),
+        ))
+        .context("Unable to generate DPP bootstrap key")?;
+    }
+    let result = loop {
+        match events_rx.recv().context("DPP stopped") {
+            Ok(DppEvent::Uri(uri)) => {
+                log::info!(
+                    "Provisioning mode: scan this DPP code with a configurator: {}",
+                    uri
+                );
+                print_qr_code(&uri);
+            }
+            Ok(DppEvent::Credentials(ssid, password)) => break Ok((ssid, password)),
+            Ok(DppEvent::Failed(reason)) => log::warn!("DPP failed ({}), listening again", reason),
+            Err(err) => break Err(err),
+        }
+        if let Err(err) = unsafe { esp!(esp_supp_dpp_start_listen()) } {
+            break Err(err).context("Unable to listen for DPP");
+        }
+    };
+    unsafe { esp_supp_dpp_deinit() };
+    *EVENTS.lock().unwrap() = None;
+    let (ssid, password) = result?;
+    store_credentials(&mut nvs, &ssid, &password)
+}
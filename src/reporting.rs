@@ -0,0 +1,343 @@
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::{
+    bands::OctaveBands,
+    dsp::{self, Decibel, RawAdc},
+    schedule::Schedule,
+    weighting::Weighting,
+};
+
+// A few seconds of readings for the Leq of a logger interval
+const LEQ_BLOCK_LEN: usize = 64;
+
+// Decides which level readings go out. With a delta, readings within it of the last published
+// one are held back, up to `max_silence`. Heartbeats fill the silence so the backend can tell a
+// quiet room from a dead device.
+pub struct Reporter {
+    delta_db: f32,
+    max_silence: Duration,
+    last_published: Option<(Decibel, Instant)>,
+    suppressed: u32,
+    heartbeat_seq: u32,
+    last_message: Instant,
+}
+
+impl Reporter {
+    pub fn new(delta_db: f32, max_silence: Duration) -> Self {
+        Reporter {
+            delta_db,
+            max_silence,
+            last_published: None,
+            suppressed: 0,
+            heartbeat_seq: 0,
+            last_message: Instant::now(),
+        }
+    }
+
+    pub fn configure(&mut self, delta_db: f32, max_silence: Duration) {
+        self.delta_db = delta_db;
+        self.max_silence = max_silence;
+    }
+
+    // Continues the heartbeat numbering of a previous boot or wake
+    pub fn resume_heartbeats(&mut self, last_seq: u32) {
+        self.heartbeat_seq = last_seq;
+    }
+
+    pub fn heartbeat_seq(&self) -> u32 {
+        self.heartbeat_seq
+    }
+
+    // The next reading goes out whatever the delta
+    pub fn publish_next(&mut self) {
+        self.last_published = None;
+    }
+
+    pub fn should_publish(&mut self, level: Decibel, now: Instant) -> bool {
+        let unchanged = self.last_published.is_some_and(|(last, at)| {
+            (level.0 - last.0).abs() < self.delta_db && now.duration_since(at) < self.max_silence
+        });
+        if unchanged {
+            self.suppressed = self.suppressed.saturating_add(1);
+            return false;
+        }
+        self.last_published = Some((level, now));
+        self.last_message = now;
+        true
+    }
+
+    // Due once nothing went out for `interval`, a zero interval never is. Counts the readings
+    // held back since the previous heartbeat.
+    pub fn heartbeat(&mut self, interval: Duration, now: Instant, paused: bool) -> Option<String> {
+        if interval.is_zero() || now.duration_since(self.last_message) < interval {
+            return None;
+        }
+        self.last_message = now;
+        self.heartbeat_seq = self.heartbeat_seq.wrapping_add(1);
+        Some(format!(
+            "{{\"seq\":{},\"status\":\"{}\",\"suppressed\":{}}}",
+            self.heartbeat_seq,
+            if paused { "paused" } else { "running" },
+            std::mem::take(&mut self.suppressed)
+        ))
+    }
+}
+
+// The Leq of a logger interval, kept as the Leq so far and the readings behind it since an hour of
+// readings would not fit in RAM. With a schedule and the clock set, intervals close at its
+// boundaries instead of `interval` after they started, so the first one after boot is shorter.
+pub struct IntervalLeq {
+    interval: Duration,
+    schedule: Option<Schedule>,
+    started: Option<Instant>,
+    // Unix time of the boundary closing the interval under way, and the time it was found from
+    due: Option<u64>,
+    due_from: u64,
+    closed_at: Option<u64>,
+    so_far: Option<(Decibel, u32)>,
+    // Readings not in `so_far` yet, folded in a block at a time so the fixed-point build doesn't
+    // round every single one
+    block: Vec<Decibel>,
+}
+
+impl IntervalLeq {
+    pub fn new(interval: Duration, schedule: Option<Schedule>) -> Self {
+        IntervalLeq {
+            interval,
+            schedule,
+            started: None,
+            due: None,
+            due_from: 0,
+            closed_at: None,
+            so_far: None,
+            block: Vec::with_capacity(LEQ_BLOCK_LEN),
+        }
+    }
+
+    // The interval under way keeps its start
+    pub fn configure(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    // The Leq once the reading closed an interval, which the next reading starts again
+    pub fn add(&mut self, level: Decibel, now: Instant, unix_time: Option<u64>) -> Option<Decibel> {
+        let started = *self.started.get_or_insert(now);
+        if let (Some(schedule), Some(unix_time)) = (self.schedule, unix_time) {
+            // The clock can jump, e.g. once SNTP replaces the broker's time: back before the
+            // interval began, or past the boundary after the one due, that one is stale
+            let stale = self.due.is_some_and(|due| {
+                unix_time < self.due_from || unix_time >= schedule.next_after(due)
+            });
+            if self.due.is_none() || stale {
+                self.due = Some(schedule.next_after(unix_time));
+                self.due_from = unix_time;
+            }
+        }
+        self.block.push(level);
+        if self.block.len() == LEQ_BLOCK_LEN {
+            self.fold_block();
+        }
+        let closed = match (self.due, unix_time) {
+            (Some(due), Some(unix_time)) => unix_time >= due,
+            _ => now.duration_since(started) >= self.interval,
+        };
+        if !closed {
+            return None;
+        }
+        self.fold_block();
+        self.closed_at = self.due.take();
+        self.started = None;
+        Some(self.so_far.take()?.0)
+    }
+
+    fn fold_block(&mut self) {
+        let readings = self.block.len() as u32;
+        let block = dsp::leq(&self.block).map(|leq| (leq, readings));
+        let count = self.so_far.map_or(0, |(_, count)| count) + readings;
+        self.so_far =
+            dsp::leq_weighted(self.so_far.into_iter().chain(block)).map(|leq| (leq, count));
+        self.block.clear();
+    }
+
+    // The boundary of the schedule that closed the last interval, for its timestamp
+    pub fn closed_at(&self) -> Option<u64> {
+        self.closed_at
+    }
+}
+
+// Collects level readings, bare numbers or documents, into one JSON array per publish. Readings
+// go out as they come with a size below 2.
+pub struct LevelBatch {
+    max_len: usize,
+    interval: Duration,
+    readings: Vec<String>,
+    first_at: Option<Instant>,
+}
+
+impl LevelBatch {
+    pub fn new(max_len: usize, interval: Duration) -> Self {
+        LevelBatch {
+            max_len,
+            interval,
+            readings: Vec::with_capacity(max_len),
+            first_at: None,
+        }
+    }
+
+    // What was collected under the old settings goes out with the next reading
+    pub fn configure(&mut self, max_len: usize, interval: Duration) {
+        self.max_len = max_len;
+        self.interval = interval;
+    }
+
+    // The payload to publish, if the reading completed a batch
+    pub fn add(&mut self, reading: String, now: Instant) -> Option<String> {
+        if self.max_len < 2 && self.readings.is_empty() {
+            return Some(reading);
+        }
+        self.readings.push(reading);
+        self.first_at.get_or_insert(now);
+        self.take_due(now)
+    }
+
+    // Also due once the oldest reading waited for the interval, a zero interval never is
+    pub fn take_due(&mut self, now: Instant) -> Option<String> {
+        let full = !self.readings.is_empty() && self.readings.len() >= self.max_len;
+        let expired = self.first_at.is_some_and(|first_at| {
+            !self.interval.is_zero() && now.duration_since(first_at) >= self.interval
+        });
+        if !full && !expired {
+            return None;
+        }
+        self.first_at = None;
+        let payload = format!("[{}]", self.readings.join(","));
+        // Keeps the capacity for the next batch
+        self.readings.clear();
+        Some(payload)
+    }
+}
+
+// The level topic's document when it carries more than a bare number
+#[derive(Debug, Serialize)]
+pub struct LevelReading<'a> {
+    pub device_id: &'a str,
+    // Monotonic across reboots and sleep cycles, for deduplication
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u32>,
+    // Unix seconds, null until SNTP or the broker's time topic has set the clock
+    pub ts: Option<u64>,
+    // `broker` with how many seconds `ts` may be off while the time comes from the time topic,
+    // left out with SNTP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ts_source: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ts_accuracy_s: Option<u64>,
+    pub db: f32,
+    // `A` or `C` for weighted levels, left out for the plain ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weighting: Option<&'static str>,
+    // ADC samples behind the level, none for simulated ones
+    pub samples: usize,
+    pub rssi: Option<i8>,
+    #[serde(flatten)]
+    pub raw_rms: Option<RawRms>,
+    // Octave band levels of the same samples, with `band_fft_len`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bands_db: Option<OctaveBands>,
+    // Only there in maintenance mode, so analytics can leave the reading out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<bool>,
+}
+
+// Whole seconds, rounded up
+pub fn accuracy_s(accuracy: Duration) -> u64 {
+    (accuracy.as_millis() as u64 + 999) / 1000
+}
+
+// `ts_source` and `ts_accuracy_s` as JSON fields for the documents not built from a struct, empty
+// with SNTP
+pub fn broker_time_fields(ts_accuracy_s: Option<u64>) -> String {
+    ts_accuracy_s.map_or_else(String::new, |accuracy_s| {
+        format!("\"ts_source\":\"broker\",\"ts_accuracy_s\":{}", accuracy_s)
+    })
+}
+
+// The RMS the level was derived from, in counts and millivolts, so the backend can recompute
+// levels once the calibration improves
+#[derive(Debug, Serialize)]
+pub struct RawRms {
+    pub rms_counts: Option<f32>,
+    pub rms_mv: Option<f32>,
+}
+
+impl<'a> LevelReading<'a> {
+    pub fn new(
+        device_id: &'a str,
+        ts: Option<u64>,
+        level: Decibel,
+        samples: Option<&[RawAdc]>,
+        rssi: Option<i8>,
+        with_raw_rms: bool,
+    ) -> Self {
+        LevelReading {
+            device_id,
+            seq: None,
+            ts,
+            ts_source: None,
+            ts_accuracy_s: None,
+            db: level.0,
+            weighting: None,
+            samples: samples.map_or(0, <[RawAdc]>::len),
+            rssi,
+            raw_rms: with_raw_rms.then(|| RawRms {
+                rms_counts: samples.map(dsp::rms_counts),
+                rms_mv: samples.map(|samples| dsp::rms_millivolts(samples).0),
+            }),
+            bands_db: None,
+            maintenance: None,
+        }
+    }
+
+    pub fn with_seq(self, seq: u32) -> Self {
+        LevelReading {
+            seq: Some(seq),
+            ..self
+        }
+    }
+
+    pub fn with_bands(self, bands: Option<OctaveBands>) -> Self {
+        LevelReading {
+            bands_db: bands,
+            ..self
+        }
+    }
+
+    pub fn with_broker_time(self, accuracy: Option<Duration>) -> Self {
+        LevelReading {
+            ts_source: accuracy.map(|_| "broker"),
+            ts_accuracy_s: accuracy.map(accuracy_s),
+            ..self
+        }
+    }
+
+    pub fn with_weighting(self, weighting: Weighting) -> Self {
+        LevelReading {
+            weighting: (weighting != Weighting::Z).then(|| weighting.as_str()),
+            ..self
+        }
+    }
+
+    pub fn with_maintenance(self, in_mode: bool) -> Self {
+        LevelReading {
+            maintenance: in_mode.then_some(true),
+            ..self
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        // Nothing in here fails to serialize
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
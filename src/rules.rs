@@ -0,0 +1,195 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::dsp::Decibel;
+
+// ADC inputs, the LED, strapping, USB and UART0 console pins. Anything past 23 is flash on the C6.
+const RESERVED_PINS: [u8; 10] = [0, 1, 2, 8, 9, 12, 13, 15, 16, 17];
+const MAX_PIN: u8 = 23;
+
+// What a rule does, run on the device itself so it keeps working without the internet
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Action {
+    // Drives a spare output pin, e.g. for a relay
+    Gpio { pin: u8, high: bool },
+    // A steady color instead of the status sequence, null gives the LED back to the status
+    Led(Option<[u8; 3]>),
+    // Published to `<topic>/rules/<rule name>` whenever the broker is reachable
+    Publish(String),
+    // Drives an active buzzer on a pin for a while
+    Buzzer { pin: u8, ms: u32 },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    name: String,
+    #[serde(default)]
+    above_db: Option<f32>,
+    #[serde(default)]
+    below_db: Option<f32>,
+    #[serde(default)]
+    for_s: u32,
+    // Local time as `HH:MM-HH:MM`, wrapping around midnight when the end comes first
+    #[serde(default)]
+    between: Option<String>,
+    then: Vec<Action>,
+    #[serde(default)]
+    clear: Vec<Action>,
+}
+
+// Runs `then` once its condition held for `hold`, and `clear` once it stops holding after that
+struct Rule {
+    name: String,
+    above: Option<Decibel>,
+    below: Option<Decibel>,
+    hold: Duration,
+    // Minutes since local midnight, start inclusive, end exclusive
+    window: Option<(u16, u16)>,
+    then: Vec<Action>,
+    clear: Vec<Action>,
+    since: Option<Instant>,
+    fired: bool,
+}
+
+impl Rule {
+    fn matches(&self, level: Decibel, local_minute: Option<u16>) -> bool {
+        self.above.map_or(true, |above| level >= above)
+            && self.below.map_or(true, |below| level < below)
+            // Unknown until SNTP sets the clock, then a window never matches
+            && self.window.map_or(true, |(start, end)| {
+                local_minute.is_some_and(|minute| {
+                    if start <= end {
+                        start <= minute && minute < end
+                    } else {
+                        minute >= start || minute < end
+                    }
+                })
+            })
+    }
+}
+
+#[derive(Default)]
+pub struct RuleEngine {
+    rules: Vec<Rule>,
+}
+
+impl RuleEngine {
+    // A JSON array of rules, e.g.
+    // `[{"name":"night","above_db":45,"for_s":10,"between":"22:00-07:00","then":[{"led":[255,0,0]}],"clear":[{"led":null}]}]`
+    pub fn parse(json: &str) -> Result<Self> {
+        if json.trim().is_empty() {
+            return Ok(RuleEngine::default());
+        }
+        let specs: Vec<RuleSpec> = serde_json::from_str(json).context("Invalid rules")?;
+        let rules = specs
+            .into_iter()
+            .map(|spec| {
+                let name = spec.name.clone();
+                parse_rule(spec).with_context(|| format!("Invalid rule {:?}", name))
+            })
+            .collect::<Result<_>>()?;
+        Ok(RuleEngine { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // The actions due with this level reading, each with the name of its rule
+    pub fn update(
+        &mut self,
+        level: Decibel,
+        local_minute: Option<u16>,
+        now: Instant,
+    ) -> Vec<(&str, Action)> {
+        let mut due = vec![];
+        for rule in self.rules.iter_mut() {
+            if rule.matches(level, local_minute) {
+                let since = *rule.since.get_or_insert(now);
+                if !rule.fired && now.duration_since(since) >= rule.hold {
+                    rule.fired = true;
+                    log::info!("Rule {} triggered", rule.name);
+                    due.extend(
+                        rule.then
+                            .iter()
+                            .map(|action| (rule.name.as_str(), action.clone())),
+                    );
+                }
+            } else {
+                rule.since = None;
+                if rule.fired {
+                    rule.fired = false;
+                    log::info!("Rule {} cleared", rule.name);
+                    due.extend(
+                        rule.clear
+                            .iter()
+                            .map(|action| (rule.name.as_str(), action.clone())),
+                    );
+                }
+            }
+        }
+        due
+    }
+}
+
+fn parse_rule(spec: RuleSpec) -> Result<Rule> {
+    // Rule names end up in topics
+    if spec.name.is_empty()
+        || !spec
+            .name
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'_')
+    {
+        bail!("Rule names are lowercase letters, digits and underscores");
+    }
+    if spec.then.is_empty() {
+        bail!("Nothing to do");
+    }
+    if spec
+        .above_db
+        .into_iter()
+        .chain(spec.below_db)
+        .any(|level| !level.is_finite())
+    {
+        bail!("Invalid level");
+    }
+    for action in spec.then.iter().chain(spec.clear.iter()) {
+        if let Action::Gpio { pin, .. } | Action::Buzzer { pin, .. } = action {
+            if *pin > MAX_PIN || RESERVED_PINS.contains(pin) {
+                bail!("GPIO{} is not available", pin);
+            }
+        }
+    }
+    let window = spec
+        .between
+        .as_deref()
+        .map(|between| {
+            let (start, end) = between.split_once('-').context("Expected HH:MM-HH:MM")?;
+            Ok::<_, anyhow::Error>((parse_time(start)?, parse_time(end)?))
+        })
+        .transpose()?;
+    Ok(Rule {
+        name: spec.name,
+        above: spec.above_db.map(Decibel),
+        below: spec.below_db.map(Decibel),
+        hold: Duration::from_secs(spec.for_s.into()),
+        window,
+        then: spec.then,
+        clear: spec.clear,
+        since: None,
+        fired: false,
+    })
+}
+
+// `HH:MM` as minutes since midnight
+fn parse_time(time: &str) -> Result<u16> {
+    let (hour, minute) = time.trim().split_once(':').context("Expected HH:MM")?;
+    match (hour.parse::<u16>(), minute.parse::<u16>()) {
+        (Ok(hour), Ok(minute)) if hour < 24 && minute < 60 => Ok(hour * 60 + minute),
+        _ => bail!("Invalid time {:?}", time),
+    }
+}
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use esp_idf_svc::{
+    hal::{
+        adc::{
+            continuous::{config::Config, AdcChannels, AdcDriver, AdcMeasurement, Atten11dB},
+            ADC1,
+        },
+        delay::{TickType, NON_BLOCK},
+        gpio::ADCPin,
+        peripheral::Peripheral,
+        units::Hertz,
+    },
+    sys::{adc_channel_t, ESP_ERR_TIMEOUT},
+};
+
+use crate::{
+    dsp::RawAdc,
+    frame::{Frame, Input},
+};
+
+// Per input. Well above the 3 kHz of smoke alarms and, with all three inputs, below the 83 kHz
+// the ADC converts at most.
+pub const SAMPLE_RATE_HZ: u32 = 16_000;
+// Conversions per DMA frame and frames in the driver's pool, a window's worth of headroom
+const FRAME_MEASUREMENTS: usize = 400;
+const FRAMES_COUNT: usize = 8;
+// A DMA frame takes 25 ms at most, anything much longer means the DMA stopped
+const READ_TIMEOUT_MS: u64 = 500;
+
+// Samples per input in a window
+pub fn window_len(window: Duration) -> usize {
+    (u128::from(SAMPLE_RATE_HZ) * window.as_millis() / 1000) as usize
+}
+
+// The inputs converted by the ADC on its own, at a fixed rate and through DMA, so the sensor task
+// only has to pick up full windows
+pub struct Sampler<'d> {
+    driver: AdcDriver<'d>,
+    inputs: Vec<(adc_channel_t, Input)>,
+    buffer: Vec<AdcMeasurement>,
+}
+
+impl<'d> Sampler<'d> {
+    pub fn new<GPIO, MIC2, VIB>(
+        adc1: impl Peripheral<P = ADC1> + 'd,
+        mic_pin: impl Peripheral<P = GPIO> + 'd,
+        second_mic_pin: Option<impl Peripheral<P = MIC2> + 'd>,
+        vibration_pin: Option<impl Peripheral<P = VIB> + 'd>,
+    ) -> Result<Self>
+    where
+        GPIO: ADCPin<Adc = ADC1>,
+        MIC2: ADCPin<Adc = ADC1>,
+        VIB: ADCPin<Adc = ADC1>,
+    {
+        let mic = Atten11dB::db11(mic_pin);
+        // The pattern has a type of its own for every combination of inputs
+        let (driver, inputs) = match (second_mic_pin, vibration_pin) {
+            (None, None) => start(adc1, mic, &[Input::Mic]),
+            (Some(second_mic_pin), None) => start(
+                adc1,
+                mic.chain(Atten11dB::db11(second_mic_pin)),
+                &[Input::Mic, Input::SecondMic],
+            ),
+            (None, Some(vibration_pin)) => start(
+                adc1,
+                mic.chain(Atten11dB::db11(vibration_pin)),
+                &[Input::Mic, Input::Vibration],
+            ),
+            (Some(second_mic_pin), Some(vibration_pin)) => start(
+                adc1,
+                mic.chain(Atten11dB::db11(second_mic_pin))
+                    .chain(Atten11dB::db11(vibration_pin)),
+                &[Input::Mic, Input::SecondMic, Input::Vibration],
+            ),
+        }?;
+        Ok(Sampler {
+            driver,
+            inputs,
+            buffer: vec![AdcMeasurement::new(); FRAME_MEASUREMENTS],
+        })
+    }
+
+    // For windows of `window`, i.e. `sample_window_ms`
+    pub fn frame(&self, window: Duration) -> Frame {
+        Frame::new(window_len(window), SAMPLE_RATE_HZ as f32)
+    }
+
+    // The next full window, after dropping what piled up in the pool while the loop was busy
+    pub fn read(&mut self, frame: &mut Frame) -> Result<()> {
+        while self.read_into(None, NON_BLOCK)? > 0 {}
+        frame.clear();
+        let timeout = TickType::new_millis(READ_TIMEOUT_MS).ticks();
+        while !frame.is_full() {
+            if self.read_into(Some(frame), timeout)? == 0 {
+                anyhow::bail!("ADC delivered no samples for {} ms", READ_TIMEOUT_MS);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_into(&mut self, frame: Option<&mut Frame>, timeout: u32) -> Result<usize> {
+        let len = match self.driver.read(&mut self.buffer, timeout) {
+            Ok(len) => len,
+            Err(err) if err.code() == ESP_ERR_TIMEOUT => 0,
+            Err(err) => return Err(err).context("Unable to read the ADC"),
+        };
+        if let Some(frame) = frame {
+            for measurement in &self.buffer[..len] {
+                if let Some((_, input)) = self
+                    .inputs
+                    .iter()
+                    .find(|(channel, _)| *channel == measurement.channel())
+                {
+                    frame.push(*input, RawAdc(measurement.data()));
+                }
+            }
+        }
+        Ok(len)
+    }
+}
+
+fn start<'d, C>(
+    adc1: impl Peripheral<P = ADC1> + 'd,
+    channels: C,
+    inputs: &[Input],
+) -> Result<(AdcDriver<'d>, Vec<(adc_channel_t, Input)>)>
+where
+    C: AdcChannels<Adc = ADC1> + 'd,
+{
+    let inputs = channels
+        .iter()
+        .map(|(channel, _)| channel)
+        .zip(inputs.iter().copied())
+        .collect::<Vec<_>>();
+    let config = Config::new()
+        .sample_freq(Hertz(SAMPLE_RATE_HZ * inputs.len() as u32))
+        .frame_measurements(FRAME_MEASUREMENTS)
+        .frames_count(FRAMES_COUNT);
+    let mut driver =
+        AdcDriver::new(adc1, &config, channels).context("Unable to set up continuous ADC")?;
+    driver.start().context("Unable to start continuous ADC")?;
+    Ok((driver, inputs))
+}
@@ -0,0 +1,77 @@
+const MINUTES: u32 = 60;
+const HOURS: u32 = 24;
+
+// When logger intervals close, like the first two fields of a crontab and in UTC so a fleet
+// across time zones shares its buckets: `*/5` every fifth minute, `0` hourly at :00, `0 */6`
+// every six hours, `0,30 8-18` on the half hour during the day. The hour is `*` when left out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Schedule {
+    // One bit per minute of the hour and per hour of the day
+    minutes: u64,
+    hours: u32,
+}
+
+impl Schedule {
+    pub fn parse(spec: &str) -> Result<Self, &'static str> {
+        let mut fields = spec.split_whitespace();
+        let (Some(minutes), hours, None) = (fields.next(), fields.next(), fields.next()) else {
+            return Err("Expected minute and optionally hour");
+        };
+        Ok(Schedule {
+            minutes: field(minutes, MINUTES)?,
+            hours: field(hours.unwrap_or("*"), HOURS)? as u32,
+        })
+    }
+
+    fn matches(&self, minute: u64) -> bool {
+        let (minute_of_hour, hour) = (minute % 60, minute / 60 % 24);
+        self.minutes & (1 << minute_of_hour) != 0 && self.hours & (1 << hour) != 0
+    }
+
+    // The first boundary after `unix_time`, within a day as both fields match something
+    pub fn next_after(&self, unix_time: u64) -> u64 {
+        let mut minute = unix_time / 60 + 1;
+        while !self.matches(minute) {
+            minute += 1;
+        }
+        minute * 60
+    }
+}
+
+// A comma separated list of `*`, `n` or `a-b`, each optionally `/step`, as a bit mask
+fn field(spec: &str, range: u32) -> Result<u64, &'static str> {
+    let mut mask = 0u64;
+    for part in spec.split(',') {
+        let (values, step) = match part.split_once('/') {
+            Some((values, step)) => (
+                values,
+                step.parse()
+                    .ok()
+                    .filter(|step: &u32| *step > 0)
+                    .ok_or("Invalid step")?,
+            ),
+            None => (part, 1),
+        };
+        let value = |value: &str| {
+            value
+                .parse()
+                .ok()
+                .filter(|value: &u32| *value < range)
+                .ok_or("Value out of range")
+        };
+        let (first, last) = match values.split_once('-') {
+            _ if values == "*" => (0, range - 1),
+            Some((first, last)) => (value(first)?, value(last)?),
+            // Like `*/step` from there on
+            None if step > 1 => (value(values)?, range - 1),
+            None => (value(values)?, value(values)?),
+        };
+        if first > last {
+            return Err("Ranges go from low to high");
+        }
+        for value in (first..=last).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
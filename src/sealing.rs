@@ -0,0 +1,100 @@
+use std::{ffi::c_void, sync::Mutex};
+
+use anyhow::{bail, Context, Result};
+use esp_idf_svc::{
+    nvs::{EspDefaultNvsPartition, EspNvs},
+    sys::{
+        esp_fill_random, mbedtls_cipher_id_t_MBEDTLS_CIPHER_ID_AES, mbedtls_gcm_context,
+        mbedtls_gcm_crypt_and_tag, mbedtls_gcm_free, mbedtls_gcm_init, mbedtls_gcm_setkey,
+        MBEDTLS_GCM_ENCRYPT,
+    },
+};
+
+use crate::security::SecurityState;
+
+const NVS_KEYS_NAMESPACE: &str = "bzzz_keys";
+const NVS_PAYLOAD_KEY: &str = "payload_key";
+// AES-256
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+// First byte of every sealed payload, so the framing can change without guessing
+const FRAMING_VERSION: u8 = 1;
+
+static KEY: Mutex<Option<[u8; KEY_LEN]>> = Mutex::new(None);
+
+// The per-device key is written at manufacture into the default NVS partition, which
+// sdkconfig.security encrypts, e.g. with the NVS partition generator and a CSV line
+// `payload_key,data,hex2bin,<64 hex digits>` under the `bzzz_keys` namespace
+pub fn load_key(nvs_partition: EspDefaultNvsPartition) -> Result<()> {
+    let nvs = EspNvs::new(nvs_partition, NVS_KEYS_NAMESPACE, false)
+        .context("No payload key provisioned")?;
+    let mut key = [0u8; KEY_LEN];
+    match nvs.get_blob(NVS_PAYLOAD_KEY, &mut key) {
+        Ok(Some(blob)) if blob.len() == KEY_LEN => {}
+        Ok(Some(blob)) => bail!(
+            "Payload key has {} bytes instead of {}",
+            blob.len(),
+            KEY_LEN
+        ),
+        Ok(None) => bail!("No payload key provisioned"),
+        Err(err) => return Err(err).context("Unable to read payload key"),
+    }
+    if !SecurityState::detect().flash_encryption {
+        log::warn!("Payload key stored without flash encryption");
+    }
+    *KEY.lock().unwrap() = Some(key);
+    log::info!("Payload encryption enabled");
+    Ok(())
+}
+
+// With a key, `payload` becomes the framing version, a random nonce, the AES-GCM ciphertext and
+// its tag. The topic is authenticated too, so the broker can't move a payload to another topic.
+// Without a key it is returned as is. A failure gives an empty payload rather than the clear
+// text.
+pub fn seal(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let Some(key) = *KEY.lock().unwrap() else {
+        return payload.to_vec();
+    };
+    let mut sealed = vec![0u8; 1 + NONCE_LEN + payload.len() + TAG_LEN];
+    sealed[0] = FRAMING_VERSION;
+    let (nonce, rest) = sealed[1..].split_at_mut(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at_mut(payload.len());
+    let result = unsafe {
+        esp_fill_random(nonce.as_mut_ptr() as *mut c_void, NONCE_LEN);
+        let mut gcm: mbedtls_gcm_context = std::mem::zeroed();
+        mbedtls_gcm_init(&mut gcm);
+        let mut result = mbedtls_gcm_setkey(
+            &mut gcm,
+            mbedtls_cipher_id_t_MBEDTLS_CIPHER_ID_AES,
+            key.as_ptr(),
+            (KEY_LEN * 8) as _,
+        );
+        if result == 0 {
+            result = mbedtls_gcm_crypt_and_tag(
+                &mut gcm,
+                MBEDTLS_GCM_ENCRYPT as _,
+                payload.len(),
+                nonce.as_ptr(),
+                NONCE_LEN,
+                topic.as_ptr(),
+                topic.len(),
+                payload.as_ptr(),
+                ciphertext.as_mut_ptr(),
+                TAG_LEN,
+                tag.as_mut_ptr(),
+            );
+        }
+        mbedtls_gcm_free(&mut gcm);
+        result
+    };
+    if result != 0 {
+        log::error!(
+            "Unable to encrypt payload for {}: mbedTLS error {}",
+            topic,
+            result
+        );
+        return vec![];
+    }
+    sealed
+}
@@ -0,0 +1,43 @@
+use std::{ffi::c_int, sync::Mutex};
+
+use anyhow::{bail, Result};
+use esp_idf_svc::sys::mbedtls_pk_context;
+
+// The device key sits in this slot from factory provisioning (TrustFLEX/TNG parts) and never
+// leaves the chip
+const KEY_SLOT: u16 = 0;
+const ATCA_SUCCESS: c_int = 0;
+
+#[repr(C)]
+struct ATCAIfaceCfg {
+    _private: [u8; 0],
+}
+
+// From esp-cryptoauthlib
+extern "C" {
+    static mut cfg_ateccx08a_i2c_default: ATCAIfaceCfg;
+    fn atcab_init(cfg: *mut ATCAIfaceCfg) -> c_int;
+    fn atca_mbedtls_pk_init(pkey: *mut mbedtls_pk_context, slotid: u16) -> c_int;
+}
+
+static INITIALIZED: Mutex<bool> = Mutex::new(false);
+
+// Fills `key` with the public key of the secure element and a reference to its slot instead of
+// the private scalar. With CONFIG_MBEDTLS_ATCA_HW_ECDSA_SIGN the mbedTLS ECDSA sign hook sends
+// every signature made with it to the chip, so the PEM written from it holds no secret and can
+// be handed to TLS like any other key.
+pub fn bind_key(key: &mut mbedtls_pk_context) -> Result<()> {
+    let mut initialized = INITIALIZED.lock().unwrap();
+    if !*initialized {
+        let status = unsafe { atcab_init(std::ptr::addr_of_mut!(cfg_ateccx08a_i2c_default)) };
+        if status != ATCA_SUCCESS {
+            bail!("Unable to initialize secure element: 0x{:02x}", status);
+        }
+        *initialized = true;
+    }
+    let result = unsafe { atca_mbedtls_pk_init(key, KEY_SLOT) };
+    if result != 0 {
+        bail!("Unable to use secure element slot {}: {}", KEY_SLOT, result);
+    }
+    Ok(())
+}
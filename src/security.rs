@@ -0,0 +1,36 @@
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+use anyhow::{bail, Result};
+use esp_idf_svc::sys::{esp_flash_encryption_enabled, esp_secure_boot_enabled};
+
+static REQUIRE_ENCRYPTION: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+pub struct SecurityState {
+    pub flash_encryption: bool,
+    pub secure_boot: bool,
+}
+
+impl SecurityState {
+    pub fn detect() -> Self {
+        unsafe {
+            SecurityState {
+                flash_encryption: esp_flash_encryption_enabled(),
+                secure_boot: esp_secure_boot_enabled(),
+            }
+        }
+    }
+}
+
+pub fn require_encryption_for_secrets(required: bool) {
+    REQUIRE_ENCRYPTION.store(required, Relaxed);
+}
+
+// NVS isn't covered by flash encryption itself, but sdkconfig.security enables NVS encryption
+// along with it, so flash encryption being on means secrets end up encrypted.
+pub fn check_secret_storage(what: &str) -> Result<()> {
+    if REQUIRE_ENCRYPTION.load(Relaxed) && !SecurityState::detect().flash_encryption {
+        bail!("Refusing to store {} without flash encryption", what);
+    }
+    Ok(())
+}
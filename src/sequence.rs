@@ -0,0 +1,115 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering::Relaxed},
+    Mutex,
+};
+
+use anyhow::{Context, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+
+use crate::wear;
+
+const NVS_SEQ_NAMESPACE: &str = "bzzz_seq";
+// Reserved in NVS a block at a time, like the signing counter, so a wake from deep sleep reads
+// the RTC copy instead of writing flash. Losing power skips what was left of the block.
+const BLOCK: u32 = 256;
+// Anything else in RTC memory is what power-on left there
+const RTC_MAGIC: u32 = 0xb222_5e01;
+
+// RTC memory keeps its contents through deep sleep and software resets
+#[link_section = ".rtc.data"]
+static RTC_VALID: AtomicU32 = AtomicU32::new(0);
+#[link_section = ".rtc.data"]
+static RTC_COUNTERS: [AtomicU32; 2] = [AtomicU32::new(0), AtomicU32::new(0)];
+
+// Identifiers downstream deduplicates on, monotonic across sleep cycles and reboots. MQTT
+// message ids can't be, they belong to the session.
+#[derive(Clone, Copy, Debug)]
+pub enum Counter {
+    // `seq` of level documents
+    Reading,
+    // `seq` of heartbeats
+    Heartbeat,
+}
+
+impl Counter {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn nvs_key(self) -> &'static str {
+        match self {
+            Counter::Reading => "reading",
+            Counter::Heartbeat => "heartbeat",
+        }
+    }
+}
+
+struct Reserved {
+    nvs: EspNvs<NvsDefault>,
+    until: [u32; 2],
+}
+
+static RESERVED: Mutex<Option<Reserved>> = Mutex::new(None);
+
+// Once at boot, before anything takes a number
+pub fn init(nvs_partition: EspDefaultNvsPartition) -> Result<()> {
+    let nvs = EspNvs::new(nvs_partition, NVS_SEQ_NAMESPACE, true)
+        .context("Unable to open sequence storage")?;
+    let rtc_valid = RTC_VALID.load(Relaxed) == RTC_MAGIC;
+    let mut until = [0u32; 2];
+    for counter in [Counter::Reading, Counter::Heartbeat] {
+        let reserved = nvs
+            .get_u32(counter.nvs_key())
+            .context("Unable to read sequence")?
+            .unwrap_or(0);
+        until[counter.index()] = reserved;
+        if !rtc_valid {
+            RTC_COUNTERS[counter.index()].store(reserved, Relaxed);
+        }
+    }
+    RTC_VALID.store(RTC_MAGIC, Relaxed);
+    log::info!(
+        "Sequences {} from {}",
+        if rtc_valid { "resumed" } else { "restored" },
+        if rtc_valid { "RTC memory" } else { "NVS" }
+    );
+    *RESERVED.lock().unwrap() = Some(Reserved { nvs, until });
+    Ok(())
+}
+
+// The last number handed out, or where the next one starts after a reboot
+pub fn last(counter: Counter) -> u32 {
+    RTC_COUNTERS[counter.index()].load(Relaxed)
+}
+
+pub fn next(counter: Counter) -> u32 {
+    let next = RTC_COUNTERS[counter.index()]
+        .fetch_add(1, Relaxed)
+        .wrapping_add(1);
+    reserve(counter, next);
+    next
+}
+
+// For counters kept elsewhere, e.g. the heartbeats of the Reporter
+pub fn record(counter: Counter, value: u32) {
+    RTC_COUNTERS[counter.index()].store(value, Relaxed);
+    reserve(counter, value);
+}
+
+fn reserve(counter: Counter, value: u32) {
+    let mut reserved = RESERVED.lock().unwrap();
+    let Some(reserved) = reserved.as_mut() else {
+        return;
+    };
+    if value < reserved.until[counter.index()] {
+        return;
+    }
+    let until = value.saturating_add(BLOCK);
+    match reserved.nvs.set_u32(counter.nvs_key(), until) {
+        Ok(_) => {
+            reserved.until[counter.index()] = until;
+            wear::nvs_written(0);
+        }
+        Err(err) => log::error!("Unable to reserve {:?} sequence: {}", counter, err),
+    }
+}
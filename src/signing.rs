@@ -0,0 +1,164 @@
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use esp_idf_svc::{
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::{mbedtls_md_hmac, mbedtls_md_info_from_type, mbedtls_md_type_t_MBEDTLS_MD_SHA256},
+};
+
+use crate::{encoding::base64url, wear};
+
+// Same namespace as the payload key, written at manufacture
+const NVS_KEYS_NAMESPACE: &str = "bzzz_keys";
+const NVS_SIGNING_KEY: &str = "signing_key";
+const NVS_COUNTER_NAMESPACE: &str = "bzzz_sign";
+const NVS_COUNTER_KEY: &str = "counter";
+const NVS_COMMAND_COUNTER_KEY: &str = "cmd_counter";
+const KEY_LEN: usize = 32;
+// Counters are reserved in blocks, so flash is written once per block instead of per payload.
+// A reset skips what was left of the block, the counter still never repeats.
+const COUNTER_BLOCK: u64 = 256;
+
+struct Signer {
+    key: [u8; KEY_LEN],
+    nvs: EspNvs<NvsDefault>,
+    next: u64,
+    reserved_until: u64,
+    // Of the last signed command accepted
+    last_command: u64,
+}
+
+impl Signer {
+    fn hmac(&self, data: &[u8]) -> Option<[u8; 32]> {
+        let mut signature = [0u8; 32];
+        let result = unsafe {
+            mbedtls_md_hmac(
+                mbedtls_md_info_from_type(mbedtls_md_type_t_MBEDTLS_MD_SHA256),
+                self.key.as_ptr(),
+                self.key.len(),
+                data.as_ptr(),
+                data.len(),
+                signature.as_mut_ptr(),
+            )
+        };
+        if result != 0 {
+            log::error!("Unable to compute HMAC: mbedTLS error {}", result);
+            return None;
+        }
+        Some(signature)
+    }
+}
+
+static SIGNER: Mutex<Option<Signer>> = Mutex::new(None);
+
+pub fn load_key(nvs_partition: EspDefaultNvsPartition) -> Result<()> {
+    let keys = EspNvs::new(nvs_partition.clone(), NVS_KEYS_NAMESPACE, false)
+        .context("No signing key provisioned")?;
+    let mut key = [0u8; KEY_LEN];
+    match keys.get_blob(NVS_SIGNING_KEY, &mut key) {
+        Ok(Some(blob)) if blob.len() == KEY_LEN => {}
+        Ok(Some(blob)) => bail!(
+            "Signing key has {} bytes instead of {}",
+            blob.len(),
+            KEY_LEN
+        ),
+        Ok(None) => bail!("No signing key provisioned"),
+        Err(err) => return Err(err).context("Unable to read signing key"),
+    }
+    let nvs = EspNvs::new(nvs_partition, NVS_COUNTER_NAMESPACE, true)
+        .context("Unable to open signing counter storage")?;
+    let next = nvs
+        .get_u64(NVS_COUNTER_KEY)
+        .context("Unable to read signing counter")?
+        .unwrap_or(0);
+    let last_command = nvs
+        .get_u64(NVS_COMMAND_COUNTER_KEY)
+        .context("Unable to read command counter")?
+        .unwrap_or(0);
+    *SIGNER.lock().unwrap() = Some(Signer {
+        key,
+        nvs,
+        next,
+        reserved_until: next,
+        last_command,
+    });
+    log::info!("Signing payloads from counter {}", next);
+    Ok(())
+}
+
+// Appends `"ctr"` and `"sig"` to a JSON object once a key is loaded. The signature is the
+// HMAC-SHA256 of the topic, the counter and the object without both fields, separated by NUL
+// bytes, so the backend can check where a measurement comes from and drop any counter it has
+// already seen. Without a key, or if the counter can't be reserved, the object goes out as is.
+pub fn sign(topic: &str, json: String) -> String {
+    let mut signer = SIGNER.lock().unwrap();
+    let Some(signer) = signer.as_mut() else {
+        return json;
+    };
+    let Some(fields) = json.strip_suffix('}') else {
+        return json;
+    };
+    if signer.next == signer.reserved_until {
+        let reserved_until = signer.next + COUNTER_BLOCK;
+        if let Err(err) = signer.nvs.set_u64(NVS_COUNTER_KEY, reserved_until) {
+            log::error!("Unable to reserve signing counters: {}", err);
+            return json;
+        }
+        wear::nvs_written(0);
+        signer.reserved_until = reserved_until;
+    }
+    let counter = signer.next;
+    signer.next += 1;
+    let mut signed = format!("{}\0{}\0", topic, counter).into_bytes();
+    signed.extend_from_slice(json.as_bytes());
+    let Some(signature) = signer.hmac(&signed) else {
+        return json;
+    };
+    format!(
+        "{},\"ctr\":{},\"sig\":\"{}\"}}",
+        fields,
+        counter,
+        base64url(&signature)
+    )
+}
+
+// Commands that open the device up are signed by the backend with the same key: the HMAC-SHA256
+// of `cmd`, the counter and the command without counter and signature, separated by NUL bytes.
+// Each counter has to be above the last one accepted, which is kept in NVS, so a command
+// recorded off the broker can't be played again. Without a key nothing is accepted.
+pub fn verify_command(command: &str, counter: u64, signature: &[u8]) -> bool {
+    let mut signer = SIGNER.lock().unwrap();
+    let Some(signer) = signer.as_mut() else {
+        log::warn!("No signing key to check commands with");
+        return false;
+    };
+    if counter <= signer.last_command {
+        log::warn!("Replayed command counter {}", counter);
+        return false;
+    }
+    let mut signed = format!("cmd\0{}\0", counter).into_bytes();
+    signed.extend_from_slice(command.as_bytes());
+    let Some(expected) = signer.hmac(&signed) else {
+        return false;
+    };
+    let valid = signature.len() == expected.len()
+        && signature
+            .iter()
+            .zip(expected)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+    if !valid {
+        log::warn!("Invalid command signature");
+        return false;
+    }
+    match signer.nvs.set_u64(NVS_COMMAND_COUNTER_KEY, counter) {
+        Ok(_) => wear::nvs_written(0),
+        // Not accepted unless the counter sticks, or a reboot would open it up to replays
+        Err(err) => {
+            log::error!("Unable to persist command counter: {}", err);
+            return false;
+        }
+    }
+    signer.last_command = counter;
+    true
+}
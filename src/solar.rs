@@ -0,0 +1,37 @@
+// Sunrise equation as described in https://en.wikipedia.org/wiki/Sunrise_equation, accurate to a
+// couple of minutes, which is more than enough to switch measurement profiles.
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2_440_587.5;
+const J2000_JULIAN_DAY: f64 = 2_451_545.0;
+const SECS_PER_DAY: f64 = 86_400.0;
+
+pub fn is_daytime(unix_time: u64, latitude: f32, longitude: f32) -> bool {
+    let julian_day = unix_time as f64 / SECS_PER_DAY + UNIX_EPOCH_JULIAN_DAY;
+    let latitude = (latitude as f64).to_radians();
+    let longitude = longitude as f64;
+
+    let day_number = (julian_day - J2000_JULIAN_DAY + 0.0008).round();
+    let mean_solar_time = day_number - longitude / 360.0;
+    let anomaly = (357.5291 + 0.985_600_28 * mean_solar_time)
+        .rem_euclid(360.0)
+        .to_radians();
+    let center =
+        1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic_longitude = (anomaly.to_degrees() + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit = J2000_JULIAN_DAY + mean_solar_time + 0.0053 * anomaly.sin()
+        - 0.0069 * (2.0 * ecliptic_longitude).sin();
+    let declination = (ecliptic_longitude.sin() * 23.4397f64.to_radians().sin()).asin();
+    let cos_hour_angle = ((-0.833f64).to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if cos_hour_angle > 1.0 {
+        // Polar night
+        return false;
+    }
+    if cos_hour_angle < -1.0 {
+        // Midnight sun
+        return true;
+    }
+    let half_day = cos_hour_angle.acos().to_degrees() / 360.0;
+    (transit - half_day..transit + half_day).contains(&julian_day)
+}
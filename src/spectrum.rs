@@ -0,0 +1,52 @@
+use crate::dsp::{self, RawAdc};
+
+// 16 ms at the rate of the sample window
+pub const BURST_LEN: usize = 256;
+// Octave bands, those at or above Nyquist of a burst are left out
+const CENTROID_BINS_HZ: [f32; 7] = [125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0];
+
+// Consecutive mic samples out of a sample window
+pub struct Burst {
+    pub samples: Vec<RawAdc>,
+    pub sample_rate_hz: f32,
+}
+
+// Averages of the spectral features of the bursts between two diagnostics reports
+#[derive(Default)]
+pub struct SpectralStats {
+    bursts: u32,
+    zero_crossing_rate_sum: f32,
+    centroids: u32,
+    centroid_sum: f32,
+}
+
+impl SpectralStats {
+    pub fn add(&mut self, burst: &Burst) {
+        self.bursts += 1;
+        self.zero_crossing_rate_sum +=
+            dsp::zero_crossing_rate(&burst.samples, burst.sample_rate_hz);
+        if let Some(centroid) =
+            dsp::spectral_centroid(&burst.samples, burst.sample_rate_hz, &CENTROID_BINS_HZ)
+        {
+            self.centroids += 1;
+            self.centroid_sum += centroid;
+        }
+    }
+
+    // The JSON fields for the diagnostics report, then starts over
+    pub fn take_json_fields(&mut self) -> String {
+        let stats = std::mem::take(self);
+        let average = |sum: f32, count: u32| {
+            if count == 0 {
+                String::from("null")
+            } else {
+                format!("{:.0}", sum / count as f32)
+            }
+        };
+        format!(
+            "\"zcr_hz\":{},\"centroid_hz\":{}",
+            average(stats.zero_crossing_rate_sum, stats.bursts),
+            average(stats.centroid_sum, stats.centroids)
+        )
+    }
+}
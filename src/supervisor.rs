@@ -0,0 +1,45 @@
+use std::{
+    sync::atomic::{AtomicU8, Ordering::Relaxed},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use esp_idf_svc::sys::esp_random;
+
+use crate::{
+    backoff::{Backoff, BackoffPolicy},
+    led::DeviceStatus,
+};
+
+const RESTART_BACKOFF: BackoffPolicy = BackoffPolicy {
+    initial: Duration::from_secs(1),
+    max: Duration::from_secs(60),
+    multiplier: 2,
+    jitter: 0.2,
+};
+// A worker that survived this long is considered healthy again and restarts from the initial delay
+const STABLE_RUN: Duration = Duration::from_secs(300);
+
+// Runs the worker again whenever it fails, so that one failing subsystem doesn't leave the
+// device half-functional until it is power cycled.
+pub fn supervise<F>(name: &str, status: &AtomicU8, mut worker: F) -> !
+where
+    F: FnMut() -> Result<()>,
+{
+    let mut backoff = Backoff::new(RESTART_BACKOFF, unsafe { esp_random() });
+    loop {
+        let started = Instant::now();
+        match worker() {
+            Ok(()) => log::error!("{} worker stopped", name),
+            Err(err) => log::error!("{} worker failed: {:#}", name, err),
+        }
+        status.store(DeviceStatus::WorkerError as u8, Relaxed);
+        if started.elapsed() >= STABLE_RUN {
+            backoff.reset();
+        }
+        let delay = backoff.next_delay();
+        log::info!("Restarting {} worker in {:?}", name, delay);
+        thread::sleep(delay);
+    }
+}
@@ -0,0 +1,57 @@
+mod stream;
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use esp_idf_svc::log::EspLogger;
+use log::LevelFilter;
+
+pub use stream::adc_json;
+
+// Of every window, a slice rather than all of it: 32 ms at 16 kHz as 1 KiB of samples
+pub const ADC_STREAM_LEN: usize = 512;
+pub const ADC_STREAM_INTERVAL: Duration = Duration::from_secs(1);
+// Per task heap and stack through the profiler, which takes a few seconds of its own
+pub const MEMORY_INTERVAL: Duration = Duration::from_secs(60);
+// CONFIG_LOG_DEFAULT_LEVEL, what the log goes back to
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+// Remote assistance, started by a signed `support` command: everything logs at debug level and
+// raw samples and memory diagnostics are published, until the session runs out, `support off`
+// or a restart. Normal operation doesn't pay for any of it.
+static UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+
+pub fn start(duration: Duration) {
+    log::info!("Support session for {:?}", duration);
+    set_log_level(LevelFilter::Debug);
+    *UNTIL.lock().unwrap() = Some(Instant::now() + duration);
+}
+
+pub fn end() {
+    if UNTIL.lock().unwrap().take().is_some() {
+        set_log_level(DEFAULT_LOG_LEVEL);
+        log::info!("Support session over");
+    }
+}
+
+pub fn active() -> bool {
+    UNTIL.lock().unwrap().is_some()
+}
+
+// Ends a session whose time is up, true if it just did
+pub fn expire() -> bool {
+    let expired = matches!(*UNTIL.lock().unwrap(), Some(until) if Instant::now() >= until);
+    if expired {
+        end();
+    }
+    expired
+}
+
+// For every tag, the ESP-IDF components' too, up to CONFIG_LOG_MAXIMUM_LEVEL
+fn set_log_level(level: LevelFilter) {
+    if let Err(err) = EspLogger.set_target_level("*", level) {
+        log::error!("Unable to set log level: {}", err);
+    }
+}
@@ -0,0 +1,14 @@
+use crate::{dsp::RawAdc, encoding};
+
+// Raw mic samples as little-endian u16 in base64, the signal as the ADC saw it
+pub fn adc_json(samples: &[RawAdc], sample_rate_hz: f32) -> String {
+    let bytes: Vec<u8> = samples
+        .iter()
+        .flat_map(|sample| sample.0.to_le_bytes())
+        .collect();
+    format!(
+        "{{\"sample_rate_hz\":{},\"samples\":\"{}\"}}",
+        sample_rate_hz,
+        encoding::base64(&bytes)
+    )
+}
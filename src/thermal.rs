@@ -0,0 +1,67 @@
+use std::{
+    ptr,
+    sync::atomic::{AtomicBool, Ordering::Relaxed},
+};
+
+use esp_idf_svc::sys::{
+    esp, soc_periph_temperature_sensor_clk_src_t_TEMPERATURE_SENSOR_CLK_SRC_DEFAULT,
+    temperature_sensor_config_t, temperature_sensor_disable, temperature_sensor_enable,
+    temperature_sensor_get_celsius, temperature_sensor_handle_t, temperature_sensor_install,
+    temperature_sensor_uninstall, EspError,
+};
+
+// Degrees below the limit that the die has to cool down to before throttling is lifted
+const THROTTLE_HYSTERESIS_C: f32 = 5.0;
+
+// Optional workloads check this before doing anything expensive
+static THROTTLED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_throttled() -> bool {
+    THROTTLED.load(Relaxed)
+}
+
+// Updates the throttling state from a new reading and tells whether it changed
+pub fn update_throttle(celsius: f32, limit: f32) -> bool {
+    let throttled = is_throttled();
+    let next = if throttled {
+        celsius > limit - THROTTLE_HYSTERESIS_C
+    } else {
+        celsius >= limit
+    };
+    THROTTLED.store(next, Relaxed);
+    next != throttled
+}
+
+// Internal die temperature sensor. Its range is set for what an enclosure can realistically see.
+pub struct ChipTemperature(temperature_sensor_handle_t);
+
+impl ChipTemperature {
+    pub fn new() -> Result<Self, EspError> {
+        let config = temperature_sensor_config_t {
+            range_min: -10,
+            range_max: 80,
+            clk_src: soc_periph_temperature_sensor_clk_src_t_TEMPERATURE_SENSOR_CLK_SRC_DEFAULT,
+        };
+        let mut handle: temperature_sensor_handle_t = ptr::null_mut();
+        unsafe {
+            esp!(temperature_sensor_install(&config, &mut handle))?;
+            esp!(temperature_sensor_enable(handle))?;
+        }
+        Ok(ChipTemperature(handle))
+    }
+
+    pub fn celsius(&self) -> Result<f32, EspError> {
+        let mut celsius = 0.0f32;
+        unsafe { esp!(temperature_sensor_get_celsius(self.0, &mut celsius))? };
+        Ok(celsius)
+    }
+}
+
+impl Drop for ChipTemperature {
+    fn drop(&mut self) {
+        unsafe {
+            temperature_sensor_disable(self.0);
+            temperature_sensor_uninstall(self.0);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    alerting::AlertRule,
+    dsp::{self, Decibel},
+    spectrum::Burst,
+};
+
+// Broadband noise also reaches the level at any single frequency, a tone dominates the burst
+const MIN_SHARE: f32 = 0.5;
+// One event per detector and ongoing sound, not one per burst: the alert rule raises at the first
+// burst with the tone and re-arms once it was gone this long, far longer than the gaps of a cadence
+const TONE_CLEAR_DWELL: Duration = Duration::from_secs(60);
+// What a burst without the tone counts as
+const NO_TONE: Decibel = Decibel(f32::NEG_INFINITY);
+
+// 4 ms of the sample window, still enough for a resolution of a few hundred Hz
+pub const BURST_LEN: usize = 64;
+
+// Fires an event when a burst holds a tone at `frequency_hz`, e.g. the 3 kHz of a smoke alarm,
+// and another when the tone follows an alarm cadence
+pub struct ToneDetector {
+    event: String,
+    frequency_hz: f32,
+    min_level: Decibel,
+    rule: AlertRule,
+    cadence: CadenceDetector,
+}
+
+// `event:frequency_hz:min_level_db`, comma separated, e.g. `smoke_alarm_suspected:3100:50`. Event
+// names end up in alerts and the alert journal, so they stay lowercase words.
+pub fn parse_detectors(spec: &str) -> Result<Vec<ToneDetector>, &'static str> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|detector| !detector.is_empty())
+        .map(|detector| {
+            let mut fields = detector.split(':');
+            let (Some(event), Some(frequency), Some(min_level), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err("Expected event:frequency_hz:min_level_db");
+            };
+            if event.is_empty()
+                || !event
+                    .bytes()
+                    .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'_')
+            {
+                return Err("Event names are lowercase letters, digits and underscores");
+            }
+            let frequency_hz = frequency
+                .parse()
+                .ok()
+                .filter(|frequency: &f32| *frequency > 0.0)
+                .ok_or("Invalid frequency")?;
+            let min_level = min_level
+                .parse()
+                .ok()
+                .filter(|level: &f32| level.is_finite())
+                .ok_or("Invalid level")?;
+            Ok(ToneDetector {
+                event: event.to_string(),
+                frequency_hz,
+                min_level: Decibel(min_level),
+                rule: AlertRule::new(
+                    Decibel(min_level),
+                    Decibel(min_level),
+                    Duration::ZERO,
+                    TONE_CLEAR_DWELL,
+                ),
+                cadence: CadenceDetector::default(),
+            })
+        })
+        .collect()
+}
+
+impl ToneDetector {
+    // Takes the bursts in the order they were captured at `now`
+    pub fn update(&mut self, burst: &Burst, now: Instant) -> Option<ToneEvent> {
+        let level = self.tone_level(burst);
+        let raised = self.rule.update(level.unwrap_or(NO_TONE), now);
+        if let Some(found) = self.cadence.update(now, level) {
+            return Some(ToneEvent::Cadence(found));
+        }
+        let level = level.filter(|_| raised)?;
+        log::info!("Tone at {} Hz: {} dB", self.frequency_hz, level);
+        Some(ToneEvent::Tone(level))
+    }
+
+    pub fn event(&self) -> &str {
+        &self.event
+    }
+
+    // The level of the tone if the burst holds it
+    fn tone_level(&self, burst: &Burst) -> Option<Decibel> {
+        // Above Nyquist the bin would only see aliases
+        if self.frequency_hz >= burst.sample_rate_hz / 2.0 {
+            return None;
+        }
+        let level = dsp::tone_level(&burst.samples, burst.sample_rate_hz, self.frequency_hz);
+        (level >= self.min_level
+            && dsp::tone_share(&burst.samples, burst.sample_rate_hz, self.frequency_hz)
+                >= MIN_SHARE)
+            .then_some(level)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cadence {
+    // ISO 8201 evacuation pattern of smoke alarms: three 0.5 s pulses, 0.5 s apart, then 1.5 s
+    T3,
+    // Carbon monoxide alarms: four 0.1 s pulses, 0.1 s apart, then 5 s
+    T4,
+}
+
+impl Cadence {
+    pub fn alert(&self) -> &'static str {
+        match self {
+            Cadence::T3 => "smoke_alarm",
+            Cadence::T4 => "co_alarm",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Cadence::T3 => "T3",
+            Cadence::T4 => "T4",
+        }
+    }
+
+    fn pulses(&self) -> usize {
+        match self {
+            Cadence::T3 => 3,
+            Cadence::T4 => 4,
+        }
+    }
+
+    // Nominal length of the pulses and of the gaps between them
+    fn nominal(&self) -> Duration {
+        match self {
+            Cadence::T3 => Duration::from_millis(500),
+            Cadence::T4 => Duration::from_millis(100),
+        }
+    }
+}
+
+const CADENCES: [Cadence; 2] = [Cadence::T3, Cadence::T4];
+// Pulses and gaps may be off by this much of their nominal length and still count
+const TIMING_TOLERANCE: f32 = 0.6;
+// Longer than any gap within a group, shorter than the pause between groups
+const GROUP_GAP: Duration = Duration::from_millis(1000);
+// Silence after which an alarm is over
+const EPISODE_GAP: Duration = Duration::from_secs(10);
+// One group can be a coincidence, two in a row hardly
+const MIN_GROUPS: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneEvent {
+    // The tone alone, named by the configuration
+    Tone(Decibel),
+    // The tone in a standard alarm cadence
+    Cadence(CadenceMatch),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CadenceMatch {
+    pub cadence: Cadence,
+    // How closely the pulses and gaps followed the nominal timing, from 0.0 to 1.0
+    pub confidence: f32,
+    // Since the first pulse of the alarm
+    pub duration: Duration,
+    // The loudest the tone got
+    pub level: Decibel,
+}
+
+struct Episode {
+    cadence: Cadence,
+    groups: u32,
+    timing_sum: f32,
+    started: Instant,
+    peak: Decibel,
+    reported: bool,
+}
+
+// Turns tone/no tone per burst into pulses, groups of pulses and alarm cadences. Reports each
+// alarm once, after MIN_GROUPS matching groups in a row.
+#[derive(Default)]
+pub struct CadenceDetector {
+    // Since when the tone has been present or absent
+    edge: Option<(Instant, bool)>,
+    pulses: Vec<Duration>,
+    gaps: Vec<Duration>,
+    group_started: Option<Instant>,
+    group_peak: Decibel,
+    episode: Option<Episode>,
+}
+
+impl CadenceDetector {
+    pub fn update(&mut self, now: Instant, level: Option<Decibel>) -> Option<CadenceMatch> {
+        let present = level.is_some();
+        let (since, was_present) = *self.edge.get_or_insert((now, false));
+        if let Some(level) = level {
+            self.group_peak = if level > self.group_peak {
+                level
+            } else {
+                self.group_peak
+            };
+        }
+        if present != was_present {
+            let length = now.duration_since(since);
+            if was_present {
+                self.pulses.push(length);
+            } else if !self.pulses.is_empty() {
+                self.gaps.push(length);
+            }
+            if present && self.group_started.is_none() {
+                self.group_started = Some(now);
+            }
+            self.edge = Some((now, present));
+            return None;
+        }
+        let silence = now.duration_since(since);
+        if present || silence < GROUP_GAP {
+            return None;
+        }
+        if silence >= EPISODE_GAP {
+            self.episode = None;
+        }
+        if self.pulses.is_empty() {
+            return None;
+        }
+        self.close_group()
+    }
+
+    fn close_group(&mut self) -> Option<CadenceMatch> {
+        let pulses = std::mem::take(&mut self.pulses);
+        let gaps = std::mem::take(&mut self.gaps);
+        let started = self.group_started.take()?;
+        let peak = std::mem::take(&mut self.group_peak);
+        let Some((cadence, timing)) = CADENCES
+            .iter()
+            .find_map(|cadence| Some((*cadence, timing_score(*cadence, &pulses, &gaps)?)))
+        else {
+            self.episode = None;
+            return None;
+        };
+        let episode = match self.episode.take() {
+            Some(mut episode) if episode.cadence == cadence => {
+                episode.groups += 1;
+                episode.timing_sum += timing;
+                episode.peak = if peak > episode.peak {
+                    peak
+                } else {
+                    episode.peak
+                };
+                episode
+            }
+            _ => Episode {
+                cadence,
+                groups: 1,
+                timing_sum: timing,
+                started,
+                peak,
+                reported: false,
+            },
+        };
+        let episode = self.episode.insert(episode);
+        if episode.reported || episode.groups < MIN_GROUPS {
+            return None;
+        }
+        episode.reported = true;
+        Some(CadenceMatch {
+            cadence,
+            confidence: episode.timing_sum / episode.groups as f32,
+            duration: started.duration_since(episode.started) + total(&pulses) + total(&gaps),
+            level: episode.peak,
+        })
+    }
+}
+
+// From 1.0 for nominal timing down to 0.0 at the tolerance, None if the group doesn't have the
+// cadence at all
+fn timing_score(cadence: Cadence, pulses: &[Duration], gaps: &[Duration]) -> Option<f32> {
+    if pulses.len() != cadence.pulses() || gaps.len() != cadence.pulses() - 1 {
+        return None;
+    }
+    let nominal = cadence.nominal().as_secs_f32();
+    let scores: Vec<f32> = pulses
+        .iter()
+        .chain(gaps)
+        .map(|length| 1.0 - (length.as_secs_f32() - nominal).abs() / (nominal * TIMING_TOLERANCE))
+        .collect();
+    if scores.iter().any(|score| *score < 0.0) {
+        return None;
+    }
+    Some(scores.iter().sum::<f32>() / scores.len() as f32)
+}
+
+fn total(lengths: &[Duration]) -> Duration {
+    lengths.iter().sum()
+}
@@ -0,0 +1,201 @@
+use anyhow::{bail, Result};
+
+// Where a device is installed. With a tenant, every topic starts with it, so one broker can keep
+// customers apart with ACLs on `<tenant_id>/#`.
+pub struct Namespace<'a> {
+    pub tenant_id: &'a str,
+    pub site_id: &'a str,
+    pub floor: &'a str,
+}
+
+// The topics a device used before it moved into a tenant namespace, kept working so backends
+// can follow at their own pace
+pub struct LegacyTopics {
+    pub level: String,
+    pub cmd: String,
+    pub config: String,
+    // Retained pointer to the new base topic
+    pub moved: String,
+}
+
+// Every topic the sensor uses hangs from the base topic rendered from the configured template
+pub struct Topics {
+    pub level: String,
+    pub state: String,
+    pub availability: String,
+    pub info: String,
+    // Retained on every connection, says which device is where
+    pub birth: String,
+    pub boot: String,
+    pub diagnostics: String,
+    pub classification: String,
+    pub alerts: String,
+    // The levels around an alert
+    pub alert_burst: String,
+    pub direction: String,
+    pub vibration: String,
+    pub fused: String,
+    pub heartbeat: String,
+    // Readings queued while the broker was away, each in an envelope with its time, so the level
+    // topic keeps one schema
+    pub replay: String,
+    pub rules: String,
+    // Retained, the offset that makes the levels SPL
+    pub calibration: String,
+    // Retained, how far threshold learning got and what it proposes
+    pub autotune: String,
+    pub outage: String,
+    pub profile: String,
+    pub benchmark: String,
+    // The manifest of a diagnostic bundle, its chunks below it under the bundle's id
+    pub diag_bundle: String,
+    pub loopback: String,
+    // Raw samples during a support session
+    pub adc_stream: String,
+    pub firmware: String,
+    // Retained firmware inventory, not to be confused with the firmware metrics above
+    pub fw: String,
+    // Retained, what each LED pattern means
+    pub led_codes: String,
+    pub cmd: String,
+    pub config: String,
+    pub legacy: Option<LegacyTopics>,
+    // JSON fields identifying the device in documents stored by the backend
+    pub metadata: String,
+}
+
+impl Topics {
+    pub fn new(
+        template: &str,
+        device_id: &str,
+        mac: &str,
+        namespace: &Namespace,
+        with_legacy: bool,
+    ) -> Result<Self> {
+        for (name, value) in [
+            ("tenant_id", namespace.tenant_id),
+            ("site_id", namespace.site_id),
+        ] {
+            if value.contains(['/', '+', '#', '"', '\\']) {
+                bail!("{} {:?} can't be part of a topic", name, value);
+            }
+        }
+        let flat = render(template, device_id, mac, namespace)?;
+        let mut prefix = String::new();
+        if !namespace.tenant_id.is_empty() && !template.contains("{tenant_id}") {
+            prefix = format!("{}/", namespace.tenant_id);
+            if !namespace.site_id.is_empty()
+                && !template.contains("{site_id}")
+                && !template.contains("{site}")
+            {
+                prefix.push_str(namespace.site_id);
+                prefix.push('/');
+            }
+        }
+        let base = format!("{prefix}{flat}");
+        let legacy = (with_legacy && base != flat).then(|| LegacyTopics {
+            level: flat.clone(),
+            cmd: format!("{flat}/cmd"),
+            config: format!("{flat}/config"),
+            moved: format!("{flat}/moved"),
+        });
+        let mut metadata = format!("\"device_id\":\"{}\"", device_id);
+        for (name, value) in [
+            ("tenant_id", namespace.tenant_id),
+            ("site_id", namespace.site_id),
+        ] {
+            if !value.is_empty() {
+                metadata.push_str(&format!(",\"{}\":\"{}\"", name, value));
+            }
+        }
+        let diagnostics = format!("{base}/diagnostics");
+        Ok(Topics {
+            state: format!("{base}/state"),
+            availability: format!("{base}/status"),
+            info: format!("{base}/info"),
+            birth: format!("{base}/birth"),
+            boot: format!("{base}/boot"),
+            classification: format!("{base}/classification"),
+            alerts: format!("{base}/alerts"),
+            alert_burst: format!("{base}/alerts/burst"),
+            direction: format!("{base}/direction"),
+            vibration: format!("{base}/vibration"),
+            fused: format!("{base}/fused"),
+            heartbeat: format!("{base}/heartbeat"),
+            replay: format!("{base}/replay"),
+            rules: format!("{base}/rules"),
+            calibration: format!("{base}/calibration"),
+            autotune: format!("{base}/autotune"),
+            outage: format!("{diagnostics}/outage"),
+            profile: format!("{diagnostics}/profile"),
+            benchmark: format!("{diagnostics}/benchmark"),
+            diag_bundle: format!("{diagnostics}/bundle"),
+            loopback: format!("{diagnostics}/loopback"),
+            adc_stream: format!("{diagnostics}/adc"),
+            firmware: format!("{diagnostics}/firmware"),
+            fw: format!("{base}/fw"),
+            led_codes: format!("{base}/meta/led_codes"),
+            cmd: format!("{base}/cmd"),
+            config: format!("{base}/config"),
+            legacy,
+            metadata,
+            diagnostics,
+            level: base,
+        })
+    }
+}
+
+// Supports `{device_id}` or `{mac}` (the base MAC address, also when manufacture gave the device
+// another id), `{tenant_id}`, `{site_id}` (or `{site}`) and `{floor}`. One of the first two is
+// mandatory, otherwise several sensors would end up publishing to the same topics.
+fn render(template: &str, device_id: &str, mac: &str, namespace: &Namespace) -> Result<String> {
+    let mut topic = String::with_capacity(template.len() + device_id.len());
+    let mut has_device_id = false;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        topic.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            bail!("Unclosed placeholder in topic template {:?}", template);
+        };
+        let placeholder = &rest[start + 1..start + len];
+        let value = match placeholder {
+            "device_id" => {
+                has_device_id = true;
+                device_id
+            }
+            "mac" => {
+                has_device_id = true;
+                mac
+            }
+            "tenant_id" => namespace.tenant_id,
+            "site_id" | "site" => namespace.site_id,
+            "floor" => namespace.floor,
+            _ => bail!("Unknown placeholder {{{}}} in topic template", placeholder),
+        };
+        if value.is_empty() {
+            bail!(
+                "No value configured for {{{}}} in topic template",
+                placeholder
+            );
+        }
+        topic.push_str(value);
+        rest = &rest[start + len + 1..];
+    }
+    topic.push_str(rest);
+    if !has_device_id {
+        bail!(
+            "Topic template {:?} lacks {{device_id}} or {{mac}}",
+            template
+        );
+    }
+    if topic.contains('}') {
+        bail!("Unbalanced braces in topic template {:?}", template);
+    }
+    if topic.contains(['+', '#']) {
+        bail!("Topic {:?} contains MQTT wildcards", topic);
+    }
+    if topic.split('/').any(str::is_empty) {
+        bail!("Topic {:?} has empty levels", topic);
+    }
+    Ok(topic)
+}
@@ -0,0 +1,64 @@
+use std::f32::consts::PI;
+
+use crate::dsp::{Decibel, RawAdc};
+
+// What the sample window is decimated to. Structure-borne noise (footsteps, doors, bass through
+// the floor) sits well below the 50 Hz that allows.
+const NOMINAL_RATE_HZ: f32 = 100.0;
+// Takes out gravity on an accelerometer and the bias of a piezo amplifier, including their drift
+const HIGH_PASS_HZ: f32 = 1.0;
+
+// Level and peak of a piezo or accelerometer channel over a reporting interval, on the scale of
+// the mic levels: dB relative to one ADC count
+pub struct VibrationMeter {
+    alpha: f32,
+    previous: Option<(f32, f32)>,
+    squares: f32,
+    count: u32,
+    peak: f32,
+}
+
+impl Default for VibrationMeter {
+    fn default() -> Self {
+        let rc = 1.0 / (2.0 * PI * HIGH_PASS_HZ);
+        VibrationMeter {
+            alpha: rc / (rc + 1.0 / NOMINAL_RATE_HZ),
+            previous: None,
+            squares: 0.0,
+            count: 0,
+            peak: 0.0,
+        }
+    }
+}
+
+impl VibrationMeter {
+    pub fn add(&mut self, sample: RawAdc) {
+        let input = sample.0 as f32;
+        // The first sample only primes the filter, a step from zero would read as a hit
+        let output = match self.previous {
+            None => 0.0,
+            Some((previous_input, previous_output)) => {
+                self.alpha * (previous_output + input - previous_input)
+            }
+        };
+        self.previous = Some((input, output));
+        self.squares += output * output;
+        self.count += 1;
+        self.peak = self.peak.max(output.abs());
+    }
+
+    // RMS level and peak since the last call, None without samples. Anything below one count
+    // is below the resolution of the ADC and reads as 0 dB.
+    pub fn take(&mut self) -> Option<(Decibel, Decibel)> {
+        if self.count == 0 {
+            return None;
+        }
+        let rms = (self.squares / self.count as f32).sqrt();
+        let peak = self.peak;
+        self.squares = 0.0;
+        self.count = 0;
+        self.peak = 0.0;
+        let to_db = |counts: f32| Decibel(20.0 * counts.max(1.0).log10());
+        Some((to_db(rms), to_db(peak)))
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    ptr,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use esp_idf_svc::{
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::{esp_restart, esp_task_wdt_add, esp_task_wdt_reset},
+};
+
+const NVS_WATCHDOG_NAMESPACE: &str = "bzzz_wdt";
+// Task names are the other keys of the namespace, holding their timeout in seconds
+const NVS_TRIPPED_KEY: &str = "_tripped";
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Registration {
+    id: u32,
+    task_name: &'static str,
+    timeout: Duration,
+    last_feed: Instant,
+}
+
+struct Registry {
+    nvs: Option<EspNvs<NvsDefault>>,
+    tasks: Vec<Registration>,
+    next_id: u32,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    nvs: None,
+    tasks: Vec::new(),
+    next_id: 0,
+});
+
+// Stops watching the task when dropped, e.g. when a supervised worker fails
+pub struct Watchdog {
+    id: u32,
+}
+
+impl Watchdog {
+    pub fn feed(&self) {
+        let mut registry = REGISTRY.lock().unwrap();
+        if let Some(task) = registry.tasks.iter_mut().find(|task| task.id == self.id) {
+            task.last_feed = Instant::now();
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        REGISTRY
+            .lock()
+            .unwrap()
+            .tasks
+            .retain(|task| task.id != self.id);
+    }
+}
+
+// The timeout stored in NVS under the task name, if any, wins over the default given here
+pub fn register(task_name: &'static str, timeout: Duration) -> Watchdog {
+    let mut registry = REGISTRY.lock().unwrap();
+    let timeout = registry
+        .nvs
+        .as_ref()
+        .and_then(|nvs| nvs.get_u32(task_name).ok().flatten())
+        .map_or(timeout, |secs| Duration::from_secs(secs.into()));
+    let id = registry.next_id;
+    registry.next_id = registry.next_id.wrapping_add(1);
+    registry.tasks.push(Registration {
+        id,
+        task_name,
+        timeout,
+        last_feed: Instant::now(),
+    });
+    log::info!("Watchdog for {} set to {:?}", task_name, timeout);
+    Watchdog { id }
+}
+
+// The task that tripped the watchdog before the last reset, cleared once read
+pub fn take_tripped() -> Option<String> {
+    let mut registry = REGISTRY.lock().unwrap();
+    let nvs = registry.nvs.as_mut()?;
+    let mut buffer = [0u8; 16];
+    let task_name = nvs.get_str(NVS_TRIPPED_KEY, &mut buffer).ok()??.to_string();
+    let _ = nvs.remove(NVS_TRIPPED_KEY);
+    Some(task_name)
+}
+
+// One hardware watchdog timeout can't fit tasks that legitimately block for very different
+// times, so tasks are checked in software by a monitor that feeds the hardware one itself.
+pub fn start(nvs_partition: EspDefaultNvsPartition) {
+    REGISTRY.lock().unwrap().nvs = EspNvs::new(nvs_partition, NVS_WATCHDOG_NAMESPACE, true)
+        .map_err(|err| log::error!("Unable to open watchdog settings: {}", err))
+        .ok();
+    if let Err(err) = thread::Builder::new().stack_size(4096).spawn(monitor) {
+        log::error!("Unable to start watchdog: {}", err);
+    }
+}
+
+fn monitor() {
+    let hardware_watched = unsafe { esp_task_wdt_add(ptr::null_mut()) } == 0;
+    loop {
+        thread::sleep(CHECK_INTERVAL);
+        if hardware_watched {
+            unsafe { esp_task_wdt_reset() };
+        }
+        let mut registry = REGISTRY.lock().unwrap();
+        let Some(task_name) = registry
+            .tasks
+            .iter()
+            .find(|task| task.last_feed.elapsed() > task.timeout)
+            .map(|task| task.task_name)
+        else {
+            continue;
+        };
+        log::error!("Watchdog tripped by {}, restarting", task_name);
+        if let Some(nvs) = registry.nvs.as_mut() {
+            let _ = nvs.set_str(NVS_TRIPPED_KEY, task_name);
+        }
+        unsafe { esp_restart() };
+    }
+}
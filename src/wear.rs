@@ -0,0 +1,201 @@
+mod estimate;
+
+use std::{
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        Mutex,
+    },
+};
+
+use anyhow::{Context, Result};
+use esp_idf_svc::{
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::{esp, esp_ota_get_app_partition_count, nvs_get_stats, nvs_stats_t},
+};
+
+pub use estimate::WearEstimate;
+
+const NVS_WEAR_NAMESPACE: &str = "bzzz_wear";
+// Saved a block of entries at a time, so tracking adds next to nothing to the wear it tracks.
+// Losing power forgets what was written since.
+const SAVE_EVERY_ENTRIES: u64 = 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Partition {
+    Nvs,
+    Ota,
+}
+
+impl Partition {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Partition::Nvs => "nvs",
+            Partition::Ota => "ota",
+        }
+    }
+}
+
+struct Tracker {
+    nvs: EspNvs<NvsDefault>,
+    estimates: [WearEstimate; 2],
+    saved: [u64; 2],
+    endurance_cycles: u32,
+    throttle_pct: u32,
+}
+
+impl Tracker {
+    fn save(&mut self) {
+        for partition in [Partition::Nvs, Partition::Ota] {
+            let written = self.estimates[partition.index()].written;
+            if written == self.saved[partition.index()] {
+                continue;
+            }
+            match self.nvs.set_u64(partition.as_str(), written) {
+                Ok(_) => {
+                    self.saved[partition.index()] = written;
+                    self.estimates[Partition::Nvs.index()].written += estimate::nvs_entries(0);
+                }
+                Err(err) => log::error!("Unable to save {:?} wear: {}", partition, err),
+            }
+        }
+    }
+
+    fn update_throttled(&self) {
+        let throttled = self
+            .estimates
+            .iter()
+            .any(|estimate| estimate.used_pct(self.endurance_cycles) >= self.throttle_pct as f32);
+        if throttled && !THROTTLED.swap(true, Relaxed) {
+            log::warn!(
+                "Flash past {}% of its rated erase cycles, writing less often",
+                self.throttle_pct
+            );
+        } else if !throttled {
+            THROTTLED.store(false, Relaxed);
+        }
+    }
+}
+
+static TRACKER: Mutex<Option<Tracker>> = Mutex::new(None);
+// Read by every writer that can afford to write less often
+static THROTTLED: AtomicBool = AtomicBool::new(false);
+
+// Once at boot, before the partitions see much writing
+pub fn init(
+    nvs_partition: EspDefaultNvsPartition,
+    endurance_cycles: u32,
+    throttle_pct: u32,
+) -> Result<()> {
+    let nvs = EspNvs::new(nvs_partition, NVS_WEAR_NAMESPACE, true)
+        .context("Unable to open wear storage")?;
+    let mut stats = nvs_stats_t::default();
+    esp!(unsafe { nvs_get_stats(ptr::null(), &mut stats) })
+        .context("Unable to read NVS statistics")?;
+    let mut estimates = [
+        WearEstimate {
+            written: 0,
+            capacity: stats.total_entries as u64,
+        },
+        WearEstimate {
+            written: 0,
+            capacity: u64::from(unsafe { esp_ota_get_app_partition_count() }),
+        },
+    ];
+    let mut saved = [0u64; 2];
+    for partition in [Partition::Nvs, Partition::Ota] {
+        let written = nvs
+            .get_u64(partition.as_str())
+            .context("Unable to read wear")?
+            .unwrap_or(0);
+        estimates[partition.index()].written = written;
+        saved[partition.index()] = written;
+    }
+    log::info!(
+        "Flash wear: NVS {:.2} and OTA {:.2} erase cycles",
+        estimates[Partition::Nvs.index()].cycles(),
+        estimates[Partition::Ota.index()].cycles()
+    );
+    let tracker = Tracker {
+        nvs,
+        estimates,
+        saved,
+        endurance_cycles,
+        throttle_pct,
+    };
+    tracker.update_throttled();
+    *TRACKER.lock().unwrap() = Some(tracker);
+    Ok(())
+}
+
+pub fn configure(endurance_cycles: u32, throttle_pct: u32) {
+    if let Some(tracker) = TRACKER.lock().unwrap().as_mut() {
+        tracker.endurance_cycles = endurance_cycles;
+        tracker.throttle_pct = throttle_pct;
+        tracker.update_throttled();
+    }
+}
+
+// After every NVS write that went through, with the length of the string or blob, 0 for integers
+pub fn nvs_written(data_len: usize) {
+    let mut tracker = TRACKER.lock().unwrap();
+    let Some(tracker) = tracker.as_mut() else {
+        return;
+    };
+    tracker.estimates[Partition::Nvs.index()].written += estimate::nvs_entries(data_len);
+    let unsaved =
+        tracker.estimates[Partition::Nvs.index()].written - tracker.saved[Partition::Nvs.index()];
+    if unsaved >= SAVE_EVERY_ENTRIES {
+        tracker.save();
+        tracker.update_throttled();
+    }
+}
+
+// After every firmware image written to an OTA slot
+pub fn ota_written() {
+    if let Some(tracker) = TRACKER.lock().unwrap().as_mut() {
+        tracker.estimates[Partition::Ota.index()].written += 1;
+        tracker.save();
+        tracker.update_throttled();
+    }
+}
+
+// Before a commanded restart, so it doesn't forget the last block
+pub fn save() {
+    if let Some(tracker) = TRACKER.lock().unwrap().as_mut() {
+        tracker.save();
+    }
+}
+
+pub fn throttled() -> bool {
+    THROTTLED.load(Relaxed)
+}
+
+// Diagnostics fields, estimated erase cycles and the share of the rated ones per partition
+pub fn json_fields() -> String {
+    let tracker = TRACKER.lock().unwrap();
+    let Some(tracker) = tracker.as_ref() else {
+        return String::from("\"flash_wear\":null,\"flash_throttled\":false");
+    };
+    let partitions: Vec<String> = [Partition::Nvs, Partition::Ota]
+        .iter()
+        .map(|partition| {
+            let estimate = &tracker.estimates[partition.index()];
+            format!(
+                "\"{}\":{{\"cycles\":{:.2},\"used_pct\":{:.3}}}",
+                partition.as_str(),
+                estimate.cycles(),
+                estimate.used_pct(tracker.endurance_cycles)
+            )
+        })
+        .collect();
+    format!(
+        "\"flash_wear\":{{{}}},\"flash_throttled\":{}",
+        partitions.join(","),
+        throttled()
+    )
+}
@@ -0,0 +1,31 @@
+// NVS appends values to its pages in entries of this size
+const NVS_ENTRY_LEN: usize = 32;
+
+// The erase cycles a partition went through, from what was written to it and how much it takes
+// before every sector had to be erased once. Both in the partition's own unit: entries for NVS,
+// which erases a page once garbage collection needs it back, images for the OTA slots, which
+// are erased whole for every update.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WearEstimate {
+    pub written: u64,
+    pub capacity: u64,
+}
+
+impl WearEstimate {
+    pub fn cycles(&self) -> f32 {
+        if self.capacity == 0 {
+            return 0.0;
+        }
+        self.written as f32 / self.capacity as f32
+    }
+
+    // Of the endurance the flash is rated for
+    pub fn used_pct(&self, endurance_cycles: u32) -> f32 {
+        100.0 * self.cycles() / endurance_cycles.max(1) as f32
+    }
+}
+
+// A header entry, and for strings and blobs as many more as their data takes
+pub fn nvs_entries(data_len: usize) -> u64 {
+    1 + ((data_len + NVS_ENTRY_LEN - 1) / NVS_ENTRY_LEN) as u64
+}
@@ -0,0 +1,128 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use esp_idf_svc::sys::esp_random;
+
+use crate::encoding;
+
+// Wrong credentials in a row before everyone is locked out for a while. There is no per-client
+// state, so an attacker can lock out the installer too, but never guess faster than this.
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Granted,
+    // No or wrong credentials
+    Denied,
+    LockedOut(Duration),
+}
+
+struct Failures {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+// Protects the onboard HTTP server with a token, sent either as a bearer token or as the password
+// of basic auth, which is what browsers prompt for. An empty token matches nothing, so
+// everything is denied rather than open.
+pub struct WebAuth {
+    user: &'static str,
+    token: &'static str,
+    // Handed to authenticated pages so they can open the stream, WebSockets from browsers can't
+    // carry an Authorization header
+    stream_ticket: String,
+    failures: Mutex<Failures>,
+}
+
+impl WebAuth {
+    pub fn new(user: &'static str, token: &'static str) -> Self {
+        let stream_ticket = (0..4)
+            .map(|_| format!("{:08x}", unsafe { esp_random() }))
+            .collect();
+        WebAuth {
+            user,
+            token,
+            stream_ticket,
+            failures: Mutex::new(Failures {
+                count: 0,
+                locked_until: None,
+            }),
+        }
+    }
+
+    pub fn stream_ticket(&self) -> &str {
+        &self.stream_ticket
+    }
+
+    // Checks the Authorization header of a request. A missing header isn't a failed attempt,
+    // browsers always try without credentials first.
+    pub fn check_header(&self, authorization: Option<&str>) -> Access {
+        let Some(authorization) = authorization else {
+            return self.locked_out().unwrap_or(Access::Denied);
+        };
+        let valid = match authorization.trim().split_once(' ') {
+            Some(("Bearer", token)) => constant_time_eq(token.trim().as_bytes(), self.token),
+            Some(("Basic", credentials)) => encoding::decode_base64(credentials)
+                .as_deref()
+                .and_then(|credentials| {
+                    let separator = credentials.iter().position(|byte| *byte == b':')?;
+                    Some(
+                        (&credentials[..separator] == self.user.as_bytes())
+                            & constant_time_eq(&credentials[separator + 1..], self.token),
+                    )
+                })
+                .unwrap_or(false),
+            _ => false,
+        };
+        self.attempt(valid)
+    }
+
+    // Checks the first frame of a stream client, either the token or the ticket of a page
+    pub fn check_stream_key(&self, key: &[u8]) -> Access {
+        let valid = constant_time_eq(key, self.token) | constant_time_eq(key, &self.stream_ticket);
+        self.attempt(valid)
+    }
+
+    fn locked_out(&self) -> Option<Access> {
+        let failures = self.failures.lock().unwrap();
+        let remaining = failures
+            .locked_until?
+            .checked_duration_since(Instant::now())?;
+        Some(Access::LockedOut(remaining))
+    }
+
+    fn attempt(&self, valid: bool) -> Access {
+        // Even the right credentials are refused while locked out, or they could still be guessed
+        if let Some(locked_out) = self.locked_out() {
+            return locked_out;
+        }
+        let mut failures = self.failures.lock().unwrap();
+        if valid && !self.token.is_empty() {
+            failures.count = 0;
+            return Access::Granted;
+        }
+        failures.count += 1;
+        log::warn!("Failed HTTP authentication attempt {}", failures.count);
+        if failures.count >= MAX_FAILURES {
+            log::warn!("Locking out HTTP clients for {:?}", LOCKOUT);
+            failures.count = 0;
+            failures.locked_until = Some(Instant::now() + LOCKOUT);
+        }
+        Access::Denied
+    }
+}
+
+// Doesn't stop at the first difference, so response times don't reveal how much of a guess was
+// right
+fn constant_time_eq(given: &[u8], expected: &str) -> bool {
+    let expected = expected.as_bytes();
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
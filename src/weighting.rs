@@ -0,0 +1,151 @@
+use std::f64::consts::PI;
+
+use crate::dsp::{self, Decibel, RawAdc};
+
+// Poles of the IEC 61672 curves
+const POLE_1_HZ: f64 = 20.598_997;
+const POLE_2_HZ: f64 = 107.652_65;
+const POLE_3_HZ: f64 = 737.862_23;
+const POLE_4_HZ: f64 = 12_194.217;
+// Where both curves are 0 dB
+const REFERENCE_HZ: f64 = 1000.0;
+
+// Frequency weighting of the level, like a sound level meter's. Z is the plain RMS of the samples
+// without their DC bias, the scale the default thresholds are set for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Weighting {
+    Z,
+    A,
+    C,
+}
+
+impl Weighting {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Z" => Some(Weighting::Z),
+            "A" => Some(Weighting::A),
+            "C" => Some(Weighting::C),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Weighting::Z => "Z",
+            Weighting::A => "A",
+            Weighting::C => "C",
+        }
+    }
+}
+
+// One second-order section, a = [1, a1, a2]
+#[derive(Clone, Copy, Debug)]
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+}
+
+impl Biquad {
+    // (b0 s² + b1 s + b2) / (s² + a1 s + a2) through the bilinear transform, without prewarping:
+    // the curves hardly matter near Nyquist of the sample window
+    fn bilinear(b: [f64; 3], a: [f64; 2], sample_rate_hz: f64) -> Self {
+        let c = 2.0 * sample_rate_hz;
+        let c2 = c * c;
+        let a0 = c2 + a[0] * c + a[1];
+        Biquad {
+            b: [
+                ((b[0] * c2 + b[1] * c + b[2]) / a0) as f32,
+                (2.0 * (b[2] - b[0] * c2) / a0) as f32,
+                ((b[0] * c2 - b[1] * c + b[2]) / a0) as f32,
+            ],
+            a: [
+                (2.0 * (a[1] - c2) / a0) as f32,
+                ((c2 - a[0] * c + a[1]) / a0) as f32,
+            ],
+        }
+    }
+
+    // (s + w1)(s + w2)
+    fn poles(w1: f64, w2: f64) -> [f64; 2] {
+        [w1 + w2, w1 * w2]
+    }
+
+    fn magnitude(&self, omega: f64) -> f64 {
+        let response = |c: [f64; 3]| {
+            let re = c[0] + c[1] * omega.cos() + c[2] * (2.0 * omega).cos();
+            let im = c[1] * omega.sin() + c[2] * (2.0 * omega).sin();
+            (re * re + im * im).sqrt()
+        };
+        let b = self.b.map(f64::from);
+        response(b) / response([1.0, f64::from(self.a[0]), f64::from(self.a[1])])
+    }
+}
+
+pub struct WeightingFilter {
+    weighting: Weighting,
+    sections: Vec<Biquad>,
+    gain: f32,
+}
+
+impl WeightingFilter {
+    pub fn new(weighting: Weighting, sample_rate_hz: f32) -> Self {
+        let fs = f64::from(sample_rate_hz);
+        let w = |hz: f64| 2.0 * PI * hz;
+        // s² over two poles, and one over two poles
+        let high_pass =
+            |p1: f64, p2: f64| Biquad::bilinear([1.0, 0.0, 0.0], Biquad::poles(w(p1), w(p2)), fs);
+        let low_pass =
+            |p1: f64, p2: f64| Biquad::bilinear([0.0, 0.0, 1.0], Biquad::poles(w(p1), w(p2)), fs);
+        let sections = match weighting {
+            Weighting::Z => vec![],
+            Weighting::A => vec![
+                high_pass(POLE_1_HZ, POLE_1_HZ),
+                high_pass(POLE_2_HZ, POLE_3_HZ),
+                low_pass(POLE_4_HZ, POLE_4_HZ),
+            ],
+            Weighting::C => vec![
+                high_pass(POLE_1_HZ, POLE_1_HZ),
+                low_pass(POLE_4_HZ, POLE_4_HZ),
+            ],
+        };
+        let omega = 2.0 * PI * REFERENCE_HZ / fs;
+        let gain = 1.0
+            / sections
+                .iter()
+                .map(|section| section.magnitude(omega))
+                .product::<f64>();
+        WeightingFilter {
+            weighting,
+            sections,
+            gain: gain as f32,
+        }
+    }
+
+    pub fn weighting(&self) -> Weighting {
+        self.weighting
+    }
+
+    // Of one window. Every window starts the filter from rest, without its mean, which the
+    // weighted curves drop anyway and which would otherwise ring through the high-pass.
+    pub fn level(&self, samples: &[RawAdc]) -> Decibel {
+        if self.weighting == Weighting::Z || samples.is_empty() {
+            return dsp::rms_to_db(samples);
+        }
+        let mean = samples.iter().map(|sample| sample.0 as f32).sum::<f32>() / samples.len() as f32;
+        let mut state = vec![[0.0f32; 2]; self.sections.len()];
+        let mut squares = 0.0f32;
+        for sample in samples {
+            let mut value = sample.0 as f32 - mean;
+            // Transposed direct form II
+            for (section, state) in self.sections.iter().zip(state.iter_mut()) {
+                let output = section.b[0] * value + state[0];
+                state[0] = section.b[1] * value - section.a[0] * output + state[1];
+                state[1] = section.b[2] * value - section.a[1] * output;
+                value = output;
+            }
+            value *= self.gain;
+            squares += value * value;
+        }
+        Decibel(10.0 * (squares / samples.len() as f32).log10())
+    }
+}